@@ -6,7 +6,7 @@ use std::process::exit;
 use clap::{App, Arg, ArgMatches};
 use rusty_usn::mapping::FolderMapping;
 
-static VERSION: &'static str = "0.0.1";
+static VERSION: &str = "0.0.1";
 
 
 fn make_app<'a, 'b>() -> App<'a, 'b> {
@@ -42,7 +42,7 @@ fn main() {
 
     let iter = usn::IterRecords::new(
         String::from("Test source"),
-        raw_buffer.iter().cloned().collect(),
+        raw_buffer.to_vec(),
         0,
         raw_buffer.len()
     );
@@ -0,0 +1,692 @@
+//! Members of zip and gzip archives as Read + Seek streams, so collected
+//! files can be parsed without unpacking them. Zip members may be stored or
+//! deflated, and encrypted with the traditional PKWARE (ZipCrypto) cipher.
+//!
+//! Compressed streams can only be read forward. Seeking forward decompresses
+//! and discards the bytes in between. The last bytes decompressed are kept, so
+//! a short seek backward (such as the overlap between the chunks of a parser)
+//! is read from memory; a longer one starts over from the beginning of the
+//! member, so members are best read in order.
+//!
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::{seek_position, SharedHandle};
+
+
+const ZIP_LOCAL_SIGNATURE: &[u8; 4] = b"PK\x03\x04";
+const ZIP_CENTRAL_SIGNATURE: &[u8; 4] = b"PK\x01\x02";
+const ZIP_END_SIGNATURE: &[u8; 4] = b"PK\x05\x06";
+const ZIP64_END_SIGNATURE: &[u8; 4] = b"PK\x06\x06";
+const ZIP64_LOCATOR_SIGNATURE: &[u8; 4] = b"PK\x06\x07";
+const GZIP_SIGNATURE: &[u8; 2] = b"\x1f\x8b";
+const SIZE_ZIP_LOCAL_HEADER: u64 = 30;
+const SIZE_ZIP_CENTRAL_HEADER: usize = 46;
+const SIZE_ZIP_END: u64 = 22;
+const SIZE_ZIP64_LOCATOR: u64 = 20;
+const SIZE_ZIP64_END: u64 = 56;
+// The end of central directory record is followed by a comment of up to 64 KiB
+const MAX_ZIP_COMMENT: u64 = 0xFFFF;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const ZIP_FLAG_ENCRYPTED: u16 = 0x0001;
+const ZIP_FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const ZIP_METHOD_STORED: u16 = 0;
+const ZIP_METHOD_DEFLATE: u16 = 8;
+const SIZE_ZIPCRYPTO_HEADER: u64 = 12;
+const GZIP_FLAG_EXTRA: u8 = 0x04;
+const GZIP_FLAG_NAME: u8 = 0x08;
+// Bytes decompressed and thrown away at a time when seeking forward
+const SIZE_DISCARD: usize = 65536;
+// Bytes kept before the stream position for seeks backward. Parser chunks
+// overlap by 1 KiB, which this covers with room to spare.
+const SIZE_LOOK_BACK: usize = 65536;
+
+
+lazy_static! {
+    static ref CRC32_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (index, value) in table.iter_mut().enumerate() {
+            let mut crc = index as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+            *value = crc;
+        }
+        table
+    };
+}
+
+
+/// Check if the handle starts with a zip local file header or an empty zip's end record.
+pub fn is_zip<T: ReadSeek>(handle: &mut T) -> Result<bool, UsnError> {
+    let signature = read_signature(handle, 4)?;
+    Ok(signature == ZIP_LOCAL_SIGNATURE || signature == ZIP_END_SIGNATURE)
+}
+
+/// Check if the handle starts with a gzip member header.
+pub fn is_gzip<T: ReadSeek>(handle: &mut T) -> Result<bool, UsnError> {
+    Ok(read_signature(handle, 2)? == GZIP_SIGNATURE)
+}
+
+/// Split an `archive!member` location into the archive path and the member
+/// name. Returns None unless the part before a `!` is an existing file.
+pub fn split_member_path(location: &str) -> Option<(&str, &str)> {
+    location.match_indices('!')
+        .map(|(index, _)| (&location[..index], &location[index + 1..]))
+        .find(|(archive, _)| Path::new(archive).is_file())
+}
+
+/// Open an `archive!member` location or a gzip file. Returns None if the
+/// location is neither, so it can be opened as a plain file.
+pub fn open_member(location: &str, password: Option<&str>) -> Result<Option<MemberReader<File>>, UsnError> {
+    let (archive_path, member_name) = match split_member_path(location) {
+        Some((archive_path, member_name)) => (archive_path, Some(member_name)),
+        None => (location, None)
+    };
+
+    let mut handle = File::open(archive_path)?;
+    if is_gzip(&mut handle)? {
+        return Ok(Some(MemberReader::from_gzip(archive_path, handle)?));
+    }
+
+    let member_name = match member_name {
+        Some(member_name) => member_name,
+        None => return Ok(None)
+    };
+
+    let mut archive = ZipArchive::from_read_seek(archive_path, handle)?;
+    if let Some(password) = password {
+        archive = archive.with_password(password);
+    }
+
+    let member = archive.find_member(member_name)
+        .ok_or_else(|| UsnError::invalid_archive(format!("{} has no member {}", archive_path, member_name)))?
+        .clone();
+
+    Ok(Some(archive.open_member(&member)?))
+}
+
+
+/// A file in a zip archive.
+#[derive(Debug, Clone)]
+pub struct ZipMember {
+    /// The name of the member, with `/` separators.
+    pub name: String,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub method: u16,
+    pub encrypted: bool,
+    flags: u16,
+    modified_time: u16,
+    crc32: u32,
+    header_offset: u64,
+}
+
+impl ZipMember {
+    pub fn is_directory(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+
+/// A zip archive, read through its central directory.
+pub struct ZipArchive<T> {
+    handle: SharedHandle<T>,
+    source: String,
+    members: Vec<ZipMember>,
+    password: Option<Vec<u8>>,
+}
+
+impl ZipArchive<File> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, UsnError> {
+        let path = path.as_ref();
+        Self::from_read_seek(&path.to_string_lossy(), File::open(path)?)
+    }
+}
+
+impl<T: ReadSeek + Send + 'static> ZipArchive<T> {
+    /// `source` names the archive in the source of member readers.
+    pub fn from_read_seek(source: &str, mut handle: T) -> Result<Self, UsnError> {
+        let (entry_count, directory_offset, directory_size) = read_end_record(&mut handle)?;
+        let file_size = handle.seek(SeekFrom::End(0))?;
+
+        let directory = read_at(&mut handle, directory_offset, directory_size)?;
+        // Every entry needs at least a fixed size header
+        if entry_count > directory_size / SIZE_ZIP_CENTRAL_HEADER as u64 {
+            return Err(UsnError::invalid_archive(format!(
+                "central directory of {} bytes cannot hold {} entries", directory_size, entry_count
+            )));
+        }
+        let mut members = Vec::with_capacity(entry_count as usize);
+        let mut offset = 0;
+        while members.len() < entry_count as usize {
+            let (member, header_size) = read_central_header(&directory[offset..])?;
+            // The local header and data must fit in the file
+            let data_end = member.header_offset
+                .checked_add(SIZE_ZIP_LOCAL_HEADER)
+                .and_then(|offset| offset.checked_add(member.compressed_size));
+            if data_end.is_none_or(|data_end| data_end > file_size) {
+                return Err(UsnError::invalid_archive(format!(
+                    "data of {} is past the end of the file", member.name
+                )));
+            }
+            members.push(member);
+            offset += header_size;
+        }
+
+        Ok(ZipArchive {
+            handle: Arc::new(Mutex::new(handle)),
+            source: source.to_string(),
+            members,
+            password: None,
+        })
+    }
+
+    /// The password for encrypted members.
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = Some(password.as_bytes().to_vec());
+        self
+    }
+
+    pub fn get_members(&self) -> &[ZipMember] {
+        &self.members
+    }
+
+    /// Find a member by name. `\` and `/` are treated as the same separator.
+    pub fn find_member(&self, name: &str) -> Option<&ZipMember> {
+        let name = name.replace('\\', "/");
+        self.members.iter().find(|member| member.name == name)
+    }
+
+    /// A reader of the uncompressed (and decrypted) data of a member.
+    pub fn open_member(&self, member: &ZipMember) -> Result<MemberReader<T>, UsnError> {
+        if member.method != ZIP_METHOD_STORED && member.method != ZIP_METHOD_DEFLATE {
+            return Err(UsnError::invalid_archive(format!(
+                "{} uses unsupported compression method {}", member.name, member.method
+            )));
+        }
+        if member.encrypted && self.password.is_none() {
+            return Err(UsnError::invalid_archive(format!("{} is encrypted and no password was given", member.name)));
+        }
+
+        let local_header = {
+            let mut handle = self.handle.lock().map_err(|_| UsnError::invalid_archive(
+                "archive lock poisoned".to_string()
+            ))?;
+            read_at(&mut *handle, member.header_offset, SIZE_ZIP_LOCAL_HEADER)?
+        };
+        if &local_header[0..4] != ZIP_LOCAL_SIGNATURE {
+            return Err(UsnError::invalid_archive(format!("bad local header for {}", member.name)));
+        }
+        let data_offset = member.header_offset
+            .checked_add(SIZE_ZIP_LOCAL_HEADER +
+                u64::from(LittleEndian::read_u16(&local_header[26..28])) +
+                u64::from(LittleEndian::read_u16(&local_header[28..30])))
+            .ok_or_else(|| UsnError::invalid_archive(format!("bad local header for {}", member.name)))?;
+
+        let mut reader = MemberReader {
+            handle: self.handle.clone(),
+            source: format!("{}!{}", self.source, member.name),
+            layout: MemberLayout::Zip {
+                member: member.clone(),
+                data_offset,
+                password: self.password.clone()
+            },
+            stream: None,
+            stream_position: 0,
+            look_back: Vec::new(),
+            position: 0,
+            size: Some(member.size),
+        };
+
+        // Check the password now rather than on the first read
+        reader.open_stream(0)?;
+        Ok(reader)
+    }
+}
+
+
+enum MemberLayout {
+    Zip {
+        member: ZipMember,
+        data_offset: u64,
+        password: Option<Vec<u8>>
+    },
+    Gzip
+}
+
+
+/// The uncompressed data of an archive member.
+pub struct MemberReader<T> {
+    handle: SharedHandle<T>,
+    source: String,
+    layout: MemberLayout,
+    stream: Option<Box<dyn Read + Send>>,
+    stream_position: u64,
+    /// The last bytes read from the stream, ending at `stream_position`.
+    look_back: Vec<u8>,
+    position: u64,
+    size: Option<u64>,
+}
+
+impl<T: ReadSeek + Send + 'static> MemberReader<T> {
+    /// A reader of a gzip file. Concatenated gzip members are read as one stream.
+    pub fn from_gzip(source: &str, mut handle: T) -> Result<Self, UsnError> {
+        let name = read_gzip_name(&mut handle)?.unwrap_or_else(|| {
+            let path = Path::new(source);
+            path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+        });
+
+        Ok(MemberReader {
+            handle: Arc::new(Mutex::new(handle)),
+            source: format!("{}!{}", source, name),
+            layout: MemberLayout::Gzip,
+            stream: None,
+            stream_position: 0,
+            look_back: Vec::new(),
+            position: 0,
+            size: None,
+        })
+    }
+
+    /// The archive and member as `archive!member`.
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    /// Start the uncompressed stream over, at `offset` if the member can be
+    /// read from there directly.
+    fn open_stream(&mut self, offset: u64) -> io::Result<()> {
+        let (stream, stream_position): (Box<dyn Read + Send>, u64) = match self.layout {
+            MemberLayout::Zip { ref member, data_offset, ref password } => {
+                let end = data_offset.checked_add(member.compressed_size)
+                    .ok_or_else(region_overflow_error)?;
+                let mut region = ArchiveRegion {
+                    handle: self.handle.clone(),
+                    start: data_offset,
+                    end,
+                    position: 0
+                };
+
+                match password {
+                    Some(password) if member.encrypted => {
+                        let decrypted = ZipCryptoReader::new(region, password, member)?;
+                        match member.method {
+                            ZIP_METHOD_DEFLATE => (Box::new(DeflateDecoder::new(decrypted)), 0),
+                            _ => (Box::new(decrypted), 0)
+                        }
+                    },
+                    _ => match member.method {
+                        ZIP_METHOD_DEFLATE => (Box::new(DeflateDecoder::new(region)), 0),
+                        _ => {
+                            region.position = offset.min(member.size);
+                            let position = region.position;
+                            (Box::new(region), position)
+                        }
+                    }
+                }
+            },
+            MemberLayout::Gzip => {
+                let region = ArchiveRegion {
+                    handle: self.handle.clone(),
+                    start: 0,
+                    end: u64::MAX,
+                    position: 0
+                };
+                (Box::new(MultiGzDecoder::new(region)), 0)
+            }
+        };
+
+        self.stream = Some(stream);
+        self.stream_position = stream_position;
+        self.look_back.clear();
+        Ok(())
+    }
+
+    /// Decompress up to the current position.
+    fn align_stream(&mut self) -> io::Result<()> {
+        if self.stream.is_none() || self.stream_position > self.position {
+            self.open_stream(self.position)?;
+        }
+
+        let stream = self.stream.as_mut().expect("stream was just opened");
+        let mut discard = vec![0u8; SIZE_DISCARD];
+        while self.stream_position < self.position {
+            let to_read = (self.position - self.stream_position).min(SIZE_DISCARD as u64) as usize;
+            let read = stream.read(&mut discard[..to_read])?;
+            if read == 0 {
+                break;
+            }
+            keep_look_back(&mut self.look_back, &discard[..read]);
+            self.stream_position += read as u64;
+        }
+
+        Ok(())
+    }
+
+    /// The uncompressed size. Gzip only records the size modulo 4 GiB, so the
+    /// stream is decompressed once to count it.
+    fn get_size(&mut self) -> io::Result<u64> {
+        if let Some(size) = self.size {
+            return Ok(size);
+        }
+
+        let position = self.position;
+        self.position = self.stream_position;
+        self.align_stream()?;
+        let stream = self.stream.as_mut().expect("stream is open");
+        let size = self.stream_position + io::copy(stream, &mut io::sink())?;
+
+        self.stream = None;
+        self.look_back.clear();
+        self.position = position;
+        self.size = Some(size);
+        Ok(size)
+    }
+}
+
+impl<T: ReadSeek + Send + 'static> Read for MemberReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // A short seek backward is read from the bytes kept
+        let behind = self.stream_position.saturating_sub(self.position);
+        if behind > 0 && behind <= self.look_back.len() as u64 {
+            let start = self.look_back.len() - behind as usize;
+            let count = buf.len().min(behind as usize);
+            buf[..count].copy_from_slice(&self.look_back[start..start + count]);
+            self.position += count as u64;
+            return Ok(count);
+        }
+
+        self.align_stream()?;
+        if self.stream_position < self.position {
+            // Seeked past the end
+            return Ok(0);
+        }
+
+        let stream = self.stream.as_mut().expect("stream is open");
+        let read = stream.read(buf)?;
+        keep_look_back(&mut self.look_back, &buf[..read]);
+        self.stream_position += read as u64;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: ReadSeek + Send + 'static> Seek for MemberReader<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // The stream is only moved on the next read
+        self.position = seek_position(pos, self.position, || self.get_size())?;
+        Ok(self.position)
+    }
+}
+
+
+/// Add bytes read from a stream to its look back, keeping at least the last
+/// `SIZE_LOOK_BACK` bytes. It is trimmed once it is twice that, so that bytes
+/// are not moved on every read.
+fn keep_look_back(look_back: &mut Vec<u8>, data: &[u8]) {
+    look_back.extend_from_slice(data);
+    if look_back.len() > 2 * SIZE_LOOK_BACK {
+        look_back.drain(..look_back.len() - SIZE_LOOK_BACK);
+    }
+}
+
+
+/// The raw bytes of a member within the archive file.
+struct ArchiveRegion<T> {
+    handle: SharedHandle<T>,
+    start: u64,
+    end: u64,
+    position: u64,
+}
+
+fn region_overflow_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "member data offset overflows")
+}
+
+impl<T: ReadSeek> Read for ArchiveRegion<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = self.start.checked_add(self.position).ok_or_else(region_overflow_error)?;
+        let remaining = self.end.saturating_sub(offset);
+        let to_read = (buf.len() as u64).min(remaining) as usize;
+        if to_read == 0 {
+            return Ok(0);
+        }
+
+        let mut handle = self.handle.lock().map_err(|_| io::Error::other("archive lock poisoned"))?;
+        handle.seek(SeekFrom::Start(offset))?;
+        let read = handle.read(&mut buf[..to_read])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+
+/// Decrypts traditional PKWARE encryption.
+struct ZipCryptoReader<R> {
+    inner: R,
+    keys: [u32; 3],
+}
+
+impl<R: Read> ZipCryptoReader<R> {
+    /// Decrypt the encryption header, whose last byte checks the password.
+    fn new(inner: R, password: &[u8], member: &ZipMember) -> io::Result<Self> {
+        let mut reader = ZipCryptoReader {
+            inner,
+            keys: [0x1234_5678, 0x2345_6789, 0x3456_7890]
+        };
+        for byte in password {
+            reader.update_keys(*byte);
+        }
+
+        let mut header = [0u8; SIZE_ZIPCRYPTO_HEADER as usize];
+        reader.read_exact(&mut header)?;
+
+        // With a data descriptor the CRC may not be known when the header is
+        // written, so the high byte of the modified time is used instead.
+        let check = match member.flags & ZIP_FLAG_DATA_DESCRIPTOR {
+            0 => (member.crc32 >> 24) as u8,
+            _ => (member.modified_time >> 8) as u8
+        };
+        if header[11] != check {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("wrong password for {}", member.name)
+            ));
+        }
+
+        Ok(reader)
+    }
+
+    fn update_keys(&mut self, byte: u8) {
+        self.keys[0] = crc32_update(self.keys[0], byte);
+        self.keys[1] = self.keys[1].wrapping_add(self.keys[0] & 0xFF)
+            .wrapping_mul(134_775_813)
+            .wrapping_add(1);
+        self.keys[2] = crc32_update(self.keys[2], (self.keys[1] >> 24) as u8);
+    }
+
+    fn decrypt_byte(&self) -> u8 {
+        let temp = (self.keys[2] | 2) as u16;
+        (temp.wrapping_mul(temp ^ 1) >> 8) as u8
+    }
+}
+
+impl<R: Read> Read for ZipCryptoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for byte in buf[..read].iter_mut() {
+            *byte ^= self.decrypt_byte();
+            self.update_keys(*byte);
+        }
+        Ok(read)
+    }
+}
+
+
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    (crc >> 8) ^ CRC32_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize]
+}
+
+fn read_signature<T: ReadSeek>(handle: &mut T, size: usize) -> Result<Vec<u8>, UsnError> {
+    let mut signature = vec![0u8; size];
+    handle.seek(SeekFrom::Start(0))?;
+
+    let result = match handle.read_exact(&mut signature) {
+        Ok(_) => signature,
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => Vec::new(),
+        Err(error) => return Err(UsnError::from(error))
+    };
+
+    handle.seek(SeekFrom::Start(0))?;
+    Ok(result)
+}
+
+fn read_at<T: ReadSeek>(handle: &mut T, offset: u64, size: u64) -> Result<Vec<u8>, UsnError> {
+    let mut buffer = vec![0u8; size as usize];
+    handle.seek(SeekFrom::Start(offset))?;
+    handle.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Find the end of central directory record, following the zip64 locator if
+/// the archive needs it. Returns the entry count and the central directory's
+/// offset and size.
+fn read_end_record<T: ReadSeek>(handle: &mut T) -> Result<(u64, u64, u64), UsnError> {
+    let file_size = handle.seek(SeekFrom::End(0))?;
+    if file_size < SIZE_ZIP_END {
+        return Err(UsnError::invalid_archive("file is too small to be a zip".to_string()));
+    }
+
+    let tail_size = file_size.min(SIZE_ZIP_END + MAX_ZIP_COMMENT);
+    let tail_offset = file_size - tail_size;
+    let tail = read_at(handle, tail_offset, tail_size)?;
+
+    let end_index = (0..=tail.len() - SIZE_ZIP_END as usize)
+        .rev()
+        .find(|index| &tail[*index..*index + 4] == ZIP_END_SIGNATURE)
+        .ok_or_else(|| UsnError::invalid_archive("no end of central directory record".to_string()))?;
+    let end = &tail[end_index..];
+
+    let mut entry_count = u64::from(LittleEndian::read_u16(&end[10..12]));
+    let mut directory_size = u64::from(LittleEndian::read_u32(&end[12..16]));
+    let mut directory_offset = u64::from(LittleEndian::read_u32(&end[16..20]));
+
+    let end_offset = tail_offset + end_index as u64;
+    if end_offset >= SIZE_ZIP64_LOCATOR {
+        let locator = read_at(handle, end_offset - SIZE_ZIP64_LOCATOR, SIZE_ZIP64_LOCATOR)?;
+        if &locator[0..4] == ZIP64_LOCATOR_SIGNATURE {
+            let zip64_end = read_at(handle, LittleEndian::read_u64(&locator[8..16]), SIZE_ZIP64_END)?;
+            if &zip64_end[0..4] != ZIP64_END_SIGNATURE {
+                return Err(UsnError::invalid_archive("bad zip64 end of central directory record".to_string()));
+            }
+
+            entry_count = LittleEndian::read_u64(&zip64_end[32..40]);
+            directory_size = LittleEndian::read_u64(&zip64_end[40..48]);
+            directory_offset = LittleEndian::read_u64(&zip64_end[48..56]);
+        }
+    }
+
+    if directory_offset.checked_add(directory_size).is_none_or(|end| end > file_size) {
+        return Err(UsnError::invalid_archive("central directory is past the end of the file".to_string()));
+    }
+
+    Ok((entry_count, directory_offset, directory_size))
+}
+
+/// Parse a central directory file header. Returns the member and the size of the header.
+fn read_central_header(buffer: &[u8]) -> Result<(ZipMember, usize), UsnError> {
+    if buffer.len() < SIZE_ZIP_CENTRAL_HEADER || &buffer[0..4] != ZIP_CENTRAL_SIGNATURE {
+        return Err(UsnError::invalid_archive("bad central directory file header".to_string()));
+    }
+
+    let name_length = usize::from(LittleEndian::read_u16(&buffer[28..30]));
+    let extra_length = usize::from(LittleEndian::read_u16(&buffer[30..32]));
+    let comment_length = usize::from(LittleEndian::read_u16(&buffer[32..34]));
+    let header_size = SIZE_ZIP_CENTRAL_HEADER + name_length + extra_length + comment_length;
+    if buffer.len() < header_size {
+        return Err(UsnError::invalid_archive("truncated central directory file header".to_string()));
+    }
+
+    let name_end = SIZE_ZIP_CENTRAL_HEADER + name_length;
+    let flags = LittleEndian::read_u16(&buffer[8..10]);
+    let mut member = ZipMember {
+        name: String::from_utf8_lossy(&buffer[SIZE_ZIP_CENTRAL_HEADER..name_end]).replace('\\', "/"),
+        size: u64::from(LittleEndian::read_u32(&buffer[24..28])),
+        compressed_size: u64::from(LittleEndian::read_u32(&buffer[20..24])),
+        method: LittleEndian::read_u16(&buffer[10..12]),
+        encrypted: flags & ZIP_FLAG_ENCRYPTED != 0,
+        flags,
+        modified_time: LittleEndian::read_u16(&buffer[12..14]),
+        crc32: LittleEndian::read_u32(&buffer[16..20]),
+        header_offset: u64::from(LittleEndian::read_u32(&buffer[42..46])),
+    };
+
+    // Values too large for the header are in the zip64 extra field, in this order
+    let mut extra = &buffer[name_end..name_end + extra_length];
+    while extra.len() >= 4 {
+        let id = LittleEndian::read_u16(&extra[0..2]);
+        let size = usize::from(LittleEndian::read_u16(&extra[2..4])).min(extra.len() - 4);
+        if id == ZIP64_EXTRA_ID {
+            let mut values = extra[4..4 + size].chunks_exact(8).map(LittleEndian::read_u64);
+            for field in [&mut member.size, &mut member.compressed_size, &mut member.header_offset] {
+                if *field == 0xFFFF_FFFF {
+                    match values.next() {
+                        Some(value) => *field = value,
+                        None => break
+                    }
+                }
+            }
+        }
+        extra = &extra[4 + size..];
+    }
+
+    Ok((member, header_size))
+}
+
+/// Read the original file name from a gzip header, if it has one.
+fn read_gzip_name<T: ReadSeek>(handle: &mut T) -> Result<Option<String>, UsnError> {
+    let mut header = [0u8; 10];
+    handle.seek(SeekFrom::Start(0))?;
+    handle.read_exact(&mut header)?;
+
+    let flags = header[3];
+    if flags & GZIP_FLAG_NAME == 0 {
+        return Ok(None);
+    }
+
+    if flags & GZIP_FLAG_EXTRA != 0 {
+        let mut extra_length = [0u8; 2];
+        handle.read_exact(&mut extra_length)?;
+        handle.seek(SeekFrom::Current(i64::from(LittleEndian::read_u16(&extra_length))))?;
+    }
+
+    // The name is zero terminated, and read in pieces as its length is unknown
+    let mut name = Vec::new();
+    let mut piece = [0u8; 64];
+    'name: loop {
+        let read = handle.read(&mut piece)?;
+        if read == 0 {
+            break;
+        }
+        for byte in &piece[..read] {
+            if *byte == 0 {
+                break 'name;
+            }
+            name.push(*byte);
+        }
+    }
+
+    // The name is ISO 8859-1
+    Ok(Some(name.into_iter().map(char::from).collect()))
+}
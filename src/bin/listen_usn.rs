@@ -2,16 +2,15 @@
 extern crate log;
 extern crate clap;
 extern crate chrono;
-use std::thread;
-use std::sync::mpsc;
+use std::io;
+use std::io::BufWriter;
 use log::LevelFilter;
 use std::process::exit;
-use serde_json::value::Value;
 use clap::{App, Arg, ArgMatches};
-use std::sync::mpsc::{Sender, Receiver};
 use rusty_usn::liveusn::listener::UsnVolumeListener;
+use rusty_usn::output::Format;
 
-static VERSION: &'static str = "1.1.0";
+static VERSION: &str = "1.1.0";
 
 
 fn make_app<'a, 'b>() -> App<'a, 'b> {
@@ -27,6 +26,16 @@ fn make_app<'a, 'b>() -> App<'a, 'b> {
         .long("historical")
         .help("List historical records along with listening to new changes.");
 
+    let format_arg = Arg::with_name("format")
+        .long("format")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .possible_values(&Format::all().iter()
+            .filter(|format| !format.needs_path())
+            .map(|format| format.name())
+            .collect::<Vec<_>>())
+        .help("The output format. [default: jsonl]");
+
     let verbose = Arg::with_name("debug")
         .short("-d")
         .long("debug")
@@ -38,9 +47,10 @@ fn make_app<'a, 'b>() -> App<'a, 'b> {
     App::new("listen_usn")
         .version(VERSION)
         .author("Matthew Seyer <https://github.com/forensicmatt/RustyUsn>")
-        .about("USN listener written in Rust. Output is JSONL.")
+        .about("USN listener written in Rust. Output is JSONL unless --format says otherwise.")
         .arg(source_arg)
         .arg(historical_arg)
+        .arg(format_arg)
         .arg(verbose)
 }
 
@@ -98,28 +108,27 @@ fn process_volume(volume_str: &str, options: &ArgMatches) {
     info!("listening on {}", volume_str);
     let historical_flag = options.is_present("historical");
 
-    let (tx, rx): (Sender<Value>, Receiver<Value>) = mpsc::channel();
+    let format = options.value_of("format")
+        .and_then(Format::from_name)
+        .unwrap_or(Format::Jsonl);
+
+    let sink = match format.to_writer(BufWriter::new(io::stdout())) {
+        Ok(sink) => sink,
+        Err(error) => {
+            eprintln!("Error opening {} output: {}", format.name(), error.message);
+            exit(-1);
+        }
+    };
 
     let volume_listener = UsnVolumeListener::new(
         volume_str.to_string(),
         historical_flag,
-        tx.clone()
+        sink
     );
 
-    let _thread = thread::spawn(move || {
-        volume_listener.listen_to_volume()
-    });
-
-    loop{
-        match rx.recv() {
-            Ok(entry) => {
-                let json_str = serde_json::to_string(
-                    &entry
-                ).unwrap();
-                println!("{}", json_str);
-            },
-            Err(_) => panic!("Worker threads disconnected before the solution was found!"),
-        }
+    if let Err(error) = volume_listener.listen_to_volume() {
+        eprintln!("Error listening to {}: {:?}", volume_str, error);
+        exit(-1);
     }
 }
 
@@ -3,17 +3,56 @@ extern crate log;
 extern crate clap;
 extern crate chrono;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use log::LevelFilter;
+#[cfg(feature = "multithreading")]
+use rayon::prelude::*;
+use chrono::{DateTime, FixedOffset, Utc};
 use std::process::exit;
-use serde_json::value::Value;
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgGroup, ArgMatches};
 use rusty_usn::mapping::FolderMapping;
-use rusty_usn::usn::{UsnParserSettings, UsnParser};
-use rusty_usn::record::UsnEntry;
+use rusty_usn::usn::{ScanMode, UsnParserSettings, UsnParser};
+use rusty_usn::record::{SnapshotMeta, UsnEntry};
 use rusty_usn::flags;
+use rusty_usn::delta::ReasonDecoder;
+use rusty_usn::session::SessionAggregator;
+use rusty_usn::output::{Format, RecordSink};
+use rusty_usn::output::csv::{Column, CsvWriter};
+use rusty_usn::output::ecs::EcsWriter;
+use rusty_usn::output::jsonl::JsonlWriter;
+use rusty_usn::output::sessions::SessionWriter;
+use rusty_usn::output::options::{FlagFormat, SerializeOptions, TimestampFormat};
+use rusty_usn::error::UsnError;
+use rusty_usn::triage::{discover, pair_files, TriageVolume, VolumeMeta};
+use rusty_usn::archive::{is_zip, open_member, split_member_path, ZipArchive};
+use rusty_usn::ReadSeek;
+use rusty_usn::image::{find_ntfs_volumes, open_image, BoxedImage, SharedHandle};
+use rusty_usn::image::ntfs::NtfsVolume;
+use rusty_usn::image::ewf::{is_ewf, EwfImage};
+use rusty_usn::image::vhd::is_vhd;
+use rusty_usn::image::vhdx::is_vhdx;
+use rusty_usn::image::vss::VssVolume;
 
-static VERSION: &'static str = "1.6.0";
+static VERSION: &str = "1.6.0";
+
+/// Hidden flags that name a format, kept as aliases of --format for scripts
+/// written against them. The sqlite and parquet flags take the path that
+/// --output would.
+const FORMAT_ALIASES: [(&str, Format, Option<&str>); 9] = [
+    ("csv", Format::Csv, None),
+    ("tsv", Format::Tsv, None),
+    ("bodyfile", Format::Bodyfile, None),
+    ("tln", Format::Tln, None),
+    ("sqlite", Format::Sqlite, Some("DATABASE")),
+    ("parquet", Format::Parquet, Some("FILE")),
+    ("ecs", Format::Ecs, None),
+    ("timesketch", Format::Timesketch, None),
+    ("ocsf", Format::Ocsf, None),
+];
 
 
 fn is_a_non_negative_number(value: String) -> Result<(), String> {
@@ -24,16 +63,232 @@ fn is_a_non_negative_number(value: String) -> Result<(), String> {
 }
 
 
+fn is_a_percentage(value: String) -> Result<(), String> {
+    match value.parse::<u8>() {
+        Ok(number) if number <= 100 => Ok(()),
+        _ => Err("Expected value to be a number from 0 to 100.".to_owned()),
+    }
+}
+
+
+fn is_a_number(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err("Expected value to be a number.".to_owned()),
+    }
+}
+
+
+fn is_a_timestamp(value: String) -> Result<(), String> {
+    match DateTime::parse_from_rfc3339(&value) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("Expected value to be an RFC 3339 timestamp (e.g. 2019-01-01T00:00:00Z).".to_owned()),
+    }
+}
+
+
+fn is_an_index_name(value: String) -> Result<(), String> {
+    // Elasticsearch index names are lowercase, without these characters
+    if value.is_empty() || value != value.to_lowercase() ||
+        value.contains(['\\', '/', '*', '?', '"', '<', '>', '|', ' ', ',', '#', ':']) ||
+        value.starts_with(['-', '_', '+']) {
+        return Err("Expected a lowercase Elasticsearch index name.".to_owned());
+    }
+    Ok(())
+}
+
+fn is_an_offset(value: String) -> Result<(), String> {
+    match value.parse::<FixedOffset>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err("Expected an offset from UTC such as +02:00 or -05:00.".to_owned()),
+    }
+}
+
+fn is_a_column_list(value: String) -> Result<(), String> {
+    match Column::parse_list(&value) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(format!("{}. Columns are: {}.", error.message, Column::all()
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+        )),
+    }
+}
+
+
 fn make_app<'a, 'b>() -> App<'a, 'b> {
     let source_arg = Arg::with_name("source")
         .short("s")
         .long("source")
         .value_name("PATH")
-        .help("The source to parse. If the source is a directory, the directoy will \
-        be recursed looking for any files that end with '$J'. (Do not use a directory \
-        if using an MFT file.)")
+        .help("The source to parse. If the source is a directory, the directory is \
+        recursed for journals ($J, $UsnJrnl$J, $UsnJrnl_$J or $UsnJrnl%3A$J) as laid out by \
+        KAPE, Velociraptor and CyLR. Each journal is paired with the $MFT of its volume \
+        folder, and volumes are processed in parallel.")
         .takes_value(true);
 
+    let image_arg = Arg::with_name("image")
+        .short("i")
+        .long("image")
+        .help("The source is a disk image (raw, E01, VHD or VHDX) or block device \
+        containing NTFS. The $UsnJrnl:$J of each NTFS volume is located through its $MFT and parsed with \
+        full paths. (--mft is not needed.)");
+
+    let verify_arg = Arg::with_name("verify_hash")
+        .long("verify-hash")
+        .help("Verify the acquisition hashes of an EWF (E01/Ex01) source before \
+        parsing it. Parsing stops if a stored hash does not match.");
+
+    let format_arg = Arg::with_name("format")
+        .long("format")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .possible_values(&Format::all().iter().map(|format| format.name()).collect::<Vec<_>>())
+        .conflicts_with("format_alias")
+        .help("The output format. [default: jsonl]");
+
+    let output_arg = Arg::with_name("output")
+        .short("o")
+        .long("output")
+        .value_name("PATH")
+        .takes_value(true)
+        .conflicts_with_all(&["sqlite", "parquet"])
+        .help("Write the output to a file instead of stdout. Required by the sqlite and parquet formats.");
+
+    let columns_arg = Arg::with_name("columns")
+        .long("columns")
+        .value_name("COLUMNS")
+        .takes_value(true)
+        .validator(is_a_column_list)
+        .help("The comma separated columns of csv or tsv output, in order. Defaults to every column.");
+
+    let format_alias_names: Vec<&str> = FORMAT_ALIASES.iter().map(|(name, _, _)| *name).collect();
+    let format_alias_args: Vec<Arg> = FORMAT_ALIASES.iter()
+        .map(|(name, format, value_name)| {
+            let arg = Arg::with_name(name)
+                .long(name)
+                .hidden(true)
+                .help(format.name());
+            match value_name {
+                Some(value_name) => arg.value_name(value_name).takes_value(true),
+                None => arg
+            }
+        })
+        .collect();
+
+    let es_index_arg = Arg::with_name("es_index")
+        .long("es-index")
+        .value_name("INDEX")
+        .takes_value(true)
+        .validator(is_an_index_name)
+        .help("The index of the ecs action lines. [default: rusty-usn]");
+
+    let flags_arg = Arg::with_name("flags")
+        .long("flags")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .possible_values(&FlagFormat::all().iter().map(|format| format.name()).collect::<Vec<_>>())
+        .help("How jsonl, csv and tsv output writes the reason, attribute and source info flags: names joined \
+        with ' | ', an array of names, the raw integer, or Windows names (USN_REASON_CLOSE). [default: text]");
+
+    let timestamps_arg = Arg::with_name("timestamps")
+        .long("timestamps")
+        .value_name("FORMAT")
+        .takes_value(true)
+        .possible_values(&TimestampFormat::all().iter().map(|format| format.name()).collect::<Vec<_>>())
+        .help("How jsonl, csv and tsv output writes timestamps: RFC 3339 to the microsecond, RFC 3339 to the \
+        100 nanoseconds, the raw FILETIME, or Unix epoch seconds. [default: rfc3339]");
+
+    let utc_offset_arg = Arg::with_name("utc_offset")
+        .long("utc-offset")
+        .value_name("OFFSET")
+        .takes_value(true)
+        .validator(is_an_offset)
+        .help("The offset from UTC (such as +02:00) of RFC 3339 timestamps in jsonl, csv and tsv output. \
+        The offset is fixed: it does not follow daylight saving time. [default: +00:00]");
+
+    let reason_delta_arg = Arg::with_name("reason_delta")
+        .long("reason-delta")
+        .help("Add the reasons each record introduced (reason_delta), as reasons accumulate on the records \
+        of a file until it is closed. Used with the jsonl, csv, tsv, ecs, timesketch and ocsf formats.");
+
+    let session_limit_arg = Arg::with_name("session_limit")
+        .long("session-limit")
+        .value_name("COUNT")
+        .takes_value(true)
+        .validator(is_a_non_negative_number)
+        .help("The number of files with an open session kept by the sessions format. Past it, the session \
+        written to least recently is ended as if it never closed. [default: 100000]");
+
+    let flush_unclosed_arg = Arg::with_name("flush_unclosed")
+        .long("flush-unclosed")
+        .help("Also write the sessions of the sessions format that never closed, when they are pushed out by \
+        --session-limit or at the end of the journal. They have \"closed\":false.");
+
+    let password_arg = Arg::with_name("password")
+        .long("password")
+        .value_name("PASSWORD")
+        .takes_value(true)
+        .help("The password of an encrypted (ZipCrypto) zip source.");
+
+    let vss_arg = Arg::with_name("vss")
+        .long("vss")
+        .requires("image")
+        .help("Also parse the $UsnJrnl:$J of every volume shadow copy of each NTFS \
+        volume. Entries are tagged with the snapshot id and creation time.");
+
+    let strict_arg = Arg::with_name("strict")
+        .long("strict")
+        .help("Walk the journal record by record using each record's length instead of \
+        carving for records. Use for allocated $J files, not unallocated space. The offsets \
+        where the journal structure breaks are reported.");
+
+    let confidence_arg = Arg::with_name("min_confidence")
+        .long("min-confidence")
+        .value_name("SCORE")
+        .takes_value(true)
+        .validator(is_a_percentage)
+        .help("Drop records with a confidence score (0 to 100) below SCORE. Every record \
+        carries its score in meta__confidence and the checks behind it in meta__confidence_checks.");
+
+    let latest_timestamp_arg = Arg::with_name("latest_timestamp")
+        .long("latest-timestamp")
+        .value_name("TIMESTAMP")
+        .takes_value(true)
+        .validator(is_a_timestamp)
+        .help("Records after TIMESTAMP (RFC 3339), such as the acquisition time, fail the timestamp \
+        check of their confidence score. [default: the time of the run]");
+
+    let usn_start_arg = Arg::with_name("usn_start")
+        .long("usn-start")
+        .value_name("USN")
+        .takes_value(true)
+        .validator(is_a_number)
+        .help("Only output records with a USN of at least USN.");
+
+    let usn_end_arg = Arg::with_name("usn_end")
+        .long("usn-end")
+        .value_name("USN")
+        .takes_value(true)
+        .validator(is_a_number)
+        .help("Only output records with a USN of at most USN.");
+
+    let since_arg = Arg::with_name("since")
+        .long("since")
+        .value_name("TIMESTAMP")
+        .takes_value(true)
+        .validator(is_a_timestamp)
+        .help("Only output records from TIMESTAMP (RFC 3339) on. The start of the range is \
+        found with a binary search, so earlier pages of the journal are not read.");
+
+    let until_arg = Arg::with_name("until")
+        .long("until")
+        .value_name("TIMESTAMP")
+        .takes_value(true)
+        .validator(is_a_timestamp)
+        .help("Only output records up to TIMESTAMP (RFC 3339).");
+
     let usn_arg = Arg::with_name("mft")
         .short("m")
         .long("mft")
@@ -62,6 +317,29 @@ fn make_app<'a, 'b>() -> App<'a, 'b> {
         .author("Matthew Seyer <https://github.com/forensicmatt/RustyUsn>")
         .about("USN Parser written in Rust. Output is JSONL.")
         .arg(source_arg)
+        .arg(image_arg)
+        .arg(verify_arg)
+        .arg(password_arg)
+        .arg(format_arg)
+        .arg(output_arg)
+        .arg(flags_arg)
+        .arg(timestamps_arg)
+        .arg(utc_offset_arg)
+        .arg(reason_delta_arg)
+        .arg(session_limit_arg)
+        .arg(flush_unclosed_arg)
+        .args(&format_alias_args)
+        .group(ArgGroup::with_name("format_alias").args(&format_alias_names))
+        .arg(columns_arg)
+        .arg(es_index_arg)
+        .arg(vss_arg)
+        .arg(strict_arg)
+        .arg(confidence_arg)
+        .arg(latest_timestamp_arg)
+        .arg(usn_start_arg)
+        .arg(usn_end_arg)
+        .arg(since_arg)
+        .arg(until_arg)
         .arg(usn_arg)
         .arg(thread_count)
         .arg(verbose)
@@ -132,95 +410,625 @@ fn is_directory(source: &str)->bool{
 }
 
 
-fn process_directory(directory: &str, options: &ArgMatches) {
-    let dir_entries = match fs::read_dir(directory) {
-        Ok(entries) => entries,
+fn process_directory(directory: &str, options: &ArgMatches, output: &Output) {
+    let volumes = match discover(Path::new(directory)) {
+        Ok(volumes) => volumes,
         Err(e) => {
             eprintln!("Error reading directory {}: {}", directory, e);
             exit(-1);
         }
     };
 
-    for entry in dir_entries {
-        match entry {
-            Ok(dir_entry) => {
-                // Process the directory entry
-                let path = dir_entry.path();
-                if path.to_string_lossy().ends_with("$J") {
-                    process_file(path.to_str().unwrap(), options);
-                }
-            },
-            Err(e) => {
-                eprintln!("Error reading directory entry: {}", e);
-                continue;
-            }
-        }
-    }
+    info!("found {} journals under {}", volumes.len(), directory);
+
+    let process_volume = |volume: &TriageVolume| {
+        // The $MFT collected with the journal takes precedence over --mft
+        let mft_location = volume.mft.as_ref()
+            .map(|mft| mft.to_string_lossy().into_owned())
+            .or_else(|| options.value_of("mft").map(str::to_string));
+
+        process_journal(
+            &volume.journal.to_string_lossy(),
+            mft_location.as_deref(),
+            Some(volume.meta.clone()),
+            options,
+            output
+        );
+    };
+
+    #[cfg(feature = "multithreading")]
+    volumes.par_iter().for_each(process_volume);
+
+    #[cfg(not(feature = "multithreading"))]
+    volumes.iter().for_each(process_volume);
 }
 
 
-fn process_file(file_location: &str, options: &ArgMatches) {
-    info!("processing {}", file_location);
+fn get_parser_settings(options: &ArgMatches, threads: usize) -> UsnParserSettings {
+    let scan_mode = match options.is_present("strict") {
+        true => ScanMode::Strict,
+        false => ScanMode::Carve
+    };
+
+    let min_confidence = options
+        .value_of("min_confidence")
+        .map(|value| value.parse::<u8>().expect("used validator"))
+        .unwrap_or(0);
+
+    let get_usn = |name| options
+        .value_of(name)
+        .map(|value| value.parse::<u64>().expect("used validator"));
+    let get_time = |name| options
+        .value_of(name)
+        .map(|value| DateTime::parse_from_rfc3339(value).expect("used validator").with_timezone(&Utc));
 
+    UsnParserSettings::new()
+        .thread_count(threads)
+        .scan_mode(scan_mode)
+        .min_confidence(min_confidence)
+        .latest_timestamp(Some(get_time("latest_timestamp").unwrap_or_else(Utc::now)))
+        .usn_range(get_usn("usn_start"), get_usn("usn_end"))
+        .time_range(get_time("since"), get_time("until"))
+}
+
+
+fn get_thread_count(options: &ArgMatches) -> usize {
     let thread_option = options
             .value_of("threads")
-            .and_then(|value| Some(value.parse::<usize>().expect("used validator")));
+            .map(|value| value.parse::<usize>().expect("used validator"));
 
-    let mut threads = match (cfg!(feature = "multithreading"), thread_option) {
+    match (cfg!(feature = "multithreading"), thread_option) {
         (true, Some(number)) => number,
         (true, None) => 0,
         (false, _) => {
             eprintln!("turned on threads, but library was compiled without `multithreading` feature!");
             1
         }
+    }
+}
+
+
+/// Open the source as an EWF segment set if it starts with an EWF signature.
+/// The acquisition hashes are verified if requested.
+fn open_ewf(location: &str, options: &ArgMatches) -> Option<EwfImage<File>> {
+    let is_ewf_source = match File::open(location) {
+        Ok(mut handle) => is_ewf(&mut handle).unwrap_or(false),
+        Err(_) => false
+    };
+    if !is_ewf_source {
+        return None;
+    }
+
+    let mut image = match EwfImage::open(location) {
+        Ok(image) => image,
+        Err(error) => {
+            eprintln!("Error opening EWF image {}: {}", location, error);
+            exit(-1);
+        }
+    };
+
+    if options.is_present("verify_hash") {
+        info!("verifying acquisition hashes of {}", location);
+        let verification = match image.verify_hashes() {
+            Ok(verification) => verification,
+            Err(error) => {
+                eprintln!("Error hashing EWF image {}: {}", location, error);
+                exit(-1);
+            }
+        };
+
+        for (name, matches) in &[("MD5", verification.md5_matches()), ("SHA1", verification.sha1_matches())] {
+            match matches {
+                Some(true) => eprintln!("{} {} verified.", location, name),
+                Some(false) => eprintln!("{} {} does not match the acquisition hash!", location, name),
+                None => eprintln!("{} has no stored {}.", location, name)
+            }
+        }
+
+        if !verification.is_verified() {
+            eprintln!("Hash verification of {} failed.", location);
+            exit(-1);
+        }
+    }
+
+    Some(image)
+}
+
+
+/// Open the source through its container format (EWF, VHD or VHDX). `None`
+/// is returned if the source is raw.
+fn open_container(location: &str, options: &ArgMatches) -> Option<BoxedImage> {
+    if let Some(image) = open_ewf(location, options) {
+        return Some(Box::new(image));
+    }
+
+    let is_virtual_disk = match File::open(location) {
+        Ok(mut handle) => is_vhdx(&mut handle).unwrap_or(false) || is_vhd(&mut handle).unwrap_or(false),
+        Err(_) => false
+    };
+    if !is_virtual_disk {
+        return None;
+    }
+
+    match open_image(location) {
+        Ok(image) => Some(image),
+        Err(error) => {
+            eprintln!("Error opening virtual disk {}: {}", location, error);
+            exit(-1);
+        }
+    }
+}
+
+
+fn process_image(image_location: &str, options: &ArgMatches, output: &Output) {
+    info!("processing image {}", image_location);
+
+    match open_container(image_location, options) {
+        Some(image) => process_image_handle(image, image_location, options, output),
+        None => match File::open(image_location) {
+            Ok(handle) => process_image_handle(handle, image_location, options, output),
+            Err(error) => {
+                eprintln!("Error opening image {}: {}", image_location, error);
+                exit(-1);
+            }
+        }
+    }
+}
+
+
+fn process_image_handle<T: ReadSeek>(mut image_handle: T, image_location: &str, options: &ArgMatches, output: &Output) {
+    let volumes = match find_ntfs_volumes(&mut image_handle) {
+        Ok(volumes) => volumes,
+        Err(error) => {
+            eprintln!("Error reading partitions of {}: {}", image_location, error);
+            exit(-1);
+        }
+    };
+    if volumes.is_empty() {
+        eprintln!("No NTFS volumes found in {}", image_location);
+        exit(-1);
+    }
+
+    // The volume's MFT is always used, so the default thread count is quietly 1
+    if get_thread_count(options) > 1 {
+        eprintln!("When using MFT to create folder map, threads can only be 1.");
+    }
+
+    let config = get_parser_settings(options, 1);
+    let shared_handle = Arc::new(Mutex::new(image_handle));
+    for location in volumes {
+        let source = location.label(image_location);
+        info!("processing NTFS volume {} at offset {}", source, location.offset);
+
+        process_ntfs_volume(shared_handle.clone(), location.offset, &source, None, &config, output);
+
+        if options.is_present("vss") {
+            process_shadow_copies(shared_handle.clone(), location.offset, &source, &config, output);
+        }
+    }
+}
+
+
+fn process_shadow_copies<T: ReadSeek>(handle: SharedHandle<T>, volume_offset: u64, source: &str, config: &UsnParserSettings, output: &Output) {
+    let vss_volume = match VssVolume::open(handle, volume_offset) {
+        Ok(Some(vss_volume)) => vss_volume,
+        Ok(None) => {
+            info!("no volume shadow copies in {}", source);
+            return;
+        },
+        Err(error) => {
+            eprintln!("Error reading volume shadow copies of {}: {}", source, error);
+            return;
+        }
+    };
+
+    for index in 0..vss_volume.get_snapshot_count() {
+        let snapshot = match vss_volume.snapshot(index) {
+            Some(snapshot) => snapshot,
+            None => continue
+        };
+
+        let info = snapshot.get_info().clone();
+        let snapshot_source = format!("{}:vss{}", source, index + 1);
+        info!("processing shadow copy {} ({})", snapshot_source, info.shadow_copy_id);
+
+        let snapshot_meta = SnapshotMeta {
+            id: info.shadow_copy_id,
+            created: info.creation_time
+        };
+
+        process_ntfs_volume(
+            Arc::new(Mutex::new(snapshot)), 0, &snapshot_source, Some(snapshot_meta), config, output
+        );
+    }
+}
+
+
+fn process_ntfs_volume<T: ReadSeek>(handle: SharedHandle<T>, volume_offset: u64, source: &str, snapshot: Option<SnapshotMeta>, config: &UsnParserSettings, output: &Output) {
+    let volume = match NtfsVolume::open(handle, volume_offset) {
+        Ok(volume) => volume,
+        Err(error) => {
+            eprintln!("Error opening NTFS volume {}: {}", source, error);
+            return;
+        }
+    };
+
+    let scan = match volume.scan_mft() {
+        Ok(scan) => scan,
+        Err(error) => {
+            eprintln!("Error scanning $MFT of {}: {}", source, error);
+            return;
+        }
+    };
+
+    let usn_journal = match scan.usn_journal {
+        Some(stream) => stream,
+        None => {
+            eprintln!("No $UsnJrnl:$J found in {}", source);
+            return;
+        }
     };
 
+    let data_ranges = usn_journal.data_ranges();
+    let mut parser = match UsnParser::from_read_seek(format!("{}:$Extend/$UsnJrnl:$J", source), usn_journal) {
+        Ok(parser) => parser
+            .with_configuration(config.clone())
+            .with_data_ranges(data_ranges),
+        Err(error) => {
+            eprintln!("Error creating parser for {}: {}", source, error);
+            return;
+        }
+    };
+    if let Some(snapshot) = snapshot {
+        parser = parser.with_snapshot(snapshot);
+    }
+
+    output_records(&mut parser, Some(scan.folder_mapping), output);
+}
+
+
+fn process_file(file_location: &str, options: &ArgMatches, output: &Output) {
+    let is_zip_file = match File::open(file_location) {
+        Ok(mut handle) => is_zip(&mut handle).unwrap_or(false),
+        Err(_) => false
+    };
+
+    if is_zip_file {
+        process_zip(file_location, options, output);
+    } else {
+        process_journal(file_location, options.value_of("mft"), None, options, output);
+    }
+}
+
+
+/// Parse the journals in a zip triage archive, paired with the $MFT members of their volumes.
+fn process_zip(archive_location: &str, options: &ArgMatches, output: &Output) {
+    let archive = match ZipArchive::open(archive_location) {
+        Ok(archive) => archive,
+        Err(error) => {
+            eprintln!("Error opening archive {}: {}", archive_location, error);
+            exit(-1);
+        }
+    };
+
+    let members: Vec<PathBuf> = archive.get_members()
+        .iter()
+        .filter(|member| !member.is_directory())
+        .map(|member| PathBuf::from(&member.name))
+        .collect();
+    let volumes = pair_files(Path::new(""), &members);
+
+    info!("found {} journals in {}", volumes.len(), archive_location);
+
+    // CyLR names its archives after the host
+    let archive_host = Path::new(archive_location)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned());
+
+    let process_volume = |volume: &TriageVolume| {
+        let member_location = |member: &Path| format!("{}!{}", archive_location, member.to_string_lossy());
+        let mft_location = volume.mft.as_deref()
+            .map(member_location)
+            .or_else(|| options.value_of("mft").map(str::to_string));
+
+        let mut meta = volume.meta.clone();
+        if meta.host.is_none() {
+            meta.host = archive_host.clone();
+        }
+
+        process_journal(&member_location(&volume.journal), mft_location.as_deref(), Some(meta), options, output);
+    };
+
+    #[cfg(feature = "multithreading")]
+    volumes.par_iter().for_each(process_volume);
+
+    #[cfg(not(feature = "multithreading"))]
+    volumes.iter().for_each(process_volume);
+}
+
+
+fn process_journal(file_location: &str, mft_location: Option<&str>, volume: Option<VolumeMeta>, options: &ArgMatches, output: &Output) {
+    info!("processing {}", file_location);
+
+    let mut threads = get_thread_count(options);
+
     let mut folder_mapping: Option<FolderMapping> = None;
 
-    if options.is_present("mft") {
+    if let Some(mft_path) = mft_location {
         if threads != 1 {
             threads = 1;
             eprintln!("When using MFT to create folder map, threads can only be 1.");
         }
 
-        let mft_path = options.value_of("mft").unwrap();
-        folder_mapping = match FolderMapping::from_mft_path(mft_path){
+        let mapping_result = match open_member(mft_path, options.value_of("password")) {
+            Ok(Some(member)) => FolderMapping::from_mft_read_seek(member),
+            Ok(None) => FolderMapping::from_mft_path(mft_path),
+            Err(error) => Err(std::io::Error::other(error.message))
+        };
+
+        folder_mapping = match mapping_result {
             Ok(mapping) => Some(mapping),
             Err(err) => {
-                eprintln!("Error creating folder mapping. {}", err);
+                eprintln!("Error creating folder mapping from {}. {}", mft_path, err);
+                // One bad $MFT in a collection should not stop the other volumes
+                if volume.is_some() {
+                    return;
+                }
                 exit(-1);
             }
         };
     }
 
-    let config = UsnParserSettings::new().thread_count(threads);
+    let config = get_parser_settings(options, threads);
+
+    match open_member(file_location, options.value_of("password")) {
+        Ok(Some(member)) => {
+            let source = member.get_source().to_string();
+            let mut parser = match UsnParser::from_read_seek(source, member) {
+                Ok(parser) => with_volume(parser.with_configuration(config), volume),
+                Err(error) => {
+                    eprintln!("Error creating parser for {}: {}", file_location, error);
+                    return;
+                }
+            };
+
+            output_records(&mut parser, folder_mapping, output);
+            return;
+        },
+        Ok(None) => {},
+        Err(error) => {
+            eprintln!("Error opening {}: {}", file_location, error.message);
+            return;
+        }
+    }
+
+    if let Some(image) = open_container(file_location, options) {
+        let mut parser = match UsnParser::from_read_seek(file_location.to_string(), image) {
+            Ok(parser) => with_volume(parser.with_configuration(config), volume),
+            Err(error) => {
+                eprintln!("Error creating parser for {}: {}", file_location, error);
+                return;
+            }
+        };
+
+        output_records(&mut parser, folder_mapping, output);
+        return;
+    }
 
     let mut parser = match UsnParser::from_path(file_location) {
-        Ok(parser) => parser.with_configuration(config),
+        Ok(parser) => with_volume(parser.with_configuration(config), volume),
         Err(error) => {
             eprintln!("Error creating parser for {}: {}", file_location, error);
             return;
         }
     };
 
-    if folder_mapping.is_some(){
+    output_records(&mut parser, folder_mapping, output);
+}
+
+
+fn with_volume<T: ReadSeek>(parser: UsnParser<T>, volume: Option<VolumeMeta>) -> UsnParser<T> {
+    match volume {
+        Some(volume) => parser.with_volume(volume),
+        None => parser
+    }
+}
+
+
+/// Where parsed entries are written. Volumes may be parsed in parallel, so
+/// writes are serialized.
+struct Output {
+    sink: Mutex<Box<dyn RecordSink + Send>>,
+    reason_delta: bool,
+    // Entries are written oldest to newest even with a folder mapping
+    usn_order: bool,
+}
+
+impl Output {
+    fn from_options(options: &ArgMatches) -> Self {
+        let format = Self::format_from_options(options);
+        let location = options.value_of("sqlite")
+            .or_else(|| options.value_of("parquet"))
+            .or_else(|| options.value_of("output"));
+
+        if options.is_present("es_index") && format != Format::Ecs {
+            eprintln!("--es-index is only used with the ecs format.");
+            exit(-1);
+        }
+        let serialize_flags = ["flags", "timestamps", "utc_offset"];
+        if serialize_flags.iter().any(|flag| options.is_present(flag)) &&
+            !matches!(format, Format::Jsonl | Format::Csv | Format::Tsv) {
+            eprintln!("--flags, --timestamps and --utc-offset are only used with the jsonl, csv and tsv formats.");
+            exit(-1);
+        }
+        if (options.is_present("session_limit") || options.is_present("flush_unclosed")) && format != Format::Sessions {
+            eprintln!("--session-limit and --flush-unclosed are only used with the sessions format.");
+            exit(-1);
+        }
+        let reason_delta = options.is_present("reason_delta");
+        if reason_delta && matches!(format, Format::Bodyfile | Format::Tln | Format::Sessions | Format::Sqlite | Format::Parquet) {
+            eprintln!("--reason-delta is only used with the jsonl, csv, tsv, ecs, timesketch and ocsf formats.");
+            exit(-1);
+        }
+
+        let sink = match location {
+            Some(location) if format.needs_path() => format.create(location),
+            Some(location) => File::create(location)
+                .map_err(UsnError::from)
+                .and_then(|file| Self::to_sink(format, BufWriter::new(file), options)),
+            None if format.needs_path() => {
+                eprintln!("The {} format needs a file to write to (--output).", format.name());
+                exit(-1);
+            },
+            None => Self::to_sink(format, BufWriter::new(io::stdout()), options)
+        };
+
+        match sink {
+            Ok(sink) => Output {
+                sink: Mutex::new(sink),
+                reason_delta,
+                usn_order: format.needs_usn_order()
+            },
+            Err(error) => {
+                eprintln!("Error opening {} output {}: {}", format.name(), location.unwrap_or("on stdout"), error.message);
+                exit(-1);
+            }
+        }
+    }
+
+    /// The format of `--format`, or of its alias.
+    fn format_from_options(options: &ArgMatches) -> Format {
+        if let Some(name) = options.value_of("format") {
+            return Format::from_name(name).expect("used possible_values");
+        }
+
+        FORMAT_ALIASES.iter()
+            .find(|(name, _, _)| options.is_present(name))
+            .map(|(_, format, _)| *format)
+            .unwrap_or(Format::Jsonl)
+    }
+
+    fn serialize_options(options: &ArgMatches) -> SerializeOptions {
+        let mut serialize_options = SerializeOptions::new();
+        if let Some(name) = options.value_of("flags") {
+            serialize_options = serialize_options.with_flag_format(
+                FlagFormat::from_name(name).expect("used possible_values")
+            );
+        }
+        if let Some(name) = options.value_of("timestamps") {
+            serialize_options = serialize_options.with_timestamp_format(
+                TimestampFormat::from_name(name).expect("used possible_values")
+            );
+        }
+        if let Some(offset) = options.value_of("utc_offset") {
+            serialize_options = serialize_options.with_offset(offset.parse().expect("used validator"));
+        }
+        serialize_options
+    }
+
+    /// A sink of a format that can be streamed, with the options of the format applied.
+    fn to_sink<W: Write + Send + 'static>(format: Format, writer: W, options: &ArgMatches) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        match format {
+            Format::Jsonl => Ok(Box::new(
+                JsonlWriter::new(writer).with_options(Self::serialize_options(options))
+            )),
+            Format::Csv | Format::Tsv => {
+                let mut writer = match format {
+                    Format::Tsv => CsvWriter::new_tsv(writer),
+                    _ => CsvWriter::new(writer)
+                }.with_options(Self::serialize_options(options));
+                if let Some(columns) = options.value_of("columns") {
+                    writer = writer.with_columns(Column::parse_list(columns).expect("used validator"));
+                }
+
+                // The header is written even if no records are found
+                writer.write_header()?;
+                Ok(Box::new(writer))
+            },
+            Format::Ecs => {
+                let mut writer = EcsWriter::new(writer);
+                if let Some(index) = options.value_of("es_index") {
+                    writer = writer.with_index(index);
+                }
+                Ok(Box::new(writer))
+            },
+            Format::Sessions => {
+                let mut aggregator = SessionAggregator::new()
+                    .with_flush_unclosed(options.is_present("flush_unclosed"));
+                if let Some(limit) = options.value_of("session_limit") {
+                    aggregator = aggregator.with_limit(limit.parse().expect("used validator"));
+                }
+                Ok(Box::new(SessionWriter::new(writer).with_aggregator(aggregator)))
+            },
+            _ => format.to_writer(writer)
+        }
+    }
+
+    fn exit_on_error(result: Result<(), UsnError>) {
+        if let Err(error) = result {
+            eprintln!("Error writing output: {}", error.message);
+            exit(-1);
+        }
+    }
+
+    /// Write an entry, with the full name enumerated from a folder mapping if there is one.
+    fn write_entry(&self, entry: &UsnEntry, full_name: Option<&str>) {
+        Self::exit_on_error(self.sink.lock()
+            .expect("output lock poisoned")
+            .write_entry(entry, full_name));
+    }
+
+    /// Write a batch of entries without full names, as parsed from a set of chunks.
+    fn write_entries(&self, entries: &[UsnEntry]) {
+        Self::exit_on_error(self.sink.lock()
+            .expect("output lock poisoned")
+            .write_entries(entries));
+    }
+
+    fn flush(&self) {
+        Self::exit_on_error(self.sink.lock()
+            .expect("output lock poisoned")
+            .flush());
+    }
+
+    /// Finish the output once every source is written, such as the footer of a Parquet file.
+    fn finish(self) {
+        Self::exit_on_error(self.sink.into_inner()
+            .expect("output lock poisoned")
+            .finish());
+    }
+}
+
+
+fn output_records<T: ReadSeek>(parser: &mut UsnParser<T>, folder_mapping: Option<FolderMapping>, output: &Output) {
+    let mut record_count = 0;
+    // Reasons accumulate in USN order, so deltas are decoded before the
+    // records are reversed for the folder mapping.
+    let mut decoder = match output.reason_delta {
+        true => Some(ReasonDecoder::new()),
+        false => None
+    };
+
+    if let Some(mut mapping) = folder_mapping {
         // Because we are going to enumerate folder names, we must
         // iterate records from the newest to oldest inorder to correctly
         // enumerate the paths. This means we must store all the records 
         // because they are parsed from oldest to newest. Unfortunately,
         // this does take up more memory.
-        let mut mapping = folder_mapping.unwrap();
         let mut entry_list: Vec::<UsnEntry> = Vec::new();
-        for record in parser.records(){
+        for mut record in parser.records(){
+            if let Some(decoder) = decoder.as_mut() {
+                decoder.decode(&mut record);
+            }
             entry_list.push(record);
         }
         entry_list.reverse();
+        record_count = entry_list.len();
 
+        // Formats that group records get them back in USN order, with their paths
+        let mut named_entries: Vec<(UsnEntry, String)> = Vec::new();
         for entry in entry_list {
-            let mut entry_json_value = entry.to_json_value().unwrap();
-            let json_map = entry_json_value.as_object_mut().unwrap();
-
-            let record = entry.record;
+            let record = &entry.record;
 
             let reason = record.get_reason_code();
             let file_attributes = record.get_file_attributes();
@@ -252,23 +1060,48 @@ fn process_file(file_location: &str, options: &ArgMatches) {
             // Create teh fullname string
             let full_name = format!("{}/{}", full_path, file_name);
 
-            // Add the fullname string to the json record
-            let fn_value = Value::String(full_name);
-            json_map.insert("full_name".to_string(), fn_value);
-
-            // Create a json string to print
-            let json_str = serde_json::to_string(&json_map).unwrap();
-            println!("{}", json_str);
+            match output.usn_order {
+                true => named_entries.push((entry, full_name)),
+                false => output.write_entry(&entry, Some(&full_name))
+            }
+        }
+        for (entry, full_name) in named_entries.iter().rev() {
+            output.write_entry(entry, Some(full_name));
         }
     } else{
-        for record in parser.records(){
-            let json_str = serde_json::to_string(
-                &record.to_json_value().unwrap()
-            ).unwrap();
-
-            println!("{}", json_str);
+        for mut entries in parser.record_batches(){
+            if let Some(decoder) = decoder.as_mut() {
+                entries.iter_mut().for_each(|entry| { decoder.decode(entry); });
+            }
+            output.write_entries(&entries);
+            record_count += entries.len();
         }
     }
+
+    output.flush();
+    print_summary(parser, record_count);
+
+    let break_offsets = parser.get_break_offsets();
+    if !break_offsets.is_empty() {
+        eprintln!("The journal structure of {} breaks at offsets: {:?}", parser.get_source(), break_offsets);
+    }
+}
+
+
+/// Print the record count, skipped bytes and rejected hits by kind of a run to stderr.
+fn print_summary<T: ReadSeek>(parser: &UsnParser<T>, record_count: usize) {
+    let rejections: Vec<String> = parser.get_rejection_counts()
+        .iter()
+        .map(|(kind, count)| format!("{:?}: {}", kind, count))
+        .collect();
+
+    eprintln!(
+        "{}: {} records, {} sparse or zeroed bytes skipped, rejected: {}",
+        parser.get_source(),
+        record_count,
+        parser.get_skipped_bytes(),
+        if rejections.is_empty() { "none".to_string() } else { rejections.join(", ") }
+    );
 }
 
 
@@ -282,8 +1115,8 @@ fn main() {
         true => {
             match options.value_of("source") {
                 Some(path_location) => {
-                    // Verify that the supplied path exists
-                    if !Path::new(path_location).exists() {
+                    // Verify that the supplied path (or archive of an archive!member path) exists
+                    if !Path::new(path_location).exists() && split_member_path(path_location).is_none() {
                         eprintln!("{} does not exist.", path_location);
                         exit(-1);
                     }
@@ -302,9 +1135,17 @@ fn main() {
         }
     };
 
-    if is_directory(source_location) {
-        process_directory(source_location, &options);
+    let output = Output::from_options(&options);
+
+    if options.is_present("image") {
+        process_image(source_location, &options, &output);
+    } else if split_member_path(source_location).is_some() {
+        process_file(source_location, &options, &output);
+    } else if is_directory(source_location) {
+        process_directory(source_location, &options, &output);
     } else {
-        process_file(source_location, &options);
+        process_file(source_location, &options, &output);
     }
+
+    output.finish();
 }
@@ -0,0 +1,179 @@
+//! Confidence scoring for carved records. A carved hit that passes the header
+//! checks can still be noise, so each record is scored on how plausible its
+//! fields are.
+//!
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use byteorder::{ByteOrder, LittleEndian};
+use crate::flags;
+use crate::record::{UsnRecord, USN_RECORD_V4_HEADER_SIZE};
+
+
+// Change journals were introduced with NTFS 3.0 (Windows 2000)
+const EARLIEST_TIMESTAMP_YEAR: i32 = 1999;
+// Entry numbers this large would need an $MFT of several terabytes
+const MAX_ENTRY_NUMBER: u64 = 1 << 32;
+// The root folder is entry 5, so no parent can have a lower number
+const ROOT_ENTRY_NUMBER: u64 = 5;
+
+
+/// The result of each check behind a confidence score. Checks that do not apply
+/// to a record version, such as the file name of a V4 record, pass.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceChecks {
+    /// The timestamp is from 1999 on, and not after the latest timestamp if one is given.
+    pub timestamp: bool,
+    /// The reason is not empty and has only known bits set.
+    pub reason: bool,
+    /// The file attributes have only known bits set.
+    pub file_attributes: bool,
+    /// The file name is valid UTF-16 without control characters or separators.
+    pub file_name: bool,
+    /// The file and parent references have plausible entry and sequence numbers.
+    pub references: bool,
+    /// The record length is exactly what the name (or extents) needs.
+    pub record_length: bool,
+}
+
+impl ConfidenceChecks {
+    fn results(&self) -> [bool; 6] {
+        [
+            self.timestamp,
+            self.reason,
+            self.file_attributes,
+            self.file_name,
+            self.references,
+            self.record_length
+        ]
+    }
+}
+
+
+/// A 0 to 100 score of how likely a record is genuine, with the checks behind it.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Confidence {
+    #[serde(rename(serialize = "meta__confidence"))]
+    pub score: u8,
+    #[serde(rename(serialize = "meta__confidence_checks"))]
+    pub checks: ConfidenceChecks,
+}
+
+impl Confidence {
+    /// Score a parsed record. `raw` starts at the record, and is used for the
+    /// fields the parsed record does not keep exactly (flag bits and the name).
+    /// `latest` is the latest plausible timestamp, such as the acquisition time
+    /// of the source.
+    pub fn assess(record: &UsnRecord, raw: &[u8], latest: Option<DateTime<Utc>>) -> Confidence {
+        let checks = match record {
+            UsnRecord::V2(record) => {
+                let raw_reason = read_u32_at(raw, 40);
+                let raw_attributes = read_u32_at(raw, 52);
+
+                ConfidenceChecks {
+                    timestamp: check_timestamp(read_u64_at(raw, 32), latest),
+                    reason: check_reason(raw_reason),
+                    file_attributes: check_file_attributes(raw_attributes),
+                    file_name: check_file_name(raw, record.file_name_offset, record.file_name_length),
+                    references: check_references(
+                        record.file_reference.entry, record.file_reference.sequence,
+                        record.parent_reference.entry, record.parent_reference.sequence
+                    ),
+                    record_length: record.record_length ==
+                        align8(u32::from(record.file_name_offset) + u32::from(record.file_name_length))
+                }
+            },
+            UsnRecord::V3(record) => {
+                let file_reference = record.file_reference.as_mft_reference();
+                let parent_reference = record.parent_reference.as_mft_reference();
+
+                ConfidenceChecks {
+                    timestamp: check_timestamp(read_u64_at(raw, 48), latest),
+                    reason: check_reason(read_u32_at(raw, 56)),
+                    file_attributes: check_file_attributes(read_u32_at(raw, 68)),
+                    file_name: check_file_name(raw, record.file_name_offset, record.file_name_length),
+                    references: check_references(
+                        file_reference.entry, file_reference.sequence,
+                        parent_reference.entry, parent_reference.sequence
+                    ) && record.file_reference.0 >> 64 == 0 && record.parent_reference.0 >> 64 == 0,
+                    record_length: record.record_length ==
+                        align8(u32::from(record.file_name_offset) + u32::from(record.file_name_length))
+                }
+            },
+            UsnRecord::V4(record) => {
+                let file_reference = record.file_reference.as_mft_reference();
+                let parent_reference = record.parent_reference.as_mft_reference();
+
+                ConfidenceChecks {
+                    timestamp: true,
+                    reason: check_reason(read_u32_at(raw, 48)),
+                    file_attributes: true,
+                    file_name: true,
+                    references: check_references(
+                        file_reference.entry, file_reference.sequence,
+                        parent_reference.entry, parent_reference.sequence
+                    ) && record.file_reference.0 >> 64 == 0 && record.parent_reference.0 >> 64 == 0,
+                    record_length: record.record_length ==
+                        USN_RECORD_V4_HEADER_SIZE + u32::from(record.number_of_extents) * u32::from(record.extent_size)
+                }
+            }
+        };
+
+        let results = checks.results();
+        let passed = results.iter().filter(|result| **result).count();
+
+        Confidence {
+            score: (passed * 100 / results.len()) as u8,
+            checks
+        }
+    }
+}
+
+
+fn read_u32_at(raw: &[u8], offset: usize) -> u32 {
+    raw.get(offset..offset + 4).map(LittleEndian::read_u32).unwrap_or(0)
+}
+
+fn read_u64_at(raw: &[u8], offset: usize) -> u64 {
+    raw.get(offset..offset + 8).map(LittleEndian::read_u64).unwrap_or(0)
+}
+
+fn align8(length: u32) -> u32 {
+    (length + 7) & !7
+}
+
+fn check_timestamp(filetime: u64, latest: Option<DateTime<Utc>>) -> bool {
+    let earliest = Utc.with_ymd_and_hms(EARLIEST_TIMESTAMP_YEAR, 1, 1, 0, 0, 0).unwrap();
+
+    // FILETIME counts 100 ns intervals since 1601, and is compared in seconds
+    // to stay clear of chrono's range limits for garbage values.
+    let seconds = (filetime / 10_000_000) as i64 - 11_644_473_600;
+    seconds >= earliest.timestamp() && latest.is_none_or(|latest| seconds <= latest.timestamp())
+}
+
+fn check_reason(raw_reason: u32) -> bool {
+    raw_reason != 0 && raw_reason & !flags::Reason::all().bits() == 0
+}
+
+fn check_file_attributes(raw_attributes: u32) -> bool {
+    raw_attributes & !flags::FileAttributes::all().bits() == 0
+}
+
+fn check_file_name(raw: &[u8], offset: u16, length: u16) -> bool {
+    let start = usize::from(offset);
+    let end = start + usize::from(length);
+    let name = match raw.get(start..end) {
+        Some(name) if !name.is_empty() && name.len() % 2 == 0 => name,
+        _ => return false
+    };
+
+    let units = name.chunks_exact(2).map(LittleEndian::read_u16);
+    std::char::decode_utf16(units).all(|character| match character {
+        Ok(character) => !character.is_control() && character != '/' && character != '\\',
+        Err(_) => false
+    })
+}
+
+fn check_references(entry: u64, sequence: u16, parent_entry: u64, parent_sequence: u16) -> bool {
+    entry < MAX_ENTRY_NUMBER && sequence != 0 &&
+        (ROOT_ENTRY_NUMBER..MAX_ENTRY_NUMBER).contains(&parent_entry) && parent_sequence != 0
+}
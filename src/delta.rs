@@ -0,0 +1,50 @@
+//! Reason-delta decoding. The reasons of a record accumulate until the file is
+//! closed, so a file that was created and then extended shows
+//! `FILE_CREATE | DATA_EXTEND` on every record up to its `CLOSE`. The delta of
+//! a record is the reasons it added to the previous record of the same file.
+//!
+use std::collections::HashMap;
+use winstructs::ntfs::mft_reference::MftReference;
+use crate::flags;
+use crate::record::{UsnEntry, UsnRecord};
+
+
+/// Tracks the accumulated reasons of each open file. Records must be decoded
+/// in USN order. The first record seen of a file, including one whose earlier
+/// records were not in the journal, has all of its reasons as the delta.
+#[derive(Debug, Default)]
+pub struct ReasonDecoder {
+    open: HashMap<MftReference, flags::Reason>,
+}
+
+impl ReasonDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The reasons a record introduced. A `CLOSE` record ends the
+    /// accumulation, so the next record of the file starts over.
+    pub fn delta(&mut self, record: &UsnRecord) -> flags::Reason {
+        let file_reference = record.get_file_reference();
+        let reason = record.get_reason_code();
+
+        let previous = match reason.contains(flags::Reason::CLOSE) {
+            true => self.open.remove(&file_reference),
+            false => self.open.insert(file_reference, reason)
+        }.unwrap_or_else(flags::Reason::empty);
+
+        flags::Reason::from_bits_retain(reason.bits() & !previous.bits())
+    }
+
+    /// Set the reason delta of an entry, and return it.
+    pub fn decode(&mut self, entry: &mut UsnEntry) -> flags::Reason {
+        let delta = self.delta(&entry.record);
+        entry.reason_delta = Some(delta);
+        delta
+    }
+
+    /// The number of files whose reasons are accumulating.
+    pub fn open_count(&self) -> usize {
+        self.open.len()
+    }
+}
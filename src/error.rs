@@ -4,17 +4,24 @@ use std::io;
 use serde_json::error::Error as SjError;
 use winstructs::err::Error as WinstructError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ErrorKind {
     InvalidUsnRecord,
     InvalidUsnV2Record,
     InvalidUsnV3Record,
+    InvalidUsnV4Record,
     UnsupportedVersion,
     WinstructError,
     Utf16DecodeError,
     IoError,
     SerdeJsonError,
     ValueError,
+    InvalidImage,
+    LowConfidence,
+    InvalidArchive,
+    SqliteError,
+    ParquetError,
+    UnsupportedFormat,
 }
 
 /// USN Record Parsing Error
@@ -65,6 +72,46 @@ impl UsnError{
         }
     }
 
+    #[allow(dead_code)]
+    pub fn invalid_v4_record(msg: String) -> Self {
+        UsnError {
+            message: msg,
+            kind: ErrorKind::InvalidUsnV4Record,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn invalid_image(msg: String) -> Self {
+        UsnError {
+            message: msg,
+            kind: ErrorKind::InvalidImage,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn low_confidence(msg: String) -> Self {
+        UsnError {
+            message: msg,
+            kind: ErrorKind::LowConfidence,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn invalid_archive(msg: String) -> Self {
+        UsnError {
+            message: msg,
+            kind: ErrorKind::InvalidArchive,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn unsupported_format(msg: String) -> Self {
+        UsnError {
+            message: msg,
+            kind: ErrorKind::UnsupportedFormat,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn invalid_usn_record_length(msg: String) -> Self {
         UsnError {
@@ -101,6 +148,36 @@ impl From<WinstructError> for UsnError {
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for UsnError {
+    fn from(err: rusqlite::Error) -> Self {
+        UsnError {
+            message: format!("{}", err),
+            kind: ErrorKind::SqliteError,
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for UsnError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        UsnError {
+            message: format!("{}", err),
+            kind: ErrorKind::ParquetError,
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<arrow::error::ArrowError> for UsnError {
+    fn from(err: arrow::error::ArrowError) -> Self {
+        UsnError {
+            message: format!("{}", err),
+            kind: ErrorKind::ParquetError,
+        }
+    }
+}
+
 impl Display for UsnError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { 
         writeln!(f, "{}", self.message)
@@ -1,8 +1,10 @@
 use std::fmt;
+use bitflags::Flags;
 use serde::ser;
 
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct FileAttributes: u32 {
         const ARCHIVE                = 0x0000_0020;
         const COMPRESSED             = 0x0000_0800;
@@ -15,17 +17,21 @@ bitflags! {
         const NOT_CONTENT_INDEXED    = 0x0000_2000;
         const NO_SCRUB_DATA          = 0x0002_0000;
         const OFFLINE                = 0x0000_1000;
+        const PINNED                 = 0x0008_0000;
         const READONLY               = 0x0000_0001;
         const RECALL_ON_DATA_ACCESS  = 0x0040_0000;
         const RECALL_ON_OPEN         = 0x0004_0000;
         const REPARSE_POINT          = 0x0000_0400;
         const SPARSE_FILE            = 0x0000_0200;
+        const STRICTLY_SEQUENTIAL    = 0x2000_0000;
         const SYSTEM                 = 0x0000_0004;
         const TEMPORARY              = 0x0000_0100;
-        const VIRTUAL                = 0x0000_1000;
+        const UNPINNED               = 0x0010_0000;
+        const VIRTUAL                = 0x0001_0000;
     }
 }
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct Reason: u32 {
         const BASIC_INFO_CHANGE      = 0x0000_8000;
         const CLOSE                  = 0x8000_0000;
@@ -33,6 +39,7 @@ bitflags! {
         const DATA_EXTEND            = 0x0000_0002;
         const DATA_OVERWRITE         = 0x0000_0001;
         const DATA_TRUNCATION        = 0x0000_0004;
+        const DESIRED_STORAGE_CLASS_CHANGE = 0x0100_0000;
         const EA_CHANGE              = 0x0000_0400;
         const ENCRYPTION_CHANGE      = 0x0004_0000;
         const FILE_CREATE            = 0x0000_0100;
@@ -52,7 +59,26 @@ bitflags! {
         const TRANSACTED_CHANGE      = 0x0040_0000;
     }
 }
+
+/// The reasons for which the data of the file changed.
+pub const DATA_REASONS: Reason = Reason::from_bits_truncate(
+    Reason::DATA_OVERWRITE.bits() | Reason::DATA_EXTEND.bits() |
+    Reason::DATA_TRUNCATION.bits() | Reason::NAMED_DATA_OVERWRITE.bits() |
+    Reason::NAMED_DATA_EXTEND.bits() | Reason::NAMED_DATA_TRUNCATION.bits()
+);
+
+/// The reasons for which metadata other than the name or security of the file changed.
+pub const METADATA_REASONS: Reason = Reason::from_bits_truncate(
+    Reason::BASIC_INFO_CHANGE.bits() | Reason::COMPRESSION_CHANGE.bits() |
+    Reason::DESIRED_STORAGE_CLASS_CHANGE.bits() | Reason::EA_CHANGE.bits() | Reason::ENCRYPTION_CHANGE.bits() |
+    Reason::HARD_LINK_CHANGE.bits() | Reason::INDEXABLE_CHANGE.bits() |
+    Reason::INTEGRITY_CHANGE.bits() | Reason::OBJECT_ID_CHANGE.bits() |
+    Reason::REPARSE_POINT_CHANGE.bits() | Reason::STREAM_CHANGE.bits() |
+    Reason::TRANSACTED_CHANGE.bits()
+);
+
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct SourceInfo: u32 {
         const AUXILIARY_DATA                 = 0x0000_0002;
         const DATA_MANAGEMENT                = 0x0000_0001;
@@ -71,7 +97,7 @@ impl ser::Serialize for FileAttributes {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: ser::Serializer
     {
-        serializer.serialize_str(&format!("{:?}", self))
+        serializer.serialize_str(&flag_text(self))
     }
 }
 
@@ -85,7 +111,7 @@ impl ser::Serialize for Reason {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: ser::Serializer
     {
-        serializer.serialize_str(&format!("{:?}", self))
+        serializer.serialize_str(&flag_text(self))
     }
 }
 
@@ -99,6 +125,61 @@ impl ser::Serialize for SourceInfo {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: ser::Serializer
     {
-        serializer.serialize_str(&format!("{:?}", self))
+        serializer.serialize_str(&flag_text(self))
+    }
+}
+
+/// The flag types, as written by `output::options::SerializeOptions`.
+///
+/// Bits the tables do not name are kept by `from_bits_retain`, so that flags
+/// added by newer versions of Windows still show up (as `UNKNOWN(0x...)`)
+/// and in `bits()`.
+pub trait FlagSet: Flags<Bits = u32> {
+    /// The prefix of the flag names in the Windows headers, such as `USN_REASON_`.
+    const WINDOWS_PREFIX: &'static str;
+}
+
+impl FlagSet for FileAttributes {
+    const WINDOWS_PREFIX: &'static str = "FILE_ATTRIBUTE_";
+}
+
+impl FlagSet for Reason {
+    const WINDOWS_PREFIX: &'static str = "USN_REASON_";
+}
+
+impl FlagSet for SourceInfo {
+    const WINDOWS_PREFIX: &'static str = "USN_SOURCE_";
+}
+
+/// The names of the flags that are set, in the order they are declared,
+/// followed by `UNKNOWN(0x...)` for any bits without a name.
+pub fn flag_names<T: Flags<Bits = u32>>(flags: &T) -> Vec<String> {
+    let mut names: Vec<String> = flags.iter_names()
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let unknown = flags.bits() & !T::all().bits();
+    if unknown != 0 {
+        names.push(format!("UNKNOWN(0x{:08X})", unknown));
+    }
+    names
+}
+
+/// The flag names joined with ` | `, or `(empty)`.
+pub fn flag_text<T: Flags<Bits = u32>>(flags: &T) -> String {
+    let names = flag_names(flags);
+    match names.is_empty() {
+        true => "(empty)".to_string(),
+        false => names.join(" | ")
     }
-}
\ No newline at end of file
+}
+
+/// The flag names as the Windows headers spell them, such as `USN_REASON_CLOSE`.
+/// Bits without a name stay `UNKNOWN(0x...)`.
+pub fn windows_flag_names<T: FlagSet>(flags: &T) -> Vec<String> {
+    flag_names(flags).into_iter()
+        .map(|name| match name.starts_with("UNKNOWN(") {
+            true => name,
+            false => format!("{}{}", T::WINDOWS_PREFIX, name)
+        })
+        .collect()
+}
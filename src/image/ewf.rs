@@ -0,0 +1,861 @@
+//! Expert Witness Format (EWF) evidence containers. Version 1 (E01) and
+//! version 2 (Ex01) segment sets are read as a single Read + Seek stream of
+//! the acquired media.
+//!
+use std::io;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::all::UTF_16LE;
+use encoding::{DecoderTrap, Encoding};
+use flate2::read::ZlibDecoder;
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::seek_position;
+
+
+const EWF1_SIGNATURE: &[u8; 8] = b"EVF\x09\x0d\x0a\xff\x00";
+const EWF2_SIGNATURE: &[u8; 8] = b"EVF2\x0d\x0a\x81\x00";
+const SIZE_EWF1_FILE_HEADER: u64 = 13;
+const SIZE_EWF2_FILE_HEADER: u64 = 32;
+const SIZE_EWF1_SECTION_DESCRIPTOR: u64 = 76;
+const SIZE_EWF2_SECTION_DESCRIPTOR: u64 = 64;
+const SIZE_EWF1_TABLE_HEADER: u64 = 24;
+const SIZE_EWF2_TABLE_HEADER: u64 = 32;
+const SIZE_EWF2_TABLE_ENTRY: u64 = 16;
+const EWF1_CHUNK_COMPRESSED: u32 = 0x8000_0000;
+const EWF2_CHUNK_COMPRESSED: u32 = 0x01;
+const EWF2_CHUNK_CHECKSUM: u32 = 0x02;
+const EWF2_CHUNK_PATTERN_FILL: u32 = 0x04;
+const EWF2_SECTION_DEVICE_INFORMATION: u32 = 0x01;
+const EWF2_SECTION_CASE_DATA: u32 = 0x02;
+const EWF2_SECTION_SECTOR_TABLE: u32 = 0x04;
+const EWF2_SECTION_MD5_HASH: u32 = 0x08;
+const EWF2_SECTION_SHA1_HASH: u32 = 0x09;
+const EWF2_SECTION_DONE: u32 = 0x0F;
+const EWF2_COMPRESSION_BZIP2: u16 = 2;
+// Uncompressed chunks are followed by an Adler-32 checksum
+const SIZE_CHUNK_CHECKSUM: u64 = 4;
+// EnCase writes 64 sectors of 512 bytes per chunk by default
+const DEFAULT_CHUNK_SIZE: u64 = 32768;
+const DEFAULT_BYTES_PER_SECTOR: u64 = 512;
+// Chunks are decoded in memory, so refuse sizes no acquisition tool writes
+const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+
+/// Check if the handle starts with an EWF segment file signature.
+pub fn is_ewf<T: ReadSeek>(handle: &mut T) -> Result<bool, UsnError> {
+    let mut signature = [0u8; 8];
+    handle.seek(SeekFrom::Start(0))?;
+
+    let is_ewf = match handle.read_exact(&mut signature) {
+        Ok(_) => &signature == EWF1_SIGNATURE || &signature == EWF2_SIGNATURE,
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(error) => return Err(UsnError::from(error))
+    };
+
+    handle.seek(SeekFrom::Start(0))?;
+    Ok(is_ewf)
+}
+
+
+/// The extension of segment `number` (starting at 1) of a set whose first
+/// segment has the extension `first_extension` (E01, e01, Ex01, ...). After
+/// 99 the last two characters run from AA to ZZ and carry into the character
+/// before them, so E99 is followed by EAA and EZZ by FAA.
+///
+pub fn segment_extension(first_extension: &str, number: usize) -> Option<String> {
+    if number == 0 || !first_extension.ends_with("01") || !first_extension.is_ascii() {
+        return None;
+    }
+    let prefix = &first_extension[..first_extension.len() - 2];
+
+    if number <= 99 {
+        return Some(format!("{}{:02}", prefix, number));
+    }
+
+    let index = number - 100;
+    let carry = index / (26 * 26);
+    let last = prefix.chars().last()?;
+    let (base, end) = match last.is_ascii_lowercase() {
+        true => (b'a', b'z'),
+        false => (b'A', b'Z')
+    };
+
+    let carried = last as usize + carry;
+    if carried > end as usize {
+        return None;
+    }
+
+    Some(format!(
+        "{}{}{}{}",
+        &prefix[..prefix.len() - 1],
+        carried as u8 as char,
+        (base + ((index / 26) % 26) as u8) as char,
+        (base + (index % 26) as u8) as char
+    ))
+}
+
+
+/// The segment files of the set that `first_segment` starts. Files are
+/// added in order until the next segment does not exist.
+pub fn segment_paths(first_segment: &Path) -> Result<Vec<PathBuf>, UsnError> {
+    let first_extension = first_segment.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+
+    let mut paths = vec![first_segment.to_path_buf()];
+    for number in 2.. {
+        let extension = match segment_extension(first_extension, number) {
+            Some(extension) => extension,
+            None => break
+        };
+
+        let path = first_segment.with_extension(extension);
+        if !path.exists() {
+            break;
+        }
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+
+/// The EWF version of a segment set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EwfFormat {
+    /// EnCase 1-6 (.E01)
+    Ewf1,
+    /// EnCase 7+ (.Ex01)
+    Ewf2,
+}
+
+
+/// The acquisition hashes stored in a segment set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EwfHashes {
+    pub md5: Option<[u8; 16]>,
+    pub sha1: Option<[u8; 20]>,
+}
+
+
+/// The hashes computed over the media compared to the stored acquisition hashes.
+#[derive(Debug, Clone)]
+pub struct HashVerification {
+    pub stored: EwfHashes,
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+}
+
+impl HashVerification {
+    /// `None` if the segment set has no stored MD5.
+    pub fn md5_matches(&self) -> Option<bool> {
+        self.stored.md5.map(|md5| md5 == self.md5)
+    }
+
+    /// `None` if the segment set has no stored SHA1.
+    pub fn sha1_matches(&self) -> Option<bool> {
+        self.stored.sha1.map(|sha1| sha1 == self.sha1)
+    }
+
+    /// True if at least one hash is stored and every stored hash matches.
+    pub fn is_verified(&self) -> bool {
+        let results = [self.md5_matches(), self.sha1_matches()];
+        results.iter().any(|result| result.is_some()) &&
+            results.iter().all(|result| *result != Some(false))
+    }
+}
+
+
+#[derive(Debug, Clone)]
+enum ChunkStorage {
+    Compressed,
+    /// Uncompressed, and followed by an Adler-32 checksum if `checksum` is set.
+    Stored { checksum: bool },
+    Pattern([u8; 8]),
+}
+
+#[derive(Debug, Clone)]
+struct ChunkLocation {
+    segment: usize,
+    offset: u64,
+    size: u64,
+    storage: ChunkStorage,
+}
+
+
+/// Values gathered while walking the sections of the segment files.
+#[derive(Default)]
+struct SectionValues {
+    chunk_size: Option<u64>,
+    bytes_per_sector: Option<u64>,
+    sector_count: Option<u64>,
+    chunks: Vec<Option<ChunkLocation>>,
+    hashes: EwfHashes,
+    done: bool,
+}
+
+impl SectionValues {
+    /// The media size, once the sections describing it have been read.
+    fn media_size(&self) -> Result<Option<u64>, UsnError> {
+        match (self.sector_count, self.bytes_per_sector) {
+            (Some(sector_count), Some(bytes_per_sector)) => sector_count.checked_mul(bytes_per_sector)
+                .map(Some)
+                .ok_or_else(|| UsnError::invalid_image(format!(
+                    "EWF media of {} sectors of {} bytes is too large.", sector_count, bytes_per_sector
+                ))),
+            _ => Ok(None)
+        }
+    }
+
+    /// Add a chunk from a table. The index must be within the media, or
+    /// follow the chunks seen so far if the media size is not known yet.
+    fn set_chunk(&mut self, index: u64, location: ChunkLocation) -> Result<(), UsnError> {
+        let chunk_count = match (self.media_size()?, self.chunk_size) {
+            (Some(media_size), Some(chunk_size)) => media_size.div_ceil(chunk_size),
+            _ => self.chunks.len() as u64 + 1
+        };
+        if index >= chunk_count {
+            return Err(UsnError::invalid_image(
+                format!("EWF chunk {} is past the end of the media.", index)
+            ));
+        }
+
+        let index = index as usize;
+        if self.chunks.len() <= index {
+            self.chunks.resize(index + 1, None);
+        }
+        self.chunks[index] = Some(location);
+        Ok(())
+    }
+}
+
+
+/// A Read + Seek view of the media stored in an EWF segment set. Decoded
+/// chunks are cached one at a time, so sequential reads decompress each
+/// chunk once.
+///
+pub struct EwfImage<T: ReadSeek> {
+    segments: Vec<T>,
+    format: EwfFormat,
+    chunks: Vec<ChunkLocation>,
+    chunk_size: u64,
+    media_size: u64,
+    hashes: EwfHashes,
+    position: u64,
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+impl EwfImage<File> {
+    /// Open the segment set that starts with the segment file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, UsnError> {
+        let mut segments = Vec::new();
+        for segment_path in segment_paths(path.as_ref())? {
+            debug!("opening EWF segment {}", segment_path.display());
+            segments.push(File::open(&segment_path)?);
+        }
+
+        Self::from_segments(segments)
+    }
+}
+
+impl<T: ReadSeek> EwfImage<T> {
+    /// Create an image from the handles of every segment file in the set.
+    /// The handles can be in any order, they are sorted by segment number.
+    pub fn from_segments(segments: Vec<T>) -> Result<Self, UsnError> {
+        let mut numbered = Vec::new();
+        let mut format = None;
+
+        for mut segment in segments {
+            let (segment_format, number) = read_file_header(&mut segment)?;
+            if format.is_some() && format != Some(segment_format) {
+                return Err(UsnError::invalid_image(
+                    "EWF segment set mixes format versions.".to_string()
+                ));
+            }
+            format = Some(segment_format);
+            numbered.push((number, segment));
+        }
+
+        numbered.sort_by_key(|(number, _)| *number);
+        for (index, (number, _)) in numbered.iter().enumerate() {
+            if *number != index as u32 + 1 {
+                return Err(UsnError::invalid_image(
+                    format!("EWF segment {} is missing.", index + 1)
+                ));
+            }
+        }
+
+        let mut segments: Vec<T> = numbered.into_iter().map(|(_, segment)| segment).collect();
+        let format = format.ok_or_else(|| UsnError::invalid_image(
+            "No EWF segment files given.".to_string()
+        ))?;
+
+        let mut values = SectionValues::default();
+        for (index, segment) in segments.iter_mut().enumerate() {
+            match format {
+                EwfFormat::Ewf1 => read_ewf1_sections(segment, index, &mut values)?,
+                EwfFormat::Ewf2 => read_ewf2_sections(segment, index, &mut values)?,
+            }
+        }
+
+        if !values.done {
+            return Err(UsnError::invalid_image(format!(
+                "EWF segment set is incomplete, segment {} is missing.", segments.len() + 1
+            )));
+        }
+
+        let stored_media_size = values.media_size()?;
+        let mut chunks = Vec::with_capacity(values.chunks.len());
+        for (index, chunk) in values.chunks.into_iter().enumerate() {
+            match chunk {
+                Some(chunk) => chunks.push(chunk),
+                None => return Err(UsnError::invalid_image(
+                    format!("EWF chunk {} is not in any table.", index)
+                ))
+            }
+        }
+
+        let chunk_size = match values.chunk_size {
+            Some(chunk_size) => chunk_size,
+            None => infer_chunk_size(&mut segments, &chunks)?
+        };
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(UsnError::invalid_image(format!("EWF chunk size {} is invalid.", chunk_size)));
+        }
+
+        let media_size = match stored_media_size {
+            Some(media_size) => media_size,
+            None => chunks.len() as u64 * chunk_size
+        };
+
+        Ok(EwfImage {
+            segments,
+            format,
+            chunks,
+            chunk_size,
+            media_size,
+            hashes: values.hashes,
+            position: 0,
+            cache: None
+        })
+    }
+
+    pub fn get_format(&self) -> EwfFormat {
+        self.format
+    }
+
+    pub fn get_media_size(&self) -> u64 {
+        self.media_size
+    }
+
+    pub fn get_chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// The acquisition hashes stored in the segment set.
+    pub fn get_stored_hashes(&self) -> &EwfHashes {
+        &self.hashes
+    }
+
+    /// Hash the whole media and compare it to the stored acquisition hashes.
+    /// The read position is left unchanged.
+    pub fn verify_hashes(&mut self) -> Result<HashVerification, UsnError> {
+        let position = self.position;
+        self.seek(SeekFrom::Start(0))?;
+
+        let mut md5_context = md5::Context::new();
+        let mut sha1_context = sha1::Sha1::new();
+        let mut buffer = vec![0u8; self.chunk_size as usize];
+        loop {
+            let bytes_read = self.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            md5_context.consume(&buffer[..bytes_read]);
+            sha1_context.update(&buffer[..bytes_read]);
+        }
+
+        self.position = position;
+        Ok(HashVerification {
+            stored: self.hashes.clone(),
+            md5: md5_context.compute().0,
+            sha1: sha1_context.digest().bytes()
+        })
+    }
+
+    fn read_chunk(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let location = match self.chunks.get(index) {
+            Some(location) => location.clone(),
+            None => return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("EWF chunk {} is past the last chunk", index)
+            ))
+        };
+
+        let chunk_size = self.chunk_size as usize;
+        let mut chunk = match location.storage {
+            ChunkStorage::Pattern(pattern) => {
+                pattern.iter().cycle().take(chunk_size).cloned().collect()
+            },
+            ChunkStorage::Stored { .. } | ChunkStorage::Compressed => {
+                let segment = &mut self.segments[location.segment];
+                let mut raw = vec![0u8; location.size as usize];
+                segment.seek(SeekFrom::Start(location.offset))?;
+                segment.read_exact(&mut raw)?;
+
+                if let ChunkStorage::Stored { checksum: true } = location.storage {
+                    let mut checksum = [0u8; 4];
+                    segment.read_exact(&mut checksum)?;
+                    if adler32(&raw) != u32::from_le_bytes(checksum) {
+                        warn!("EWF chunk {} does not match its checksum", index);
+                    }
+                }
+
+                match location.storage {
+                    ChunkStorage::Compressed => {
+                        let mut decoded = Vec::with_capacity(chunk_size);
+                        ZlibDecoder::new(&raw[..])
+                            .take(chunk_size as u64)
+                            .read_to_end(&mut decoded)
+                            .map_err(|error| io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("EWF chunk {} does not decompress: {}", index, error)
+                            ))?;
+                        decoded
+                    },
+                    _ => raw
+                }
+            }
+        };
+
+        // The last chunk of the media can be short
+        if chunk.len() != chunk_size {
+            if index + 1 != self.chunks.len() {
+                warn!("EWF chunk {} is {} bytes, expected {}", index, chunk.len(), chunk_size);
+            }
+            chunk.resize(chunk_size, 0);
+        }
+
+        Ok(chunk)
+    }
+}
+
+impl<T: ReadSeek> Read for EwfImage<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.media_size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let index = (self.position / self.chunk_size) as usize;
+        let chunk_offset = (self.position % self.chunk_size) as usize;
+
+        let cached = matches!(self.cache, Some((cached_index, _)) if cached_index == index);
+        if !cached {
+            let chunk = self.read_chunk(index)?;
+            self.cache = Some((index, chunk));
+        }
+        let chunk = match self.cache {
+            Some((_, ref chunk)) => chunk,
+            None => unreachable!("chunk was just cached")
+        };
+
+        let to_read = (chunk.len() - chunk_offset)
+            .min((self.media_size - self.position) as usize)
+            .min(buf.len());
+        buf[..to_read].copy_from_slice(&chunk[chunk_offset..chunk_offset + to_read]);
+
+        self.position += to_read as u64;
+        Ok(to_read)
+    }
+}
+
+impl<T: ReadSeek> Seek for EwfImage<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(pos, self.position, || Ok(self.media_size))?;
+        Ok(self.position)
+    }
+}
+
+
+/// The chunk size of a volume or case data section.
+fn chunk_size(sectors_per_chunk: u64, bytes_per_sector: u64) -> Result<u64, UsnError> {
+    match sectors_per_chunk.checked_mul(bytes_per_sector) {
+        Some(chunk_size) if chunk_size > 0 && chunk_size <= MAX_CHUNK_SIZE => Ok(chunk_size),
+        _ => Err(UsnError::invalid_image(format!(
+            "EWF chunks of {} sectors of {} bytes are invalid.", sectors_per_chunk, bytes_per_sector
+        )))
+    }
+}
+
+fn read_at<T: ReadSeek>(handle: &mut T, offset: u64, size: u64) -> Result<Vec<u8>, UsnError> {
+    let mut buffer = vec![0u8; size as usize];
+    handle.seek(SeekFrom::Start(offset))?;
+    handle.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Read the format version and segment number from a segment file header.
+fn read_file_header<T: ReadSeek>(segment: &mut T) -> Result<(EwfFormat, u32), UsnError> {
+    let header = read_at(segment, 0, SIZE_EWF1_FILE_HEADER)?;
+
+    if &header[0..8] == EWF1_SIGNATURE {
+        Ok((EwfFormat::Ewf1, u32::from(LittleEndian::read_u16(&header[9..11]))))
+    } else if &header[0..8] == EWF2_SIGNATURE {
+        let header = read_at(segment, 0, SIZE_EWF2_FILE_HEADER)?;
+        if LittleEndian::read_u16(&header[10..12]) == EWF2_COMPRESSION_BZIP2 {
+            return Err(UsnError::invalid_image(
+                "bzip2 compressed Ex01 images are not supported.".to_string()
+            ));
+        }
+        Ok((EwfFormat::Ewf2, LittleEndian::read_u32(&header[12..16])))
+    } else {
+        Err(UsnError::invalid_image("Not an EWF segment file.".to_string()))
+    }
+}
+
+/// Walk the section descriptors of a version 1 segment file. Each descriptor
+/// holds the absolute offset of the next one.
+fn read_ewf1_sections<T: ReadSeek>(segment: &mut T, segment_index: usize, values: &mut SectionValues) -> Result<(), UsnError> {
+    let file_size = segment.seek(SeekFrom::End(0))?;
+    let mut offset = SIZE_EWF1_FILE_HEADER;
+    // Chunks are stored in a sectors section in front of their table
+    let mut sectors_end: Option<u64> = None;
+
+    loop {
+        let descriptor = read_at(segment, offset, SIZE_EWF1_SECTION_DESCRIPTOR)?;
+        let section_type = String::from_utf8_lossy(&descriptor[0..16])
+            .trim_end_matches('\u{0}')
+            .to_string();
+        let next_offset = LittleEndian::read_u64(&descriptor[16..24]);
+        let section_size = LittleEndian::read_u64(&descriptor[24..32]);
+        if adler32(&descriptor[0..72]) != LittleEndian::read_u32(&descriptor[72..76]) {
+            warn!("EWF section '{}' at {} of segment {} has a bad checksum", section_type, offset, segment_index + 1);
+        }
+
+        let data_offset = offset + SIZE_EWF1_SECTION_DESCRIPTOR;
+        let data_size = section_size.saturating_sub(SIZE_EWF1_SECTION_DESCRIPTOR);
+        let section_end = offset.saturating_add(section_size).min(file_size);
+        trace!("EWF section '{}' at {} ({} bytes)", section_type, offset, section_size);
+
+        match section_type.as_str() {
+            "volume" | "disk" => {
+                let data = read_at(segment, data_offset, data_size.min(1052))?;
+                if data.len() < 20 {
+                    return Err(UsnError::invalid_image(
+                        "EWF volume section is too small.".to_string()
+                    ));
+                }
+                let sectors_per_chunk = u64::from(LittleEndian::read_u32(&data[8..12]));
+                let bytes_per_sector = u64::from(LittleEndian::read_u32(&data[12..16]));
+                // SMART volume sections only have a 32-bit sector count
+                let sector_count = match data.len() >= 24 && data_size >= 1052 {
+                    true => LittleEndian::read_u64(&data[16..24]),
+                    false => u64::from(LittleEndian::read_u32(&data[16..20]))
+                };
+
+                values.chunk_size = Some(chunk_size(sectors_per_chunk, bytes_per_sector)?);
+                values.bytes_per_sector = Some(bytes_per_sector);
+                values.sector_count = Some(sector_count);
+            },
+            "sectors" => {
+                sectors_end = Some(section_end);
+            },
+            "table" => {
+                read_ewf1_table(segment, segment_index, data_offset, section_end, sectors_end, values)?;
+            },
+            "hash" => {
+                let data = read_at(segment, data_offset, 16)?;
+                let mut md5 = [0u8; 16];
+                md5.copy_from_slice(&data);
+                values.hashes.md5 = Some(md5);
+            },
+            "digest" => {
+                let data = read_at(segment, data_offset, 36)?;
+                let mut md5 = [0u8; 16];
+                let mut sha1 = [0u8; 20];
+                md5.copy_from_slice(&data[0..16]);
+                sha1.copy_from_slice(&data[16..36]);
+                values.hashes.md5 = Some(md5);
+                values.hashes.sha1 = Some(sha1);
+            },
+            "done" => {
+                values.done = true;
+                break;
+            },
+            "next" => break,
+            _ => {}
+        }
+
+        // The next offset must move forward to guard against loops
+        if next_offset <= offset {
+            break;
+        }
+        offset = next_offset;
+    }
+
+    Ok(())
+}
+
+fn read_ewf1_table<T: ReadSeek>(segment: &mut T, segment_index: usize, data_offset: u64, section_end: u64,
+                                sectors_end: Option<u64>, values: &mut SectionValues) -> Result<(), UsnError> {
+    let header = read_at(segment, data_offset, SIZE_EWF1_TABLE_HEADER)?;
+    let entry_count = u64::from(LittleEndian::read_u32(&header[0..4]));
+    let base_offset = LittleEndian::read_u64(&header[8..16]);
+    let entries_offset = data_offset + SIZE_EWF1_TABLE_HEADER;
+    if entry_count * 4 > section_end.saturating_sub(entries_offset) {
+        return Err(UsnError::invalid_image(
+            format!("EWF table at {} has more entries than fit in its section.", data_offset)
+        ));
+    }
+    let entries = read_at(segment, entries_offset, entry_count * 4)?;
+
+    let mut offsets: Vec<(u64, bool)> = Vec::with_capacity(entries.len() / 4);
+    for entry in entries.chunks(4) {
+        let value = LittleEndian::read_u32(entry);
+        let offset = base_offset.checked_add(u64::from(value & !EWF1_CHUNK_COMPRESSED)).ok_or_else(||
+            UsnError::invalid_image(format!("EWF table at {} has an offset past the end of its segment.", data_offset))
+        )?;
+        offsets.push((offset, value & EWF1_CHUNK_COMPRESSED != 0));
+    }
+
+    for (i, (offset, compressed)) in offsets.iter().enumerate() {
+        // The last chunk ends with the sectors section, or with the table
+        // section itself in files that store chunks after the table
+        let end = match offsets.get(i + 1) {
+            Some((next_offset, _)) => *next_offset,
+            None => match sectors_end {
+                Some(end) if end > *offset => end,
+                _ => section_end
+            }
+        };
+        if end <= *offset {
+            return Err(UsnError::invalid_image(
+                format!("EWF table entry at {} has no data.", offset)
+            ));
+        }
+
+        let (size, storage) = match compressed {
+            true if end - offset > MAX_CHUNK_SIZE + SIZE_CHUNK_CHECKSUM => {
+                return Err(UsnError::invalid_image(
+                    format!("EWF table at {} has a chunk of {} bytes.", data_offset, end - offset)
+                ));
+            },
+            true => (end - offset, ChunkStorage::Compressed),
+            false => {
+                let size = (end - offset).saturating_sub(SIZE_CHUNK_CHECKSUM);
+                let size = values.chunk_size.map_or(size, |chunk_size| size.min(chunk_size));
+                (size, ChunkStorage::Stored { checksum: end - offset >= size + SIZE_CHUNK_CHECKSUM })
+            }
+        };
+
+        let index = values.chunks.len() as u64;
+        values.set_chunk(index, ChunkLocation {
+            segment: segment_index,
+            offset: *offset,
+            size,
+            storage
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Walk the section descriptors of a version 2 segment file. Descriptors
+/// follow their section data and hold the offset of the previous descriptor,
+/// so the walk starts at the end of the file.
+fn read_ewf2_sections<T: ReadSeek>(segment: &mut T, segment_index: usize, values: &mut SectionValues) -> Result<(), UsnError> {
+    let file_size = segment.seek(SeekFrom::End(0))?;
+    if file_size < SIZE_EWF2_FILE_HEADER + SIZE_EWF2_SECTION_DESCRIPTOR {
+        return Err(UsnError::invalid_image(
+            format!("EWF segment {} is too small.", segment_index + 1)
+        ));
+    }
+
+    let mut offset = file_size - SIZE_EWF2_SECTION_DESCRIPTOR;
+    let mut sections = Vec::new();
+    loop {
+        let descriptor = read_at(segment, offset, SIZE_EWF2_SECTION_DESCRIPTOR)?;
+        if adler32(&descriptor[0..60]) != LittleEndian::read_u32(&descriptor[60..64]) {
+            return Err(UsnError::invalid_image(format!(
+                "EWF section descriptor at {} of segment {} has a bad checksum.", offset, segment_index + 1
+            )));
+        }
+
+        let section_type = LittleEndian::read_u32(&descriptor[0..4]);
+        let previous_offset = LittleEndian::read_u64(&descriptor[8..16]);
+        let data_size = LittleEndian::read_u64(&descriptor[16..24]);
+        let data_offset = offset.checked_sub(data_size).ok_or_else(|| UsnError::invalid_image(
+            format!("EWF section at {} is larger than its segment.", offset)
+        ))?;
+        sections.push((section_type, data_offset, data_size));
+
+        if previous_offset == 0 || previous_offset >= offset {
+            break;
+        }
+        offset = previous_offset;
+    }
+
+    // Handle the sections in file order so metadata is read before tables
+    for (section_type, data_offset, data_size) in sections.into_iter().rev() {
+        trace!("EWF section 0x{:02X} at {} ({} bytes)", section_type, data_offset, data_size);
+
+        match section_type {
+            EWF2_SECTION_DEVICE_INFORMATION => {
+                let values_map = read_ewf2_text_values(segment, data_offset, data_size)?;
+                if let Some(bytes_per_sector) = parse_value(&values_map, "bp") {
+                    values.bytes_per_sector = Some(bytes_per_sector);
+                }
+                if let Some(sector_count) = parse_value(&values_map, "ts") {
+                    values.sector_count = Some(sector_count);
+                }
+            },
+            EWF2_SECTION_CASE_DATA => {
+                let values_map = read_ewf2_text_values(segment, data_offset, data_size)?;
+                if let Some(sectors_per_chunk) = parse_value(&values_map, "sb") {
+                    let bytes_per_sector = values.bytes_per_sector.unwrap_or(DEFAULT_BYTES_PER_SECTOR);
+                    values.chunk_size = Some(chunk_size(sectors_per_chunk, bytes_per_sector)?);
+                }
+            },
+            EWF2_SECTION_SECTOR_TABLE => {
+                read_ewf2_table(segment, segment_index, data_offset, data_size, values)?;
+            },
+            EWF2_SECTION_MD5_HASH => {
+                let data = read_at(segment, data_offset, 16)?;
+                let mut md5 = [0u8; 16];
+                md5.copy_from_slice(&data);
+                values.hashes.md5 = Some(md5);
+            },
+            EWF2_SECTION_SHA1_HASH => {
+                let data = read_at(segment, data_offset, 20)?;
+                let mut sha1 = [0u8; 20];
+                sha1.copy_from_slice(&data);
+                values.hashes.sha1 = Some(sha1);
+            },
+            EWF2_SECTION_DONE => values.done = true,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn read_ewf2_table<T: ReadSeek>(segment: &mut T, segment_index: usize, data_offset: u64, data_size: u64,
+                                values: &mut SectionValues) -> Result<(), UsnError> {
+    let header = read_at(segment, data_offset, SIZE_EWF2_TABLE_HEADER)?;
+    let first_chunk = LittleEndian::read_u64(&header[0..8]);
+    let entry_count = u64::from(LittleEndian::read_u32(&header[8..12]));
+    if entry_count * SIZE_EWF2_TABLE_ENTRY > data_size.saturating_sub(SIZE_EWF2_TABLE_HEADER) {
+        return Err(UsnError::invalid_image(
+            format!("EWF table at {} has more entries than fit in its section.", data_offset)
+        ));
+    }
+    let entries = read_at(segment, data_offset + SIZE_EWF2_TABLE_HEADER, entry_count * SIZE_EWF2_TABLE_ENTRY)?;
+
+    for (i, entry) in entries.chunks(SIZE_EWF2_TABLE_ENTRY as usize).enumerate() {
+        let offset = LittleEndian::read_u64(&entry[0..8]);
+        let size = u64::from(LittleEndian::read_u32(&entry[8..12]));
+        let flags = LittleEndian::read_u32(&entry[12..16]);
+
+        let (size, storage) = if flags & EWF2_CHUNK_PATTERN_FILL != 0 {
+            // The offset field holds the 8 byte fill pattern
+            let mut pattern = [0u8; 8];
+            pattern.copy_from_slice(&entry[0..8]);
+            (0, ChunkStorage::Pattern(pattern))
+        } else if size > MAX_CHUNK_SIZE + SIZE_CHUNK_CHECKSUM {
+            return Err(UsnError::invalid_image(
+                format!("EWF table at {} has a chunk of {} bytes.", data_offset, size)
+            ));
+        } else if flags & EWF2_CHUNK_COMPRESSED != 0 {
+            (size, ChunkStorage::Compressed)
+        } else if flags & EWF2_CHUNK_CHECKSUM != 0 && size >= SIZE_CHUNK_CHECKSUM {
+            (size - SIZE_CHUNK_CHECKSUM, ChunkStorage::Stored { checksum: true })
+        } else {
+            (size, ChunkStorage::Stored { checksum: false })
+        };
+
+        let index = first_chunk.checked_add(i as u64).ok_or_else(|| UsnError::invalid_image(
+            format!("EWF table at {} starts at chunk {}.", data_offset, first_chunk)
+        ))?;
+        values.set_chunk(index, ChunkLocation {
+            segment: segment_index,
+            offset,
+            size,
+            storage
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Version 2 metadata sections are zlib compressed UTF-16 text. A line of
+/// tab separated keys is followed by a line of tab separated values.
+fn read_ewf2_text_values<T: ReadSeek>(segment: &mut T, data_offset: u64, data_size: u64) -> Result<Vec<(String, String)>, UsnError> {
+    let data = read_at(segment, data_offset, data_size)?;
+
+    let mut decoded = Vec::new();
+    if ZlibDecoder::new(&data[..]).read_to_end(&mut decoded).is_err() {
+        decoded = data;
+    }
+    if decoded.starts_with(&[0xFF, 0xFE]) {
+        decoded.drain(0..2);
+    }
+
+    let text = UTF_16LE.decode(&decoded, DecoderTrap::Replace)
+        .map_err(|error| UsnError::utf16_decode_error(format!("{}", error)))?;
+    let lines: Vec<&str> = text.lines().map(|line| line.trim_end_matches('\r')).collect();
+
+    let mut values = Vec::new();
+    for pair in lines.windows(2) {
+        if !pair[0].contains('\t') {
+            continue;
+        }
+        let keys: Vec<&str> = pair[0].split('\t').collect();
+        let row: Vec<&str> = pair[1].split('\t').collect();
+        if keys.len() == row.len() {
+            for (key, value) in keys.iter().zip(row.iter()) {
+                values.push((key.to_string(), value.to_string()));
+            }
+            break;
+        }
+    }
+
+    Ok(values)
+}
+
+fn parse_value(values: &[(String, String)], key: &str) -> Option<u64> {
+    values.iter()
+        .find(|(name, _)| name == key)
+        .and_then(|(_, value)| value.trim().parse::<u64>().ok())
+        .filter(|value| *value != 0)
+}
+
+/// The chunk size of a set that does not record it, taken from the first
+/// chunk that is not a pattern fill.
+fn infer_chunk_size<T: ReadSeek>(segments: &mut [T], chunks: &[ChunkLocation]) -> Result<u64, UsnError> {
+    let chunk = match chunks.iter().find(|chunk| !matches!(chunk.storage, ChunkStorage::Pattern(_))) {
+        Some(chunk) => chunk,
+        None => return Ok(DEFAULT_CHUNK_SIZE)
+    };
+
+    match chunk.storage {
+        ChunkStorage::Compressed => {
+            let raw = read_at(&mut segments[chunk.segment], chunk.offset, chunk.size)?;
+            let mut decoded = Vec::new();
+            ZlibDecoder::new(&raw[..]).take(MAX_CHUNK_SIZE + 1).read_to_end(&mut decoded)?;
+            Ok(decoded.len() as u64)
+        },
+        _ => Ok(chunk.size)
+    }
+}
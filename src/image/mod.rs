@@ -0,0 +1,175 @@
+//! Readers for disk images and the file systems inside them.
+//!
+pub mod ewf;
+pub mod vhd;
+pub mod vhdx;
+pub mod ntfs;
+pub mod partition;
+pub mod vss;
+
+use std::io;
+use std::fs::File;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::ewf::{is_ewf, EwfImage};
+use crate::image::vhd::{is_vhd, VhdImage};
+use crate::image::vhdx::{is_vhdx, VhdxImage};
+use crate::image::ntfs::is_ntfs_volume;
+use crate::image::partition::read_partitions;
+
+// Guard against differencing disks that are their own ancestors
+const MAX_PARENT_DEPTH: usize = 32;
+
+
+/// A handle to an image that can be shared by the streams opened on it.
+pub type SharedHandle<T> = Arc<Mutex<T>>;
+
+/// An image of any supported container format.
+pub type BoxedImage = Box<dyn ReadSeek + Send>;
+
+
+/// Open an image file, detecting EWF, VHDX and VHD containers by their
+/// signatures. Anything else is read as a raw image. The parents of
+/// differencing disks are opened from their parent locators.
+///
+pub fn open_image(path: impl AsRef<Path>) -> Result<BoxedImage, UsnError> {
+    open_image_at_depth(path.as_ref(), 0)
+}
+
+pub(crate) fn open_image_at_depth(path: &Path, depth: usize) -> Result<BoxedImage, UsnError> {
+    if depth > MAX_PARENT_DEPTH {
+        return Err(UsnError::invalid_image(
+            format!("{} exceeds {} levels of parent disks.", path.display(), MAX_PARENT_DEPTH)
+        ));
+    }
+
+    let mut handle = File::open(path)?;
+    if is_ewf(&mut handle)? {
+        Ok(Box::new(EwfImage::open(path)?))
+    } else if is_vhdx(&mut handle)? {
+        Ok(Box::new(VhdxImage::open_at_depth(path, depth)?))
+    } else if is_vhd(&mut handle)? {
+        Ok(Box::new(VhdImage::open_at_depth(path, depth)?))
+    } else {
+        Ok(Box::new(handle))
+    }
+}
+
+/// Find the parent of a differencing disk. `locations` are the paths stored
+/// in the child, tried in order. Relative paths are resolved against the
+/// child's folder, and absolute paths from another system fall back to their
+/// file name in the child's folder.
+pub(crate) fn find_parent_path(child_path: &Path, locations: &[String]) -> Option<PathBuf> {
+    let child_folder = child_path.parent().unwrap_or_else(|| Path::new(""));
+
+    for location in locations {
+        let normalized = location.trim_end_matches('\u{0}').replace('\\', "/");
+        let candidate = Path::new(&normalized);
+
+        let mut paths = vec![child_folder.join(candidate)];
+        if let Some(file_name) = candidate.file_name() {
+            paths.push(child_folder.join(file_name));
+        }
+
+        if let Some(path) = paths.into_iter().find(|path| path.is_file()) {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+/// Read from the parent of a differencing disk.
+pub(crate) fn read_parent(parent: &mut Option<BoxedImage>, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    match parent {
+        Some(parent) => {
+            parent.seek(SeekFrom::Start(offset))?;
+            parent.read_exact(buf)
+        },
+        None => Err(io::Error::other("the parent of the differencing disk is not attached"))
+    }
+}
+
+
+/// The position a seek moves a stream to. `size` is only called for seeks
+/// from the end.
+pub(crate) fn seek_position<F>(pos: SeekFrom, position: u64, size: F) -> io::Result<u64>
+    where F: FnOnce() -> io::Result<u64> {
+    let new_position = match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(offset) => size()?.checked_add_signed(offset),
+        SeekFrom::Current(offset) => position.checked_add_signed(offset),
+    };
+
+    new_position.ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "invalid seek to a negative or overflowing position"
+    ))
+}
+
+/// Read a structure whose offset and length come from the file itself. The
+/// range is checked against the file size before anything is allocated.
+pub(crate) fn read_within_file<T: ReadSeek>(handle: &mut T, offset: u64, length: u64, name: &str) -> Result<Vec<u8>, UsnError> {
+    let file_size = handle.seek(SeekFrom::End(0))?;
+    if offset.checked_add(length).is_none_or(|end| end > file_size) {
+        return Err(UsnError::invalid_image(format!(
+            "{} of {} bytes at {} is past the end of the file.", name, length, offset
+        )));
+    }
+
+    let mut buffer = vec![0u8; length as usize];
+    handle.seek(SeekFrom::Start(offset))?;
+    handle.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+
+/// The location of an NTFS volume within an image.
+#[derive(Debug, Clone)]
+pub struct VolumeLocation {
+    /// The partition index, or `None` if the image is a volume image.
+    pub index: Option<usize>,
+    /// The byte offset of the volume within the image.
+    pub offset: u64,
+}
+
+impl VolumeLocation {
+    /// A label for this volume to use in entry sources.
+    pub fn label(&self, image_source: &str) -> String {
+        match self.index {
+            Some(index) => format!("{}:p{}", image_source, index),
+            None => image_source.to_string()
+        }
+    }
+}
+
+
+/// Find the NTFS volumes in an image. The partition table (MBR or GPT) is read
+/// first. If there is none, the image itself is checked for an NTFS boot sector.
+///
+pub fn find_ntfs_volumes<T: ReadSeek>(handle: &mut T) -> Result<Vec<VolumeLocation>, UsnError> {
+    let mut volumes = Vec::new();
+
+    for partition in read_partitions(handle)? {
+        if is_ntfs_volume(handle, partition.offset)? {
+            volumes.push(VolumeLocation {
+                index: Some(partition.index),
+                offset: partition.offset
+            });
+        } else {
+            debug!("partition {} ({}) is not NTFS", partition.index, partition.description);
+        }
+    }
+
+    if volumes.is_empty() && is_ntfs_volume(handle, 0)? {
+        volumes.push(VolumeLocation {
+            index: None,
+            offset: 0
+        });
+    }
+
+    Ok(volumes)
+}
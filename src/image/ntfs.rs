@@ -0,0 +1,679 @@
+use std::io;
+use std::ops::Range;
+use std::io::{Read, Seek, SeekFrom};
+use mft::{MftEntry, MftParser};
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::all::UTF_16LE;
+use encoding::{DecoderTrap, Encoding};
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::{seek_position, SharedHandle};
+use crate::mapping::FolderMapping;
+
+
+const NTFS_OEM_ID: &[u8; 8] = b"NTFS    ";
+const ATTRIBUTE_TYPE_ATTRIBUTE_LIST: u32 = 0x20;
+pub(crate) const ATTRIBUTE_TYPE_FILE_NAME: u32 = 0x30;
+const ATTRIBUTE_TYPE_DATA: u32 = 0x80;
+const ATTRIBUTE_TYPE_END: u32 = 0xFFFF_FFFF;
+// The $Extend metadata directory is always entry 11
+const ENTRY_EXTEND: u64 = 11;
+const USN_JOURNAL_NAME: &str = "$UsnJrnl";
+const USN_JOURNAL_STREAM: &str = "$J";
+// Larger shifts overflow the sizes derived from them
+const MAX_SIZE_SHIFT: u32 = 30;
+// MFT records are 1 KiB or 4 KiB in practice
+const MAX_MFT_RECORD_SIZE: u64 = 64 * 1024;
+
+
+/// Check if there is an NTFS boot sector at the given offset.
+pub fn is_ntfs_volume<T: ReadSeek>(handle: &mut T, offset: u64) -> Result<bool, UsnError> {
+    let mut oem_id = [0u8; 8];
+    handle.seek(SeekFrom::Start(offset + 3))?;
+
+    match handle.read_exact(&mut oem_id) {
+        Ok(_) => Ok(&oem_id == NTFS_OEM_ID),
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(UsnError::from(error))
+    }
+}
+
+
+/// The fields of the NTFS boot sector needed to locate the $MFT.
+#[derive(Debug, Clone)]
+pub struct NtfsBootSector {
+    pub bytes_per_sector: u16,
+    pub cluster_size: u64,
+    pub total_sectors: u64,
+    pub mft_lcn: u64,
+    pub mft_record_size: u64,
+}
+
+impl NtfsBootSector {
+    pub fn from_buffer(buffer: &[u8]) -> Result<NtfsBootSector, UsnError> {
+        if buffer.len() < 512 || &buffer[3..11] != NTFS_OEM_ID {
+            return Err(UsnError::invalid_image(
+                "Buffer is not an NTFS boot sector.".to_string()
+            ));
+        }
+
+        let bytes_per_sector = LittleEndian::read_u16(&buffer[11..13]);
+        // Values over 0x80 are a negative power of two
+        let sectors_per_cluster = match buffer[13] {
+            value if value > 0x80 => 1u64 << size_shift(256 - u32::from(value), "sectors per cluster")?,
+            value => u64::from(value)
+        };
+        let cluster_size = u64::from(bytes_per_sector) * sectors_per_cluster;
+        if cluster_size == 0 {
+            return Err(UsnError::invalid_image(
+                "NTFS boot sector has a cluster size of 0.".to_string()
+            ));
+        }
+
+        let total_sectors = LittleEndian::read_u64(&buffer[40..48]);
+        let mft_lcn = LittleEndian::read_u64(&buffer[48..56]);
+
+        // A negative value is a power of two in bytes, otherwise it is a count of clusters
+        let mft_record_size = match buffer[64] as i8 {
+            value if value < 0 => 1u64 << size_shift(i32::from(value).unsigned_abs(), "MFT record size")?,
+            value => (value as u64).checked_mul(cluster_size).ok_or_else(|| UsnError::invalid_image(
+                format!("MFT record size of {} clusters overflows.", value)
+            ))?
+        };
+        if mft_record_size == 0 || mft_record_size > MAX_MFT_RECORD_SIZE {
+            return Err(UsnError::invalid_image(
+                format!("NTFS boot sector has an MFT record size of {}.", mft_record_size)
+            ));
+        }
+
+        Ok(NtfsBootSector {
+            bytes_per_sector,
+            cluster_size,
+            total_sectors,
+            mft_lcn,
+            mft_record_size
+        })
+    }
+}
+
+
+/// Check a power of two exponent read from the boot sector.
+fn size_shift(shift: u32, field: &str) -> Result<u32, UsnError> {
+    match shift {
+        shift if shift <= MAX_SIZE_SHIFT => Ok(shift),
+        shift => Err(UsnError::invalid_image(
+            format!("NTFS boot sector {} of 2^{} is too large.", field, shift)
+        ))
+    }
+}
+
+
+/// A run of clusters of a non-resident attribute. A run without an LCN is sparse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRun {
+    pub vcn: u64,
+    pub length: u64,
+    pub lcn: Option<u64>,
+}
+
+/// Decode the mapping pairs of a non-resident attribute.
+/// https://docs.microsoft.com/en-us/windows/win32/devnotes/attribute-record-header
+///
+pub fn decode_data_runs(buffer: &[u8], starting_vcn: u64) -> Result<Vec<DataRun>, UsnError> {
+    let mut runs = Vec::new();
+    let mut vcn = starting_vcn;
+    let mut lcn: i64 = 0;
+    let mut index = 0;
+
+    while index < buffer.len() && buffer[index] != 0 {
+        let length_size = (buffer[index] & 0x0F) as usize;
+        let offset_size = (buffer[index] >> 4) as usize;
+        index += 1;
+
+        if length_size == 0 || length_size > 8 || offset_size > 8 ||
+            index + length_size + offset_size > buffer.len() {
+            return Err(UsnError::invalid_image(
+                format!("Invalid data run header at index {}.", index - 1)
+            ));
+        }
+
+        let length = LittleEndian::read_uint(&buffer[index..index + length_size], length_size);
+        index += length_size;
+
+        let run_lcn = if offset_size == 0 {
+            None
+        } else {
+            // The offset is a signed value relative to the previous run's LCN
+            let delta = LittleEndian::read_int(&buffer[index..index + offset_size], offset_size);
+            index += offset_size;
+            lcn = lcn.checked_add(delta).ok_or_else(|| UsnError::invalid_image(
+                format!("Data run LCN overflows at index {}.", index)
+            ))?;
+            if lcn < 0 {
+                return Err(UsnError::invalid_image(
+                    format!("Data run points to negative LCN {}.", lcn)
+                ));
+            }
+            Some(lcn as u64)
+        };
+
+        runs.push(DataRun {
+            vcn,
+            length,
+            lcn: run_lcn
+        });
+        vcn = vcn.checked_add(length).ok_or_else(|| UsnError::invalid_image(
+            format!("Data run VCN overflows at index {}.", index)
+        ))?;
+    }
+
+    Ok(runs)
+}
+
+
+/// The part of an attribute record header needed to find streams and names.
+pub(crate) struct AttributeRecord<'a> {
+    pub(crate) type_code: u32,
+    pub(crate) name: String,
+    pub(crate) form: AttributeForm<'a>,
+}
+
+pub(crate) enum AttributeForm<'a> {
+    Resident {
+        value: &'a [u8]
+    },
+    NonResident {
+        lowest_vcn: u64,
+        data_size: u64,
+        mapping_pairs: &'a [u8]
+    },
+}
+
+/// Read the attribute records of an MFT entry that has had its fixups applied.
+pub(crate) fn read_attributes(entry: &MftEntry) -> Vec<AttributeRecord<'_>> {
+    let data = &entry.data;
+    let mut attributes = Vec::new();
+    let mut offset = entry.header.first_attribute_record_offset as usize;
+
+    while offset + 16 <= data.len() {
+        let type_code = LittleEndian::read_u32(&data[offset..offset + 4]);
+        if type_code == ATTRIBUTE_TYPE_END {
+            break;
+        }
+
+        let record_length = LittleEndian::read_u32(&data[offset + 4..offset + 8]) as usize;
+        if record_length < 16 || offset + record_length > data.len() {
+            debug!("entry {}: invalid attribute length {}", entry.header.record_number, record_length);
+            break;
+        }
+        let record = &data[offset..offset + record_length];
+
+        let non_resident = record[8] != 0;
+        let name_length = record[9] as usize;
+        let name_offset = LittleEndian::read_u16(&record[10..12]) as usize;
+        let name = if name_length > 0 && name_offset + name_length * 2 <= record.len() {
+            UTF_16LE.decode(&record[name_offset..name_offset + name_length * 2], DecoderTrap::Replace)
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        let form = if non_resident {
+            if record.len() < 64 {
+                break;
+            }
+            let pairs_offset = LittleEndian::read_u16(&record[32..34]) as usize;
+            AttributeForm::NonResident {
+                lowest_vcn: LittleEndian::read_u64(&record[16..24]),
+                data_size: LittleEndian::read_u64(&record[48..56]),
+                mapping_pairs: record.get(pairs_offset..).unwrap_or(&[])
+            }
+        } else {
+            if record.len() < 24 {
+                break;
+            }
+            let value_length = LittleEndian::read_u32(&record[16..20]) as usize;
+            let value_offset = LittleEndian::read_u16(&record[20..22]) as usize;
+            AttributeForm::Resident {
+                value: record.get(value_offset..value_offset + value_length).unwrap_or(&[])
+            }
+        };
+
+        attributes.push(AttributeRecord {
+            type_code,
+            name,
+            form
+        });
+
+        offset += record_length;
+    }
+
+    attributes
+}
+
+/// The fields of a $FILE_NAME attribute value needed to match it.
+pub(crate) struct FileNameValue<'a> {
+    pub(crate) parent_entry: u64,
+    pub(crate) parent_sequence: u16,
+    pub(crate) namespace: u8,
+    /// The name as UTF-16LE.
+    pub(crate) name: &'a [u8],
+}
+
+impl<'a> FileNameValue<'a> {
+    pub(crate) fn from_value(value: &'a [u8]) -> Option<FileNameValue<'a>> {
+        let name_length = usize::from(*value.get(64)?) * 2;
+        Some(FileNameValue {
+            parent_entry: LittleEndian::read_u64(&value[0..8]) & 0x0000_FFFF_FFFF_FFFF,
+            parent_sequence: LittleEndian::read_u16(&value[6..8]),
+            namespace: *value.get(65)?,
+            name: value.get(66..66 + name_length)?
+        })
+    }
+}
+
+/// The non-resident pieces of a named stream, keyed by their lowest VCN.
+#[derive(Default)]
+struct StreamPieces {
+    data_size: Option<u64>,
+    runs: Vec<DataRun>,
+}
+
+impl StreamPieces {
+    fn add(&mut self, lowest_vcn: u64, data_size: u64, mapping_pairs: &[u8]) -> Result<(), UsnError> {
+        // The data size is only valid in the piece that starts at VCN 0
+        if lowest_vcn == 0 {
+            self.data_size = Some(data_size);
+        }
+        self.runs.extend(decode_data_runs(mapping_pairs, lowest_vcn)?);
+        Ok(())
+    }
+
+    fn into_runs(mut self) -> (Vec<DataRun>, u64) {
+        self.runs.sort_by_key(|run| run.vcn);
+        let data_size = self.data_size.unwrap_or(0);
+        (self.runs, data_size)
+    }
+}
+
+
+/// A Read + Seek view of a non-resident NTFS stream. Sparse runs and the
+/// space past the last run read as zeros.
+///
+pub struct NtfsStream<T: ReadSeek> {
+    handle: SharedHandle<T>,
+    volume_offset: u64,
+    cluster_size: u64,
+    runs: Vec<DataRun>,
+    size: u64,
+    position: u64,
+}
+
+impl<T: ReadSeek> NtfsStream<T> {
+    pub fn new(handle: SharedHandle<T>, volume_offset: u64, cluster_size: u64, runs: Vec<DataRun>, size: u64) -> Self {
+        NtfsStream {
+            handle,
+            volume_offset,
+            cluster_size,
+            runs,
+            size,
+            position: 0
+        }
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_runs(&self) -> &[DataRun] {
+        &self.runs
+    }
+
+    /// The byte ranges of the stream that are backed by clusters, in order.
+    /// Adjacent runs are merged.
+    pub fn data_ranges(&self) -> Vec<Range<u64>> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+
+        for run in self.runs.iter().filter(|run| run.lcn.is_some()) {
+            let (start, end) = match self.run_bounds(run) {
+                Some((start, end)) => (start, end.min(self.size)),
+                // Runs are sorted, so no later run can be addressed either
+                None => break
+            };
+            if start >= end {
+                continue;
+            }
+
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end)
+            }
+        }
+
+        ranges
+    }
+
+    /// The byte range of a run within the stream, or None if it overflows.
+    fn run_bounds(&self, run: &DataRun) -> Option<(u64, u64)> {
+        let start = run.vcn.checked_mul(self.cluster_size)?;
+        let end = run.vcn.checked_add(run.length)?.checked_mul(self.cluster_size)?;
+        Some((start, end))
+    }
+}
+
+fn overflow_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "data run offset overflows")
+}
+
+impl<T: ReadSeek> Read for NtfsStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let vcn = self.position / self.cluster_size;
+        let run = self.runs.iter().find(|run| vcn >= run.vcn && vcn - run.vcn < run.length);
+
+        // Only read up to the end of this run or the end of the stream
+        let run_end = match run {
+            Some(run) => self.run_bounds(run).ok_or_else(overflow_error)?.1,
+            None => {
+                // Read zeros up to the next run
+                self.runs.iter()
+                    .filter_map(|run| run.vcn.checked_mul(self.cluster_size))
+                    .filter(|start| *start > self.position)
+                    .min()
+                    .unwrap_or(self.size)
+            }
+        };
+        let to_read = (run_end.min(self.size) - self.position).min(buf.len() as u64) as usize;
+
+        match run.and_then(|run| run.lcn.map(|lcn| (run, lcn))) {
+            Some((run, lcn)) => {
+                let run_start = self.run_bounds(run).ok_or_else(overflow_error)?.0;
+                let physical_offset = lcn.checked_mul(self.cluster_size)
+                    .and_then(|offset| offset.checked_add(self.volume_offset))
+                    .and_then(|offset| offset.checked_add(self.position - run_start))
+                    .ok_or_else(overflow_error)?;
+
+                let mut handle = self.handle.lock().map_err(|_| io::Error::other("image handle lock poisoned"))?;
+                handle.seek(SeekFrom::Start(physical_offset))?;
+                handle.read_exact(&mut buf[..to_read])?;
+            },
+            None => {
+                for byte in buf[..to_read].iter_mut() {
+                    *byte = 0;
+                }
+            }
+        }
+
+        self.position += to_read as u64;
+        Ok(to_read)
+    }
+}
+
+impl<T: ReadSeek> Seek for NtfsStream<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(pos, self.position, || Ok(self.size))?;
+        Ok(self.position)
+    }
+}
+
+
+/// The result of a single pass over a volume's $MFT.
+pub struct MftScan<T: ReadSeek> {
+    /// The $UsnJrnl:$J stream, if the volume has one.
+    pub usn_journal: Option<NtfsStream<T>>,
+    pub folder_mapping: FolderMapping,
+}
+
+
+/// An NTFS volume within an image.
+pub struct NtfsVolume<T: ReadSeek> {
+    handle: SharedHandle<T>,
+    offset: u64,
+    boot_sector: NtfsBootSector,
+    mft_runs: Vec<DataRun>,
+    mft_size: u64,
+}
+
+impl<T: ReadSeek> NtfsVolume<T> {
+    /// Open the NTFS volume that starts at `offset` in the image and resolve
+    /// the $MFT's data runs.
+    pub fn open(handle: SharedHandle<T>, offset: u64) -> Result<Self, UsnError> {
+        let mut boot_buffer = vec![0; 512];
+        {
+            let mut locked = handle.lock().map_err(|_| UsnError::invalid_image(
+                "Image handle lock poisoned.".to_string()
+            ))?;
+            locked.seek(SeekFrom::Start(offset))?;
+            locked.read_exact(&mut boot_buffer)?;
+        }
+        let boot_sector = NtfsBootSector::from_buffer(&boot_buffer)?;
+
+        let mut volume = NtfsVolume {
+            handle,
+            offset,
+            boot_sector,
+            mft_runs: Vec::new(),
+            mft_size: 0,
+        };
+
+        // Bootstrap the $MFT runs from entry 0, which starts at the $MFT LCN
+        let mut mft_entry_buffer = vec![0; volume.boot_sector.mft_record_size as usize];
+        let mft_offset = volume.boot_sector.mft_lcn.checked_mul(volume.boot_sector.cluster_size)
+            .ok_or_else(|| UsnError::invalid_image(
+                format!("$MFT LCN {} is out of range.", volume.boot_sector.mft_lcn)
+            ))?;
+        volume.read_volume(mft_offset, &mut mft_entry_buffer)?;
+        let mft_entry = MftEntry::from_buffer(mft_entry_buffer, 0)
+            .map_err(|error| UsnError::invalid_image(format!("Unable to read $MFT entry: {}", error)))?;
+        if !mft_entry.header.is_valid() {
+            return Err(UsnError::invalid_image(
+                "$MFT entry 0 does not have a FILE signature.".to_string()
+            ));
+        }
+
+        let mut pieces = StreamPieces::default();
+        let mut extension_entries = Vec::new();
+        for attribute in read_attributes(&mft_entry) {
+            match (attribute.type_code, attribute.form) {
+                (ATTRIBUTE_TYPE_DATA, AttributeForm::NonResident { lowest_vcn, data_size, mapping_pairs }) if attribute.name.is_empty() => {
+                    pieces.add(lowest_vcn, data_size, mapping_pairs)?;
+                },
+                (ATTRIBUTE_TYPE_ATTRIBUTE_LIST, AttributeForm::Resident { value }) => {
+                    extension_entries = data_attribute_list_entries(value);
+                },
+                _ => {}
+            }
+        }
+        let (runs, size) = pieces.into_runs();
+        volume.mft_runs = runs;
+        volume.mft_size = size;
+
+        // A fragmented $MFT can have more $DATA pieces in extension entries.
+        if !extension_entries.is_empty() {
+            let mut pieces = StreamPieces {
+                data_size: Some(volume.mft_size),
+                runs: volume.mft_runs.clone()
+            };
+
+            for entry_number in extension_entries {
+                let entry = volume.read_mft_entry(entry_number)?;
+                for attribute in read_attributes(&entry) {
+                    if let (ATTRIBUTE_TYPE_DATA, AttributeForm::NonResident { lowest_vcn, mapping_pairs, .. }) = (attribute.type_code, attribute.form) {
+                        if attribute.name.is_empty() && lowest_vcn != 0 {
+                            pieces.runs.extend(decode_data_runs(mapping_pairs, lowest_vcn)?);
+                        }
+                    }
+                }
+            }
+
+            let (runs, _) = pieces.into_runs();
+            volume.mft_runs = runs;
+        }
+
+        Ok(volume)
+    }
+
+    pub fn get_boot_sector(&self) -> &NtfsBootSector {
+        &self.boot_sector
+    }
+
+    fn read_volume(&self, offset: u64, buffer: &mut [u8]) -> Result<(), UsnError> {
+        let mut handle = self.handle.lock().map_err(|_| UsnError::invalid_image(
+            "Image handle lock poisoned.".to_string()
+        ))?;
+        let volume_offset = self.offset.checked_add(offset).ok_or_else(|| UsnError::invalid_image(
+            format!("Volume offset {} is out of range.", offset)
+        ))?;
+        handle.seek(SeekFrom::Start(volume_offset))?;
+        handle.read_exact(buffer)?;
+        Ok(())
+    }
+
+    fn read_mft_entry(&self, entry_number: u64) -> Result<MftEntry, UsnError> {
+        let record_size = self.boot_sector.mft_record_size;
+        let mut stream = self.mft_stream();
+        let mut buffer = vec![0; record_size as usize];
+
+        let entry_offset = entry_number.checked_mul(record_size).ok_or_else(|| UsnError::invalid_image(
+            format!("MFT entry {} is out of range.", entry_number)
+        ))?;
+        stream.seek(SeekFrom::Start(entry_offset))?;
+        stream.read_exact(&mut buffer)?;
+
+        MftEntry::from_buffer(buffer, entry_number)
+            .map_err(|error| UsnError::invalid_image(format!("Unable to read MFT entry {}: {}", entry_number, error)))
+    }
+
+    /// A stream over the volume's $MFT.
+    pub fn mft_stream(&self) -> NtfsStream<T> {
+        NtfsStream::new(
+            self.handle.clone(),
+            self.offset,
+            self.boot_sector.cluster_size,
+            self.mft_runs.clone(),
+            self.mft_size
+        )
+    }
+
+    /// Walk every entry of the $MFT once. Directory entries are added to a
+    /// FolderMapping and the $Extend\$UsnJrnl:$J stream is located.
+    pub fn scan_mft(&self) -> Result<MftScan<T>, UsnError> {
+        let mut mft_parser = MftParser::from_read_seek(self.mft_stream(), Some(self.mft_size))
+            .map_err(|error| UsnError::invalid_image(format!("Unable to read $MFT: {}", error)))?;
+
+        let mut folder_mapping = FolderMapping::new();
+        let mut usn_journal_entry: Option<u64> = None;
+        // $J pieces keyed by their base entry. The journal's base entry may
+        // come after its extension entries.
+        let mut journal_pieces: Vec<(u64, u64, u64, Vec<DataRun>)> = Vec::new();
+
+        for entry in mft_parser.iter_entries() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    debug!("error reading mft entry: {}", error);
+                    continue;
+                }
+            };
+            if !entry.header.is_valid() {
+                continue;
+            }
+
+            folder_mapping.add_mft_entry(&entry);
+
+            let base_entry = match entry.header.base_reference.entry {
+                0 => entry.header.record_number,
+                base => base
+            };
+
+            for attribute in read_attributes(&entry) {
+                match (attribute.type_code, attribute.form) {
+                    (ATTRIBUTE_TYPE_FILE_NAME, AttributeForm::Resident { value }) if is_usn_journal_name(value) => {
+                        usn_journal_entry = Some(base_entry);
+                    },
+                    (ATTRIBUTE_TYPE_DATA, AttributeForm::NonResident { lowest_vcn, data_size, mapping_pairs }) if attribute.name == USN_JOURNAL_STREAM => {
+                        // Any file can have a $J stream, so a corrupt one is
+                        // skipped rather than failing the volume
+                        match decode_data_runs(mapping_pairs, lowest_vcn) {
+                            Ok(runs) => journal_pieces.push((base_entry, lowest_vcn, data_size, runs)),
+                            Err(error) => warn!(
+                                "skipping $J stream of entry {}: {}", entry.header.record_number, error.message
+                            )
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        let usn_journal = match usn_journal_entry {
+            Some(journal_entry) => {
+                let mut pieces = StreamPieces::default();
+                for (base_entry, lowest_vcn, data_size, runs) in journal_pieces {
+                    if base_entry == journal_entry {
+                        if lowest_vcn == 0 {
+                            pieces.data_size = Some(data_size);
+                        }
+                        pieces.runs.extend(runs);
+                    }
+                }
+                let (runs, size) = pieces.into_runs();
+
+                debug!("found $UsnJrnl at entry {} with {} runs and size {}", journal_entry, runs.len(), size);
+                Some(NtfsStream::new(
+                    self.handle.clone(),
+                    self.offset,
+                    self.boot_sector.cluster_size,
+                    runs,
+                    size
+                ))
+            },
+            None => None
+        };
+
+        Ok(MftScan {
+            usn_journal,
+            folder_mapping
+        })
+    }
+}
+
+/// Check if a $FILE_NAME value is $UsnJrnl in the $Extend directory.
+fn is_usn_journal_name(value: &[u8]) -> bool {
+    let file_name = match FileNameValue::from_value(value) {
+        Some(file_name) if file_name.parent_entry == ENTRY_EXTEND => file_name,
+        _ => return false
+    };
+
+    match UTF_16LE.decode(file_name.name, DecoderTrap::Replace) {
+        Ok(name) => name == USN_JOURNAL_NAME,
+        Err(_) => false
+    }
+}
+
+/// Get the entry numbers of the extension entries referenced by $DATA
+/// attributes in an attribute list.
+fn data_attribute_list_entries(value: &[u8]) -> Vec<u64> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + 26 <= value.len() {
+        let type_code = LittleEndian::read_u32(&value[offset..offset + 4]);
+        let record_length = LittleEndian::read_u16(&value[offset + 4..offset + 6]) as usize;
+        let entry = LittleEndian::read_u64(&value[offset + 16..offset + 24]) & 0x0000_FFFF_FFFF_FFFF;
+
+        if type_code == ATTRIBUTE_TYPE_DATA && entry != 0 && !entries.contains(&entry) {
+            entries.push(entry);
+        }
+
+        if record_length == 0 {
+            break;
+        }
+        offset += record_length;
+    }
+
+    entries
+}
@@ -0,0 +1,209 @@
+use std::io::SeekFrom;
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::all::UTF_16LE;
+use encoding::{DecoderTrap, Encoding};
+use crate::ReadSeek;
+use crate::error::UsnError;
+
+
+const SIZE_SECTOR: u64 = 512;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+// Guard against looping extended boot record chains
+const MAX_EXTENDED_PARTITIONS: usize = 128;
+// GPT tables have 128 entries in practice
+const MAX_GPT_ENTRIES: usize = 4096;
+const MAX_GPT_ENTRIES_SIZE: usize = 1024 * 1024;
+
+
+/// A partition found in an MBR or GPT partition table.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    /// The partition's index in the order it was found, starting at 0.
+    pub index: usize,
+    pub offset: u64,
+    pub size: u64,
+    pub description: String,
+}
+
+
+fn read_sector<T: ReadSeek>(handle: &mut T, offset: u64, size: usize) -> Result<Option<Vec<u8>>, UsnError> {
+    let mut buffer = vec![0; size];
+    handle.seek(SeekFrom::Start(offset))?;
+
+    let mut filled = 0;
+    while filled < size {
+        let bytes_read = handle.read(&mut buffer[filled..])?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        filled += bytes_read;
+    }
+
+    Ok(Some(buffer))
+}
+
+
+/// Read the partitions of an image. GPT is preferred when the MBR is a
+/// protective MBR. An empty list is returned if no partition table is found.
+///
+pub fn read_partitions<T: ReadSeek>(handle: &mut T) -> Result<Vec<Partition>, UsnError> {
+    let mbr = match read_sector(handle, 0, SIZE_SECTOR as usize)? {
+        Some(buffer) => buffer,
+        None => return Ok(Vec::new())
+    };
+
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let partition_types: Vec<u8> = (0..4).map(|i| mbr[446 + i * 16 + 4]).collect();
+    if partition_types.contains(&MBR_TYPE_GPT_PROTECTIVE) {
+        // GPT can use 512 or 4096 byte sectors
+        for sector_size in &[512u64, 4096] {
+            if let Some(partitions) = read_gpt(handle, *sector_size)? {
+                return Ok(partitions);
+            }
+        }
+    }
+
+    read_mbr(handle, &mbr)
+}
+
+fn read_mbr<T: ReadSeek>(handle: &mut T, mbr: &[u8]) -> Result<Vec<Partition>, UsnError> {
+    let mut partitions = Vec::new();
+
+    for i in 0..4 {
+        let entry = &mbr[446 + i * 16..446 + (i + 1) * 16];
+        let partition_type = entry[4];
+        let start_lba = u64::from(LittleEndian::read_u32(&entry[8..12]));
+        let sector_count = u64::from(LittleEndian::read_u32(&entry[12..16]));
+
+        if partition_type == 0 || sector_count == 0 {
+            continue;
+        }
+
+        if MBR_TYPES_EXTENDED.contains(&partition_type) {
+            read_extended_partitions(handle, start_lba, &mut partitions)?;
+            continue;
+        }
+
+        partitions.push(Partition {
+            index: partitions.len(),
+            offset: start_lba * SIZE_SECTOR,
+            size: sector_count * SIZE_SECTOR,
+            description: format!("MBR type 0x{:02X}", partition_type)
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// Follow the chain of extended boot records. Logical partitions are relative
+/// to their EBR and the next EBR is relative to the start of the extended partition.
+fn read_extended_partitions<T: ReadSeek>(handle: &mut T, extended_lba: u64, partitions: &mut Vec<Partition>) -> Result<(), UsnError> {
+    let mut ebr_lba = extended_lba;
+
+    for _ in 0..MAX_EXTENDED_PARTITIONS {
+        let ebr = match read_sector(handle, ebr_lba * SIZE_SECTOR, SIZE_SECTOR as usize)? {
+            Some(buffer) => buffer,
+            None => break
+        };
+        if ebr[510..512] != MBR_SIGNATURE {
+            break;
+        }
+
+        let logical = &ebr[446..462];
+        let start_lba = u64::from(LittleEndian::read_u32(&logical[8..12]));
+        let sector_count = u64::from(LittleEndian::read_u32(&logical[12..16]));
+        if logical[4] != 0 && sector_count != 0 {
+            partitions.push(Partition {
+                index: partitions.len(),
+                offset: (ebr_lba + start_lba) * SIZE_SECTOR,
+                size: sector_count * SIZE_SECTOR,
+                description: format!("MBR logical type 0x{:02X}", logical[4])
+            });
+        }
+
+        let next = &ebr[462..478];
+        let next_lba = u64::from(LittleEndian::read_u32(&next[8..12]));
+        if next[4] == 0 || next_lba == 0 {
+            break;
+        }
+        ebr_lba = extended_lba + next_lba;
+    }
+
+    Ok(())
+}
+
+fn read_gpt<T: ReadSeek>(handle: &mut T, sector_size: u64) -> Result<Option<Vec<Partition>>, UsnError> {
+    let header = match read_sector(handle, sector_size, 92)? {
+        Some(buffer) => buffer,
+        None => return Ok(None)
+    };
+
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+
+    let entries_lba = LittleEndian::read_u64(&header[72..80]);
+    let entry_count = LittleEndian::read_u32(&header[80..84]) as usize;
+    let entry_size = LittleEndian::read_u32(&header[84..88]) as usize;
+    if entry_size < 128 || entry_size % 8 != 0 {
+        return Err(UsnError::invalid_image(
+            format!("GPT partition entry size {} is invalid.", entry_size)
+        ));
+    }
+    if entry_count > MAX_GPT_ENTRIES || entry_count * entry_size > MAX_GPT_ENTRIES_SIZE {
+        return Err(UsnError::invalid_image(
+            format!("GPT has {} partition entries of {} bytes.", entry_count, entry_size)
+        ));
+    }
+    let entries_offset = entries_lba.checked_mul(sector_size).ok_or_else(|| UsnError::invalid_image(
+        format!("GPT partition entries LBA {} is out of range.", entries_lba)
+    ))?;
+
+    let entries = match read_sector(handle, entries_offset, entry_count * entry_size)? {
+        Some(buffer) => buffer,
+        None => return Err(UsnError::invalid_image(
+            "GPT partition entries are past the end of the image.".to_string()
+        ))
+    };
+
+    let mut partitions = Vec::new();
+    for entry in entries.chunks(entry_size) {
+        // Unused entries have a zero type GUID
+        if entry[0..16].iter().all(|b| *b == 0) {
+            continue;
+        }
+
+        let first_lba = LittleEndian::read_u64(&entry[32..40]);
+        let last_lba = LittleEndian::read_u64(&entry[40..48]);
+        let name = UTF_16LE.decode(&entry[56..128], DecoderTrap::Replace)
+            .unwrap_or_default()
+            .trim_end_matches('\u{0}')
+            .to_string();
+
+        let (offset, size) = match (
+            first_lba.checked_mul(sector_size),
+            last_lba.saturating_add(1).saturating_sub(first_lba).checked_mul(sector_size)
+        ) {
+            (Some(offset), Some(size)) => (offset, size),
+            _ => {
+                debug!("GPT partition entry {} is out of range", name);
+                continue;
+            }
+        };
+
+        partitions.push(Partition {
+            index: partitions.len(),
+            offset,
+            size,
+            description: format!("GPT {}", name)
+        });
+    }
+
+    Ok(Some(partitions))
+}
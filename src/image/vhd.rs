@@ -0,0 +1,381 @@
+//! Microsoft Virtual Hard Disk (VHD) images. Fixed, dynamic and differencing
+//! disks are read as a single Read + Seek stream of the virtual disk.
+//!
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder};
+use encoding::all::{UTF_16BE, UTF_16LE};
+use encoding::{DecoderTrap, Encoding};
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::{find_parent_path, open_image_at_depth, read_parent, read_within_file, seek_position, BoxedImage};
+
+
+const VHD_FOOTER_COOKIE: &[u8; 8] = b"conectix";
+const VHD_DYNAMIC_COOKIE: &[u8; 8] = b"cxsparse";
+const SIZE_VHD_FOOTER: u64 = 512;
+const SIZE_VHD_DYNAMIC_HEADER: u64 = 1024;
+const SIZE_SECTOR: u64 = 512;
+const VHD_TYPE_FIXED: u32 = 2;
+const VHD_TYPE_DYNAMIC: u32 = 3;
+const VHD_TYPE_DIFFERENCING: u32 = 4;
+const BAT_ENTRY_UNUSED: u32 = 0xFFFF_FFFF;
+const PARENT_LOCATOR_COUNT: usize = 8;
+
+
+/// Check if the handle ends with a VHD footer.
+pub fn is_vhd<T: ReadSeek>(handle: &mut T) -> Result<bool, UsnError> {
+    let size = handle.seek(SeekFrom::End(0))?;
+    if size < SIZE_VHD_FOOTER {
+        handle.seek(SeekFrom::Start(0))?;
+        return Ok(false);
+    }
+
+    let mut cookie = [0u8; 8];
+    handle.seek(SeekFrom::Start(size - SIZE_VHD_FOOTER))?;
+    handle.read_exact(&mut cookie)?;
+    handle.seek(SeekFrom::Start(0))?;
+
+    Ok(&cookie == VHD_FOOTER_COOKIE)
+}
+
+
+/// The kind of VHD.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VhdDiskType {
+    Fixed,
+    Dynamic,
+    Differencing,
+}
+
+
+/// The fields of the VHD footer needed to read the disk.
+#[derive(Debug, Clone)]
+pub struct VhdFooter {
+    pub data_offset: u64,
+    pub current_size: u64,
+    pub disk_type: VhdDiskType,
+    pub unique_id: [u8; 16],
+}
+
+impl VhdFooter {
+    pub fn from_buffer(buffer: &[u8]) -> Result<VhdFooter, UsnError> {
+        if buffer.len() < SIZE_VHD_FOOTER as usize || &buffer[0..8] != VHD_FOOTER_COOKIE {
+            return Err(UsnError::invalid_image(
+                "Buffer is not a VHD footer.".to_string()
+            ));
+        }
+
+        let disk_type = match BigEndian::read_u32(&buffer[60..64]) {
+            VHD_TYPE_FIXED => VhdDiskType::Fixed,
+            VHD_TYPE_DYNAMIC => VhdDiskType::Dynamic,
+            VHD_TYPE_DIFFERENCING => VhdDiskType::Differencing,
+            other => return Err(UsnError::invalid_image(
+                format!("Unknown VHD disk type {}.", other)
+            ))
+        };
+
+        let mut unique_id = [0u8; 16];
+        unique_id.copy_from_slice(&buffer[68..84]);
+
+        Ok(VhdFooter {
+            data_offset: BigEndian::read_u64(&buffer[16..24]),
+            current_size: BigEndian::read_u64(&buffer[48..56]),
+            disk_type,
+            unique_id
+        })
+    }
+}
+
+
+/// A Read + Seek view of the virtual disk in a VHD. The parent of a
+/// differencing disk is read for sectors the child does not hold.
+///
+pub struct VhdImage<T: ReadSeek> {
+    handle: T,
+    footer: VhdFooter,
+    block_size: u64,
+    bitmap_size: u64,
+    bat: Vec<u32>,
+    parent_unique_id: Option<[u8; 16]>,
+    parent_locations: Vec<String>,
+    parent: Option<BoxedImage>,
+    bitmap_cache: Option<(usize, Vec<u8>)>,
+    position: u64,
+}
+
+impl VhdImage<File> {
+    /// Open a VHD. The parents of a differencing disk are found through its
+    /// parent locators and opened as well.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, UsnError> {
+        Self::open_at_depth(path.as_ref(), 0)
+    }
+
+    pub(crate) fn open_at_depth(path: &Path, depth: usize) -> Result<Self, UsnError> {
+        let image = VhdImage::new(File::open(path)?)?;
+        if image.footer.disk_type != VhdDiskType::Differencing {
+            return Ok(image);
+        }
+
+        let parent_path = find_parent_path(path, &image.parent_locations).ok_or_else(|| {
+            UsnError::invalid_image(format!(
+                "Parent of differencing disk {} not found. Tried: {}",
+                path.display(), image.parent_locations.join(", ")
+            ))
+        })?;
+        debug!("opening parent {} of {}", parent_path.display(), path.display());
+
+        let parent = open_image_at_depth(&parent_path, depth + 1)?;
+        Ok(image.with_parent(parent))
+    }
+}
+
+impl<T: ReadSeek> VhdImage<T> {
+    /// Read the footer, dynamic header and block allocation table. The parent
+    /// of a differencing disk has to be attached with `with_parent`.
+    pub fn new(mut handle: T) -> Result<Self, UsnError> {
+        let file_size = handle.seek(SeekFrom::End(0))?;
+        if file_size < SIZE_VHD_FOOTER {
+            return Err(UsnError::invalid_image("File is too small to be a VHD.".to_string()));
+        }
+
+        let mut footer_buffer = vec![0u8; SIZE_VHD_FOOTER as usize];
+        handle.seek(SeekFrom::Start(file_size - SIZE_VHD_FOOTER))?;
+        handle.read_exact(&mut footer_buffer)?;
+        let footer = match VhdFooter::from_buffer(&footer_buffer) {
+            Ok(footer) => footer,
+            Err(_) => {
+                // Dynamic disks keep a copy of the footer at the start
+                handle.seek(SeekFrom::Start(0))?;
+                handle.read_exact(&mut footer_buffer)?;
+                warn!("VHD footer is damaged, using the copy at the start of the file");
+                VhdFooter::from_buffer(&footer_buffer)?
+            }
+        };
+
+        let mut image = VhdImage {
+            handle,
+            footer,
+            block_size: 0,
+            bitmap_size: 0,
+            bat: Vec::new(),
+            parent_unique_id: None,
+            parent_locations: Vec::new(),
+            parent: None,
+            bitmap_cache: None,
+            position: 0
+        };
+
+        if image.footer.disk_type != VhdDiskType::Fixed {
+            image.read_dynamic_header()?;
+        }
+
+        Ok(image)
+    }
+
+    /// Attach the parent disk of a differencing disk.
+    pub fn with_parent(mut self, parent: BoxedImage) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn get_footer(&self) -> &VhdFooter {
+        &self.footer
+    }
+
+    pub fn get_disk_type(&self) -> VhdDiskType {
+        self.footer.disk_type
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.footer.current_size
+    }
+
+    /// The unique id of the parent of a differencing disk.
+    pub fn get_parent_unique_id(&self) -> Option<[u8; 16]> {
+        self.parent_unique_id
+    }
+
+    /// The parent paths stored in the parent locators, in the order they
+    /// should be tried.
+    pub fn get_parent_locations(&self) -> &[String] {
+        &self.parent_locations
+    }
+
+    fn read_dynamic_header(&mut self) -> Result<(), UsnError> {
+        let mut header = vec![0u8; SIZE_VHD_DYNAMIC_HEADER as usize];
+        self.handle.seek(SeekFrom::Start(self.footer.data_offset))?;
+        self.handle.read_exact(&mut header)?;
+        if &header[0..8] != VHD_DYNAMIC_COOKIE {
+            return Err(UsnError::invalid_image(
+                "VHD dynamic disk header not found.".to_string()
+            ));
+        }
+
+        let table_offset = BigEndian::read_u64(&header[16..24]);
+        let table_entries = u64::from(BigEndian::read_u32(&header[28..32]));
+        self.block_size = u64::from(BigEndian::read_u32(&header[32..36]));
+        if self.block_size == 0 || self.block_size % SIZE_SECTOR != 0 {
+            return Err(UsnError::invalid_image(
+                format!("Invalid VHD block size {}.", self.block_size)
+            ));
+        }
+
+        // One bit per sector, padded to a sector boundary
+        let bitmap_bytes = (self.block_size / SIZE_SECTOR).div_ceil(8);
+        self.bitmap_size = bitmap_bytes.div_ceil(SIZE_SECTOR) * SIZE_SECTOR;
+
+        let table = read_within_file(&mut self.handle, table_offset, table_entries * 4, "VHD BAT")?;
+        self.bat = table.chunks(4).map(BigEndian::read_u32).collect();
+
+        if self.footer.disk_type == VhdDiskType::Differencing {
+            let mut parent_unique_id = [0u8; 16];
+            parent_unique_id.copy_from_slice(&header[40..56]);
+            self.parent_unique_id = Some(parent_unique_id);
+            self.parent_locations = self.read_parent_locations(&header)?;
+        }
+
+        Ok(())
+    }
+
+    /// Relative locators are listed before absolute ones, and the parent's
+    /// name from the header is the last resort.
+    fn read_parent_locations(&mut self, header: &[u8]) -> Result<Vec<String>, UsnError> {
+        let mut relative = Vec::new();
+        let mut absolute = Vec::new();
+
+        for i in 0..PARENT_LOCATOR_COUNT {
+            let entry = &header[576 + i * 24..576 + (i + 1) * 24];
+            let platform_code = &entry[0..4];
+            let data_length = u64::from(BigEndian::read_u32(&entry[8..12]));
+            let data_offset = BigEndian::read_u64(&entry[16..24]);
+            if data_length == 0 {
+                continue;
+            }
+
+            let data = read_within_file(&mut self.handle, data_offset, data_length, "VHD parent locator")?;
+
+            match platform_code {
+                b"W2ru" => relative.push(decode_utf16le(&data)),
+                b"W2ku" => absolute.push(decode_utf16le(&data)),
+                b"MacX" => absolute.push(
+                    String::from_utf8_lossy(&data).trim_start_matches("file://").to_string()
+                ),
+                _ => {}
+            }
+        }
+
+        let parent_name = UTF_16BE.decode(&header[64..576], DecoderTrap::Replace)
+            .unwrap_or_default()
+            .trim_end_matches('\u{0}')
+            .to_string();
+
+        let mut locations = relative;
+        locations.extend(absolute);
+        if !parent_name.is_empty() {
+            locations.push(parent_name);
+        }
+
+        Ok(locations)
+    }
+
+    /// Read part of a block that has been allocated in this file.
+    fn read_allocated(&mut self, block: usize, block_offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let block_start = u64::from(self.bat[block]) * SIZE_SECTOR;
+
+        if self.footer.disk_type != VhdDiskType::Differencing {
+            self.handle.seek(SeekFrom::Start(block_start + self.bitmap_size + block_offset))?;
+            self.handle.read_exact(buf)?;
+            return Ok(buf.len());
+        }
+
+        let cached = matches!(self.bitmap_cache, Some((cached_block, _)) if cached_block == block);
+        if !cached {
+            let mut bitmap = vec![0u8; self.bitmap_size as usize];
+            self.handle.seek(SeekFrom::Start(block_start))?;
+            self.handle.read_exact(&mut bitmap)?;
+            self.bitmap_cache = Some((block, bitmap));
+        }
+        let bitmap = match self.bitmap_cache {
+            Some((_, ref bitmap)) => bitmap,
+            None => unreachable!("bitmap was just cached")
+        };
+
+        // Read the run of sectors that are all in this file or all in the parent.
+        // Bits are most significant first.
+        let is_present = |sector: u64| bitmap[(sector / 8) as usize] & (0x80 >> (sector % 8)) != 0;
+        let first_sector = block_offset / SIZE_SECTOR;
+        let present = is_present(first_sector);
+        let mut run_end = (first_sector + 1) * SIZE_SECTOR;
+        while run_end < block_offset + buf.len() as u64 && is_present(run_end / SIZE_SECTOR) == present {
+            run_end += SIZE_SECTOR;
+        }
+        let to_read = ((run_end - block_offset) as usize).min(buf.len());
+
+        if present {
+            self.handle.seek(SeekFrom::Start(block_start + self.bitmap_size + block_offset))?;
+            self.handle.read_exact(&mut buf[..to_read])?;
+        } else {
+            let disk_offset = block as u64 * self.block_size + block_offset;
+            read_parent(&mut self.parent, disk_offset, &mut buf[..to_read])?;
+        }
+
+        Ok(to_read)
+    }
+}
+
+impl<T: ReadSeek> Read for VhdImage<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.footer.current_size;
+        if self.position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let to_read = if self.footer.disk_type == VhdDiskType::Fixed {
+            let to_read = ((size - self.position) as usize).min(buf.len());
+            self.handle.seek(SeekFrom::Start(self.position))?;
+            self.handle.read_exact(&mut buf[..to_read])?;
+            to_read
+        } else {
+            let block = (self.position / self.block_size) as usize;
+            let block_offset = self.position % self.block_size;
+            let to_read = ((self.block_size - block_offset).min(size - self.position) as usize).min(buf.len());
+
+            match self.bat.get(block) {
+                Some(&entry) if entry != BAT_ENTRY_UNUSED => {
+                    self.read_allocated(block, block_offset, &mut buf[..to_read])?
+                },
+                _ if self.footer.disk_type == VhdDiskType::Differencing => {
+                    read_parent(&mut self.parent, self.position, &mut buf[..to_read])?;
+                    to_read
+                },
+                _ => {
+                    for byte in buf[..to_read].iter_mut() {
+                        *byte = 0;
+                    }
+                    to_read
+                }
+            }
+        };
+
+        self.position += to_read as u64;
+        Ok(to_read)
+    }
+}
+
+impl<T: ReadSeek> Seek for VhdImage<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(pos, self.position, || Ok(self.footer.current_size))?;
+        Ok(self.position)
+    }
+}
+
+
+fn decode_utf16le(data: &[u8]) -> String {
+    UTF_16LE.decode(data, DecoderTrap::Replace)
+        .unwrap_or_default()
+        .trim_end_matches('\u{0}')
+        .to_string()
+}
@@ -0,0 +1,486 @@
+//! Microsoft Virtual Hard Disk v2 (VHDX) images. Fixed, dynamic and
+//! differencing disks are read as a single Read + Seek stream of the virtual
+//! disk. The log is not replayed.
+//!
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::all::UTF_16LE;
+use encoding::{DecoderTrap, Encoding};
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::{find_parent_path, open_image_at_depth, read_parent, read_within_file, seek_position, BoxedImage};
+
+
+const VHDX_SIGNATURE: &[u8; 8] = b"vhdxfile";
+const VHDX_HEADER_SIGNATURE: &[u8; 4] = b"head";
+const VHDX_REGION_SIGNATURE: &[u8; 4] = b"regi";
+const VHDX_METADATA_SIGNATURE: &[u8; 8] = b"metadata";
+const VHDX_HEADER_OFFSETS: [u64; 2] = [0x1_0000, 0x2_0000];
+const VHDX_REGION_TABLE_OFFSETS: [u64; 2] = [0x3_0000, 0x4_0000];
+const SIZE_VHDX_HEADER: usize = 4096;
+const SIZE_VHDX_REGION_TABLE: usize = 0x1_0000;
+const SIZE_VHDX_SECTOR_BITMAP: usize = 0x10_0000;
+const GUID_BAT_REGION: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+const GUID_METADATA_REGION: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+const GUID_FILE_PARAMETERS: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+const GUID_VIRTUAL_DISK_SIZE: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+const GUID_LOGICAL_SECTOR_SIZE: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+const GUID_PARENT_LOCATOR: &str = "A8D35F2D-B30B-454D-ABF7-D3D84834AB0C";
+const FILE_PARAMETERS_HAS_PARENT: u32 = 0x02;
+// Each sector bitmap block covers 2^23 sectors
+const SECTORS_PER_BITMAP_BLOCK: u64 = 1 << 23;
+const BAT_STATE_MASK: u64 = 0x07;
+const BAT_OFFSET_MASK: u64 = !0xF_FFFF;
+const PAYLOAD_BLOCK_NOT_PRESENT: u64 = 0;
+const PAYLOAD_BLOCK_UNDEFINED: u64 = 1;
+const PAYLOAD_BLOCK_ZERO: u64 = 2;
+const PAYLOAD_BLOCK_UNMAPPED: u64 = 3;
+const PAYLOAD_BLOCK_FULLY_PRESENT: u64 = 6;
+const PAYLOAD_BLOCK_PARTIALLY_PRESENT: u64 = 7;
+const SECTOR_BITMAP_BLOCK_PRESENT: u64 = 6;
+// Parent locator keys, in the order they are tried
+const PARENT_LOCATOR_KEYS: [&str; 3] = ["relative_path", "absolute_win32_path", "volume_path"];
+
+
+/// Check if the handle starts with the VHDX file identifier.
+pub fn is_vhdx<T: ReadSeek>(handle: &mut T) -> Result<bool, UsnError> {
+    let mut signature = [0u8; 8];
+    handle.seek(SeekFrom::Start(0))?;
+
+    let is_vhdx = match handle.read_exact(&mut signature) {
+        Ok(_) => &signature == VHDX_SIGNATURE,
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => false,
+        Err(error) => return Err(UsnError::from(error))
+    };
+
+    handle.seek(SeekFrom::Start(0))?;
+    Ok(is_vhdx)
+}
+
+
+/// The values from the metadata region needed to read the disk.
+#[derive(Debug, Clone)]
+pub struct VhdxMetadata {
+    pub block_size: u64,
+    pub has_parent: bool,
+    pub virtual_disk_size: u64,
+    pub logical_sector_size: u64,
+    /// Parent locator key/value pairs of a differencing disk.
+    pub parent_locator: Vec<(String, String)>,
+}
+
+
+/// A Read + Seek view of the virtual disk in a VHDX. The parent of a
+/// differencing disk is read for sectors the child does not hold.
+///
+pub struct VhdxImage<T: ReadSeek> {
+    handle: T,
+    metadata: VhdxMetadata,
+    chunk_ratio: u64,
+    bat: Vec<u64>,
+    parent: Option<BoxedImage>,
+    bitmap_cache: Option<(usize, Vec<u8>)>,
+    position: u64,
+}
+
+impl VhdxImage<File> {
+    /// Open a VHDX. The parents of a differencing disk are found through its
+    /// parent locator and opened as well.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, UsnError> {
+        Self::open_at_depth(path.as_ref(), 0)
+    }
+
+    pub(crate) fn open_at_depth(path: &Path, depth: usize) -> Result<Self, UsnError> {
+        let image = VhdxImage::new(File::open(path)?)?;
+        if !image.metadata.has_parent {
+            return Ok(image);
+        }
+
+        let locations = image.get_parent_locations();
+        let parent_path = find_parent_path(path, &locations).ok_or_else(|| {
+            UsnError::invalid_image(format!(
+                "Parent of differencing disk {} not found. Tried: {}",
+                path.display(), locations.join(", ")
+            ))
+        })?;
+        debug!("opening parent {} of {}", parent_path.display(), path.display());
+
+        let parent = open_image_at_depth(&parent_path, depth + 1)?;
+        Ok(image.with_parent(parent))
+    }
+}
+
+impl<T: ReadSeek> VhdxImage<T> {
+    /// Read the headers, region table, metadata and block allocation table.
+    /// The parent of a differencing disk has to be attached with `with_parent`.
+    pub fn new(mut handle: T) -> Result<Self, UsnError> {
+        if !is_vhdx(&mut handle)? {
+            return Err(UsnError::invalid_image("Not a VHDX file.".to_string()));
+        }
+
+        let header = read_current_header(&mut handle)?;
+        let log_guid = &header[48..64];
+        if log_guid.iter().any(|byte| *byte != 0) {
+            warn!("VHDX has a log that has not been replayed, recent writes may be missing");
+        }
+
+        let regions = read_region_table(&mut handle)?;
+        let find_region = |guid: &str| {
+            let guid = guid_bytes(guid);
+            regions.iter()
+                .find(|(region_guid, _, _)| *region_guid == guid)
+                .map(|(_, offset, length)| (*offset, *length))
+        };
+        let (bat_offset, bat_length) = find_region(GUID_BAT_REGION).ok_or_else(|| {
+            UsnError::invalid_image("VHDX has no BAT region.".to_string())
+        })?;
+        let (metadata_offset, metadata_length) = find_region(GUID_METADATA_REGION).ok_or_else(|| {
+            UsnError::invalid_image("VHDX has no metadata region.".to_string())
+        })?;
+
+        let metadata = read_metadata(&mut handle, metadata_offset, metadata_length)?;
+        if metadata.block_size == 0 || metadata.logical_sector_size == 0 {
+            return Err(UsnError::invalid_image(
+                "VHDX block size or logical sector size is 0.".to_string()
+            ));
+        }
+        let chunk_ratio = SECTORS_PER_BITMAP_BLOCK * metadata.logical_sector_size / metadata.block_size;
+        if chunk_ratio == 0 {
+            return Err(UsnError::invalid_image(
+                format!("VHDX block size {} is too large.", metadata.block_size)
+            ));
+        }
+
+        let table = read_within_file(&mut handle, bat_offset, bat_length, "VHDX BAT")?;
+        let bat = table.chunks(8).map(LittleEndian::read_u64).collect();
+
+        Ok(VhdxImage {
+            handle,
+            metadata,
+            chunk_ratio,
+            bat,
+            parent: None,
+            bitmap_cache: None,
+            position: 0
+        })
+    }
+
+    /// Attach the parent disk of a differencing disk.
+    pub fn with_parent(mut self, parent: BoxedImage) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn get_metadata(&self) -> &VhdxMetadata {
+        &self.metadata
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.metadata.virtual_disk_size
+    }
+
+    /// The parent paths stored in the parent locator, in the order they
+    /// should be tried.
+    pub fn get_parent_locations(&self) -> Vec<String> {
+        PARENT_LOCATOR_KEYS.iter()
+            .filter_map(|key| {
+                self.metadata.parent_locator.iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value.clone())
+            })
+            .collect()
+    }
+
+    /// Payload BAT entries are interleaved with a sector bitmap entry after
+    /// every `chunk_ratio` payload entries.
+    fn payload_entry(&self, block: u64) -> u64 {
+        let index = block + block / self.chunk_ratio;
+        self.bat.get(index as usize).cloned().unwrap_or(0)
+    }
+
+    fn sector_bitmap_entry(&self, chunk: u64) -> u64 {
+        let index = chunk * (self.chunk_ratio + 1) + self.chunk_ratio;
+        self.bat.get(index as usize).cloned().unwrap_or(0)
+    }
+
+    /// Read from a partially present block, where a sector bitmap tells which
+    /// sectors are in this file.
+    fn read_partial(&mut self, block: u64, block_offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.metadata.logical_sector_size;
+        let chunk = block / self.chunk_ratio;
+        let bitmap_entry = self.sector_bitmap_entry(chunk);
+        let disk_offset = block * self.metadata.block_size + block_offset;
+
+        if bitmap_entry & BAT_STATE_MASK != SECTOR_BITMAP_BLOCK_PRESENT {
+            read_parent(&mut self.parent, disk_offset, buf)?;
+            return Ok(buf.len());
+        }
+
+        let cached = matches!(self.bitmap_cache, Some((cached_chunk, _)) if cached_chunk == chunk as usize);
+        if !cached {
+            let mut bitmap = vec![0u8; SIZE_VHDX_SECTOR_BITMAP];
+            self.handle.seek(SeekFrom::Start(bitmap_entry & BAT_OFFSET_MASK))?;
+            self.handle.read_exact(&mut bitmap)?;
+            self.bitmap_cache = Some((chunk as usize, bitmap));
+        }
+        let bitmap = match self.bitmap_cache {
+            Some((_, ref bitmap)) => bitmap,
+            None => unreachable!("bitmap was just cached")
+        };
+
+        // Read the run of sectors that are all in this file or all in the parent.
+        // Bits are least significant first.
+        let chunk_start_sector = chunk * SECTORS_PER_BITMAP_BLOCK;
+        let is_present = |offset: u64| {
+            let sector = offset / sector_size - chunk_start_sector;
+            bitmap[(sector / 8) as usize] & (1 << (sector % 8)) != 0
+        };
+        let present = is_present(disk_offset);
+        let mut run_end = (disk_offset / sector_size + 1) * sector_size;
+        while run_end < disk_offset + buf.len() as u64 && is_present(run_end) == present {
+            run_end += sector_size;
+        }
+        let to_read = ((run_end - disk_offset) as usize).min(buf.len());
+
+        if present {
+            let payload_offset = self.payload_entry(block) & BAT_OFFSET_MASK;
+            self.handle.seek(SeekFrom::Start(payload_offset + block_offset))?;
+            self.handle.read_exact(&mut buf[..to_read])?;
+        } else {
+            read_parent(&mut self.parent, disk_offset, &mut buf[..to_read])?;
+        }
+
+        Ok(to_read)
+    }
+}
+
+impl<T: ReadSeek> Read for VhdxImage<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.metadata.virtual_disk_size;
+        if self.position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.metadata.block_size;
+        let block = self.position / block_size;
+        let block_offset = self.position % block_size;
+        let to_read = ((block_size - block_offset).min(size - self.position) as usize).min(buf.len());
+
+        let entry = self.payload_entry(block);
+        let bytes_read = match entry & BAT_STATE_MASK {
+            PAYLOAD_BLOCK_FULLY_PRESENT => {
+                self.handle.seek(SeekFrom::Start((entry & BAT_OFFSET_MASK) + block_offset))?;
+                self.handle.read_exact(&mut buf[..to_read])?;
+                to_read
+            },
+            PAYLOAD_BLOCK_PARTIALLY_PRESENT if self.metadata.has_parent => {
+                self.read_partial(block, block_offset, &mut buf[..to_read])?
+            },
+            // Only blocks that were never written come from the parent of a
+            // differencing disk. Zeroed and unmapped blocks read as zeros.
+            PAYLOAD_BLOCK_NOT_PRESENT if self.metadata.has_parent => {
+                read_parent(&mut self.parent, self.position, &mut buf[..to_read])?;
+                to_read
+            },
+            PAYLOAD_BLOCK_NOT_PRESENT | PAYLOAD_BLOCK_UNDEFINED | PAYLOAD_BLOCK_ZERO | PAYLOAD_BLOCK_UNMAPPED => {
+                for byte in buf[..to_read].iter_mut() {
+                    *byte = 0;
+                }
+                to_read
+            },
+            state => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("VHDX block {} has an invalid BAT state {}", block, state)
+            ))
+        };
+
+        self.position += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<T: ReadSeek> Seek for VhdxImage<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(pos, self.position, || Ok(self.metadata.virtual_disk_size))?;
+        Ok(self.position)
+    }
+}
+
+
+/// Convert a GUID string to its on disk form. The first three fields are
+/// little endian.
+fn guid_bytes(guid: &str) -> [u8; 16] {
+    let digits: Vec<u8> = guid.split('-')
+        .flat_map(|part| {
+            (0..part.len()).step_by(2)
+                .map(|i| u8::from_str_radix(&part[i..i + 2], 16).unwrap_or(0))
+                .collect::<Vec<u8>>()
+        })
+        .collect();
+
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digits);
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x82F6_3B78,
+                _ => crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Check the CRC-32C of a structure whose checksum is at offset 4.
+fn has_valid_checksum(buffer: &[u8]) -> bool {
+    let stored = LittleEndian::read_u32(&buffer[4..8]);
+    let mut copy = buffer.to_vec();
+    copy[4..8].copy_from_slice(&[0; 4]);
+    crc32c(&copy) == stored
+}
+
+/// Of the two headers, the valid one with the highest sequence number is current.
+fn read_current_header<T: ReadSeek>(handle: &mut T) -> Result<Vec<u8>, UsnError> {
+    let mut current: Option<(u64, Vec<u8>)> = None;
+
+    for offset in VHDX_HEADER_OFFSETS.iter() {
+        let mut header = vec![0u8; SIZE_VHDX_HEADER];
+        handle.seek(SeekFrom::Start(*offset))?;
+        handle.read_exact(&mut header)?;
+
+        if &header[0..4] != VHDX_HEADER_SIGNATURE || !has_valid_checksum(&header) {
+            warn!("VHDX header at {} is not valid", offset);
+            continue;
+        }
+
+        let sequence_number = LittleEndian::read_u64(&header[8..16]);
+        match current {
+            Some((current_sequence, _)) if current_sequence >= sequence_number => {},
+            _ => current = Some((sequence_number, header))
+        }
+    }
+
+    current.map(|(_, header)| header).ok_or_else(|| {
+        UsnError::invalid_image("VHDX has no valid header.".to_string())
+    })
+}
+
+/// Read the region table entries as (GUID, file offset, length).
+fn read_region_table<T: ReadSeek>(handle: &mut T) -> Result<Vec<([u8; 16], u64, u64)>, UsnError> {
+    for offset in VHDX_REGION_TABLE_OFFSETS.iter() {
+        let mut table = vec![0u8; SIZE_VHDX_REGION_TABLE];
+        handle.seek(SeekFrom::Start(*offset))?;
+        handle.read_exact(&mut table)?;
+
+        if &table[0..4] != VHDX_REGION_SIGNATURE || !has_valid_checksum(&table) {
+            warn!("VHDX region table at {} is not valid", offset);
+            continue;
+        }
+
+        let entry_count = (LittleEndian::read_u32(&table[8..12]) as usize).min((SIZE_VHDX_REGION_TABLE - 16) / 32);
+        let regions = table[16..16 + entry_count * 32].chunks(32)
+            .map(|entry| {
+                let mut guid = [0u8; 16];
+                guid.copy_from_slice(&entry[0..16]);
+                (guid, LittleEndian::read_u64(&entry[16..24]), u64::from(LittleEndian::read_u32(&entry[24..28])))
+            })
+            .collect();
+        return Ok(regions);
+    }
+
+    Err(UsnError::invalid_image("VHDX has no valid region table.".to_string()))
+}
+
+fn read_metadata<T: ReadSeek>(handle: &mut T, offset: u64, length: u64) -> Result<VhdxMetadata, UsnError> {
+    let region = read_within_file(handle, offset, length, "VHDX metadata region")?;
+    if region.len() < 32 || &region[0..8] != VHDX_METADATA_SIGNATURE {
+        return Err(UsnError::invalid_image("VHDX metadata table not found.".to_string()));
+    }
+
+    let entry_count = LittleEndian::read_u16(&region[10..12]) as usize;
+    let mut items: Vec<([u8; 16], &[u8])> = Vec::new();
+    for i in 0..entry_count {
+        let entry_offset = 32 + i * 32;
+        if entry_offset + 32 > region.len() {
+            break;
+        }
+        let entry = &region[entry_offset..entry_offset + 32];
+        let item_offset = LittleEndian::read_u32(&entry[16..20]) as usize;
+        let item_length = LittleEndian::read_u32(&entry[20..24]) as usize;
+        if item_offset + item_length > region.len() {
+            return Err(UsnError::invalid_image(
+                format!("VHDX metadata item {} is outside the metadata region.", i)
+            ));
+        }
+
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&entry[0..16]);
+        items.push((guid, &region[item_offset..item_offset + item_length]));
+    }
+
+    let find_item = |guid: &str, minimum_length: usize| {
+        let guid = guid_bytes(guid);
+        items.iter()
+            .find(|(item_guid, item)| *item_guid == guid && item.len() >= minimum_length)
+            .map(|(_, item)| *item)
+    };
+    let missing = |name: &str| UsnError::invalid_image(format!("VHDX metadata has no {}.", name));
+
+    let file_parameters = find_item(GUID_FILE_PARAMETERS, 8).ok_or_else(|| missing("file parameters"))?;
+    let virtual_disk_size = find_item(GUID_VIRTUAL_DISK_SIZE, 8).ok_or_else(|| missing("virtual disk size"))?;
+    let logical_sector_size = find_item(GUID_LOGICAL_SECTOR_SIZE, 4).ok_or_else(|| missing("logical sector size"))?;
+    let has_parent = LittleEndian::read_u32(&file_parameters[4..8]) & FILE_PARAMETERS_HAS_PARENT != 0;
+
+    let parent_locator = match (has_parent, find_item(GUID_PARENT_LOCATOR, 20)) {
+        (true, Some(item)) => read_parent_locator(item),
+        _ => Vec::new()
+    };
+
+    Ok(VhdxMetadata {
+        block_size: u64::from(LittleEndian::read_u32(&file_parameters[0..4])),
+        has_parent,
+        virtual_disk_size: LittleEndian::read_u64(&virtual_disk_size[0..8]),
+        logical_sector_size: u64::from(LittleEndian::read_u32(&logical_sector_size[0..4])),
+        parent_locator
+    })
+}
+
+/// Key/value entries follow a 20 byte header. Offsets are relative to the
+/// start of the item and the strings are UTF-16.
+fn read_parent_locator(item: &[u8]) -> Vec<(String, String)> {
+    let count = LittleEndian::read_u16(&item[18..20]) as usize;
+    let decode = |offset: usize, length: usize| {
+        item.get(offset..offset + length)
+            .and_then(|bytes| UTF_16LE.decode(bytes, DecoderTrap::Replace).ok())
+    };
+
+    let mut pairs = Vec::new();
+    for i in 0..count {
+        let entry = match item.get(20 + i * 12..32 + i * 12) {
+            Some(entry) => entry,
+            None => break
+        };
+        let key_offset = LittleEndian::read_u32(&entry[0..4]) as usize;
+        let value_offset = LittleEndian::read_u32(&entry[4..8]) as usize;
+        let key_length = LittleEndian::read_u16(&entry[8..10]) as usize;
+        let value_length = LittleEndian::read_u16(&entry[10..12]) as usize;
+
+        if let (Some(key), Some(value)) = (decode(key_offset, key_length), decode(value_offset, value_length)) {
+            pairs.push((key, value));
+        }
+    }
+
+    pairs
+}
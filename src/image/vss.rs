@@ -0,0 +1,378 @@
+//! Volume Shadow Copies (VSS) of NTFS volumes. The catalog and the block lists
+//! of each store are read from the volume, and every snapshot is exposed as a
+//! Read + Seek view of the volume as it was when the snapshot was taken.
+//!
+//! Blocks a snapshot did not preserve are read from newer stores and then the
+//! live volume. The store bitmaps are not applied, so blocks that were free when
+//! the snapshot was taken read as their current contents rather than zeros.
+//!
+use std::io;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Utc};
+use crate::ReadSeek;
+use crate::error::UsnError;
+use crate::image::{seek_position, SharedHandle};
+use crate::utils::u64_to_datetime;
+
+
+const VSS_IDENTIFIER: [u8; 16] = [
+    0x6B, 0x87, 0x08, 0x38, 0x76, 0xC1, 0x48, 0x4E,
+    0xB7, 0xAE, 0x04, 0x04, 0x6E, 0x6C, 0xC7, 0x52
+];
+const VSS_VOLUME_HEADER_OFFSET: u64 = 0x1E00;
+const SIZE_VSS_BLOCK: u64 = 0x4000;
+const SIZE_VSS_BLOCK_HEADER: usize = 128;
+const SIZE_CATALOG_ENTRY: usize = 128;
+const SIZE_BLOCK_DESCRIPTOR: usize = 32;
+const SIZE_SECTOR: u64 = 512;
+const CATALOG_ENTRY_STORE_INFO: u64 = 2;
+const CATALOG_ENTRY_STORE_LOCATION: u64 = 3;
+const BLOCK_FLAG_FORWARDER: u32 = 0x1;
+const BLOCK_FLAG_OVERLAY: u32 = 0x2;
+const BLOCK_FLAG_NOT_USED: u32 = 0x4;
+// Guard against catalogs and block lists that loop
+const MAX_LIST_BLOCKS: usize = 1 << 20;
+
+
+/// Check if the NTFS volume at `volume_offset` has a VSS volume header.
+pub fn has_vss<T: ReadSeek>(handle: &mut T, volume_offset: u64) -> Result<bool, UsnError> {
+    let mut header = [0u8; 16];
+    handle.seek(SeekFrom::Start(volume_offset + VSS_VOLUME_HEADER_OFFSET))?;
+    match handle.read_exact(&mut header) {
+        Ok(_) => Ok(header == VSS_IDENTIFIER),
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(UsnError::from(error))
+    }
+}
+
+
+/// The catalog information of a shadow copy store.
+#[derive(Debug, Clone)]
+pub struct VssStoreInfo {
+    pub store_id: String,
+    pub shadow_copy_id: String,
+    pub shadow_copy_set_id: String,
+    pub creation_time: DateTime<Utc>,
+    pub volume_size: u64,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct BlockDescriptor {
+    relative_offset: u64,
+    store_offset: u64,
+    forwarder: bool,
+    bitmap: u32,
+}
+
+
+#[derive(Debug)]
+struct VssStore {
+    info: VssStoreInfo,
+    // Keyed by the original offset of the block in the volume
+    blocks: HashMap<u64, BlockDescriptor>,
+    overlays: HashMap<u64, Vec<BlockDescriptor>>,
+}
+
+
+#[derive(Default)]
+struct CatalogStore {
+    volume_size: u64,
+    creation_time: u64,
+    block_list_offset: Option<u64>,
+    store_header_offset: Option<u64>,
+}
+
+
+/// The shadow copy stores of an NTFS volume, ordered from oldest to newest.
+///
+pub struct VssVolume<T: ReadSeek> {
+    handle: SharedHandle<T>,
+    volume_offset: u64,
+    stores: Arc<Vec<VssStore>>,
+}
+
+impl<T: ReadSeek> VssVolume<T> {
+    /// Read the catalog of the volume at `volume_offset`. Returns `None` if
+    /// the volume has no VSS volume header.
+    pub fn open(handle: SharedHandle<T>, volume_offset: u64) -> Result<Option<Self>, UsnError> {
+        let stores = {
+            let mut guard = handle.lock().map_err(|_| UsnError::invalid_image(
+                "image handle lock poisoned".to_string()
+            ))?;
+            let image = &mut *guard;
+
+            if !has_vss(image, volume_offset)? {
+                return Ok(None);
+            }
+
+            let mut header = [0u8; 64];
+            read_at(image, volume_offset + VSS_VOLUME_HEADER_OFFSET, &mut header)?;
+            let catalog_offset = LittleEndian::read_u64(&header[48..56]);
+
+            let mut stores = Vec::new();
+            if catalog_offset != 0 {
+                for (store_id, entry) in read_catalog(image, volume_offset, catalog_offset)? {
+                    match read_store(image, volume_offset, store_id, entry) {
+                        Ok(store) => stores.push(store),
+                        Err(error) => warn!("skipping shadow copy store {}: {}", format_guid(&store_id), error)
+                    }
+                }
+            }
+
+            stores.sort_by_key(|store| store.info.creation_time);
+            stores
+        };
+
+        Ok(Some(VssVolume {
+            handle,
+            volume_offset,
+            stores: Arc::new(stores)
+        }))
+    }
+
+    pub fn get_snapshot_count(&self) -> usize {
+        self.stores.len()
+    }
+
+    pub fn get_store_info(&self, index: usize) -> Option<&VssStoreInfo> {
+        self.stores.get(index).map(|store| &store.info)
+    }
+
+    /// A view of the volume as it was when the snapshot at `index` was taken.
+    pub fn snapshot(&self, index: usize) -> Option<VssSnapshot<T>> {
+        if index >= self.stores.len() {
+            return None;
+        }
+
+        Some(VssSnapshot {
+            handle: self.handle.clone(),
+            volume_offset: self.volume_offset,
+            stores: self.stores.clone(),
+            index,
+            position: 0
+        })
+    }
+}
+
+
+/// A Read + Seek view of a volume shadow copy.
+///
+pub struct VssSnapshot<T: ReadSeek> {
+    handle: SharedHandle<T>,
+    volume_offset: u64,
+    stores: Arc<Vec<VssStore>>,
+    index: usize,
+    position: u64,
+}
+
+impl<T: ReadSeek> VssSnapshot<T> {
+    pub fn get_info(&self) -> &VssStoreInfo {
+        &self.stores[self.index].info
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.get_info().volume_size
+    }
+
+    /// The volume offset holding the snapshot's copy of the block at
+    /// `block_offset`. Stores only hold blocks that changed after they were
+    /// taken, so newer stores are searched before falling back to the volume.
+    fn resolve(&self, block_offset: u64) -> u64 {
+        let mut offset = block_offset;
+        for store in &self.stores[self.index..] {
+            if let Some(descriptor) = store.blocks.get(&offset) {
+                if descriptor.forwarder {
+                    offset = descriptor.relative_offset;
+                    continue;
+                }
+                return descriptor.store_offset;
+            }
+        }
+        offset
+    }
+}
+
+impl<T: ReadSeek> Read for VssSnapshot<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.get_size();
+        if self.position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_offset = self.position - self.position % SIZE_VSS_BLOCK;
+        let in_block = self.position - block_offset;
+        let to_read = (SIZE_VSS_BLOCK - in_block)
+            .min(size - self.position)
+            .min(buf.len() as u64) as usize;
+        let source_offset = self.resolve(block_offset);
+
+        let mut handle = self.handle.lock().map_err(|_| io::Error::other("image handle lock poisoned"))?;
+        read_at(&mut *handle, self.volume_offset + source_offset + in_block, &mut buf[..to_read])?;
+
+        // Overlays replace single sectors of the block
+        if let Some(overlays) = self.stores[self.index].overlays.get(&block_offset) {
+            let read_end = in_block + to_read as u64;
+            for overlay in overlays {
+                for sector in (0..32u64).filter(|sector| overlay.bitmap & (1 << sector) != 0) {
+                    let start = (sector * SIZE_SECTOR).max(in_block);
+                    let end = ((sector + 1) * SIZE_SECTOR).min(read_end);
+                    if start >= end {
+                        continue;
+                    }
+
+                    let target = &mut buf[(start - in_block) as usize..(end - in_block) as usize];
+                    read_at(&mut *handle, self.volume_offset + overlay.store_offset + start, target)?;
+                }
+            }
+        }
+
+        self.position += to_read as u64;
+        Ok(to_read)
+    }
+}
+
+impl<T: ReadSeek> Seek for VssSnapshot<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek_position(pos, self.position, || Ok(self.get_size()))?;
+        Ok(self.position)
+    }
+}
+
+
+fn read_at<T: ReadSeek + ?Sized>(handle: &mut T, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    handle.seek(SeekFrom::Start(offset))?;
+    handle.read_exact(buf)
+}
+
+/// Read a 16 KiB VSS block and check its header.
+fn read_block<T: ReadSeek>(handle: &mut T, volume_offset: u64, offset: u64) -> Result<Vec<u8>, UsnError> {
+    let mut block = vec![0u8; SIZE_VSS_BLOCK as usize];
+    read_at(handle, volume_offset + offset, &mut block)?;
+
+    if block[0..16] != VSS_IDENTIFIER {
+        return Err(UsnError::invalid_image(
+            format!("no VSS block header at volume offset {}", offset)
+        ));
+    }
+
+    Ok(block)
+}
+
+/// Read the catalog entries of each store, in catalog order.
+fn read_catalog<T: ReadSeek>(handle: &mut T, volume_offset: u64, catalog_offset: u64) -> Result<Vec<([u8; 16], CatalogStore)>, UsnError> {
+    let mut stores: Vec<([u8; 16], CatalogStore)> = Vec::new();
+    let mut offset = catalog_offset;
+
+    for _ in 0..MAX_LIST_BLOCKS {
+        let block = read_block(handle, volume_offset, offset)?;
+
+        for entry in block[SIZE_VSS_BLOCK_HEADER..].chunks_exact(SIZE_CATALOG_ENTRY) {
+            let entry_type = LittleEndian::read_u64(&entry[0..8]);
+            if entry_type != CATALOG_ENTRY_STORE_INFO && entry_type != CATALOG_ENTRY_STORE_LOCATION {
+                continue;
+            }
+
+            let mut store_id = [0u8; 16];
+            store_id.copy_from_slice(&entry[16..32]);
+            let index = match stores.iter().position(|(id, _)| *id == store_id) {
+                Some(index) => index,
+                None => {
+                    stores.push((store_id, CatalogStore::default()));
+                    stores.len() - 1
+                }
+            };
+            let store = &mut stores[index].1;
+
+            if entry_type == CATALOG_ENTRY_STORE_INFO {
+                store.volume_size = LittleEndian::read_u64(&entry[8..16]);
+                store.creation_time = LittleEndian::read_u64(&entry[48..56]);
+            } else {
+                store.block_list_offset = Some(LittleEndian::read_u64(&entry[8..16]));
+                store.store_header_offset = Some(LittleEndian::read_u64(&entry[32..40]));
+            }
+        }
+
+        offset = LittleEndian::read_u64(&block[40..48]);
+        if offset == 0 {
+            return Ok(stores);
+        }
+    }
+
+    Err(UsnError::invalid_image("the VSS catalog does not end".to_string()))
+}
+
+fn read_store<T: ReadSeek>(handle: &mut T, volume_offset: u64, store_id: [u8; 16], entry: CatalogStore) -> Result<VssStore, UsnError> {
+    let (block_list_offset, store_header_offset) = match (entry.block_list_offset, entry.store_header_offset) {
+        (Some(block_list), Some(header)) => (block_list, header),
+        _ => return Err(UsnError::invalid_image("the catalog has no store location".to_string()))
+    };
+
+    let header = read_block(handle, volume_offset, store_header_offset)?;
+    let store_information = &header[SIZE_VSS_BLOCK_HEADER..];
+
+    let mut blocks = HashMap::new();
+    let mut overlays: HashMap<u64, Vec<BlockDescriptor>> = HashMap::new();
+    let mut offset = block_list_offset;
+
+    for _ in 0..MAX_LIST_BLOCKS {
+        let block = read_block(handle, volume_offset, offset)?;
+
+        for raw_descriptor in block[SIZE_VSS_BLOCK_HEADER..].chunks_exact(SIZE_BLOCK_DESCRIPTOR) {
+            if raw_descriptor.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+
+            let original_offset = LittleEndian::read_u64(&raw_descriptor[0..8]);
+            let flags = LittleEndian::read_u32(&raw_descriptor[24..28]);
+            if flags & BLOCK_FLAG_NOT_USED != 0 {
+                continue;
+            }
+
+            let descriptor = BlockDescriptor {
+                relative_offset: LittleEndian::read_u64(&raw_descriptor[8..16]),
+                store_offset: LittleEndian::read_u64(&raw_descriptor[16..24]),
+                forwarder: flags & BLOCK_FLAG_FORWARDER != 0,
+                bitmap: LittleEndian::read_u32(&raw_descriptor[28..32])
+            };
+
+            if flags & BLOCK_FLAG_OVERLAY != 0 {
+                overlays.entry(original_offset).or_default().push(descriptor);
+            } else {
+                blocks.insert(original_offset, descriptor);
+            }
+        }
+
+        offset = LittleEndian::read_u64(&block[40..48]);
+        if offset == 0 {
+            break;
+        }
+    }
+
+    Ok(VssStore {
+        info: VssStoreInfo {
+            store_id: format_guid(&store_id),
+            shadow_copy_id: format_guid(&store_information[16..32]),
+            shadow_copy_set_id: format_guid(&store_information[32..48]),
+            creation_time: u64_to_datetime(entry.creation_time),
+            volume_size: entry.volume_size
+        },
+        blocks,
+        overlays
+    })
+}
+
+/// Format a little-endian GUID as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{}-{}",
+        LittleEndian::read_u32(&bytes[0..4]),
+        LittleEndian::read_u16(&bytes[4..6]),
+        LittleEndian::read_u16(&bytes[6..8]),
+        hex::encode(&bytes[8..10]),
+        hex::encode(&bytes[10..16])
+    )
+}
@@ -10,14 +10,21 @@ pub mod utils;
 pub mod flags;
 pub mod liveusn;
 pub mod mapping;
+pub mod image;
+pub mod confidence;
+pub mod delta;
+pub mod session;
+pub mod triage;
+pub mod archive;
+pub mod output;
 
 
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek};
 
 pub trait ReadSeek: Read + Seek {
     fn tell(&mut self) -> io::Result<u64> {
-        self.seek(SeekFrom::Current(0))
+        self.stream_position()
     }
 }
 
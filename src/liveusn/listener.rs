@@ -2,10 +2,9 @@ use std::thread;
 use std::fs::File;
 use std::process::exit;
 use std::time::Duration;
-use std::sync::mpsc::Sender;
-use serde_json::value::Value;
 use byteorder::{ByteOrder, LittleEndian};
 use crate::flags;
+use crate::output::RecordSink;
 use crate::record::EntryMeta;
 use crate::liveusn::winfuncs::{
     query_usn_journal,
@@ -17,26 +16,28 @@ use crate::liveusn::live::WindowsLiveNtfs;
 use crate::liveusn::ntfs::ReadUsnJournalData;
 
 
-pub struct UsnVolumeListener {
+/// Writes the entries of a live volume's journal to a sink as they are added,
+/// with their full names. A `Sender<Value>` sink sends them as JSON values.
+pub struct UsnVolumeListener<S: RecordSink> {
     source: String,
     sleep_ms: u64,
     historical_flag: bool,
-    sender: Sender<Value>
+    sink: S
 }
 
-impl UsnVolumeListener {
-    pub fn new(source: String, historical_flag: bool, sender: Sender<Value>) -> Self {
+impl<S: RecordSink> UsnVolumeListener<S> {
+    pub fn new(source: String, historical_flag: bool, sink: S) -> Self {
         let sleep_ms = 100;
 
         UsnVolumeListener {
             source,
             sleep_ms,
             historical_flag,
-            sender
+            sink
         }
     }
 
-    pub fn listen_to_volume(self) -> Result<(), UsnLiveError> {
+    pub fn listen_to_volume(mut self) -> Result<(), UsnLiveError> {
         let live_volume = WindowsLiveNtfs::from_volume_path(
             &self.source
         )?;
@@ -102,7 +103,7 @@ impl UsnVolumeListener {
                         let parent_ref = usn_entry.record.get_parent_reference();
                         let file_attributes = usn_entry.record.get_file_attributes();
 
-                        if file_attributes.contains(flags::FileAttributes::FILE_ATTRIBUTE_DIRECTORY){
+                        if file_attributes.contains(flags::FileAttributes::DIRECTORY){
                             if reason_code.contains(flags::Reason::RENAME_OLD_NAME) {
                                 // We can remove old names from the mapping because we no longer need these.
                                 // On new names, we add the name to the mapping.
@@ -146,32 +147,22 @@ impl UsnVolumeListener {
                             None => "[<unknown>]".to_string()
                         };
 
-                        let mut entry_value = match usn_entry.to_json_value(){
-                            Ok(value) => value,
-                            Err(e) => {
-                                eprintln!("Error serializing entry to json value {:?}: {:?}", usn_entry, e);
-                                continue;
-                            }
-                        };
-
                         let full_file_name = format!("{}/{}", &full_path, &file_name);
 
-                        let map = entry_value.as_object_mut().unwrap();
-                        map.insert(
-                            "full_path".to_string(), 
-                            Value::String(full_file_name)
-                        );
-
-                        match self.sender.send(entry_value) {
+                        match self.sink.write_entry(&usn_entry, Some(&full_file_name)) {
                             Ok(_) => {
                                 record_count += 1;
                             },
                             Err(error) => {
-                                eprintln!("error sending usn entry: {:?}", error);
+                                eprintln!("error writing usn entry: {}", error.message);
                             }
                         }
                     }
 
+                    if let Err(error) = self.sink.flush() {
+                        eprintln!("error writing usn entries: {}", error.message);
+                    }
+
                     record_count
                 },
                 Err(error) => {
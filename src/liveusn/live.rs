@@ -2,7 +2,7 @@ use std::fs::File;
 use std::io::Read;
 use mft::MftEntry;
 use byteorder::{ReadBytesExt, LittleEndian};
-use crate::mapping::FolderMapping;
+use crate::mapping::{EntryMapping, FolderMapping};
 use crate::liveusn::winfuncs;
 use crate::liveusn::error::UsnLiveError;
 use crate::liveusn::ntfs::NtfsVolumeData;
@@ -94,8 +94,8 @@ impl WindowsLiveNtfs {
                     }
 
                     // Get the best name attribute or <NA>
-                    let fn_attr = match entry.find_best_name_attribute() {
-                        Some(fn_attr) => fn_attr,
+                    let entry_map = match EntryMapping::from_mft_entry(&entry) {
+                        Some(entry_map) => entry_map,
                         None => continue
                     };
 
@@ -108,8 +108,8 @@ impl WindowsLiveNtfs {
                     // Add this entry to the folder mapping
                     folder_mapping.add_mapping(
                         entry_reference,
-                        fn_attr.name,
-                        fn_attr.parent
+                        entry_map.name,
+                        entry_map.parent
                     );
                 },
                 Err(error) => {
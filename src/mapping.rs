@@ -1,12 +1,14 @@
 use std::io;
 use std::fmt;
-use mft::MftParser;
+use mft::{MftEntry, MftParser};
 use crate::ReadSeek;
 use serde::Serialize;
 use lru::LruCache;
 use std::collections::HashMap;
 use winstructs::ntfs::mft_reference::MftReference;
 use serde::ser::{Serializer, SerializeMap};
+use crate::image::ntfs::{read_attributes, AttributeForm, FileNameValue, ATTRIBUTE_TYPE_FILE_NAME};
+use crate::utils::decode_utf16_name;
 
 
 #[derive(Serialize, Debug)]
@@ -15,6 +17,44 @@ pub struct EntryMapping {
     pub parent: MftReference,
 }
 
+impl EntryMapping {
+    /// The best name of an MFT entry and its parent. Invalid UTF-16 in the
+    /// name is escaped as in the file names of records.
+    pub fn from_mft_entry(e: &MftEntry) -> Option<EntryMapping> {
+        let file_name_attr = e.find_best_name_attribute()?;
+
+        // The mft crate drops invalid code units, which leaves the name short
+        let decoded_length = file_name_attr.name.encode_utf16().count();
+        let name = match decoded_length == usize::from(file_name_attr.name_length) {
+            true => file_name_attr.name,
+            false => raw_file_name(e, file_name_attr.parent, file_name_attr.namespace as u8)
+                .map(|name| decode_utf16_name(name).0)
+                .unwrap_or(file_name_attr.name)
+        };
+
+        Some(EntryMapping {
+            name,
+            parent: file_name_attr.parent
+        })
+    }
+}
+
+/// Find the name bytes of the resident $FILE_NAME attribute with the given
+/// parent and namespace.
+fn raw_file_name(e: &MftEntry, parent: MftReference, namespace: u8) -> Option<&[u8]> {
+    read_attributes(e).into_iter()
+        .filter_map(|attribute| match (attribute.type_code, attribute.form) {
+            (ATTRIBUTE_TYPE_FILE_NAME, AttributeForm::Resident { value }) => FileNameValue::from_value(value),
+            _ => None
+        })
+        .find(|file_name| {
+            file_name.namespace == namespace &&
+            file_name.parent_entry == parent.entry &&
+            file_name.parent_sequence == parent.sequence
+        })
+        .map(|file_name| file_name.name)
+}
+
 
 pub struct FolderMapping {
     pub mapping: HashMap<MftReference, EntryMapping>,
@@ -27,6 +67,12 @@ impl fmt::Debug for FolderMapping {
     }
 }
 
+impl Default for FolderMapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FolderMapping {
     pub fn new() -> Self {
         let mapping: HashMap<MftReference, EntryMapping> = HashMap::new();
@@ -60,48 +106,24 @@ impl FolderMapping {
         Ok(folder_mapping)
     }
 
+    /// Build the mapping from an $MFT read through any handle, such as a
+    /// member of a triage archive.
+    pub fn from_mft_read_seek<T: ReadSeek>(handle: T) -> Result<Self, io::Error> {
+        let mut parser = MftParser::from_read_seek(handle, None)
+            .map_err(|error| io::Error::other(error.to_string()))?;
+
+        let mut folder_mapping = FolderMapping::new();
+        folder_mapping.build_folder_mapping(
+            &mut parser
+        );
+
+        Ok(folder_mapping)
+    }
+
     pub fn build_folder_mapping<T: ReadSeek>(&mut self, mft_parser: &mut MftParser<T>) {
         for entry in mft_parser.iter_entries() {
             match entry {
-                Ok(e) =>  {
-                    if e.is_dir() {
-                        let mut l_entry = e.header.record_number;
-                        let mut l_sequence = e.header.sequence;
-
-                        if !e.is_allocated() {
-                            l_sequence -= 1;
-                        }
-
-                        // if entry is child, set entry and sequence to parent
-                        if e.header.base_reference.entry != 0 {
-                            l_entry = e.header.base_reference.entry;
-                            l_sequence = e.header.base_reference.sequence;
-                        }
-
-                        let file_name_attr = match e.find_best_name_attribute() {
-                            Some(fn_attr) => fn_attr,
-                            None => continue
-                        };
-
-                        let entry_map = EntryMapping{
-                            name: file_name_attr.name,
-                            parent: file_name_attr.parent
-                        };
-
-                        let entry_reference = MftReference::new(
-                            l_entry,
-                            l_sequence
-                        );
-
-                        self.mapping.insert(
-                            entry_reference,
-                            entry_map
-                        );
-                    } 
-                    else {
-                        continue
-                    }
-                }
+                Ok(e) => self.add_mft_entry(&e),
                 Err(err) => {
                     eprintln!("{}", err);
                 }
@@ -109,6 +131,41 @@ impl FolderMapping {
         }
     }
 
+    /// Add an MFT entry to the mapping if it is a directory.
+    pub fn add_mft_entry(&mut self, e: &MftEntry) {
+        if !e.is_dir() {
+            return;
+        }
+
+        let mut l_entry = e.header.record_number;
+        let mut l_sequence = e.header.sequence;
+
+        if !e.is_allocated() {
+            l_sequence = l_sequence.saturating_sub(1);
+        }
+
+        // if entry is child, set entry and sequence to parent
+        if e.header.base_reference.entry != 0 {
+            l_entry = e.header.base_reference.entry;
+            l_sequence = e.header.base_reference.sequence;
+        }
+
+        let entry_map = match EntryMapping::from_mft_entry(e) {
+            Some(entry_map) => entry_map,
+            None => return
+        };
+
+        let entry_reference = MftReference::new(
+            l_entry,
+            l_sequence
+        );
+
+        self.mapping.insert(
+            entry_reference,
+            entry_map
+        );
+    }
+
     pub fn remove_mapping(&mut self, entry_reference: MftReference) {
         self.mapping.remove(
             &entry_reference
@@ -117,8 +174,8 @@ impl FolderMapping {
 
     pub fn add_mapping(&mut self, entry_reference: MftReference, name: String, parent: MftReference) {
         let entry_map = EntryMapping {
-            name: name,
-            parent: parent
+            name,
+            parent
         };
 
         // If there is a cached entry for this reference, we need to remove it
@@ -135,7 +192,7 @@ impl FolderMapping {
 
     fn enumerate_path_queue(&self, lookup_ref: &MftReference, path_queue: &mut Vec<String>) {
         if lookup_ref.entry != 5 {
-            match self.mapping.get(&lookup_ref) {
+            match self.mapping.get(lookup_ref) {
                 Some(folder_map) => {
                     path_queue.push(folder_map.name.clone());
 
@@ -160,7 +217,7 @@ impl FolderMapping {
         
         match self.cache.get_mut(&lookup_ref) {
             Some(full_path) => {
-                return Some(full_path.clone());
+                Some(full_path.clone())
             },
             None => {
                 let mut path_queue: Vec<String> = Vec::new();
@@ -178,7 +235,7 @@ impl FolderMapping {
                     full_path.clone()
                 );
 
-                return Some(full_path);
+                Some(full_path)
             }
         }
     }
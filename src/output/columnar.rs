@@ -0,0 +1,342 @@
+//! Arrow record batches and Parquet files with typed columns. Timestamps are
+//! timestamp[ns] (UTC), the reason is both its u32 value and a list of flag
+//! names, and the file and parent references are structs.
+//!
+use std::io::Write;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use arrow::array::{
+    ArrayRef, BooleanBuilder, ListBuilder, StringBuilder, StructArray, TimestampNanosecondBuilder,
+    UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder
+};
+use arrow::datatypes::{DataType, Field, FieldRef, Fields, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use crate::error::UsnError;
+use crate::flags;
+use crate::output::RecordSink;
+use crate::record::UsnEntry;
+use winstructs::ntfs::mft_reference::MftReference;
+
+
+// Rows buffered by `ParquetWriter::write_entry` before a batch is written
+const DEFAULT_BATCH_SIZE: usize = 65_536;
+const TIMEZONE: &str = "UTC";
+
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Nanosecond, Some(TIMEZONE.into()))
+}
+
+fn reference_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("entry", DataType::UInt64, false),
+        Field::new("sequence", DataType::UInt16, false),
+        Field::new("u128", DataType::Utf8, false),
+    ])
+}
+
+/// The schema of the record batches. Field names match the JSONL and CSV names.
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("usn", DataType::UInt64, false),
+        Field::new("timestamp", timestamp_type(), true),
+        Field::new("file_name", DataType::Utf8, false),
+        Field::new("file_name_hex", DataType::Utf8, true),
+        Field::new("irregular_name", DataType::Boolean, false),
+        Field::new("full_name", DataType::Utf8, true),
+        Field::new("reason", DataType::UInt32, false),
+        Field::new_list("reasons", Field::new("item", DataType::Utf8, true), false),
+        Field::new("file_attributes", DataType::UInt32, false),
+        Field::new_struct("file_reference", reference_fields(), false),
+        Field::new_struct("parent_reference", reference_fields(), false),
+        Field::new("source_info", DataType::UInt32, false),
+        Field::new("security_id", DataType::UInt32, true),
+        Field::new("major_version", DataType::UInt16, false),
+        Field::new("minor_version", DataType::UInt16, false),
+        Field::new("record_length", DataType::UInt32, false),
+        Field::new("meta__source", DataType::Utf8, false),
+        Field::new("meta__offset", DataType::UInt64, false),
+        Field::new("meta__scan_mode", DataType::Utf8, false),
+        Field::new("meta__confidence", DataType::UInt8, true),
+        Field::new("meta__volume", DataType::Utf8, true),
+        Field::new("meta__host", DataType::Utf8, true),
+        Field::new("meta__snapshot_id", DataType::Utf8, true),
+        Field::new("meta__snapshot_created", timestamp_type(), true),
+    ]))
+}
+
+/// Nanoseconds since the epoch, or None outside of 1677 to 2262.
+fn nanoseconds(timestamp: Option<DateTime<Utc>>) -> Option<i64> {
+    timestamp.and_then(|timestamp| timestamp.timestamp_nanos_opt())
+}
+
+
+/// Builds the columns of a reference struct.
+struct ReferenceBuilder {
+    entry: UInt64Builder,
+    sequence: UInt16Builder,
+    id: StringBuilder,
+}
+
+impl ReferenceBuilder {
+    fn new() -> Self {
+        ReferenceBuilder {
+            entry: UInt64Builder::new(),
+            sequence: UInt16Builder::new(),
+            id: StringBuilder::new(),
+        }
+    }
+
+    fn append(&mut self, reference: MftReference, id: u128) {
+        self.entry.append_value(reference.entry);
+        self.sequence.append_value(reference.sequence);
+        self.id.append_value(id.to_string());
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(self.entry.finish()),
+            Arc::new(self.sequence.finish()),
+            Arc::new(self.id.finish()),
+        ];
+        let columns: Vec<(FieldRef, ArrayRef)> = reference_fields().iter()
+            .cloned()
+            .zip(arrays)
+            .collect();
+        Arc::new(StructArray::from(columns))
+    }
+}
+
+
+/// Builds record batches of the `schema` from entries.
+pub struct RecordBatchBuilder {
+    rows: usize,
+    usn: UInt64Builder,
+    timestamp: TimestampNanosecondBuilder,
+    file_name: StringBuilder,
+    file_name_hex: StringBuilder,
+    irregular_name: BooleanBuilder,
+    full_name: StringBuilder,
+    reason: UInt32Builder,
+    reasons: ListBuilder<StringBuilder>,
+    file_attributes: UInt32Builder,
+    file_reference: ReferenceBuilder,
+    parent_reference: ReferenceBuilder,
+    source_info: UInt32Builder,
+    security_id: UInt32Builder,
+    major_version: UInt16Builder,
+    minor_version: UInt16Builder,
+    record_length: UInt32Builder,
+    source: StringBuilder,
+    offset: UInt64Builder,
+    scan_mode: StringBuilder,
+    confidence: UInt8Builder,
+    volume: StringBuilder,
+    host: StringBuilder,
+    snapshot_id: StringBuilder,
+    snapshot_created: TimestampNanosecondBuilder,
+}
+
+impl Default for RecordBatchBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordBatchBuilder {
+    pub fn new() -> Self {
+        RecordBatchBuilder {
+            rows: 0,
+            usn: UInt64Builder::new(),
+            timestamp: TimestampNanosecondBuilder::new().with_timezone(TIMEZONE),
+            file_name: StringBuilder::new(),
+            file_name_hex: StringBuilder::new(),
+            irregular_name: BooleanBuilder::new(),
+            full_name: StringBuilder::new(),
+            reason: UInt32Builder::new(),
+            reasons: ListBuilder::new(StringBuilder::new()),
+            file_attributes: UInt32Builder::new(),
+            file_reference: ReferenceBuilder::new(),
+            parent_reference: ReferenceBuilder::new(),
+            source_info: UInt32Builder::new(),
+            security_id: UInt32Builder::new(),
+            major_version: UInt16Builder::new(),
+            minor_version: UInt16Builder::new(),
+            record_length: UInt32Builder::new(),
+            source: StringBuilder::new(),
+            offset: UInt64Builder::new(),
+            scan_mode: StringBuilder::new(),
+            confidence: UInt8Builder::new(),
+            volume: StringBuilder::new(),
+            host: StringBuilder::new(),
+            snapshot_id: StringBuilder::new(),
+            snapshot_created: TimestampNanosecondBuilder::new().with_timezone(TIMEZONE),
+        }
+    }
+
+    /// The number of rows appended since the last `finish`.
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Append an entry. `full_name` is the path enumerated from a folder mapping, if any.
+    pub fn append(&mut self, entry: &UsnEntry, full_name: Option<&str>) {
+        let record = &entry.record;
+        let meta = &entry.meta;
+        let reason = record.get_reason_code();
+
+        self.usn.append_value(record.get_usn());
+        self.timestamp.append_option(nanoseconds(record.get_timestamp()));
+        self.file_name.append_value(record.get_file_name());
+        self.file_name_hex.append_option(record.get_file_name_hex());
+        self.irregular_name.append_value(record.has_irregular_name());
+        self.full_name.append_option(full_name);
+        self.reason.append_value(reason.bits());
+        for name in flags::flag_names(&reason) {
+            self.reasons.values().append_value(name);
+        }
+        self.reasons.append(true);
+        self.file_attributes.append_value(record.get_file_attributes().bits());
+        self.file_reference.append(record.get_file_reference(), record.get_file_id());
+        self.parent_reference.append(record.get_parent_reference(), record.get_parent_id());
+        self.source_info.append_value(record.get_source_info().bits());
+        self.security_id.append_option(record.get_security_id());
+        self.major_version.append_value(record.get_major_version());
+        self.minor_version.append_value(record.get_minor_version());
+        self.record_length.append_value(record.get_record_length());
+
+        self.source.append_value(&meta.source);
+        self.offset.append_value(meta.offset);
+        self.scan_mode.append_value(format!("{:?}", meta.scan_mode).to_lowercase());
+        self.confidence.append_option(meta.confidence.map(|confidence| confidence.score));
+        self.volume.append_option(meta.volume.as_ref().map(|volume| volume.volume.as_str()));
+        self.host.append_option(meta.volume.as_ref().and_then(|volume| volume.host.as_deref()));
+        self.snapshot_id.append_option(meta.snapshot.as_ref().map(|snapshot| snapshot.id.as_str()));
+        self.snapshot_created.append_option(nanoseconds(meta.snapshot.as_ref().map(|snapshot| snapshot.created)));
+
+        self.rows += 1;
+    }
+
+    /// Build a batch of the rows appended so far, and start over.
+    pub fn finish(&mut self) -> Result<RecordBatch, UsnError> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.usn.finish()),
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.file_name.finish()),
+            Arc::new(self.file_name_hex.finish()),
+            Arc::new(self.irregular_name.finish()),
+            Arc::new(self.full_name.finish()),
+            Arc::new(self.reason.finish()),
+            Arc::new(self.reasons.finish()),
+            Arc::new(self.file_attributes.finish()),
+            self.file_reference.finish(),
+            self.parent_reference.finish(),
+            Arc::new(self.source_info.finish()),
+            Arc::new(self.security_id.finish()),
+            Arc::new(self.major_version.finish()),
+            Arc::new(self.minor_version.finish()),
+            Arc::new(self.record_length.finish()),
+            Arc::new(self.source.finish()),
+            Arc::new(self.offset.finish()),
+            Arc::new(self.scan_mode.finish()),
+            Arc::new(self.confidence.finish()),
+            Arc::new(self.volume.finish()),
+            Arc::new(self.host.finish()),
+            Arc::new(self.snapshot_id.finish()),
+            Arc::new(self.snapshot_created.finish()),
+        ];
+        self.rows = 0;
+
+        Ok(RecordBatch::try_new(schema(), columns)?)
+    }
+}
+
+
+/// Build a record batch of entries, such as a batch from `UsnParser::record_batches`.
+pub fn to_record_batch(entries: &[UsnEntry]) -> Result<RecordBatch, UsnError> {
+    let mut builder = RecordBatchBuilder::new();
+    for entry in entries {
+        builder.append(entry, None);
+    }
+    builder.finish()
+}
+
+
+/// Writes entries to a Parquet file, Snappy compressed. The file is not
+/// complete until `finish` or `close` is called.
+pub struct ParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    builder: RecordBatchBuilder,
+    batch_size: usize,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(writer: W) -> Result<Self, UsnError> {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        Ok(ParquetWriter {
+            writer: ArrowWriter::try_new(writer, schema(), Some(properties))?,
+            builder: RecordBatchBuilder::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        })
+    }
+
+    /// The number of rows `write_entry` buffers before writing them.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Write a record batch, after any buffered entries.
+    pub fn write_batch(&mut self, batch: &RecordBatch) -> Result<(), UsnError> {
+        self.flush()?;
+        Ok(self.writer.write(batch)?)
+    }
+
+    /// Write the buffered entries and the file footer, and return the writer.
+    /// Used instead of `finish`.
+    pub fn close(mut self) -> Result<W, UsnError> {
+        self.flush()?;
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+impl<W: Write + Send> RecordSink for ParquetWriter<W> {
+    /// Buffer an entry, writing the buffer once it holds `batch_size` rows.
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        self.builder.append(entry, full_name);
+        if self.builder.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_entries(&mut self, entries: &[UsnEntry]) -> Result<(), UsnError> {
+        self.write_batch(&to_record_batch(entries)?)
+    }
+
+    /// Write the buffered entries.
+    fn flush(&mut self) -> Result<(), UsnError> {
+        if !self.builder.is_empty() {
+            let batch = self.builder.finish()?;
+            self.writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered entries and the file footer.
+    fn finish(&mut self) -> Result<(), UsnError> {
+        self.flush()?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
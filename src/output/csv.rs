@@ -0,0 +1,304 @@
+//! CSV and TSV output with a header row and RFC 4180 quoting.
+//!
+use std::io;
+use std::io::Write;
+use chrono::SecondsFormat;
+use serde_json::Value;
+use crate::error::UsnError;
+use crate::output::RecordSink;
+use crate::output::options::SerializeOptions;
+use crate::record::UsnEntry;
+
+
+/// A column of delimited output. The order of `Column::all` is the default order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    Usn,
+    Timestamp,
+    FullName,
+    FileName,
+    FileNameHex,
+    IrregularName,
+    Reason,
+    ReasonCode,
+    ReasonDelta,
+    FileAttributes,
+    FileAttributesCode,
+    FileReferenceEntry,
+    FileReferenceSequence,
+    FileReferenceU128,
+    ParentReferenceEntry,
+    ParentReferenceSequence,
+    ParentReferenceU128,
+    SourceInfo,
+    SourceInfoCode,
+    SecurityId,
+    MajorVersion,
+    MinorVersion,
+    RecordLength,
+    Source,
+    Offset,
+    ScanMode,
+    Confidence,
+    Volume,
+    Host,
+    SnapshotId,
+    SnapshotCreated,
+}
+
+impl Column {
+    pub fn all() -> &'static [Column] {
+        &[
+            Column::Usn,
+            Column::Timestamp,
+            Column::FullName,
+            Column::FileName,
+            Column::FileNameHex,
+            Column::IrregularName,
+            Column::Reason,
+            Column::ReasonCode,
+            Column::ReasonDelta,
+            Column::FileAttributes,
+            Column::FileAttributesCode,
+            Column::FileReferenceEntry,
+            Column::FileReferenceSequence,
+            Column::FileReferenceU128,
+            Column::ParentReferenceEntry,
+            Column::ParentReferenceSequence,
+            Column::ParentReferenceU128,
+            Column::SourceInfo,
+            Column::SourceInfoCode,
+            Column::SecurityId,
+            Column::MajorVersion,
+            Column::MinorVersion,
+            Column::RecordLength,
+            Column::Source,
+            Column::Offset,
+            Column::ScanMode,
+            Column::Confidence,
+            Column::Volume,
+            Column::Host,
+            Column::SnapshotId,
+            Column::SnapshotCreated,
+        ]
+    }
+
+    /// The header name, which matches the JSONL field name where there is one.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Usn => "usn",
+            Column::Timestamp => "timestamp",
+            Column::FullName => "full_name",
+            Column::FileName => "file_name",
+            Column::FileNameHex => "file_name_hex",
+            Column::IrregularName => "irregular_name",
+            Column::Reason => "reason",
+            Column::ReasonCode => "reason_code",
+            Column::ReasonDelta => "reason_delta",
+            Column::FileAttributes => "file_attributes",
+            Column::FileAttributesCode => "file_attributes_code",
+            Column::FileReferenceEntry => "file_reference_entry",
+            Column::FileReferenceSequence => "file_reference_sequence",
+            Column::FileReferenceU128 => "file_reference_u128",
+            Column::ParentReferenceEntry => "parent_reference_entry",
+            Column::ParentReferenceSequence => "parent_reference_sequence",
+            Column::ParentReferenceU128 => "parent_reference_u128",
+            Column::SourceInfo => "source_info",
+            Column::SourceInfoCode => "source_info_code",
+            Column::SecurityId => "security_id",
+            Column::MajorVersion => "major_version",
+            Column::MinorVersion => "minor_version",
+            Column::RecordLength => "record_length",
+            Column::Source => "meta__source",
+            Column::Offset => "meta__offset",
+            Column::ScanMode => "meta__scan_mode",
+            Column::Confidence => "meta__confidence",
+            Column::Volume => "meta__volume",
+            Column::Host => "meta__host",
+            Column::SnapshotId => "meta__snapshot_id",
+            Column::SnapshotCreated => "meta__snapshot_created",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Column> {
+        Column::all().iter()
+            .find(|column| column.name() == name)
+            .copied()
+    }
+
+    /// Parse a comma separated list of column names.
+    pub fn parse_list(names: &str) -> Result<Vec<Column>, UsnError> {
+        names.split(',')
+            .map(str::trim)
+            .map(|name| Column::from_name(name).ok_or_else(||
+                UsnError::json_value_error(format!("unknown column: {}", name))
+            ))
+            .collect()
+    }
+
+    /// The value of the column for an entry. Fields a record version does not
+    /// have are empty.
+    fn value(&self, entry: &UsnEntry, full_name: Option<&str>, options: &SerializeOptions) -> String {
+        let record = &entry.record;
+        let meta = &entry.meta;
+
+        match self {
+            Column::Usn => record.get_usn().to_string(),
+            Column::Timestamp => options.timestamp_value(record)
+                .map(value_text)
+                .unwrap_or_default(),
+            Column::FullName => full_name.unwrap_or_default().to_string(),
+            Column::FileName => record.get_file_name(),
+            Column::FileNameHex => record.get_file_name_hex()
+                .unwrap_or_default()
+                .to_string(),
+            Column::IrregularName => record.has_irregular_name().to_string(),
+            Column::Reason => value_text(options.flags_value(&record.get_reason_code())),
+            Column::ReasonCode => record.get_reason_code().bits().to_string(),
+            Column::ReasonDelta => entry.get_reason_delta()
+                .map(|reason_delta| value_text(options.flags_value(&reason_delta)))
+                .unwrap_or_default(),
+            Column::FileAttributes => value_text(options.flags_value(&record.get_file_attributes())),
+            Column::FileAttributesCode => record.get_file_attributes().bits().to_string(),
+            Column::FileReferenceEntry => record.get_file_reference().entry.to_string(),
+            Column::FileReferenceSequence => record.get_file_reference().sequence.to_string(),
+            Column::FileReferenceU128 => record.get_file_id().to_string(),
+            Column::ParentReferenceEntry => record.get_parent_reference().entry.to_string(),
+            Column::ParentReferenceSequence => record.get_parent_reference().sequence.to_string(),
+            Column::ParentReferenceU128 => record.get_parent_id().to_string(),
+            Column::SourceInfo => value_text(options.flags_value(&record.get_source_info())),
+            Column::SourceInfoCode => record.get_source_info().bits().to_string(),
+            Column::SecurityId => record.get_security_id()
+                .map(|security_id| security_id.to_string())
+                .unwrap_or_default(),
+            Column::MajorVersion => record.get_major_version().to_string(),
+            Column::MinorVersion => record.get_minor_version().to_string(),
+            Column::RecordLength => record.get_record_length().to_string(),
+            Column::Source => meta.source.clone(),
+            Column::Offset => meta.offset.to_string(),
+            Column::ScanMode => format!("{:?}", meta.scan_mode).to_lowercase(),
+            Column::Confidence => meta.confidence
+                .map(|confidence| confidence.score.to_string())
+                .unwrap_or_default(),
+            Column::Volume => meta.volume.as_ref()
+                .map(|volume| volume.volume.clone())
+                .unwrap_or_default(),
+            Column::Host => meta.volume.as_ref()
+                .and_then(|volume| volume.host.clone())
+                .unwrap_or_default(),
+            Column::SnapshotId => meta.snapshot.as_ref()
+                .map(|snapshot| snapshot.id.clone())
+                .unwrap_or_default(),
+            Column::SnapshotCreated => meta.snapshot.as_ref()
+                .map(|snapshot| snapshot.created.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+
+/// Writes entries as delimited rows, preceded by a header row. Rows end with
+/// CRLF and fields are quoted as RFC 4180 describes.
+pub struct CsvWriter<W: Write> {
+    writer: W,
+    delimiter: char,
+    columns: Vec<Column>,
+    options: SerializeOptions,
+    header_written: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// A comma separated writer of every column.
+    pub fn new(writer: W) -> Self {
+        CsvWriter {
+            writer,
+            delimiter: ',',
+            columns: Column::all().to_vec(),
+            options: SerializeOptions::default(),
+            header_written: false,
+        }
+    }
+
+    /// A tab separated writer of every column.
+    pub fn new_tsv(writer: W) -> Self {
+        Self::new(writer).with_delimiter('\t')
+    }
+
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_columns(mut self, columns: Vec<Column>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// How flags and timestamps are written.
+    pub fn with_options(mut self, options: SerializeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn get_columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Write the header row. Done on the first entry if it was not called before.
+    pub fn write_header(&mut self) -> io::Result<()> {
+        let names: Vec<String> = self.columns.iter()
+            .map(|column| column.name().to_string())
+            .collect();
+        self.write_row(&names)?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_row(&mut self, values: &[String]) -> io::Result<()> {
+        let fields: Vec<String> = values.iter()
+            .map(|value| quote_field(value, self.delimiter))
+            .collect();
+        write!(self.writer, "{}\r\n", fields.join(&self.delimiter.to_string()))
+    }
+}
+
+impl<W: Write> RecordSink for CsvWriter<W> {
+    /// Write an entry, after the header row if it was not written yet.
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        let values: Vec<String> = self.columns.iter()
+            .map(|column| column.value(entry, full_name, &self.options))
+            .collect();
+        Ok(self.write_row(&values)?)
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+
+/// The text of a rendered value: strings as they are, anything else as JSON.
+fn value_text(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        value => value.to_string()
+    }
+}
+
+/// Quote a field that holds the delimiter, a double quote or a line break,
+/// doubling any double quotes inside it.
+pub fn quote_field(value: &str, delimiter: char) -> String {
+    if value.contains([delimiter, '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
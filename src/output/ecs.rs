@@ -0,0 +1,168 @@
+//! Elasticsearch `_bulk` NDJSON output. Each entry is an action line followed
+//! by a document with Elastic Common Schema (ECS) fields. Fields ECS has no
+//! place for go under `usn`.
+//!
+use std::io::Write;
+use chrono::SecondsFormat;
+use serde_json::{json, Map, Value};
+use crate::error::UsnError;
+use crate::flags;
+use crate::output::RecordSink;
+use crate::record::UsnEntry;
+use winstructs::ntfs::mft_reference::MftReference;
+
+
+pub const DEFAULT_INDEX: &str = "rusty-usn";
+const ECS_VERSION: &str = "8.11.0";
+const DATASET: &str = "ntfs.usn_journal";
+
+
+/// The ECS `event.type` values of a reason.
+fn event_types(reason: flags::Reason) -> Vec<&'static str> {
+    let mut types = Vec::new();
+    if reason.contains(flags::Reason::FILE_CREATE) {
+        types.push("creation");
+    }
+    if reason.contains(flags::Reason::FILE_DELETE) {
+        types.push("deletion");
+    }
+    if types.is_empty() || !(reason - flags::Reason::FILE_CREATE - flags::Reason::FILE_DELETE - flags::Reason::CLOSE).is_empty() {
+        types.push("change");
+    }
+    types
+}
+
+fn reference_value(reference: MftReference, id: u128) -> Value {
+    json!({
+        "entry": reference.entry,
+        "sequence": reference.sequence,
+        "u128": id.to_string(),
+    })
+}
+
+/// Insert a value if there is one.
+fn insert_some<T: Into<Value>>(map: &mut Map<String, Value>, key: &str, value: Option<T>) {
+    if let Some(value) = value {
+        map.insert(key.to_string(), value.into());
+    }
+}
+
+
+/// The ECS document of an entry. `file.path` and `file.directory` are only set
+/// when `full_name` (the path enumerated from a folder mapping) is.
+pub fn to_ecs_value(entry: &UsnEntry, full_name: Option<&str>) -> Value {
+    let record = &entry.record;
+    let meta = &entry.meta;
+    let reason = record.get_reason_code();
+    let file_attributes = record.get_file_attributes();
+    let file_name = record.get_file_name();
+
+    let mut file = Map::new();
+    file.insert("name".to_string(), Value::String(file_name.clone()));
+    if let Some(full_name) = full_name {
+        file.insert("path".to_string(), Value::String(full_name.to_string()));
+        if let Some((directory, _)) = full_name.rsplit_once('/') {
+            file.insert("directory".to_string(), Value::String(directory.to_string()));
+        }
+    }
+    let is_directory = file_attributes.contains(flags::FileAttributes::DIRECTORY);
+    if !is_directory {
+        insert_some(&mut file, "extension", file_name.rsplit_once('.').map(|(_, extension)| extension.to_string()));
+    }
+    file.insert("type".to_string(), Value::from(if is_directory { "dir" } else { "file" }));
+    file.insert("inode".to_string(), Value::String(record.get_file_reference().entry.to_string()));
+    file.insert("attributes".to_string(), Value::from(
+        flags::flag_names(&file_attributes).iter().map(|name| name.to_lowercase()).collect::<Vec<String>>()
+    ));
+
+    let mut usn = Map::new();
+    usn.insert("usn".to_string(), Value::from(record.get_usn()));
+    usn.insert("reason".to_string(), Value::from(flags::flag_names(&reason)));
+    usn.insert("reason_code".to_string(), Value::from(reason.bits()));
+    insert_some(&mut usn, "reason_delta", entry.get_reason_delta().map(|reason_delta| flags::flag_names(&reason_delta)));
+    insert_some(&mut usn, "file_name_hex", record.get_file_name_hex());
+    if record.has_irregular_name() {
+        usn.insert("irregular_name".to_string(), Value::Bool(true));
+    }
+    usn.insert("file_attributes_code".to_string(), Value::from(file_attributes.bits()));
+    usn.insert("file_reference".to_string(), reference_value(record.get_file_reference(), record.get_file_id()));
+    usn.insert("parent_reference".to_string(), reference_value(record.get_parent_reference(), record.get_parent_id()));
+    usn.insert("source_info".to_string(), Value::from(flags::flag_names(&record.get_source_info())));
+    usn.insert("source_info_code".to_string(), Value::from(record.get_source_info().bits()));
+    insert_some(&mut usn, "security_id", record.get_security_id());
+    usn.insert("major_version".to_string(), Value::from(record.get_major_version()));
+    usn.insert("minor_version".to_string(), Value::from(record.get_minor_version()));
+    usn.insert("record_length".to_string(), Value::from(record.get_record_length()));
+    usn.insert("scan_mode".to_string(), Value::String(format!("{:?}", meta.scan_mode).to_lowercase()));
+    insert_some(&mut usn, "confidence", meta.confidence.map(|confidence| confidence.score));
+    insert_some(&mut usn, "volume", meta.volume.as_ref().map(|volume| volume.volume.clone()));
+    insert_some(&mut usn, "snapshot_id", meta.snapshot.as_ref().map(|snapshot| snapshot.id.clone()));
+    insert_some(&mut usn, "snapshot_created", meta.snapshot.as_ref()
+        .map(|snapshot| snapshot.created.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+
+    let mut document = Map::new();
+    insert_some(&mut document, "@timestamp", record.get_timestamp()
+        .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)));
+    document.insert("ecs".to_string(), json!({"version": ECS_VERSION}));
+    document.insert("event".to_string(), json!({
+        "kind": "event",
+        "category": ["file"],
+        "type": event_types(reason),
+        "action": flags::flag_text(&reason),
+        "dataset": DATASET,
+    }));
+    document.insert("file".to_string(), Value::Object(file));
+    if let Some(host) = meta.volume.as_ref().and_then(|volume| volume.host.as_ref()) {
+        document.insert("host".to_string(), json!({"name": host}));
+    }
+    document.insert("log".to_string(), json!({
+        "file": {"path": meta.source},
+        "offset": meta.offset,
+    }));
+    document.insert("usn".to_string(), Value::Object(usn));
+
+    Value::Object(document)
+}
+
+
+/// Writes entries as `_bulk` NDJSON: an `index` action line naming the index,
+/// then the ECS document.
+pub struct EcsWriter<W: Write> {
+    writer: W,
+    action: String,
+}
+
+impl<W: Write> EcsWriter<W> {
+    /// A writer into `DEFAULT_INDEX`.
+    pub fn new(writer: W) -> Self {
+        EcsWriter {
+            writer,
+            action: Self::action_line(DEFAULT_INDEX),
+        }
+    }
+
+    pub fn with_index(mut self, index: &str) -> Self {
+        self.action = Self::action_line(index);
+        self
+    }
+
+    fn action_line(index: &str) -> String {
+        json!({"index": {"_index": index}}).to_string()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for EcsWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        writeln!(self.writer, "{}", self.action)?;
+        writeln!(self.writer, "{}", to_ecs_value(entry, full_name))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -0,0 +1,65 @@
+//! JSONL output, one JSON object per entry.
+//!
+use std::io::Write;
+use serde_json::Value;
+use crate::error::UsnError;
+use crate::output::RecordSink;
+use crate::output::options::SerializeOptions;
+use crate::record::UsnEntry;
+
+
+/// The JSON value of an entry, with `full_name` added if there is one.
+pub fn to_json_value(entry: &UsnEntry, full_name: Option<&str>) -> Result<Value, UsnError> {
+    let mut value = entry.to_json_value()?;
+    if let Some(full_name) = full_name {
+        let map = value.as_object_mut().ok_or_else(||
+            UsnError::json_value_error(format!("Entry json value's object is none. {:?}", entry))
+        )?;
+        map.insert("full_name".to_string(), Value::String(full_name.to_string()));
+    }
+    Ok(value)
+}
+
+
+/// Writes entries as JSON objects, one per line.
+pub struct JsonlWriter<W: Write> {
+    writer: W,
+    options: SerializeOptions,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JsonlWriter {
+            writer,
+            options: SerializeOptions::default(),
+        }
+    }
+
+    /// How flags and timestamps are written.
+    pub fn with_options(mut self, options: SerializeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for JsonlWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        let mut value = to_json_value(entry, full_name)?;
+        if let Some(map) = value.as_object_mut() {
+            self.options.apply(&entry.record, map);
+            if let Some(reason_delta) = entry.get_reason_delta() {
+                map.insert("reason_delta".to_string(), self.options.flags_value(&reason_delta));
+            }
+        }
+        writeln!(self.writer, "{}", value)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -0,0 +1,198 @@
+//! Writers for parsed entries. Every format is a `RecordSink`, so `rusty_usn`
+//! and embedders of `UsnParser` or `UsnVolumeListener` write entries the same
+//! way whatever the format.
+//!
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use serde_json::Value;
+use crate::error::UsnError;
+use crate::record::UsnEntry;
+
+pub mod csv;
+pub mod ecs;
+pub mod jsonl;
+pub mod ocsf;
+pub mod options;
+pub mod sessions;
+#[cfg(feature = "parquet")]
+pub mod columnar;
+pub mod timeline;
+pub mod timesketch;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+
+/// A destination for parsed entries.
+pub trait RecordSink {
+    /// Write an entry. `full_name` is the path enumerated from a folder mapping, if any.
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError>;
+
+    /// Write a batch of entries without full names, such as a batch from
+    /// `UsnParser::record_batches`.
+    fn write_entries(&mut self, entries: &[UsnEntry]) -> Result<(), UsnError> {
+        for entry in entries {
+            self.write_entry(entry, None)?;
+        }
+        Ok(())
+    }
+
+    /// Write out anything buffered. The sink can still be written to.
+    fn flush(&mut self) -> Result<(), UsnError>;
+
+    /// Flush, and write whatever ends the output, such as the footer of a
+    /// Parquet file. Called once, after the last entry.
+    fn finish(&mut self) -> Result<(), UsnError> {
+        self.flush()
+    }
+}
+
+impl<S: RecordSink + ?Sized> RecordSink for Box<S> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        (**self).write_entry(entry, full_name)
+    }
+
+    fn write_entries(&mut self, entries: &[UsnEntry]) -> Result<(), UsnError> {
+        (**self).write_entries(entries)
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        (**self).flush()
+    }
+
+    fn finish(&mut self) -> Result<(), UsnError> {
+        (**self).finish()
+    }
+}
+
+/// Sends the JSON value of each entry, as `jsonl::to_json_value` builds it.
+impl RecordSink for Sender<Value> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        let value = jsonl::to_json_value(entry, full_name)?;
+        self.send(value).map_err(|error|
+            UsnError::json_value_error(format!("error sending usn entry: {}", error))
+        )
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(())
+    }
+}
+
+
+/// An output format, as selected with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Jsonl,
+    Csv,
+    Tsv,
+    Bodyfile,
+    Tln,
+    Ecs,
+    Timesketch,
+    Ocsf,
+    Sessions,
+    Sqlite,
+    Parquet,
+}
+
+impl Format {
+    pub fn all() -> &'static [Format] {
+        &[
+            Format::Jsonl,
+            Format::Csv,
+            Format::Tsv,
+            Format::Bodyfile,
+            Format::Tln,
+            Format::Ecs,
+            Format::Timesketch,
+            Format::Ocsf,
+            Format::Sessions,
+            Format::Sqlite,
+            Format::Parquet,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Bodyfile => "bodyfile",
+            Format::Tln => "tln",
+            Format::Ecs => "ecs",
+            Format::Timesketch => "timesketch",
+            Format::Ocsf => "ocsf",
+            Format::Sessions => "sessions",
+            Format::Sqlite => "sqlite",
+            Format::Parquet => "parquet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::all().iter()
+            .find(|format| format.name() == name)
+            .copied()
+    }
+
+    /// Whether the format is a file of its own, that cannot be streamed to stdout.
+    pub fn needs_path(&self) -> bool {
+        matches!(self, Format::Sqlite | Format::Parquet)
+    }
+
+    /// Whether the format groups records, and needs them from oldest to newest.
+    pub fn needs_usn_order(&self) -> bool {
+        matches!(self, Format::Sessions)
+    }
+
+    /// A sink writing the format to `writer`, with the default options of the
+    /// format. Formats that need a path are not supported.
+    pub fn to_writer<W: Write + Send + 'static>(&self, writer: W) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        let sink: Box<dyn RecordSink + Send> = match self {
+            Format::Jsonl => Box::new(jsonl::JsonlWriter::new(writer)),
+            Format::Csv => Box::new(csv::CsvWriter::new(writer)),
+            Format::Tsv => Box::new(csv::CsvWriter::new_tsv(writer)),
+            Format::Bodyfile => Box::new(timeline::BodyfileWriter::new(writer)),
+            Format::Tln => Box::new(timeline::TlnWriter::new(writer)),
+            Format::Ecs => Box::new(ecs::EcsWriter::new(writer)),
+            Format::Timesketch => Box::new(timesketch::TimesketchWriter::new(writer)),
+            Format::Ocsf => Box::new(ocsf::OcsfWriter::new(writer)),
+            Format::Sessions => Box::new(sessions::SessionWriter::new(writer)),
+            Format::Sqlite | Format::Parquet => return Err(UsnError::unsupported_format(
+                format!("{} output needs a path", self.name())
+            ))
+        };
+        Ok(sink)
+    }
+
+    /// A sink writing the format to the file at `path`. A SQLite database is
+    /// appended to, any other file is replaced.
+    pub fn create(&self, path: impl AsRef<Path>) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        match self {
+            Format::Sqlite => Self::create_sqlite(path.as_ref()),
+            Format::Parquet => Self::create_parquet(path.as_ref()),
+            _ => self.to_writer(BufWriter::new(File::create(path)?))
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn create_sqlite(path: &Path) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        Ok(Box::new(sqlite::SqliteWriter::open(path)?))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn create_sqlite(_path: &Path) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        Err(UsnError::unsupported_format("compiled without the `sqlite` feature".to_string()))
+    }
+
+    #[cfg(feature = "parquet")]
+    fn create_parquet(path: &Path) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        Ok(Box::new(columnar::ParquetWriter::new(BufWriter::new(File::create(path)?))?))
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn create_parquet(_path: &Path) -> Result<Box<dyn RecordSink + Send>, UsnError> {
+        Err(UsnError::unsupported_format("compiled without the `parquet` feature".to_string()))
+    }
+}
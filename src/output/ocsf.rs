@@ -0,0 +1,203 @@
+//! OCSF File System Activity (class 1001) JSONL output. The activity comes
+//! from the reason flags, the file from the record and its MFT reference, and
+//! the device from the volume. Fields OCSF has no place for go in `unmapped`.
+//! Records without a timestamp (V4) cannot be events and are not written.
+//!
+use std::io::Write;
+use chrono::SecondsFormat;
+use serde_json::{json, Map, Value};
+use crate::error::UsnError;
+use crate::flags;
+use crate::output::RecordSink;
+use crate::record::UsnEntry;
+
+
+const OCSF_VERSION: &str = "1.1.0";
+const CLASS_UID: u32 = 1001;
+const CATEGORY_UID: u32 = 1;
+const SEVERITY_INFORMATIONAL: u32 = 1;
+const FILE_TYPE_REGULAR: u32 = 1;
+const FILE_TYPE_FOLDER: u32 = 2;
+const DEVICE_TYPE_UNKNOWN: u32 = 0;
+
+
+/// An OCSF File System Activity `activity_id`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activity {
+    Create = 1,
+    Update = 3,
+    Delete = 4,
+    Rename = 5,
+    SetAttributes = 6,
+    SetSecurity = 7,
+    Other = 99,
+}
+
+// The first of these a reason contains is its activity
+const ACTIVITIES: &[(flags::Reason, Activity)] = &[
+    (flags::Reason::FILE_CREATE, Activity::Create),
+    (flags::Reason::FILE_DELETE, Activity::Delete),
+    (flags::Reason::RENAME_NEW_NAME, Activity::Rename),
+    (flags::Reason::RENAME_OLD_NAME, Activity::Rename),
+    (flags::DATA_REASONS, Activity::Update),
+    (flags::Reason::SECURITY_CHANGE, Activity::SetSecurity),
+    (flags::METADATA_REASONS, Activity::SetAttributes),
+];
+
+impl Activity {
+    pub fn from_reason(reason: flags::Reason) -> Activity {
+        ACTIVITIES.iter()
+            .find(|(reasons, _)| reason.intersects(*reasons))
+            .map(|(_, activity)| *activity)
+            .unwrap_or(Activity::Other)
+    }
+
+    pub fn id(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Activity::Create => "Create",
+            Activity::Update => "Update",
+            Activity::Delete => "Delete",
+            Activity::Rename => "Rename",
+            Activity::SetAttributes => "Set Attributes",
+            Activity::SetSecurity => "Set Security",
+            Activity::Other => "Other",
+        }
+    }
+}
+
+
+/// The OCSF event of an entry, or None if the record has no timestamp.
+/// `full_name` is the path enumerated from a folder mapping, if any.
+pub fn to_ocsf_value(entry: &UsnEntry, full_name: Option<&str>) -> Option<Value> {
+    let record = &entry.record;
+    let meta = &entry.meta;
+    let timestamp = record.get_timestamp()?;
+    let reason = record.get_reason_code();
+    let activity = Activity::from_reason(reason);
+    let file_attributes = record.get_file_attributes();
+    let file_reference = record.get_file_reference();
+
+    let mut file = Map::new();
+    file.insert("name".to_string(), Value::String(record.get_file_name()));
+    file.insert("path".to_string(), Value::String(
+        full_name.map(str::to_string).unwrap_or_else(|| record.get_file_name())
+    ));
+    if let Some((directory, _)) = full_name.and_then(|full_name| full_name.rsplit_once('/')) {
+        file.insert("parent_folder".to_string(), Value::String(directory.to_string()));
+    }
+    let is_directory = file_attributes.contains(flags::FileAttributes::DIRECTORY);
+    file.insert("type_id".to_string(), Value::from(if is_directory { FILE_TYPE_FOLDER } else { FILE_TYPE_REGULAR }));
+    file.insert("type".to_string(), Value::from(if is_directory { "Folder" } else { "Regular File" }));
+    file.insert("attributes".to_string(), Value::from(file_attributes.bits()));
+    file.insert("uid".to_string(), Value::String(record.get_file_id().to_string()));
+
+    let mut device = Map::new();
+    device.insert("type_id".to_string(), Value::from(DEVICE_TYPE_UNKNOWN));
+    device.insert("type".to_string(), Value::from("Unknown"));
+    if let Some(volume) = &meta.volume {
+        device.insert("name".to_string(), Value::String(volume.volume.clone()));
+        if let Some(host) = &volume.host {
+            device.insert("hostname".to_string(), Value::String(host.clone()));
+        }
+    }
+
+    let mut unmapped = Map::new();
+    unmapped.insert("usn".to_string(), Value::from(record.get_usn()));
+    unmapped.insert("reason".to_string(), Value::from(flags::flag_names(&reason)));
+    unmapped.insert("reason_code".to_string(), Value::from(reason.bits()));
+    if let Some(reason_delta) = entry.get_reason_delta() {
+        unmapped.insert("reason_delta".to_string(), Value::from(flags::flag_names(&reason_delta)));
+    }
+    unmapped.insert("file_attributes".to_string(), Value::from(flags::flag_names(&file_attributes)));
+    if let Some(file_name_hex) = record.get_file_name_hex() {
+        unmapped.insert("file_name_hex".to_string(), Value::from(file_name_hex));
+        unmapped.insert("irregular_name".to_string(), Value::Bool(true));
+    }
+    unmapped.insert("file_reference".to_string(), json!({
+        "entry": file_reference.entry,
+        "sequence": file_reference.sequence,
+    }));
+    unmapped.insert("parent_reference".to_string(), json!({
+        "entry": record.get_parent_reference().entry,
+        "sequence": record.get_parent_reference().sequence,
+        "u128": record.get_parent_id().to_string(),
+    }));
+    unmapped.insert("source_info".to_string(), Value::from(flags::flag_names(&record.get_source_info())));
+    unmapped.insert("source_info_code".to_string(), Value::from(record.get_source_info().bits()));
+    if let Some(security_id) = record.get_security_id() {
+        unmapped.insert("security_id".to_string(), Value::from(security_id));
+    }
+    unmapped.insert("major_version".to_string(), Value::from(record.get_major_version()));
+    unmapped.insert("meta__source".to_string(), Value::String(meta.source.clone()));
+    unmapped.insert("meta__offset".to_string(), Value::from(meta.offset));
+    unmapped.insert("meta__scan_mode".to_string(), Value::String(format!("{:?}", meta.scan_mode).to_lowercase()));
+    if let Some(confidence) = meta.confidence {
+        unmapped.insert("meta__confidence".to_string(), Value::from(confidence.score));
+    }
+    if let Some(snapshot) = &meta.snapshot {
+        unmapped.insert("meta__snapshot_id".to_string(), Value::String(snapshot.id.clone()));
+        unmapped.insert("meta__snapshot_created".to_string(), Value::String(
+            snapshot.created.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        ));
+    }
+
+    Some(json!({
+        "class_uid": CLASS_UID,
+        "class_name": "File System Activity",
+        "category_uid": CATEGORY_UID,
+        "category_name": "System Activity",
+        "activity_id": activity.id(),
+        "activity_name": activity.name(),
+        "type_uid": CLASS_UID * 100 + activity.id(),
+        "type_name": format!("File System Activity: {}", activity.name()),
+        "severity_id": SEVERITY_INFORMATIONAL,
+        "severity": "Informational",
+        "time": timestamp.timestamp_millis(),
+        "metadata": {
+            "version": OCSF_VERSION,
+            "product": {
+                "name": "rusty_usn",
+                "vendor_name": "RustyUsn",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "log_name": "$UsnJrnl:$J",
+            "original_time": timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        },
+        "file": file,
+        "device": device,
+        "unmapped": unmapped,
+    }))
+}
+
+
+/// Writes entries as OCSF File System Activity JSONL.
+pub struct OcsfWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> OcsfWriter<W> {
+    pub fn new(writer: W) -> Self {
+        OcsfWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for OcsfWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        if let Some(value) = to_ocsf_value(entry, full_name) {
+            writeln!(self.writer, "{}", value)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -0,0 +1,205 @@
+//! How the flags and timestamp of a record are written by the JSONL and CSV
+//! outputs. The defaults write them as the JSON of a record does.
+//!
+use chrono::{FixedOffset, SecondsFormat};
+use serde_json::{Map, Value};
+use crate::flags::{self, FlagSet};
+use crate::record::UsnRecord;
+use crate::utils::filetime_to_datetime;
+
+
+// 100 nanosecond intervals from 1601-01-01 to 1970-01-01
+const FILETIME_UNIX_EPOCH: i128 = 116_444_736_000_000_000;
+const FILETIME_PER_SECOND: i128 = 10_000_000;
+
+
+/// How flags are written.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FlagFormat {
+    /// The names joined with ` | `, such as `DATA_EXTEND | CLOSE`.
+    #[default]
+    Text,
+    /// An array of the names.
+    Array,
+    /// The raw value.
+    Integer,
+    /// The names of the Windows headers joined with ` | `, such as
+    /// `USN_REASON_DATA_EXTEND | USN_REASON_CLOSE`.
+    Windows,
+}
+
+impl FlagFormat {
+    pub fn all() -> &'static [FlagFormat] {
+        &[FlagFormat::Text, FlagFormat::Array, FlagFormat::Integer, FlagFormat::Windows]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlagFormat::Text => "text",
+            FlagFormat::Array => "array",
+            FlagFormat::Integer => "integer",
+            FlagFormat::Windows => "windows",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FlagFormat> {
+        FlagFormat::all().iter()
+            .find(|format| format.name() == name)
+            .copied()
+    }
+}
+
+
+/// How timestamps are written.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimestampFormat {
+    /// RFC 3339 to the microsecond, such as `2013-10-19T12:16:53.276040Z`.
+    #[default]
+    Rfc3339,
+    /// RFC 3339 to the 100 nanoseconds of the FILETIME, such as
+    /// `2013-10-19T12:16:53.2760403Z`.
+    Rfc3339Full,
+    /// The FILETIME as read: 100 nanosecond intervals since 1601-01-01.
+    Filetime,
+    /// Seconds since 1970-01-01 with a seven digit fraction, such as
+    /// `1382185013.2760403`. Written as a string, as a JSON number can not
+    /// hold every digit.
+    Epoch,
+}
+
+impl TimestampFormat {
+    pub fn all() -> &'static [TimestampFormat] {
+        &[TimestampFormat::Rfc3339, TimestampFormat::Rfc3339Full, TimestampFormat::Filetime, TimestampFormat::Epoch]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimestampFormat::Rfc3339 => "rfc3339",
+            TimestampFormat::Rfc3339Full => "full",
+            TimestampFormat::Filetime => "filetime",
+            TimestampFormat::Epoch => "epoch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TimestampFormat> {
+        TimestampFormat::all().iter()
+            .find(|format| format.name() == name)
+            .copied()
+    }
+}
+
+
+/// The renderings of flags and timestamps, and the offset RFC 3339 timestamps
+/// are written in (UTC by default). The offset is fixed, so timestamps on
+/// either side of a daylight saving change get the same one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerializeOptions {
+    flag_format: FlagFormat,
+    timestamp_format: TimestampFormat,
+    offset: FixedOffset,
+}
+
+impl Default for SerializeOptions {
+    fn default() -> Self {
+        SerializeOptions {
+            flag_format: FlagFormat::default(),
+            timestamp_format: TimestampFormat::default(),
+            offset: FixedOffset::east_opt(0).expect("zero offset"),
+        }
+    }
+}
+
+impl SerializeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_flag_format(mut self, flag_format: FlagFormat) -> Self {
+        self.flag_format = flag_format;
+        self
+    }
+
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: FixedOffset) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn get_flag_format(&self) -> FlagFormat {
+        self.flag_format
+    }
+
+    pub fn get_timestamp_format(&self) -> TimestampFormat {
+        self.timestamp_format
+    }
+
+    pub fn get_offset(&self) -> FixedOffset {
+        self.offset
+    }
+
+    pub fn flags_value<T: FlagSet>(&self, flags: &T) -> Value {
+        match self.flag_format {
+            FlagFormat::Text => Value::String(flags::flag_text(flags)),
+            FlagFormat::Array => Value::from(flags::flag_names(flags)),
+            FlagFormat::Integer => Value::from(flags.bits()),
+            FlagFormat::Windows => {
+                let names = flags::windows_flag_names(flags);
+                match names.is_empty() {
+                    true => Value::String("(empty)".to_string()),
+                    false => Value::String(names.join(" | "))
+                }
+            }
+        }
+    }
+
+    /// The timestamp of a record, or None for a V4 record.
+    pub fn timestamp_value(&self, record: &UsnRecord) -> Option<Value> {
+        let filetime = record.get_filetime()?;
+        let value = match self.timestamp_format {
+            TimestampFormat::Rfc3339 => {
+                let timestamp = record.get_timestamp()?.with_timezone(&self.offset);
+                Value::String(timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            },
+            TimestampFormat::Rfc3339Full => {
+                let timestamp = filetime_to_datetime(filetime).with_timezone(&self.offset);
+                let zone = match self.offset.local_minus_utc() {
+                    0 => "Z".to_string(),
+                    _ => timestamp.format("%:z").to_string()
+                };
+                Value::String(format!(
+                    "{}.{:07}{}",
+                    timestamp.format("%Y-%m-%dT%H:%M:%S"), timestamp.timestamp_subsec_nanos() / 100, zone
+                ))
+            },
+            TimestampFormat::Filetime => Value::from(filetime),
+            TimestampFormat::Epoch => Value::String(epoch_text(filetime)),
+        };
+        Some(value)
+    }
+
+    /// Replace the flag and timestamp fields of a record's JSON object with
+    /// their renderings.
+    pub fn apply(&self, record: &UsnRecord, map: &mut Map<String, Value>) {
+        map.insert("reason".to_string(), self.flags_value(&record.get_reason_code()));
+        map.insert("source_info".to_string(), self.flags_value(&record.get_source_info()));
+        if let UsnRecord::V2(_) | UsnRecord::V3(_) = record {
+            map.insert("file_attributes".to_string(), self.flags_value(&record.get_file_attributes()));
+        }
+        if let Some(timestamp) = self.timestamp_value(record) {
+            map.insert("timestamp".to_string(), timestamp);
+        }
+    }
+}
+
+/// The seconds of a FILETIME since 1970-01-01, with the 100 nanoseconds as a
+/// fraction.
+fn epoch_text(filetime: u64) -> String {
+    let intervals = i128::from(filetime) - FILETIME_UNIX_EPOCH;
+    let sign = if intervals < 0 { "-" } else { "" };
+    let intervals = intervals.abs();
+    format!("{}{}.{:07}", sign, intervals / FILETIME_PER_SECOND, intervals % FILETIME_PER_SECOND)
+}
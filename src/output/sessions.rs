@@ -0,0 +1,61 @@
+//! Session output: one JSON object per file activity session instead of per
+//! record. See `session::SessionAggregator`.
+//!
+use std::io::Write;
+use crate::error::UsnError;
+use crate::output::RecordSink;
+use crate::record::UsnEntry;
+use crate::session::{FileSession, SessionAggregator};
+
+
+/// Writes the sessions of the entries as JSON objects, one per line, as each
+/// session ends.
+pub struct SessionWriter<W: Write> {
+    writer: W,
+    aggregator: SessionAggregator,
+}
+
+impl<W: Write> SessionWriter<W> {
+    pub fn new(writer: W) -> Self {
+        SessionWriter {
+            writer,
+            aggregator: SessionAggregator::new(),
+        }
+    }
+
+    /// The aggregator to group entries with, for its limit and handling of
+    /// sessions that never closed.
+    pub fn with_aggregator(mut self, aggregator: SessionAggregator) -> Self {
+        self.aggregator = aggregator;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_sessions(&mut self, sessions: Vec<FileSession>) -> Result<(), UsnError> {
+        for session in sessions {
+            writeln!(self.writer, "{}", session.to_json_value()?)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordSink for SessionWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        let sessions = self.aggregator.add(entry, full_name);
+        self.write_sessions(sessions)
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Write the sessions that never closed, if the aggregator flushes them.
+    fn finish(&mut self) -> Result<(), UsnError> {
+        let sessions = self.aggregator.finish();
+        self.write_sessions(sessions)?;
+        self.flush()
+    }
+}
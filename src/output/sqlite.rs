@@ -0,0 +1,226 @@
+//! SQLite output. Records go in a `records` table, their decoded reason,
+//! attribute and source info flags in `record_flags`, and where they came from in `sources`.
+//! The schema is created if it does not exist, so several runs (from several
+//! hosts) can be appended into one case database.
+//!
+use std::path::Path;
+use std::collections::HashMap;
+use chrono::SecondsFormat;
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::error::UsnError;
+use crate::flags;
+use crate::output::RecordSink;
+use crate::record::{EntryMeta, UsnEntry};
+
+
+// Records written per transaction
+const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sources (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    volume TEXT,
+    host TEXT,
+    snapshot_id TEXT,
+    snapshot_created TEXT,
+    scan_mode TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    source_id INTEGER NOT NULL REFERENCES sources(id),
+    offset INTEGER NOT NULL,
+    usn INTEGER NOT NULL,
+    timestamp TEXT,
+    file_name TEXT NOT NULL,
+    file_name_hex TEXT,
+    irregular_name INTEGER NOT NULL,
+    full_name TEXT,
+    reason INTEGER NOT NULL,
+    file_attributes INTEGER NOT NULL,
+    file_reference_entry INTEGER NOT NULL,
+    file_reference_sequence INTEGER NOT NULL,
+    file_reference_u128 TEXT NOT NULL,
+    parent_reference_entry INTEGER NOT NULL,
+    parent_reference_sequence INTEGER NOT NULL,
+    parent_reference_u128 TEXT NOT NULL,
+    source_info INTEGER NOT NULL,
+    security_id INTEGER,
+    major_version INTEGER NOT NULL,
+    minor_version INTEGER NOT NULL,
+    record_length INTEGER NOT NULL,
+    confidence INTEGER
+);
+CREATE TABLE IF NOT EXISTS record_flags (
+    record_id INTEGER NOT NULL REFERENCES records(id),
+    kind TEXT NOT NULL,
+    flag TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS records_usn ON records(usn);
+CREATE INDEX IF NOT EXISTS records_timestamp ON records(timestamp);
+CREATE INDEX IF NOT EXISTS records_file_reference ON records(file_reference_entry, file_reference_sequence);
+CREATE INDEX IF NOT EXISTS records_file_name ON records(file_name);
+CREATE INDEX IF NOT EXISTS record_flags_record ON record_flags(record_id);
+CREATE INDEX IF NOT EXISTS record_flags_flag ON record_flags(kind, flag);
+";
+
+
+/// Writes entries into a SQLite database, in batched transactions.
+pub struct SqliteWriter {
+    connection: Connection,
+    batch_size: usize,
+    pending: usize,
+    source_ids: HashMap<String, i64>,
+}
+
+impl SqliteWriter {
+    /// Open (or create) a database file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, UsnError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn from_connection(connection: Connection) -> Result<Self, UsnError> {
+        connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(SqliteWriter {
+            connection,
+            batch_size: DEFAULT_BATCH_SIZE,
+            pending: 0,
+            source_ids: HashMap::new(),
+        })
+    }
+
+    /// The number of records written per transaction.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn get_connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The id of the row of `sources` for an entry's meta, added if it is new.
+    fn source_id(&mut self, meta: &EntryMeta) -> Result<i64, UsnError> {
+        let volume = meta.volume.as_ref().map(|volume| volume.volume.as_str());
+        let host = meta.volume.as_ref().and_then(|volume| volume.host.as_deref());
+        let snapshot_id = meta.snapshot.as_ref().map(|snapshot| snapshot.id.as_str());
+        let snapshot_created = meta.snapshot.as_ref()
+            .map(|snapshot| snapshot.created.to_rfc3339_opts(SecondsFormat::Micros, true));
+        let scan_mode = format!("{:?}", meta.scan_mode).to_lowercase();
+
+        let key = format!("{}|{:?}|{:?}|{:?}|{}", meta.source, volume, host, snapshot_id, scan_mode);
+        if let Some(source_id) = self.source_ids.get(&key) {
+            return Ok(*source_id);
+        }
+
+        // `IS` compares NULLs as equal
+        let existing = self.connection.query_row(
+            "SELECT id FROM sources WHERE source = ?1 AND volume IS ?2 AND host IS ?3 AND snapshot_id IS ?4 AND scan_mode = ?5",
+            params![meta.source, volume, host, snapshot_id, scan_mode],
+            |row| row.get(0)
+        ).optional()?;
+
+        let source_id = match existing {
+            Some(source_id) => source_id,
+            None => {
+                self.connection.execute(
+                    "INSERT INTO sources (source, volume, host, snapshot_id, snapshot_created, scan_mode)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![meta.source, volume, host, snapshot_id, snapshot_created, scan_mode]
+                )?;
+                self.connection.last_insert_rowid()
+            }
+        };
+
+        self.source_ids.insert(key, source_id);
+        Ok(source_id)
+    }
+}
+
+impl RecordSink for SqliteWriter {
+    /// Write an entry, committing every `batch_size` records.
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        if self.pending == 0 {
+            self.connection.execute_batch("BEGIN")?;
+        }
+
+        let source_id = self.source_id(&entry.meta)?;
+        let record = &entry.record;
+        let file_reference = record.get_file_reference();
+        let parent_reference = record.get_parent_reference();
+
+        self.connection.prepare_cached(
+            "INSERT INTO records (
+                source_id, offset, usn, timestamp, file_name, file_name_hex, irregular_name, full_name,
+                reason, file_attributes, file_reference_entry, file_reference_sequence, file_reference_u128,
+                parent_reference_entry, parent_reference_sequence, parent_reference_u128,
+                source_info, security_id, major_version, minor_version, record_length, confidence
+            ) VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22
+            )"
+        )?.execute(params![
+            source_id,
+            entry.meta.offset as i64,
+            record.get_usn() as i64,
+            record.get_timestamp().map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)),
+            record.get_file_name(),
+            record.get_file_name_hex(),
+            record.has_irregular_name(),
+            full_name,
+            record.get_reason_code().bits(),
+            record.get_file_attributes().bits(),
+            file_reference.entry as i64,
+            file_reference.sequence,
+            record.get_file_id().to_string(),
+            parent_reference.entry as i64,
+            parent_reference.sequence,
+            record.get_parent_id().to_string(),
+            record.get_source_info().bits(),
+            record.get_security_id(),
+            record.get_major_version(),
+            record.get_minor_version(),
+            record.get_record_length(),
+            entry.meta.confidence.map(|confidence| confidence.score),
+        ])?;
+        let record_id = self.connection.last_insert_rowid();
+
+        let reasons = flags::flag_names(&record.get_reason_code());
+        let attributes = flags::flag_names(&record.get_file_attributes());
+        let source_info = flags::flag_names(&record.get_source_info());
+        let mut insert_flag = self.connection.prepare_cached(
+            "INSERT INTO record_flags (record_id, kind, flag) VALUES (?1, ?2, ?3)"
+        )?;
+        for (kind, names) in [("reason", reasons), ("file_attribute", attributes), ("source_info", source_info)] {
+            for flag in names {
+                insert_flag.execute(params![record_id, kind, flag])?;
+            }
+        }
+        drop(insert_flag);
+
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Commit the records written since the last commit.
+    fn flush(&mut self) -> Result<(), UsnError> {
+        if self.pending > 0 {
+            self.connection.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteWriter {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            error!("error committing records: {}", error.message);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Bodyfile (TSK 3.x, for `mactime`) and TLN timeline output. Records without
+//! a timestamp (V4) have no place on a timeline and are not written.
+//!
+use std::io::Write;
+use crate::error::UsnError;
+use crate::flags;
+use crate::output::RecordSink;
+use crate::record::UsnEntry;
+
+
+const TLN_SOURCE: &str = "USN";
+
+
+/// The reason flags joined with commas, as `|` is the field separator of both formats.
+fn reason_text(reason: flags::Reason) -> String {
+    flags::flag_text(&reason).replace(" | ", ",")
+}
+
+/// The path and reasons of an entry, used as the bodyfile name and the TLN description.
+fn describe(entry: &UsnEntry, full_name: Option<&str>) -> String {
+    let name = match full_name {
+        Some(full_name) => full_name.to_string(),
+        None => entry.record.get_file_name()
+    };
+
+    // Carved names can hold anything, and would break the line apart
+    format!("{} ($UsnJrnl: {})", name, reason_text(entry.record.get_reason_code()))
+        .replace(['|', '\r', '\n'], "_")
+}
+
+
+/// Writes entries as bodyfile lines: `MD5|name|inode|mode|UID|GID|size|atime|mtime|ctime|crtime`.
+/// The timestamp goes in the creation time for a create, the modified time for
+/// a data change, and the changed time for anything else. The inode is the
+/// MFT reference as `entry-sequence`.
+pub struct BodyfileWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BodyfileWriter<W> {
+    pub fn new(writer: W) -> Self {
+        BodyfileWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for BodyfileWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        let record = &entry.record;
+        let timestamp = match record.get_timestamp() {
+            Some(timestamp) => timestamp.timestamp(),
+            None => return Ok(())
+        };
+
+        let reason = record.get_reason_code();
+        let (mtime, ctime, crtime) = if reason.contains(flags::Reason::FILE_CREATE) {
+            (0, 0, timestamp)
+        } else if reason.intersects(flags::DATA_REASONS) {
+            (timestamp, 0, 0)
+        } else {
+            (0, timestamp, 0)
+        };
+
+        let mode = match record.get_file_attributes().contains(flags::FileAttributes::DIRECTORY) {
+            true => "d/d---------",
+            false => "r/r---------"
+        };
+        let reference = record.get_file_reference();
+
+        writeln!(
+            self.writer, "0|{}|{}-{}|{}|0|0|0|0|{}|{}|{}",
+            describe(entry, full_name), reference.entry, reference.sequence, mode, mtime, ctime, crtime
+        )?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+
+/// Writes entries as TLN lines: `Time|Source|System|User|Description`. The
+/// system is the host of the entry, if it came from a triage collection.
+pub struct TlnWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TlnWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TlnWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for TlnWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        let timestamp = match entry.record.get_timestamp() {
+            Some(timestamp) => timestamp.timestamp(),
+            None => return Ok(())
+        };
+
+        let system = entry.meta.volume.as_ref()
+            .and_then(|volume| volume.host.as_deref())
+            .unwrap_or_default()
+            .replace('|', "_");
+
+        writeln!(
+            self.writer, "{}|{}|{}||{}",
+            timestamp, TLN_SOURCE, system, describe(entry, full_name)
+        )?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
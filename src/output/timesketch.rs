@@ -0,0 +1,118 @@
+//! Timesketch JSONL output. Every event gets the `message`, `datetime`,
+//! `timestamp` (microseconds) and `timestamp_desc` fields Timesketch requires,
+//! next to the fields of the regular JSONL output. `timestamp` replaces the
+//! string of the JSONL output, which `datetime` holds, and nested objects are
+//! flattened into `<field>_<name>` attributes. Records without a
+//! timestamp (V4) cannot be placed on a sketch and are not written.
+//!
+use std::io::Write;
+use chrono::SecondsFormat;
+use serde_json::{Map, Value};
+use crate::error::UsnError;
+use crate::flags;
+use crate::output::RecordSink;
+use crate::record::UsnEntry;
+
+
+// The first of these a reason contains describes it
+const DESCRIPTIONS: &[(flags::Reason, &str)] = &[
+    (flags::Reason::FILE_CREATE, "Created"),
+    (flags::Reason::FILE_DELETE, "Deleted"),
+    (flags::Reason::RENAME_OLD_NAME, "Renamed From"),
+    (flags::Reason::RENAME_NEW_NAME, "Renamed To"),
+    (flags::DATA_REASONS, "Content Modified"),
+    (flags::Reason::SECURITY_CHANGE, "Security Changed"),
+    (flags::METADATA_REASONS, "Metadata Modified"),
+    (flags::Reason::CLOSE, "Closed"),
+];
+
+
+/// The `timestamp_desc` of a reason, such as `File Created`.
+pub fn timestamp_desc(reason: flags::Reason) -> String {
+    let action = DESCRIPTIONS.iter()
+        .find(|(reasons, _)| reason.intersects(*reasons))
+        .map(|(_, action)| *action)
+        .unwrap_or("Changed");
+    format!("File {}", action)
+}
+
+/// The Timesketch event of an entry, or None if the record has no timestamp.
+/// `full_name` is the path enumerated from a folder mapping, if any.
+pub fn to_timesketch_value(entry: &UsnEntry, full_name: Option<&str>) -> Result<Option<Value>, UsnError> {
+    let record = &entry.record;
+    let timestamp = match record.get_timestamp() {
+        Some(timestamp) => timestamp,
+        None => return Ok(None)
+    };
+
+    let reason = record.get_reason_code();
+    let description = timestamp_desc(reason);
+    let noun = match record.get_file_attributes().contains(flags::FileAttributes::DIRECTORY) {
+        true => "Folder",
+        false => "File"
+    };
+    // `File Renamed From` becomes `renamed from`, after the noun
+    let action = description.trim_start_matches("File ").to_lowercase();
+    let message = format!(
+        "{} {}: {}", noun, action,
+        full_name.map(str::to_string).unwrap_or_else(|| record.get_file_name())
+    );
+
+    let value = entry.to_json_value()?;
+    let fields = match value {
+        Value::Object(fields) => fields,
+        _ => return Err(UsnError::json_value_error(format!("Entry json value's object is none. {:?}", entry)))
+    };
+
+    // Attributes are filtered on by name, so nested objects are flattened
+    let mut map = Map::new();
+    for (key, value) in fields {
+        match value {
+            Value::Object(nested) => for (nested_key, nested_value) in nested {
+                map.insert(format!("{}_{}", key, nested_key), nested_value);
+            },
+            value => {
+                map.insert(key, value);
+            }
+        }
+    }
+    if let Some(full_name) = full_name {
+        map.insert("full_name".to_string(), Value::String(full_name.to_string()));
+    }
+    map.insert("message".to_string(), Value::String(message));
+    map.insert("datetime".to_string(), Value::String(timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)));
+    map.insert("timestamp".to_string(), Value::from(timestamp.timestamp_micros()));
+    map.insert("timestamp_desc".to_string(), Value::String(description));
+    map.insert("data_type".to_string(), Value::String("fs:ntfs:usn_change".to_string()));
+
+    Ok(Some(Value::Object(map)))
+}
+
+
+/// Writes entries as Timesketch JSONL.
+pub struct TimesketchWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TimesketchWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TimesketchWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordSink for TimesketchWriter<W> {
+    fn write_entry(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Result<(), UsnError> {
+        if let Some(value) = to_timesketch_value(entry, full_name)? {
+            writeln!(self.writer, "{}", value)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), UsnError> {
+        Ok(self.writer.flush()?)
+    }
+}
@@ -1,7 +1,5 @@
 use std::io::Read;
 use chrono::{DateTime, Utc};
-use encoding::all::UTF_16LE;
-use encoding::{DecoderTrap, Encoding};
 use winstructs::ntfs::mft_reference::MftReference;
 use byteorder::{ByteOrder, ReadBytesExt, LittleEndian};
 use serde::ser::{SerializeStruct};
@@ -9,14 +7,24 @@ use serde::ser;
 use serde::Serialize;
 use serde_json::{Value};
 use crate::flags;
+use crate::confidence::Confidence;
+use crate::triage::VolumeMeta;
 use crate::error::UsnError;
-use crate::utils::u64_to_datetime;
+use crate::usn::ScanMode;
+use crate::utils::{decode_utf16_name, u64_to_datetime};
+
+
+/// The size of a USN_RECORD_V4 up to the start of its extents array.
+pub const USN_RECORD_V4_HEADER_SIZE: u32 = 64;
 
 
 #[derive(Debug)]
 pub struct UsnEntry {
     pub meta: EntryMeta,
     pub record: UsnRecord,
+    /// The reasons this record added to the previous record of the file, if
+    /// a `delta::ReasonDecoder` has decoded it.
+    pub reason_delta: Option<flags::Reason>,
 }
 impl UsnEntry {
     pub fn new<R: Read>(meta: EntryMeta, version: u16, mut reader: R)-> Result<UsnEntry, UsnError>{
@@ -26,17 +34,30 @@ impl UsnEntry {
         )?;
 
         Ok(UsnEntry {
-            meta: meta,
-            record: record,
+            meta,
+            record,
+            reason_delta: None,
         })
     }
 
+    /// The reasons this record added, as set by `delta::ReasonDecoder::decode`.
+    pub fn get_reason_delta(&self) -> Option<flags::Reason> {
+        self.reason_delta
+    }
+
+    /// The record and meta as a JSON object, with `reason_delta` if it is set.
     pub fn to_json_value(&self) -> Result<Value, UsnError> {
-        self.record.to_json_value(
+        let mut value = self.record.to_json_value(
             Some(
                 self.meta.to_json_value()?
             )
-        )
+        )?;
+
+        if let (Some(reason_delta), Some(map)) = (self.reason_delta, value.as_object_mut()) {
+            map.insert("reason_delta".to_string(), serde_json::to_value(reason_delta)?);
+        }
+
+        Ok(value)
     }
 }
 
@@ -49,27 +70,59 @@ pub struct EntryMeta {
     pub source: String,
     #[serde(rename(serialize = "meta__offset"))]
     pub offset: u64,
+    #[serde(rename(serialize = "meta__scan_mode"))]
+    pub scan_mode: ScanMode,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<VolumeMeta>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotMeta>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
 }
 impl EntryMeta {
     pub fn new(source: &str, offset: u64) -> Self {
         EntryMeta {
             source: source.to_string(),
-            offset: offset,
+            offset,
+            scan_mode: ScanMode::default(),
+            volume: None,
+            snapshot: None,
+            confidence: None,
+        }
+    }
+
+    /// A copy of this meta for an entry at another offset.
+    pub fn at_offset(&self, offset: u64) -> Self {
+        EntryMeta {
+            offset,
+            ..self.clone()
         }
     }
 
     pub fn to_json_value(&self) -> Result<Value, UsnError> {
-        Ok(serde_json::to_value(&self)?)
+        Ok(serde_json::to_value(self)?)
     }
 }
 
 
+/// The volume shadow copy an entry was recovered from.
+///
+#[derive(Serialize, Debug, Clone)]
+pub struct SnapshotMeta {
+    #[serde(rename(serialize = "meta__snapshot_id"))]
+    pub id: String,
+    #[serde(rename(serialize = "meta__snapshot_created"))]
+    pub created: DateTime<Utc>,
+}
+
+
 /// UsnRecord represents the multiple possible versions of the UsnRecord
 #[derive(Serialize, Debug)]
 #[serde(untagged)]
 pub enum UsnRecord {
     V2(UsnRecordV2),
-    V3(UsnRecordV3)
+    V3(UsnRecordV3),
+    V4(UsnRecordV4)
 }
 impl UsnRecord {
     pub fn new<R: Read>(_version: u16, mut reader: R)-> Result<UsnRecord, UsnError> {
@@ -78,9 +131,11 @@ impl UsnRecord {
         let major_version = reader.read_u16::<LittleEndian>()?;
         let minor_version = reader.read_u16::<LittleEndian>()?;
 
-        // Check if this is a 128-bit reference record
+        // Check if this is a 128-bit reference record. V4 records always
+        // use 128-bit references.
         let is_128bit = match major_version {
-            3 | 4 => record_length >= 76, // Minimum size for v3 with 128-bit refs
+            3 => record_length >= 76, // Minimum size for v3 with 128-bit refs
+            4 => true,
             _ => false
         };
 
@@ -96,9 +151,13 @@ impl UsnRecord {
                     Ok(UsnRecord::V3(usn_record_v3))
                 },
                 4 => {
-                    Err(UsnError::unsupported_usn_version(
-                        format!("USN v4 records not yet implemented")
-                    ))
+                    let usn_record_v4 = UsnRecordV4::new_with_header(
+                        record_length,
+                        major_version,
+                        minor_version,
+                        &mut reader
+                    )?;
+                    Ok(UsnRecord::V4(usn_record_v4))
                 },
                 _ => {
                     Err(UsnError::unsupported_usn_version(
@@ -139,22 +198,64 @@ impl UsnRecord {
 
     pub fn get_usn(&self) -> u64 {
         match self {
-            UsnRecord::V2(ref record) => record.usn.clone(),
-            UsnRecord::V3(ref record) => record.usn.clone(),
+            UsnRecord::V2(record) => record.usn,
+            UsnRecord::V3(record) => record.usn,
+            UsnRecord::V4(record) => record.usn,
+        }
+    }
+
+    /// V4 records do not carry a timestamp, so None is returned for them.
+    pub fn get_timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            UsnRecord::V2(record) => Some(record.timestamp),
+            UsnRecord::V3(record) => Some(record.timestamp),
+            UsnRecord::V4(_) => None,
         }
     }
 
+    /// The raw FILETIME of the timestamp. None for V4 records, as with `get_timestamp`.
+    pub fn get_filetime(&self) -> Option<u64> {
+        match self {
+            UsnRecord::V2(record) => Some(record.filetime),
+            UsnRecord::V3(record) => Some(record.filetime),
+            UsnRecord::V4(_) => None,
+        }
+    }
+
+    /// V4 records do not carry a file name, so an empty string is returned for them.
     pub fn get_file_name(&self) -> String {
         match self {
-            UsnRecord::V2(ref record) => record.file_name.clone(),
-            UsnRecord::V3(ref record) => record.file_name.clone(),
+            UsnRecord::V2(record) => record.file_name.clone(),
+            UsnRecord::V3(record) => record.file_name.clone(),
+            UsnRecord::V4(_) => String::new(),
         }
     }
 
+    /// The name as read, in hex, if it is not valid UTF-16.
+    pub fn get_file_name_hex(&self) -> Option<&str> {
+        match self {
+            UsnRecord::V2(record) => record.file_name_hex.as_deref(),
+            UsnRecord::V3(record) => record.file_name_hex.as_deref(),
+            UsnRecord::V4(_) => None,
+        }
+    }
+
+    /// The name is not valid UTF-16, and the invalid code units are escaped
+    /// in the name given by `get_file_name`.
+    pub fn has_irregular_name(&self) -> bool {
+        match self {
+            UsnRecord::V2(record) => record.irregular_name,
+            UsnRecord::V3(record) => record.irregular_name,
+            UsnRecord::V4(_) => false,
+        }
+    }
+
+    /// V4 records do not carry file attributes, so empty flags are returned for them.
     pub fn get_file_attributes(&self) -> flags::FileAttributes {
         match self {
             UsnRecord::V2(record) => record.file_attributes,
             UsnRecord::V3(record) => record.file_attributes,
+            UsnRecord::V4(_) => flags::FileAttributes::empty(),
         }
     }
 
@@ -162,6 +263,7 @@ impl UsnRecord {
         match self {
             UsnRecord::V2(record) => record.reason,
             UsnRecord::V3(record) => record.reason,
+            UsnRecord::V4(record) => record.reason,
         }
     }
 
@@ -169,6 +271,7 @@ impl UsnRecord {
         match self {
             UsnRecord::V2(record) => record.file_reference,
             UsnRecord::V3(record) => record.file_reference.as_mft_reference(),
+            UsnRecord::V4(record) => record.file_reference.as_mft_reference(),
         }
     }
 
@@ -176,35 +279,110 @@ impl UsnRecord {
         match self {
             UsnRecord::V2(record) => record.parent_reference,
             UsnRecord::V3(record) => record.parent_reference.as_mft_reference(),
+            UsnRecord::V4(record) => record.parent_reference.as_mft_reference(),
+        }
+    }
+
+    pub fn get_record_length(&self) -> u32 {
+        match self {
+            UsnRecord::V2(record) => record.record_length,
+            UsnRecord::V3(record) => record.record_length,
+            UsnRecord::V4(record) => record.record_length,
         }
     }
 
+    pub fn get_major_version(&self) -> u16 {
+        match self {
+            UsnRecord::V2(record) => record.major_version,
+            UsnRecord::V3(record) => record.major_version,
+            UsnRecord::V4(record) => record.major_version,
+        }
+    }
+
+    pub fn get_minor_version(&self) -> u16 {
+        match self {
+            UsnRecord::V2(record) => record.minor_version,
+            UsnRecord::V3(record) => record.minor_version,
+            UsnRecord::V4(record) => record.minor_version,
+        }
+    }
+
+    pub fn get_source_info(&self) -> flags::SourceInfo {
+        match self {
+            UsnRecord::V2(record) => record.source_info,
+            UsnRecord::V3(record) => record.source_info,
+            UsnRecord::V4(record) => record.source_info,
+        }
+    }
+
+    /// V4 records do not carry a security id, so None is returned for them.
+    pub fn get_security_id(&self) -> Option<u32> {
+        match self {
+            UsnRecord::V2(record) => Some(record.security_id),
+            UsnRecord::V3(record) => Some(record.security_id),
+            UsnRecord::V4(_) => None,
+        }
+    }
+
+    /// The whole file reference: the 64 bit reference of a V2 record, or the
+    /// 128 bit file id of a V3 or V4 record.
+    pub fn get_file_id(&self) -> u128 {
+        match self {
+            UsnRecord::V2(record) => reference_to_u128(&record.file_reference),
+            UsnRecord::V3(record) => record.file_reference.as_u128(),
+            UsnRecord::V4(record) => record.file_reference.as_u128(),
+        }
+    }
+
+    /// The whole parent reference, as with `get_file_id`.
+    pub fn get_parent_id(&self) -> u128 {
+        match self {
+            UsnRecord::V2(record) => reference_to_u128(&record.parent_reference),
+            UsnRecord::V3(record) => record.parent_reference.as_u128(),
+            UsnRecord::V4(record) => record.parent_reference.as_u128(),
+        }
+    }
+
+    /// The modified byte ranges of the file. Only V4 records carry extents.
+    pub fn get_extents(&self) -> Option<&[UsnRecordExtent]> {
+        match self {
+            UsnRecord::V4(record) => Some(&record.extents),
+            _ => None,
+        }
+    }
+
+    /// The record as a JSON object, with the raw values of its flags next to
+    /// their names (`reason_code`, `file_attributes_code` and `source_info_code`)
+    /// and the fields of `additional` added.
     pub fn to_json_value(&self, additional: Option<Value>) -> Result<Value, UsnError> {
-        let mut this_value = serde_json::to_value(&self)?;
-
-        match additional {
-            Some(additional_value) => {
-                let value_map = match this_value.as_object_mut() {
-                    Some(map) => map,
-                    None => return Err(
-                        UsnError::json_value_error(
-                            format!("Record json value's object is none. {:?}", self)
-                        )
-                    )
-                };
-
-                let additional_map = match additional_value.as_object() {
-                    Some(map) => map.to_owned(),
-                    None => return Err(
-                        UsnError::json_value_error(
-                            format!("additional value's object is none. {:?}", additional_value)
-                        )
+        let mut this_value = serde_json::to_value(self)?;
+        let value_map = match this_value.as_object_mut() {
+            Some(map) => map,
+            None => return Err(
+                UsnError::json_value_error(
+                    format!("Record json value's object is none. {:?}", self)
+                )
+            )
+        };
+
+        value_map.insert("reason_code".to_string(), Value::from(self.get_reason_code().bits()));
+        if let UsnRecord::V2(_) | UsnRecord::V3(_) = self {
+            value_map.insert("file_attributes_code".to_string(), Value::from(self.get_file_attributes().bits()));
+        }
+        value_map.insert("source_info_code".to_string(), Value::from(self.get_source_info().bits()));
+
+        if let Some(additional_value) = additional {
+
+            let additional_map = match additional_value.as_object() {
+                Some(map) => map.to_owned(),
+                None => return Err(
+                    UsnError::json_value_error(
+                        format!("additional value's object is none. {:?}", additional_value)
                     )
-                };
+                )
+            };
 
-                value_map.extend(additional_map);
-            },
-            None => {}
+            value_map.extend(additional_map);
         }
 
         Ok(this_value)
@@ -224,13 +402,23 @@ pub struct UsnRecordV2 {
     pub parent_reference: MftReference,
     pub usn: u64,
     pub timestamp: DateTime<Utc>,
+    /// The timestamp as read, in 100 nanosecond intervals since 1601.
+    #[serde(skip)]
+    pub filetime: u64,
     pub reason: flags::Reason,
     pub source_info: flags::SourceInfo,
     pub security_id: u32,
     pub file_attributes: flags::FileAttributes,
     pub file_name_length: u16,
     pub file_name_offset: u16,
-    pub file_name: String
+    pub file_name: String,
+    /// The name as read, in hex, when it is not valid UTF-16.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name_hex: Option<String>,
+    /// The name is not valid UTF-16. The invalid code units are escaped in
+    /// `file_name`, such as `\uD800`.
+    #[serde(skip_serializing_if = "is_false")]
+    pub irregular_name: bool
 }
 
 impl UsnRecordV2 {
@@ -274,30 +462,22 @@ impl UsnRecordV2 {
         let file_reference = MftReference::from_reader(&mut buffer)?;
         let parent_reference = MftReference::from_reader(&mut buffer)?;
         let usn = buffer.read_u64::<LittleEndian>()?;
-        let timestamp = u64_to_datetime(
-            buffer.read_u64::<LittleEndian>()?
-        );
-        let reason = flags::Reason::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
-        let source_info = flags::SourceInfo::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
+        let filetime = buffer.read_u64::<LittleEndian>()?;
+        let timestamp = u64_to_datetime(filetime);
+        let reason = flags::Reason::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
+        let source_info = flags::SourceInfo::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
         let security_id = buffer.read_u32::<LittleEndian>()?;
-        let file_attributes = flags::FileAttributes::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
+        let file_attributes = flags::FileAttributes::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
         let file_name_length = buffer.read_u16::<LittleEndian>()?;
         let file_name_offset = buffer.read_u16::<LittleEndian>()?;
 
         let mut name_buffer = vec![0; file_name_length as usize];
         buffer.read_exact(&mut name_buffer)?;
 
-        let file_name = match UTF_16LE.decode(&name_buffer, DecoderTrap::Ignore) {
-            Ok(file_name) => file_name,
-            Err(error) => {
-                return Err(UsnError::utf16_decode_error(
-                    format!(
-                        "Error Decoding Name [hex buffer: {}]: {:?}", 
-                        hex::encode(&name_buffer), 
-                        error
-                    )
-                ));
-            },
+        let (file_name, irregular_name) = decode_utf16_name(&name_buffer);
+        let file_name_hex = match irregular_name {
+            true => Some(hex::encode(&name_buffer)),
+            false => None
         };
 
         Ok(
@@ -309,13 +489,16 @@ impl UsnRecordV2 {
                 parent_reference,
                 usn,
                 timestamp,
+                filetime,
                 reason,
                 source_info,
                 security_id,
                 file_attributes,
                 file_name_length,
                 file_name_offset,
-                file_name
+                file_name,
+                file_name_hex,
+                irregular_name
             }
         )
     }
@@ -332,28 +515,22 @@ impl UsnRecordV2 {
         
         // Read the rest of the fields
         let usn = buffer.read_u64::<LittleEndian>()?;
-        let timestamp = u64_to_datetime(buffer.read_u64::<LittleEndian>()?);
-        let reason = flags::Reason::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
-        let source_info = flags::SourceInfo::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
+        let filetime = buffer.read_u64::<LittleEndian>()?;
+        let timestamp = u64_to_datetime(filetime);
+        let reason = flags::Reason::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
+        let source_info = flags::SourceInfo::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
         let security_id = buffer.read_u32::<LittleEndian>()?;
-        let file_attributes = flags::FileAttributes::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
+        let file_attributes = flags::FileAttributes::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
         let file_name_length = buffer.read_u16::<LittleEndian>()?;
         let file_name_offset = buffer.read_u16::<LittleEndian>()?;
 
         let mut name_buffer = vec![0; file_name_length as usize];
         buffer.read_exact(&mut name_buffer)?;
 
-        let file_name = match UTF_16LE.decode(&name_buffer, DecoderTrap::Ignore) {
-            Ok(file_name) => file_name,
-            Err(error) => {
-                return Err(UsnError::utf16_decode_error(
-                    format!(
-                        "Error Decoding Name [hex buffer: {}]: {:?}", 
-                        hex::encode(&name_buffer), 
-                        error
-                    )
-                ));
-            },
+        let (file_name, irregular_name) = decode_utf16_name(&name_buffer);
+        let file_name_hex = match irregular_name {
+            true => Some(hex::encode(&name_buffer)),
+            false => None
         };
 
         Ok(UsnRecordV2 {
@@ -364,18 +541,30 @@ impl UsnRecordV2 {
             parent_reference,
             usn,
             timestamp,
+            filetime,
             reason,
             source_info,
             security_id,
             file_attributes,
             file_name_length,
             file_name_offset,
-            file_name
+            file_name,
+            file_name_hex,
+            irregular_name
         })
     }
 }
 
 
+pub(crate) fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn reference_to_u128(reference: &MftReference) -> u128 {
+    u128::from(reference.entry) | u128::from(reference.sequence) << 48
+}
+
+
 /// Represents a 128 bit file reference
 ///
 #[derive(Debug)]
@@ -421,13 +610,23 @@ pub struct UsnRecordV3 {
     pub parent_reference: Ntfs128Reference,
     pub usn: u64,
     pub timestamp: DateTime<Utc>,
+    /// The timestamp as read, in 100 nanosecond intervals since 1601.
+    #[serde(skip)]
+    pub filetime: u64,
     pub reason: flags::Reason,
     pub source_info: flags::SourceInfo,
     pub security_id: u32,
     pub file_attributes: flags::FileAttributes,
     pub file_name_length: u16,
     pub file_name_offset: u16,
-    pub file_name: String
+    pub file_name: String,
+    /// The name as read, in hex, when it is not valid UTF-16.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_name_hex: Option<String>,
+    /// The name is not valid UTF-16. The invalid code units are escaped in
+    /// `file_name`, such as `\uD800`.
+    #[serde(skip_serializing_if = "is_false")]
+    pub irregular_name: bool
 }
 impl UsnRecordV3 {
     pub fn new_with_header<T: Read>(
@@ -442,11 +641,12 @@ impl UsnRecordV3 {
 
         // Read the rest of the fields
         let usn = buffer.read_u64::<LittleEndian>()?;
-        let timestamp = u64_to_datetime(buffer.read_u64::<LittleEndian>()?);
-        let reason = flags::Reason::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
-        let source_info = flags::SourceInfo::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
+        let filetime = buffer.read_u64::<LittleEndian>()?;
+        let timestamp = u64_to_datetime(filetime);
+        let reason = flags::Reason::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
+        let source_info = flags::SourceInfo::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
         let security_id = buffer.read_u32::<LittleEndian>()?;
-        let file_attributes = flags::FileAttributes::from_bits_truncate(buffer.read_u32::<LittleEndian>()?);
+        let file_attributes = flags::FileAttributes::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
         let file_name_length = buffer.read_u16::<LittleEndian>()?;
         let file_name_offset = buffer.read_u16::<LittleEndian>()?;
 
@@ -454,17 +654,10 @@ impl UsnRecordV3 {
         let mut name_buffer = vec![0; file_name_length as usize];
         buffer.read_exact(&mut name_buffer)?;
 
-        let file_name = match UTF_16LE.decode(&name_buffer, DecoderTrap::Ignore) {
-            Ok(file_name) => file_name,
-            Err(error) => {
-                return Err(UsnError::utf16_decode_error(
-                    format!(
-                        "Error Decoding Name [hex buffer: {}]: {:?}", 
-                        hex::encode(&name_buffer), 
-                        error
-                    )
-                ));
-            },
+        let (file_name, irregular_name) = decode_utf16_name(&name_buffer);
+        let file_name_hex = match irregular_name {
+            true => Some(hex::encode(&name_buffer)),
+            false => None
         };
 
         Ok(UsnRecordV3 {
@@ -475,13 +668,121 @@ impl UsnRecordV3 {
             parent_reference,
             usn,
             timestamp,
+            filetime,
             reason,
             source_info,
             security_id,
             file_attributes,
             file_name_length,
             file_name_offset,
-            file_name
+            file_name,
+            file_name_hex,
+            irregular_name
+        })
+    }
+}
+
+/// Represents a USN_RECORD_EXTENT structure
+/// https://docs.microsoft.com/en-us/windows/win32/api/winioctl/ns-winioctl-usn_record_extent
+///
+#[derive(Serialize, Debug, Clone)]
+pub struct UsnRecordExtent {
+    pub offset: i64,
+    pub length: i64,
+}
+impl UsnRecordExtent {
+    pub fn from_reader<T: Read>(mut buffer: T) -> Result<UsnRecordExtent, UsnError> {
+        let offset = buffer.read_i64::<LittleEndian>()?;
+        let length = buffer.read_i64::<LittleEndian>()?;
+
+        Ok(UsnRecordExtent {
+            offset,
+            length
+        })
+    }
+}
+
+/// Represents a USN_RECORD_V4 structure
+/// https://docs.microsoft.com/en-us/windows/win32/api/winioctl/ns-winioctl-usn_record_v4
+///
+/// V4 records are written on volumes with range tracking enabled and describe
+/// which byte ranges of a file were modified. They carry no timestamp, file
+/// attributes or file name.
+///
+#[derive(Serialize, Debug)]
+pub struct UsnRecordV4 {
+    pub record_length: u32,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub file_reference: Ntfs128Reference,
+    pub parent_reference: Ntfs128Reference,
+    pub usn: u64,
+    pub reason: flags::Reason,
+    pub source_info: flags::SourceInfo,
+    pub remaining_extents: u32,
+    pub number_of_extents: u16,
+    pub extent_size: u16,
+    pub extents: Vec<UsnRecordExtent>
+}
+impl UsnRecordV4 {
+    pub fn new_with_header<T: Read>(
+        record_length: u32,
+        major_version: u16,
+        minor_version: u16,
+        mut buffer: T
+    ) -> Result<UsnRecordV4, UsnError> {
+        // Read the 128-bit file references
+        let file_reference = Ntfs128Reference(buffer.read_u128::<LittleEndian>()?);
+        let parent_reference = Ntfs128Reference(buffer.read_u128::<LittleEndian>()?);
+
+        // Read the rest of the fields
+        let usn = buffer.read_u64::<LittleEndian>()?;
+        let reason = flags::Reason::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
+        let source_info = flags::SourceInfo::from_bits_retain(buffer.read_u32::<LittleEndian>()?);
+        let remaining_extents = buffer.read_u32::<LittleEndian>()?;
+        let number_of_extents = buffer.read_u16::<LittleEndian>()?;
+        let extent_size = buffer.read_u16::<LittleEndian>()?;
+
+        if extent_size < 16 {
+            return Err(UsnError::invalid_v4_record(
+                format!("Extent size {} is smaller than a USN_RECORD_EXTENT.", extent_size)
+            ));
+        }
+
+        // The extents must fit in the record
+        let extents_length = u32::from(number_of_extents) * u32::from(extent_size);
+        if USN_RECORD_V4_HEADER_SIZE + extents_length > record_length {
+            return Err(UsnError::invalid_v4_record(
+                format!(
+                    "{} extents of size {} do not fit in record length {}.",
+                    number_of_extents, extent_size, record_length
+                )
+            ));
+        }
+
+        // Read the extents. Any bytes past the known extent structure are skipped.
+        let mut extents = Vec::with_capacity(number_of_extents as usize);
+        let mut extent_buffer = vec![0; extent_size as usize];
+        for _ in 0..number_of_extents {
+            buffer.read_exact(&mut extent_buffer)?;
+            extents.push(
+                UsnRecordExtent::from_reader(&extent_buffer[..])?
+            );
+        }
+
+        Ok(UsnRecordV4 {
+            record_length,
+            major_version,
+            minor_version,
+            file_reference,
+            parent_reference,
+            usn,
+            reason,
+            source_info,
+            remaining_extents,
+            number_of_extents,
+            extent_size,
+            extents
         })
     }
-}
\ No newline at end of file
+}
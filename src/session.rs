@@ -0,0 +1,210 @@
+//! File activity sessions. The records of a file from its first record up to
+//! its `CLOSE` are what happened to it during one handle lifetime, and are
+//! summarized as one `FileSession`.
+//!
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Serialize;
+use serde_json::Value;
+use winstructs::ntfs::mft_reference::MftReference;
+use crate::error::UsnError;
+use crate::flags;
+use crate::record::{is_false, SnapshotMeta, UsnEntry};
+use crate::triage::VolumeMeta;
+
+
+/// The number of open sessions kept by default.
+pub const DEFAULT_SESSION_LIMIT: usize = 100_000;
+
+
+/// The records of a file from its first record to its `CLOSE`, or to the end
+/// of the journal for a session that never closed.
+#[derive(Serialize, Debug, Clone)]
+pub struct FileSession {
+    pub file_reference: MftReference,
+    /// The parent of the last record, which differs from the first if the file was moved.
+    pub parent_reference: MftReference,
+    pub start_usn: u64,
+    pub end_usn: u64,
+    /// None for sessions of V4 records only, which carry no timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_timestamp: Option<DateTime<Utc>>,
+    /// The reasons of every record of the session.
+    pub reason: flags::Reason,
+    /// Every name the file had, in the order they were seen.
+    pub file_names: Vec<String>,
+    /// One of the names is not valid UTF-16, and has its invalid code units escaped.
+    #[serde(skip_serializing_if = "is_false")]
+    pub irregular_name: bool,
+    /// The last path enumerated from a folder mapping, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_name: Option<String>,
+    pub record_count: u64,
+    /// The session ended with a `CLOSE` record.
+    pub closed: bool,
+    #[serde(rename(serialize = "meta__source"))]
+    pub source: String,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<VolumeMeta>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<SnapshotMeta>,
+}
+
+impl FileSession {
+    /// Start a session with its first entry.
+    pub fn new(entry: &UsnEntry, full_name: Option<&str>) -> Self {
+        let record = &entry.record;
+        let mut session = FileSession {
+            file_reference: record.get_file_reference(),
+            parent_reference: record.get_parent_reference(),
+            start_usn: record.get_usn(),
+            end_usn: record.get_usn(),
+            start_timestamp: None,
+            end_timestamp: None,
+            reason: flags::Reason::empty(),
+            file_names: Vec::new(),
+            irregular_name: false,
+            full_name: None,
+            record_count: 0,
+            closed: false,
+            source: entry.meta.source.clone(),
+            volume: entry.meta.volume.clone(),
+            snapshot: entry.meta.snapshot.clone(),
+        };
+        session.add(entry, full_name);
+        session
+    }
+
+    /// Add the next entry of the file.
+    pub fn add(&mut self, entry: &UsnEntry, full_name: Option<&str>) {
+        let record = &entry.record;
+
+        self.parent_reference = record.get_parent_reference();
+        self.end_usn = record.get_usn();
+        if let Some(timestamp) = record.get_timestamp() {
+            self.start_timestamp.get_or_insert(timestamp);
+            self.end_timestamp = Some(timestamp);
+        }
+        self.reason = flags::Reason::from_bits_retain(self.reason.bits() | record.get_reason_code().bits());
+
+        // V4 records carry no name
+        let file_name = record.get_file_name();
+        if !file_name.is_empty() && !self.file_names.contains(&file_name) {
+            self.file_names.push(file_name);
+        }
+        self.irregular_name |= record.has_irregular_name();
+        if let Some(full_name) = full_name {
+            self.full_name = Some(full_name.to_string());
+        }
+        self.record_count += 1;
+    }
+
+    /// The session as a JSON object, with the raw value of its reasons in `reason_code`.
+    pub fn to_json_value(&self) -> Result<Value, UsnError> {
+        let mut value = serde_json::to_value(self)?;
+        let map = value.as_object_mut().ok_or_else(||
+            UsnError::json_value_error(format!("Session json value's object is none. {:?}", self))
+        )?;
+        map.insert("reason_code".to_string(), Value::from(self.reason.bits()));
+        Ok(value)
+    }
+}
+
+
+/// Groups entries into sessions per file of each source. Entries must be
+/// added in USN order. The number of open sessions is bounded: when the limit
+/// is reached, the least recently written session is ended as if it never
+/// closed.
+pub struct SessionAggregator {
+    open: LruCache<(String, MftReference), FileSession>,
+    flush_unclosed: bool,
+}
+
+impl Default for SessionAggregator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionAggregator {
+    pub fn new() -> Self {
+        SessionAggregator {
+            open: LruCache::new(DEFAULT_SESSION_LIMIT),
+            flush_unclosed: false,
+        }
+    }
+
+    /// The number of open sessions kept before the oldest is ended.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.open.resize(limit.max(1));
+        self
+    }
+
+    /// Return sessions that never closed, when they are ended by the limit
+    /// or by `finish`, instead of dropping them.
+    pub fn with_flush_unclosed(mut self, flush_unclosed: bool) -> Self {
+        self.flush_unclosed = flush_unclosed;
+        self
+    }
+
+    pub fn get_limit(&self) -> usize {
+        self.open.cap()
+    }
+
+    pub fn get_flush_unclosed(&self) -> bool {
+        self.flush_unclosed
+    }
+
+    /// The number of sessions waiting for their `CLOSE`.
+    pub fn open_count(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Add an entry, and return the sessions it ended: its own session if it
+    /// is a `CLOSE` record, and the session it pushed out over the limit.
+    pub fn add(&mut self, entry: &UsnEntry, full_name: Option<&str>) -> Vec<FileSession> {
+        let key = (entry.meta.source.clone(), entry.record.get_file_reference());
+        let mut ended = Vec::new();
+
+        match self.open.get_mut(&key) {
+            Some(session) => session.add(entry, full_name),
+            None => {
+                // The cache would drop the oldest session without returning it
+                if self.open.len() >= self.open.cap() {
+                    if let Some((_, session)) = self.open.pop_lru() {
+                        if self.flush_unclosed {
+                            ended.push(session);
+                        }
+                    }
+                }
+                self.open.put(key.clone(), FileSession::new(entry, full_name));
+            }
+        }
+
+        if entry.record.get_reason_code().contains(flags::Reason::CLOSE) {
+            if let Some(mut session) = self.open.pop(&key) {
+                session.closed = true;
+                ended.push(session);
+            }
+        }
+
+        ended
+    }
+
+    /// End every open session. They are returned in USN order if sessions
+    /// that never closed are flushed, and dropped otherwise.
+    pub fn finish(&mut self) -> Vec<FileSession> {
+        let mut sessions = Vec::with_capacity(self.open.len());
+        while let Some((_, session)) = self.open.pop_lru() {
+            sessions.push(session);
+        }
+
+        if !self.flush_unclosed {
+            return Vec::new();
+        }
+        sessions.sort_by_key(|session| session.start_usn);
+        sessions
+    }
+}
@@ -0,0 +1,191 @@
+//! Discovery of change journals in triage collections. KAPE, Velociraptor and
+//! CyLR each lay out collected files differently, but they all keep the `$J`
+//! of a volume under that volume's folder, next to its `$MFT`.
+//!
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use serde::Serialize;
+
+
+// Folders Velociraptor puts between the collection and the volume folders
+const COLLECTOR_FOLDERS: &[&str] = &["uploads", "auto", "ntfs", "mft", "file"];
+
+lazy_static! {
+    // Collection-<host>-<UTC time>, as named by the Velociraptor offline collector
+    static ref RE_COLLECTION: Regex = Regex::new(
+        r"^Collection-(.+?)-\d{4}-\d{2}-\d{2}T[\d_:]+Z?$"
+    ).expect("Regex error");
+}
+
+
+/// The volume and host a journal was collected from.
+///
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct VolumeMeta {
+    #[serde(rename(serialize = "meta__volume"))]
+    pub volume: String,
+    #[serde(rename(serialize = "meta__host"), skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+
+/// A `$J` found in a collection, with the `$MFT` of the same volume if it was collected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriageVolume {
+    pub journal: PathBuf,
+    pub mft: Option<PathBuf>,
+    pub meta: VolumeMeta,
+}
+
+
+/// Recursively find every change journal under `root`. Journals are returned
+/// sorted by path.
+pub fn discover(root: &Path) -> io::Result<Vec<TriageVolume>> {
+    let mut files = Vec::new();
+    find_files(root, &mut files)?;
+    Ok(pair_files(root, &files))
+}
+
+/// Find the journals among collected `files` under `root`, and pair each with
+/// the `$MFT` of its volume folder. Used for directories and the member names
+/// of archives alike. Journals are returned sorted by path.
+pub fn pair_files(root: &Path, files: &[PathBuf]) -> Vec<TriageVolume> {
+    let mut journals: Vec<&PathBuf> = files.iter()
+        .filter(|path| is_journal_name(&path.file_name().unwrap_or_default().to_string_lossy()))
+        .collect();
+    journals.sort();
+
+    journals.into_iter()
+        .map(|journal| pair_volume(root, journal, files))
+        .collect()
+}
+
+/// Check if a collected file name is a `$J`. Collectors save the `$UsnJrnl:$J`
+/// stream as `$J`, `$UsnJrnl$J`, `$UsnJrnl_$J` or the URL encoded `$UsnJrnl%3A$J`.
+pub fn is_journal_name(name: &str) -> bool {
+    decode_name(name).to_uppercase().ends_with("$J")
+}
+
+/// Decode the percent encoding Velociraptor uses for file names, such as
+/// `%24MFT` or `%5C%5C.%5CC%3A`.
+pub fn decode_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = match bytes.get(index + 1..index + 3) {
+            Some(hex) if bytes[index] == b'%' => std::str::from_utf8(hex).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            },
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+
+/// Find the journals and $MFT files under `directory`.
+fn find_files(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                error!("error reading entry of {}: {}", directory.display(), error);
+                continue;
+            }
+        };
+
+        // Symbolic links are not followed so a link loop cannot recurse forever
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(error) => {
+                error!("error reading type of {}: {}", entry.path().display(), error);
+                continue;
+            }
+        };
+
+        if file_type.is_dir() {
+            if let Err(error) = find_files(&entry.path(), files) {
+                error!("error reading directory {}: {}", entry.path().display(), error);
+            }
+        } else if file_type.is_file() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_journal_name(&name) || decode_name(&name).eq_ignore_ascii_case("$MFT") {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the volume folder of a journal, its `$MFT` and the volume and host names.
+fn pair_volume(root: &Path, journal: &Path, files: &[PathBuf]) -> TriageVolume {
+    let mut volume_folder = journal.parent().unwrap_or(root).to_path_buf();
+    if folder_name(&volume_folder).eq_ignore_ascii_case("$Extend") && volume_folder != root {
+        if let Some(parent) = volume_folder.parent() {
+            volume_folder = parent.to_path_buf();
+        }
+    }
+
+    let mft = files.iter()
+        .find(|path| path.parent() == Some(volume_folder.as_path()) &&
+            folder_name(path).eq_ignore_ascii_case("$MFT"))
+        .cloned();
+
+    TriageVolume {
+        journal: journal.to_path_buf(),
+        mft,
+        meta: VolumeMeta {
+            volume: volume_name(&folder_name(&volume_folder)),
+            host: host_name(root, &volume_folder)
+        }
+    }
+}
+
+/// The decoded last component of a path.
+fn folder_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| decode_name(&name.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+/// Velociraptor names volume folders after the device path, such as `\\.\C:`
+/// or `C:`, while KAPE and CyLR use the drive letter alone.
+fn volume_name(folder_name: &str) -> String {
+    folder_name.trim_start_matches(['\\', '.'])
+        .trim_end_matches(':')
+        .to_string()
+}
+
+/// The host is the first folder above the volume folder, skipping the folders
+/// Velociraptor adds. Velociraptor collections are named `Collection-<host>-<time>`.
+fn host_name(root: &Path, volume_folder: &Path) -> Option<String> {
+    if volume_folder == root {
+        return None;
+    }
+
+    volume_folder.ancestors()
+        .skip(1)
+        .take_while(|folder| folder.starts_with(root))
+        .map(folder_name)
+        .find(|name| !name.is_empty() && !COLLECTOR_FOLDERS.contains(&name.to_lowercase().as_str()))
+        .map(|name| match RE_COLLECTION.captures(&name) {
+            Some(captures) => captures[1].to_string(),
+            None => name
+        })
+}
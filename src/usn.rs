@@ -2,15 +2,21 @@
 use rayon;
 use std::io;
 use regex::bytes;
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::fs::File;
 use std::io::SeekFrom;
+use std::ops::Range;
 #[cfg(feature = "multithreading")]
 use rayon::prelude::*;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use serde::Serialize;
+use chrono::{DateTime, Utc};
 use byteorder::{ByteOrder, LittleEndian};
 use crate::ReadSeek;
-use crate::record::{EntryMeta, UsnEntry};
+use crate::error::{ErrorKind, UsnError};
+use crate::confidence::Confidence;
+use crate::triage::VolumeMeta;
+use crate::record::{EntryMeta, SnapshotMeta, UsnEntry, USN_RECORD_V4_HEADER_SIZE};
 
 
 // This is the size of data chunks
@@ -20,24 +26,42 @@ const SIZE_CHUNK: usize = 17408;
 // It has been noticed that generally usn records are paged in 4096 byte pages. I have not
 // observed usn records overlaping the 4096 offset and are zero padded to the 4096 mark.
 const SIZE_SEARCH: usize = 16384;
+// Records are padded so that they do not cross a 4096 byte page
+const SIZE_PAGE: u64 = 4096;
+// The number of bytes kept from a rejected hit
+const SIZE_DIAGNOSTIC_HEADER: usize = 64;
 
 lazy_static! {
     static ref RE_USN: bytes::Regex = bytes::Regex::new(
-        "(?-u)..\x00\x00(\x02|\x03)\x00\x00\x00"
+        "(?-u)..\x00\x00(\x02|\x03|\x04)\x00\x00\x00"
     ).expect("Regex Error");
 }
 
 
-pub struct UsnParserSettings{
-    thread_count: usize
+/// How records are found in the data.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanMode {
+    /// Search for record headers anywhere in the data. Use for unallocated
+    /// space and other data that is not a $J.
+    #[default]
+    Carve,
+    /// Follow `record_length` from one record to the next, skipping the zero
+    /// padding at the end of each page. Use for allocated $J files.
+    Strict,
 }
 
-impl Default for UsnParserSettings {
-    fn default() -> Self {
-        UsnParserSettings {
-            thread_count: 0
-        }
-    }
+
+#[derive(Default, Clone)]
+pub struct UsnParserSettings{
+    thread_count: usize,
+    scan_mode: ScanMode,
+    min_confidence: u8,
+    latest_timestamp: Option<DateTime<Utc>>,
+    usn_start: Option<u64>,
+    usn_end: Option<u64>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>
 }
 
 impl UsnParserSettings {
@@ -58,6 +82,64 @@ impl UsnParserSettings {
         self
     }
 
+    /// Sets how records are found. Defaults to `ScanMode::Carve`.
+    pub fn scan_mode(mut self, scan_mode: ScanMode) -> Self {
+        self.scan_mode = scan_mode;
+        self
+    }
+
+    /// Drop records with a confidence score below `min_confidence` (0 to 100).
+    pub fn min_confidence(mut self, min_confidence: u8) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
+
+    /// The latest plausible record timestamp, such as the acquisition time of
+    /// the source. Later timestamps lower the confidence score. Unset, only
+    /// timestamps before 1999 do.
+    pub fn latest_timestamp(mut self, latest_timestamp: Option<DateTime<Utc>>) -> Self {
+        self.latest_timestamp = latest_timestamp;
+        self
+    }
+
+    /// Only return records with a USN from `start` to `end`, inclusive. The
+    /// first and last pages of the range are found with a binary search over
+    /// the pages of the journal, so only allocated $J files should be ranged.
+    pub fn usn_range(mut self, start: Option<u64>, end: Option<u64>) -> Self {
+        self.usn_start = start;
+        self.usn_end = end;
+        self
+    }
+
+    /// Only return records with a timestamp from `since` to `until`, inclusive.
+    /// V4 records have no timestamp and are kept if they are within the pages
+    /// found for the range.
+    pub fn time_range(mut self, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> Self {
+        self.since = since;
+        self.until = until;
+        self
+    }
+
+    fn has_range(&self) -> bool {
+        self.usn_start.is_some() || self.usn_end.is_some() ||
+            self.since.is_some() || self.until.is_some()
+    }
+
+    /// Check a record against the USN and time ranges.
+    fn in_range(&self, entry: &UsnEntry) -> bool {
+        let usn = entry.record.get_usn();
+        if self.usn_start.is_some_and(|start| usn < start) ||
+            self.usn_end.is_some_and(|end| usn > end) {
+            return false;
+        }
+
+        match entry.record.get_timestamp() {
+            Some(timestamp) => !(self.since.is_some_and(|since| timestamp < since) ||
+                self.until.is_some_and(|until| timestamp > until)),
+            None => true
+        }
+    }
+
     /// Does nothing and emits a warning when complied without multithreading.
     #[cfg(not(feature = "multithreading"))]
     pub fn thread_count(mut self, _thread_count: usize) -> Self {
@@ -70,19 +152,32 @@ impl UsnParserSettings {
 
 pub struct UsnParser<T: ReadSeek> {
     inner_handle: T,
-    source: String,
+    meta: EntryMeta,
     handle_size: u64,
+    data_ranges: Option<Vec<Range<u64>>>,
+    skipped_bytes: u64,
+    break_offsets: Vec<u64>,
+    rejection_counts: BTreeMap<ErrorKind, u64>,
+    // Read errors waiting to be returned by `results`
+    pending_diagnostics: Vec<Diagnostic>,
+    // The byte range to scan, once the USN and time ranges are resolved
+    scan_range: Option<Range<u64>>,
     settings: UsnParserSettings
 }
 
 impl UsnParser<File> {
+    /// Open a file to parse. The holes of a sparse file are not read.
     pub fn from_path(filename: &str) -> Result<Self, io::Error> {
         let file_handle = File::open(filename)?;
+        let data_ranges = sparse_data_ranges(&file_handle);
 
-        Self::from_read_seek(
+        let mut parser = Self::from_read_seek(
             filename.to_string(),
             file_handle
-        )
+        )?;
+        parser.data_ranges = data_ranges;
+
+        Ok(parser)
     }
 }
 
@@ -95,19 +190,246 @@ impl <T: ReadSeek> UsnParser <T> {
         inner_handle.seek(SeekFrom::Start(0))?;
 
         Ok( Self {
-            inner_handle: inner_handle,
-            source: source,
+            inner_handle,
+            meta: EntryMeta::new(&source, 0),
             handle_size: end_offset,
+            data_ranges: None,
+            skipped_bytes: 0,
+            break_offsets: Vec::new(),
+            rejection_counts: BTreeMap::new(),
+            pending_diagnostics: Vec::new(),
+            scan_range: None,
             settings: UsnParserSettings::default()
         })
     }
 
     pub fn with_configuration(mut self, configuration: UsnParserSettings) -> Self {
+        self.meta.scan_mode = configuration.scan_mode;
         self.settings = configuration;
+        self.scan_range = None;
+        self
+    }
+
+    /// Tag every entry with the volume and host the journal was collected from.
+    pub fn with_volume(mut self, volume: VolumeMeta) -> Self {
+        self.meta.volume = Some(volume);
+        self
+    }
+
+    /// Tag every entry with the volume shadow copy the handle reads from.
+    pub fn with_snapshot(mut self, snapshot: SnapshotMeta) -> Self {
+        self.meta.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Only scan the given byte ranges of the handle, such as the allocated
+    /// runs of a sparse $J. Ranges must be sorted and must not overlap.
+    ///
+    pub fn with_data_ranges(mut self, data_ranges: Vec<Range<u64>>) -> Self {
+        self.data_ranges = Some(data_ranges);
         self
     }
 
-    pub fn get_chunk_iterator(&mut self) -> IterFileChunks<T> {
+    pub fn get_source(&self) -> &str {
+        &self.meta.source
+    }
+
+    /// The number of bytes that were not scanned so far, because they were
+    /// outside of the data ranges or were entirely zero.
+    pub fn get_skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// The offsets where records stopped following each other when walking in
+    /// `ScanMode::Strict`, in order.
+    pub fn get_break_offsets(&self) -> &[u64] {
+        &self.break_offsets
+    }
+
+    /// The number of rejected hits and unreadable chunks so far, by error kind.
+    pub fn get_rejection_counts(&self) -> &BTreeMap<ErrorKind, u64> {
+        &self.rejection_counts
+    }
+
+    /// The offset of the page holding the first record with a USN of at least `usn`.
+    pub fn find_usn_offset(&mut self, usn: u64) -> io::Result<u64> {
+        let first_page = self.search_pages(|entry| Some(entry.record.get_usn() < usn))?;
+        Ok(first_page.saturating_sub(SIZE_PAGE))
+    }
+
+    /// The offset of the page holding the first record with a timestamp of at
+    /// least `time`.
+    pub fn find_time_offset(&mut self, time: DateTime<Utc>) -> io::Result<u64> {
+        let first_page = self.search_pages(|entry| entry.record.get_timestamp().map(|timestamp| timestamp < time))?;
+        Ok(first_page.saturating_sub(SIZE_PAGE))
+    }
+
+    /// Binary search for the offset of the first page whose first record is not
+    /// before the target. Records are in USN and time order, and each page starts
+    /// with a record. Pages without records are taken to be before the target,
+    /// as they are the cleared head of the journal.
+    fn search_pages<F: Fn(&UsnEntry) -> Option<bool>>(&mut self, is_before: F) -> io::Result<u64> {
+        let mut low = 0;
+        let mut high = self.handle_size.div_ceil(SIZE_PAGE);
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.probe_page(middle * SIZE_PAGE, &is_before)?.unwrap_or(true) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        Ok(low * SIZE_PAGE)
+    }
+
+    /// Walk the page at `offset` and check the first record with a key.
+    fn probe_page<F: Fn(&UsnEntry) -> Option<bool>>(&mut self, offset: u64, is_before: &F) -> io::Result<Option<bool>> {
+        let mut page = vec![0u8; SIZE_PAGE as usize];
+        self.inner_handle.seek(SeekFrom::Start(offset))?;
+        if read_fully(&mut self.inner_handle, &mut page)? == 0 {
+            return Ok(None);
+        }
+
+        let mut meta = self.meta.at_offset(offset);
+        meta.scan_mode = ScanMode::Strict;
+        let mut records = IterRecords::with_meta(meta, page, offset, SIZE_PAGE as usize);
+        while let Some(result) = records.next_result() {
+            if let Some(before) = result.ok().as_ref().and_then(is_before) {
+                return Ok(Some(before));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Narrow the bytes to scan to the pages that can hold the USN and time
+    /// ranges of the settings. The whole handle is scanned if a search fails.
+    fn resolve_scan_range(&mut self) {
+        if self.scan_range.is_some() {
+            return;
+        }
+
+        let mut scan_range = 0..self.handle_size;
+        if self.settings.has_range() {
+            let settings = self.settings.clone();
+            let result = (|| -> io::Result<Range<u64>> {
+                let mut start = 0;
+                let mut end = self.handle_size;
+                if let Some(usn) = settings.usn_start {
+                    start = max(start, self.find_usn_offset(usn)?);
+                }
+                if let Some(since) = settings.since {
+                    start = max(start, self.find_time_offset(since)?);
+                }
+                if let Some(usn) = settings.usn_end {
+                    end = min(end, self.search_pages(|entry| Some(entry.record.get_usn() <= usn))?);
+                }
+                if let Some(until) = settings.until {
+                    end = min(end, self.search_pages(
+                        |entry| entry.record.get_timestamp().map(|timestamp| timestamp <= until)
+                    )?);
+                }
+                Ok(start..max(start, end))
+            })();
+
+            match result {
+                Ok(range) => {
+                    debug!("scanning offsets {} to {} for the requested range", range.start, range.end);
+                    scan_range = range;
+                },
+                Err(error) => error!("error searching for the requested range, scanning everything: {}", error)
+            }
+        }
+
+        self.scan_range = Some(scan_range);
+    }
+
+    /// The offset to stop reading chunks at.
+    fn scan_end(&self) -> u64 {
+        match self.scan_range {
+            Some(ref range) => min(range.end, self.handle_size),
+            None => self.handle_size
+        }
+    }
+
+    /// Get the offset to read the chunk at, skipping anything outside of the data ranges.
+    /// Returns None when there is nothing left to read.
+    fn next_chunk_offset(&self, offset: u64) -> Option<u64> {
+        let next_offset = match self.data_ranges {
+            Some(ref ranges) => ranges.iter()
+                .find(|range| range.end > offset)
+                .map(|range| max(range.start, offset))?,
+            None => offset
+        };
+
+        if next_offset < self.scan_end() {
+            Some(next_offset)
+        } else {
+            None
+        }
+    }
+
+    /// Read the next chunk to search at or after `offset`. Search windows that
+    /// are entirely zero, such as the cleared head of a $J, are skipped without
+    /// creating a chunk.
+    fn read_chunk(&mut self, offset: u64, chunk_size: usize, search_size: usize) -> Option<DataChunk> {
+        let scan_start = self.scan_range.as_ref().map_or(0, |range| range.start);
+        let mut offset = max(offset, scan_start);
+
+        loop {
+            let current_offset = match self.next_chunk_offset(offset) {
+                Some(current_offset) => current_offset,
+                None => {
+                    self.skipped_bytes += self.scan_end().saturating_sub(offset);
+                    return None;
+                }
+            };
+            self.skipped_bytes += current_offset - offset;
+
+            // Create buffer for our data chunk
+            let mut buffer = vec![0u8; chunk_size];
+
+            // Increment by search size and not chunk size
+            offset = current_offset + search_size as u64;
+
+            // Seek to where we start our chunk and read into the buffer. Streams
+            // over images can return short reads at run boundaries, so keep
+            // reading until the buffer is full. Unreadable chunks are skipped.
+            let read_result = self.inner_handle.seek(SeekFrom::Start(current_offset))
+                .and_then(|_| read_fully(&mut self.inner_handle, &mut buffer));
+            if let Err(error) = read_result {
+                error!("error reading chunk at offset {}: {}", current_offset, error);
+                self.pending_diagnostics.push(Diagnostic {
+                    offset: current_offset,
+                    error: UsnError::from(error),
+                    raw_header: Vec::new()
+                });
+                continue;
+            }
+
+            if is_zeroed(&buffer[..search_size]) {
+                self.skipped_bytes += (search_size as u64).min(self.scan_end() - current_offset);
+                continue;
+            }
+
+            return Some(
+                DataChunk{
+                    meta: self.meta.clone(),
+                    offset: current_offset,
+                    search_size,
+                    min_confidence: self.settings.min_confidence,
+                    latest_timestamp: self.settings.latest_timestamp,
+                    data: buffer
+                }
+            );
+        }
+    }
+
+    pub fn get_chunk_iterator(&mut self) -> IterFileChunks<'_, T> {
+        self.resolve_scan_range();
+
         IterFileChunks{
             parser: self,
             chunk_size: SIZE_CHUNK,
@@ -116,7 +438,9 @@ impl <T: ReadSeek> UsnParser <T> {
         }
     }
 
-    pub fn into_chunk_iterator(self) -> IntoIterFileChunks<T> {
+    pub fn into_chunk_iterator(mut self) -> IntoIterFileChunks<T> {
+        self.resolve_scan_range();
+
         IntoIterFileChunks {
             parser: self,
             chunk_size: SIZE_CHUNK,
@@ -125,12 +449,34 @@ impl <T: ReadSeek> UsnParser <T> {
         }
     }
 
+    /// Iterate the parsed records. Rejected hits are counted in
+    /// `get_rejection_counts`, see `results` to inspect them.
     pub fn records(&mut self) -> impl Iterator<Item = UsnEntry> + '_ {
+        self.results().filter_map(Result::ok)
+    }
+
+    /// Iterate the parsed records along with a diagnostic for every rejected
+    /// hit and every chunk that could not be read.
+    pub fn results(&mut self) -> impl Iterator<Item = RecordResult> + '_ {
+        self.result_batches().flatten()
+    }
+
+    /// Iterate the parsed records in batches, one per set of chunks parsed
+    /// in parallel, for consumers that write in batches.
+    pub fn record_batches(&mut self) -> impl Iterator<Item = Vec<UsnEntry>> + '_ {
+        self.result_batches()
+            .map(|results| results.into_iter().filter_map(Result::ok).collect::<Vec<UsnEntry>>())
+            .filter(|entries| !entries.is_empty())
+    }
+
+    /// Like `results`, but yielding the results of each set of chunks parsed
+    /// in parallel together.
+    pub fn result_batches(&mut self) -> impl Iterator<Item = Vec<RecordResult>> + '_ {
         let num_threads = max(self.settings.thread_count, 1);
 
         let mut chunks = self.get_chunk_iterator();
 
-        let records_per_chunk = std::iter::from_fn(move || 
+        let results_per_chunk = std::iter::from_fn(move || 
             {
                 // Allocate some chunks in advance, so they can be parsed in parallel.
                 let mut list_of_chunks = Vec::with_capacity(num_threads);
@@ -141,8 +487,13 @@ impl <T: ReadSeek> UsnParser <T> {
                     };
                 }
 
+                let read_errors: Vec<RecordResult> = chunks.parser.pending_diagnostics
+                    .drain(..)
+                    .map(Err)
+                    .collect();
+
                 // We only stop once no chunks can be allocated.
-                if list_of_chunks.is_empty() {
+                if list_of_chunks.is_empty() && read_errors.is_empty() {
                     None
                 } else {
                     #[cfg(feature = "multithreading")]
@@ -152,20 +503,139 @@ impl <T: ReadSeek> UsnParser <T> {
                     let chunk_iter = list_of_chunks.into_iter();
 
                     // Serialize the records in each chunk.
-                    let iterators: Vec<Vec<UsnEntry>> = chunk_iter
-                        .map(|data_chunk| data_chunk.get_records()
-                        )
+                    let iterators: Vec<(Vec<RecordResult>, Vec<u64>)> = chunk_iter
+                        .map(|data_chunk| {
+                            let mut record_iterator = data_chunk.get_record_iterator();
+                            let results: Vec<RecordResult> = std::iter::from_fn(
+                                || record_iterator.next_result()
+                            ).collect();
+                            (results, record_iterator.into_break_offsets())
+                        })
                         .collect();
 
-                    Some(iterators.into_iter().flatten())
+                    let mut chunk_results = vec![read_errors];
+                    for (mut results, break_offsets) in iterators {
+                        chunks.parser.break_offsets.extend(break_offsets);
+                        if chunks.parser.settings.has_range() {
+                            let settings = &chunks.parser.settings;
+                            results.retain(|result| match result {
+                                Ok(entry) => settings.in_range(entry),
+                                Err(_) => true
+                            });
+                        }
+                        chunk_results.push(results);
+                    }
+
+                    for diagnostic in chunk_results.iter().flatten().filter_map(|result| result.as_ref().err()) {
+                        *chunks.parser.rejection_counts.entry(diagnostic.kind()).or_insert(0) += 1;
+                    }
+
+                    Some(chunk_results.into_iter().flatten().collect())
                 }
             }
         );
 
-        records_per_chunk.flatten()
+        results_per_chunk
+    }
+}
+
+
+/// A parsed record, or the diagnostic for a rejected hit.
+pub type RecordResult = Result<UsnEntry, Diagnostic>;
+
+
+/// A rejected record hit, or a chunk that could not be read.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// The absolute offset of the hit or chunk.
+    pub offset: u64,
+    pub error: UsnError,
+    /// Up to the first 64 bytes at the offset. Empty for read errors.
+    pub raw_header: Vec<u8>,
+}
+
+impl Diagnostic {
+    fn from_hit(block: &[u8], i: usize, offset: u64, error: UsnError) -> Diagnostic {
+        let end = min(i + SIZE_DIAGNOSTIC_HEADER, block.len());
+        Diagnostic {
+            offset,
+            error,
+            raw_header: block[i..end].to_vec()
+        }
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.error.kind
+    }
+}
+
+
+/// Fill the buffer from the handle, stopping early only at the end of the data.
+fn read_fully<T: ReadSeek>(handle: &mut T, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut bytes_read = 0;
+    while bytes_read < buffer.len() {
+        match handle.read(&mut buffer[bytes_read..]) {
+            Ok(0) => break,
+            Ok(count) => bytes_read += count,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error)
+        }
+    }
+    Ok(bytes_read)
+}
+
+/// Check if a buffer is all zeros, eight bytes at a time.
+fn is_zeroed(buffer: &[u8]) -> bool {
+    let words = buffer.chunks_exact(8);
+    let remainder = words.remainder();
+    words.into_iter().all(|word| LittleEndian::read_u64(word) == 0) &&
+        remainder.iter().all(|byte| *byte == 0)
+}
+
+/// The ranges of a sparse file that hold data, found with SEEK_DATA and
+/// SEEK_HOLE. Returns None if the file has no holes or they can not be queried.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn sparse_data_ranges(file: &File) -> Option<Vec<Range<u64>>> {
+    use std::os::unix::io::AsRawFd;
+
+    let size = file.metadata().ok()?.len();
+    let fd = file.as_raw_fd();
+    let mut ranges = Vec::new();
+    let mut offset = 0u64;
+
+    while offset < size {
+        let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            // ENXIO means there is no more data after the offset
+            match io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENXIO) => break,
+                _ => return None
+            }
+        }
+
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return None;
+        }
+
+        ranges.push(data as u64..hole as u64);
+        offset = hole as u64;
+    }
+
+    if ranges.len() == 1 && ranges[0] == (0..size) {
+        return None;
+    }
+
+    debug!("sparse file has {} data ranges", ranges.len());
+    Some(ranges)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn sparse_data_ranges(_file: &File) -> Option<Vec<Range<u64>>> {
+    None
 }
 
+
 pub struct IterFileChunks<'c, T: ReadSeek> {
     parser: &'c mut UsnParser<T>,
     // The chunk size is larger than the parse size to ensure complete end record
@@ -180,51 +650,16 @@ impl <'c, T: ReadSeek> Iterator for IterFileChunks <'c, T> {
     type Item = DataChunk;
 
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-        while self.chunk_start_offset < self.parser.handle_size {
-            // Create buffer for our data chunk
-            let mut buffer = vec![0u8; self.chunk_size];
-
-            // Get the current offset
-            let current_offset = self.chunk_start_offset;
-            
-            // Seek to where we start our chunk
-            match self.parser.inner_handle.seek(
-                SeekFrom::Start(current_offset)
-            ) {
-                Ok(_) => {},
-                Err(error) => {
-                    error!("{}", error);
-                    break;
-                }
-            }
-
-            // Read into buffer
-            let _bytes_read = match self.parser.inner_handle.read(
-                buffer.as_mut_slice()
-            ){
-                Ok(bytes_read) => bytes_read,
-                Err(error) => {
-                    error!("{}", error);
-                    return None
-                }
-            };
+        let chunk = self.parser.read_chunk(
+            self.chunk_start_offset,
+            self.chunk_size,
+            self.search_size
+        )?;
 
-            // Set the next chunk's offset
-            // Increment by search size and not chunk size
-            self.chunk_start_offset += self.search_size as u64;
+        // Set the next chunk's offset
+        self.chunk_start_offset = chunk.offset + self.search_size as u64;
 
-            // Return data chunk
-            return Some(
-                DataChunk{
-                    source: self.parser.source.to_owned(),
-                    offset: current_offset,
-                    search_size: self.search_size,
-                    data: buffer
-                }
-            );
-        }
-        
-        None
+        Some(chunk)
     }
 }
 
@@ -240,60 +675,27 @@ impl<T: ReadSeek> Iterator for IntoIterFileChunks<T> {
     type Item = DataChunk;
     
     fn next(&mut self) -> Option<<Self as Iterator>::Item> {
-        while self.chunk_start_offset < self.parser.handle_size {
-            // Create buffer for our data chunk
-            let mut buffer = vec![0u8; self.chunk_size];
-
-            // Get the current offset
-            let current_offset = self.chunk_start_offset;
-            
-            // Seek to where we start our chunk
-            match self.parser.inner_handle.seek(
-                SeekFrom::Start(current_offset)
-            ) {
-                Ok(_) => {},
-                Err(error) => {
-                    error!("{}", error);
-                    break;
-                }
-            }
-
-            // Read into buffer
-            let _bytes_read = match self.parser.inner_handle.read(
-                buffer.as_mut_slice()
-            ){
-                Ok(bytes_read) => bytes_read,
-                Err(error) => {
-                    error!("{}", error);
-                    return None
-                }
-            };
+        let chunk = self.parser.read_chunk(
+            self.chunk_start_offset,
+            self.chunk_size,
+            self.search_size
+        )?;
 
-            // Set the next chunk's offset
-            // Increment by search size and not chunk size
-            self.chunk_start_offset += self.search_size as u64;
+        // Set the next chunk's offset
+        self.chunk_start_offset = chunk.offset + self.search_size as u64;
 
-            // Return data chunk
-            return Some(
-                DataChunk{
-                    source: self.parser.source.to_owned(),
-                    offset: current_offset,
-                    search_size: self.search_size,
-                    data: buffer
-                }
-            );
-        }
-        
-        None
+        Some(chunk)
     }
 }
 
 
 #[derive(Debug)]
 pub struct DataChunk {
-    source: String,
+    meta: EntryMeta,
     offset: u64,
     search_size: usize,
+    min_confidence: u8,
+    latest_timestamp: Option<DateTime<Utc>>,
     data: Vec<u8>
 }
 
@@ -303,196 +705,284 @@ impl DataChunk {
 
         let record_iterator = self.get_record_iterator();
 
-        let records: Vec<UsnEntry> = record_iterator.collect();
-
-        return records;
+        record_iterator.collect()
     }
 
     pub fn get_record_iterator(self) -> IterRecords {
-        IterRecords::new(
-            self.source,
-            self.data, 
+        IterRecords::with_meta(
+            self.meta,
+            self.data,
             self.offset,
             self.search_size
-        )
+        ).with_min_confidence(self.min_confidence)
+            .with_latest_timestamp(self.latest_timestamp)
     }
 }
 
 #[derive(Debug)]
 pub struct IterRecords {
-    source: String,
+    meta: EntryMeta,
     block: Vec<u8>,
     start_offset: u64,
+    search_size: usize,
+    min_confidence: u8,
+    latest_timestamp: Option<DateTime<Utc>>,
     match_offsets: VecDeque<u64>,
+    // The index of the next record when walking
+    position: usize,
+    break_offsets: Vec<u64>,
 }
 
 impl IterRecords {
     pub fn new(source: String, block: Vec<u8>, start_offset: u64, search_size: usize) -> IterRecords {
-        let match_offsets: VecDeque<u64> = RE_USN.find_iter(&block[0..search_size])
-            .map(|m| m.start() as u64)
-            .collect();
+        Self::with_meta(
+            EntryMeta::new(&source, 0),
+            block,
+            start_offset,
+            search_size
+        )
+    }
+
+    /// Create the iterator with a template for the meta of each entry. Only
+    /// the offset is changed. The template's scan mode decides if records are
+    /// carved or walked.
+    pub fn with_meta(meta: EntryMeta, block: Vec<u8>, start_offset: u64, search_size: usize) -> IterRecords {
+        let match_offsets: VecDeque<u64> = match meta.scan_mode {
+            ScanMode::Carve => RE_USN.find_iter(&block[0..search_size])
+                .map(|m| m.start() as u64)
+                .collect(),
+            ScanMode::Strict => VecDeque::new()
+        };
 
         IterRecords {
-            source,
+            meta,
             block,
             start_offset,
-            match_offsets
+            search_size,
+            min_confidence: 0,
+            latest_timestamp: None,
+            match_offsets,
+            position: 0,
+            break_offsets: Vec::new()
         }
     }
-}
 
-impl Iterator for IterRecords {
-    type Item = UsnEntry;
+    /// Reject records with a confidence score below `min_confidence`.
+    pub fn with_min_confidence(mut self, min_confidence: u8) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
 
-    fn next(&mut self) -> Option<UsnEntry> {
-        loop {
-            // start of hit
-            let start_of_hit = match self.match_offsets.pop_front(){
-                Some(offset) => offset,
-                None => break
-            };
+    /// Score timestamps after `latest_timestamp` as implausible.
+    pub fn with_latest_timestamp(mut self, latest_timestamp: Option<DateTime<Utc>>) -> Self {
+        self.latest_timestamp = latest_timestamp;
+        self
+    }
 
-            // index starts at start of hit offset
-            let i = start_of_hit as usize;
+    /// The offsets where walking stopped finding records before the end of a page.
+    pub fn into_break_offsets(self) -> Vec<u64> {
+        self.break_offsets
+    }
 
-            // the entries' absolute offset
-            let entry_offset = self.start_offset + start_of_hit;
+    /// The next record, or a diagnostic for the next rejected hit.
+    pub fn next_result(&mut self) -> Option<RecordResult> {
+        match self.meta.scan_mode {
+            ScanMode::Carve => self.next_carved(),
+            ScanMode::Strict => self.next_walked(),
+        }
+    }
 
-            // validate record length is 8 byte aligned
-            let record_length = LittleEndian::read_u32(&self.block[i..i+4]);
-            if record_length % 8 != 0 {
-                debug!("not 8 byte aligned at offset {}", entry_offset);
-                continue;
+    fn next_carved(&mut self) -> Option<RecordResult> {
+        let start_of_hit = self.match_offsets.pop_front()?;
+        let i = start_of_hit as usize;
+        let entry_offset = self.start_offset + start_of_hit;
+
+        match parse_record(&self.meta, &self.block, i, entry_offset, self.min_confidence, self.latest_timestamp) {
+            Ok(entry) => Some(Ok(entry)),
+            Err(error) => {
+                debug!("{} at offset {}", error.message, entry_offset);
+                Some(Err(Diagnostic::from_hit(&self.block, i, entry_offset, error)))
             }
+        }
+    }
 
-            // Get version info from common header
-            let major = LittleEndian::read_u16(&self.block[i+4..i+6]);
-            let minor = LittleEndian::read_u16(&self.block[i+6..i+8]);
+    fn next_walked(&mut self) -> Option<RecordResult> {
+        while self.position < self.search_size {
+            let i = self.position;
+            let entry_offset = self.start_offset + i as u64;
+            let page_end = i + (SIZE_PAGE - entry_offset % SIZE_PAGE) as usize;
+
+            let record_length = LittleEndian::read_u32(&self.block[i..i+4]) as usize;
+            if record_length == 0 {
+                // The rest of the page should be padding
+                self.position = page_end;
+                if self.block[i..page_end.min(self.block.len())].iter().all(|byte| *byte == 0) {
+                    continue;
+                }
 
-            // First check if we have a 128-bit reference number
-            let is_128bit = match major {
-                3 | 4 => {
-                    // For v3/v4, check if we have enough bytes for 128-bit references
-                    if record_length >= 76 { // Minimum size for v3 with 128-bit refs
-                        true
-                    } else {
-                        false // v3 with 64-bit refs
-                    }
-                },
-                _ => false
-            };
+                debug!("page is not zero padded after offset {}", entry_offset);
+                self.break_offsets.push(entry_offset);
+                let error = UsnError::invalid_record("page is not zero padded".to_string());
+                return Some(Err(Diagnostic::from_hit(&self.block, i, entry_offset, error)));
+            }
 
-            let usn_entry = if is_128bit {
-                // Handle 128-bit reference records (v3/v4)
-                match major {
-                    3 => {
-                        // validate name offset for v3 128-bit
-                        let name_offset = LittleEndian::read_u16(&self.block[i+74..i+76]);
-                        if name_offset != 76 {
-                            debug!("name offset [{}] does not match 76 at offset {}", name_offset, entry_offset);
-                            continue;
-                        }
+            let result = if record_length < 8 || i + record_length > page_end {
+                Err(UsnError::invalid_usn_record_length(
+                    format!("record length {} does not fit in the page", record_length)
+                ))
+            } else {
+                parse_record(&self.meta, &self.block, i, entry_offset, self.min_confidence, self.latest_timestamp)
+            };
 
-                        // Create Entry Meta
-                        let entry_meta = EntryMeta::new(
-                            &self.source,
-                            entry_offset
-                        );
-
-                        // Parse entry as v3 with 128-bit references
-                        match UsnEntry::new(
-                            entry_meta,
-                            3,
-                            &self.block[start_of_hit as usize ..]
-                        ) {
-                            Ok(entry) => entry,
-                            Err(error) => {
-                                debug!("error at offset {}: {}", entry_offset, error);
-                                continue;
-                            }
-                        }
-                    },
-                    4 => {
-                        debug!("USN v4 records not yet implemented at offset {}", entry_offset);
-                        continue;
-                    },
-                    _ => {
-                        debug!("Unexpected major version {} for 128-bit reference at offset {}", major, entry_offset);
-                        continue;
+            match result {
+                Ok(entry) => {
+                    self.position += record_length;
+                    return Some(Ok(entry));
+                },
+                Err(error) => {
+                    if let ErrorKind::LowConfidence = error.kind {
+                        // The structure is intact, only the record is dropped
+                        self.position += record_length;
+                    } else {
+                        debug!("journal breaks at offset {}: {}", entry_offset, error.message);
+                        self.break_offsets.push(entry_offset);
+                        // Records start again at the next page
+                        self.position = page_end;
                     }
+                    return Some(Err(Diagnostic::from_hit(&self.block, i, entry_offset, error)));
                 }
-            } else {
-                // Handle original 64-bit reference records (v2 and v3-64bit)
-                match major {
-                    2 => {
-                        // validate minor version
-                        if minor != 0 {
-                            debug!("minor version does not match major at offset {}", entry_offset);
-                            continue;
-                        }
+            }
+        }
 
-                        // validate name offset
-                        let name_offset = LittleEndian::read_u16(&self.block[i+58..i+60]);
-                        if name_offset != 60 {
-                            debug!("name offset does not match 60 at offset {}", entry_offset);
-                            continue;
-                        }
+        None
+    }
+}
 
-                        // Create Entry Meta
-                        let entry_meta = EntryMeta::new(
-                            &self.source,
-                            entry_offset
-                        );
-
-                        // Parse entry
-                        match UsnEntry::new(
-                            entry_meta,
-                            2,
-                            &self.block[start_of_hit as usize ..]
-                        ) {
-                            Ok(entry) => entry,
-                            Err(error) => {
-                                debug!("error at offset {}: {}", entry_offset, error);
-                                continue;
-                            }
-                        }
-                    },
-                    3 => {
-                        // Handle v3 with 64-bit references (legacy format)
-                        // validate name offset
-                        let name_offset = LittleEndian::read_u16(&self.block[i+58..i+60]);
-                        if name_offset != 60 {
-                            debug!("name offset does not match 60 at offset {}", entry_offset);
-                            continue;
-                        }
 
-                        // Create Entry Meta
-                        let entry_meta = EntryMeta::new(
-                            &self.source,
-                            entry_offset
-                        );
-
-                        // Parse as v2 format but preserve v3 version number
-                        match UsnEntry::new(
-                            entry_meta,
-                            3,
-                            &self.block[start_of_hit as usize ..]
-                        ) {
-                            Ok(entry) => entry,
-                            Err(error) => {
-                                debug!("error at offset {}: {}", entry_offset, error);
-                                continue;
-                            }
-                        }
-                    },
-                    other => {
-                        debug!("Version not handled: {}; offset: {}", other, entry_offset);
-                        continue;
-                    }
+/// Validate, parse and score the record at index `i` of the block.
+fn parse_record(
+    meta: &EntryMeta,
+    block: &[u8],
+    i: usize,
+    entry_offset: u64,
+    min_confidence: u8,
+    latest_timestamp: Option<DateTime<Utc>>
+) -> Result<UsnEntry, UsnError> {
+    // every record version needs at least 64 bytes, and V3 records 76 bytes
+    let available = block.len().saturating_sub(i);
+    if available < 64 {
+        return Err(UsnError::invalid_record("record is truncated".to_string()));
+    }
+
+    // validate record length is 8 byte aligned
+    let record_length = LittleEndian::read_u32(&block[i..i+4]);
+    if record_length % 8 != 0 {
+        return Err(UsnError::invalid_usn_record_length("not 8 byte aligned".to_string()));
+    }
+
+    // Get version info from common header
+    let major = LittleEndian::read_u16(&block[i+4..i+6]);
+    let minor = LittleEndian::read_u16(&block[i+6..i+8]);
+
+    // First check if we have a 128-bit reference number
+    // V4 records always use 128-bit references
+    let is_128bit = match major {
+        // For v3, check if we have enough bytes for 128-bit references
+        3 => record_length >= 76, // Minimum size for v3 with 128-bit refs
+        4 => true,
+        _ => false
+    };
+
+    if is_128bit {
+        // Handle 128-bit reference records (v3/v4)
+        match major {
+            3 => {
+                if available < 76 {
+                    return Err(UsnError::invalid_record("record is truncated".to_string()));
                 }
-            };
 
-            return Some(usn_entry);
+                // validate name offset for v3 128-bit
+                let name_offset = LittleEndian::read_u16(&block[i+74..i+76]);
+                if name_offset != 76 {
+                    return Err(UsnError::invalid_record(
+                        format!("name offset [{}] does not match 76", name_offset)
+                    ));
+                }
+            },
+            4 => {
+                // validate extent size and that the extents fit in the record
+                let number_of_extents = LittleEndian::read_u16(&block[i+60..i+62]);
+                let extent_size = LittleEndian::read_u16(&block[i+62..i+64]);
+                if extent_size != 16 {
+                    return Err(UsnError::invalid_v4_record(
+                        format!("extent size [{}] does not match 16", extent_size)
+                    ));
+                }
+                if USN_RECORD_V4_HEADER_SIZE + u32::from(number_of_extents) * 16 > record_length {
+                    return Err(UsnError::invalid_v4_record(
+                        "extents do not fit in record length".to_string()
+                    ));
+                }
+            },
+            _ => {
+                return Err(UsnError::unsupported_usn_version(
+                    format!("Unexpected major version {} for 128-bit reference", major)
+                ));
+            }
+        }
+    } else {
+        // Handle original 64-bit reference records (v2 and v3-64bit)
+        match major {
+            2 | 3 => {
+                // validate minor version
+                if major == 2 && minor != 0 {
+                    return Err(UsnError::invalid_record(
+                        "minor version does not match major".to_string()
+                    ));
+                }
+
+                // validate name offset
+                let name_offset = LittleEndian::read_u16(&block[i+58..i+60]);
+                if name_offset != 60 {
+                    return Err(UsnError::invalid_record(
+                        "name offset does not match 60".to_string()
+                    ));
+                }
+            },
+            other => {
+                return Err(UsnError::unsupported_usn_version(
+                    format!("Version not handled: {}", other)
+                ));
+            }
+        }
+    }
+
+    // Parse v3 records with 64-bit references as v2 but preserve the version number
+    let mut entry = UsnEntry::new(
+        meta.at_offset(entry_offset),
+        major,
+        &block[i..]
+    )?;
+
+    let confidence = Confidence::assess(&entry.record, &block[i..], latest_timestamp);
+    if confidence.score < min_confidence {
+        return Err(UsnError::low_confidence(
+            format!("confidence {} is below {}: {:?}", confidence.score, min_confidence, confidence.checks)
+        ));
+    }
+    entry.meta.confidence = Some(confidence);
+
+    Ok(entry)
+}
+
+impl Iterator for IterRecords {
+    type Item = UsnEntry;
+
+    fn next(&mut self) -> Option<UsnEntry> {
+        while let Some(result) = self.next_result() {
+            if let Ok(entry) = result {
+                return Some(entry);
+            }
         }
 
         None
@@ -512,179 +1002,47 @@ pub struct IterRecordsByIndex {
 impl IterRecordsByIndex {
     pub fn new(meta: EntryMeta, block: Vec<u8>) -> Self {
         IterRecordsByIndex {
-            meta: meta,
-            block: block,
+            meta,
+            block,
             index: 0
         }
     }
+
+    /// The next record, or a diagnostic for the next rejected record. After a
+    /// rejection the walk continues 8 bytes on.
+    pub fn next_result(&mut self) -> Option<RecordResult> {
+        if self.index + 8 > self.block.len() {
+            return None;
+        }
+
+        let i = self.index;
+        let entry_offset = self.meta.offset + i as u64;
+        let record_length = LittleEndian::read_u32(&self.block[i..i+4]) as usize;
+
+        match parse_record(&self.meta, &self.block, i, entry_offset, 0, None) {
+            Ok(entry) => {
+                self.index += record_length;
+                Some(Ok(entry))
+            },
+            Err(error) => {
+                debug!("{} at offset {}", error.message, entry_offset);
+                self.index += 8;
+                Some(Err(Diagnostic::from_hit(&self.block, i, entry_offset, error)))
+            }
+        }
+    }
 }
 
 impl Iterator for IterRecordsByIndex {
     type Item = UsnEntry;
 
     fn next(&mut self) -> Option<UsnEntry> {
-        while self.index < self.block.len() {
-            self.meta.offset += self.index as u64;
-
-            let record_length = LittleEndian::read_u32(
-                &self.block[self.index..self.index+4]
-            );
-            if record_length % 8 != 0 {
-                debug!("not 8 byte aligned at offset {}", self.index);
-                self.index += 8;
-                continue;
+        while let Some(result) = self.next_result() {
+            if let Ok(entry) = result {
+                return Some(entry);
             }
-
-            // Check versions
-            let major = LittleEndian::read_u16(
-                &self.block[self.index+4..self.index+6]
-            );
-            let minor = LittleEndian::read_u16(
-                &self.block[self.index+6..self.index+8]
-            );
-
-            // First check if we have a 128-bit reference number
-            let is_128bit = match major {
-                3 | 4 => {
-                    // For v3/v4, check if we have enough bytes for 128-bit references
-                    if record_length >= 76 { // Minimum size for v3 with 128-bit refs
-                        true
-                    } else {
-                        false // v3 with 64-bit refs
-                    }
-                },
-                _ => false
-            };
-
-            let usn_entry = if is_128bit {
-                // Handle 128-bit reference records (v3/v4)
-                match major {
-                    3 => {
-                        // validate name offset for v3 128-bit
-                        let name_offset = LittleEndian::read_u16(
-                            &self.block[self.index+74..self.index+76]
-                        );
-                        if name_offset != 76 {
-                            debug!("name offset [{}] does not match 76 at offset {}", name_offset, self.index);
-                            self.index += 8;
-                            continue;
-                        }
-
-                        // Parse entry as v3 with 128-bit references
-                        match UsnEntry::new(
-                            self.meta.clone(),
-                            3,
-                            &self.block[self.index..]
-                        ) {
-                            Ok(entry) => {
-                                self.index += record_length as usize;
-                                entry
-                            },
-                            Err(error) => {
-                                debug!("error at offset {}: {}", self.index, error);
-                                self.index += 8;
-                                continue;
-                            }
-                        }
-                    },
-                    4 => {
-                        debug!("USN v4 records not yet implemented at offset {}", self.index);
-                        self.index += 8;
-                        continue;
-                    },
-                    _ => {
-                        debug!("Unexpected major version {} for 128-bit reference at offset {}", major, self.index);
-                        self.index += 8;
-                        continue;
-                    }
-                }
-            } else {
-                // Handle original 64-bit reference records (v2 and v3-64bit)
-                match major {
-                    2 => {
-                        // validate minor version
-                        if minor != 0 {
-                            debug!("minor version does not match major at offset {}", self.index);
-                            self.index += 8;
-                            continue;
-                        }
-
-                        // validate name offset
-                        let name_offset = LittleEndian::read_u16(
-                            &self.block[self.index+58..self.index+60]
-                        );
-                        if name_offset != 60 {
-                            debug!("name offset does not match 60 at offset {}", self.index);
-                            self.index += 8;
-                            continue;
-                        }
-
-                        // Parse entry
-                        let entry = match UsnEntry::new(
-                            self.meta.clone(),
-                            2,
-                            &self.block[self.index as usize ..]
-                        ) {
-                            Ok(entry) => entry,
-                            Err(error) => {
-                                debug!("error at offset {}: {}", self.index, error);
-                                self.index += 8;
-                                continue;
-                            }
-                        };
-
-                        self.index += record_length as usize;
-
-                        entry
-                    },
-                    3 => {
-                        debug!("entry: {}", hex::encode(&self.block[self.index as usize .. self.index as usize + record_length as usize]));
-                        // validate minor version
-                        if minor != 0 {
-                            debug!("minor version does not match major at offset {}", self.index);
-                            self.index += 8;
-                            continue;
-                        }
-
-                        // validate name offset
-                        let name_offset = LittleEndian::read_u16(
-                            &self.block[self.index+74..self.index+76]
-                        );
-                        if name_offset != 76 {
-                            debug!("name offset [{}] does not match 76 at offset {}", name_offset, self.index);
-                            self.index += 8;
-                            continue;
-                        }
-
-                        // Parse entry
-                        let entry = match UsnEntry::new(
-                            self.meta.clone(),
-                            3,
-                            &self.block[self.index as usize ..]
-                        ) {
-                            Ok(entry) => entry,
-                            Err(error) => {
-                                debug!("error at offset {}: {}", self.index, error);
-                                self.index += 8;
-                                continue;
-                            }
-                        };
-
-                        self.index += record_length as usize;
-
-                        entry
-                    },
-                    other => {
-                        debug!("Version not handled: {}; offset: {}", other, self.index);
-                        self.index += 8;
-                        continue;
-                    }
-                }
-            };
-
-            return Some(usn_entry);
         }
 
         None
     }
-}
\ No newline at end of file
+}
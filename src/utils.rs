@@ -11,4 +11,40 @@ pub fn u64_to_datetime(timestamp_u64: u64) -> DateTime<Utc> {
     
     let datetime = base_time + chrono::Duration::microseconds(nanos);
     DateTime::from_naive_utc_and_offset(datetime, Utc)
-}
\ No newline at end of file
+}
+
+/// Convert a u64 Windows 100 nanosecond timestamp to a chrono DateTime, keeping
+/// the 100 nanoseconds `u64_to_datetime` truncates.
+///
+pub fn filetime_to_datetime(filetime: u64) -> DateTime<Utc> {
+    u64_to_datetime(filetime) + chrono::Duration::nanoseconds((filetime % 10) as i64 * 100)
+}
+
+/// Decode a UTF-16LE name without losing anything. Unpaired surrogates are
+/// escaped as `\uD800` and a trailing odd byte as `\x41`. POSIX names can
+/// contain a backslash, so an escape is only told apart from the same text in
+/// a name by the returned flag, which is set when anything had to be escaped.
+///
+pub fn decode_utf16_name(buffer: &[u8]) -> (String, bool) {
+    let units = buffer.chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+
+    let mut name = String::with_capacity(buffer.len() / 2);
+    let mut irregular = false;
+    for character in std::char::decode_utf16(units) {
+        match character {
+            Ok(character) => name.push(character),
+            Err(error) => {
+                name.push_str(&format!("\\u{:04X}", error.unpaired_surrogate()));
+                irregular = true;
+            }
+        }
+    }
+
+    if let Some(byte) = buffer.chunks_exact(2).remainder().first() {
+        name.push_str(&format!("\\x{:02X}", byte));
+        irregular = true;
+    }
+
+    (name, irregular)
+}
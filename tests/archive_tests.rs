@@ -0,0 +1,329 @@
+extern crate rusty_usn;
+mod common;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use flate2::Compression;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Crc, GzBuilder};
+use rusty_usn::archive::{MemberReader, ZipArchive};
+use rusty_usn::usn::UsnParser;
+use common::V2_RECORD;
+
+const PASSWORD: &str = "infected";
+
+
+/// A journal with a record at the start of every page.
+fn build_journal(pages: usize) -> Vec<u8> {
+    let mut data = vec![0u8; pages * 4096];
+    for page in 0..pages {
+        data[page * 4096..page * 4096 + V2_RECORD.len()].copy_from_slice(V2_RECORD);
+    }
+    data
+}
+
+/// Traditional PKWARE encryption, for building encrypted members.
+struct ZipCrypto {
+    keys: [u32; 3],
+}
+
+impl ZipCrypto {
+    fn new(password: &str) -> Self {
+        let mut cipher = ZipCrypto { keys: [0x1234_5678, 0x2345_6789, 0x3456_7890] };
+        for byte in password.bytes() {
+            cipher.update_keys(byte);
+        }
+        cipher
+    }
+
+    fn crc32_update(crc: u32, byte: u8) -> u32 {
+        let mut value = (crc ^ u32::from(byte)) & 0xFF;
+        for _ in 0..8 {
+            value = if value & 1 == 1 { (value >> 1) ^ 0xEDB8_8320 } else { value >> 1 };
+        }
+        (crc >> 8) ^ value
+    }
+
+    fn update_keys(&mut self, byte: u8) {
+        self.keys[0] = Self::crc32_update(self.keys[0], byte);
+        self.keys[1] = self.keys[1].wrapping_add(self.keys[0] & 0xFF).wrapping_mul(134_775_813).wrapping_add(1);
+        self.keys[2] = Self::crc32_update(self.keys[2], (self.keys[1] >> 24) as u8);
+    }
+
+    fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        data.iter().map(|byte| {
+            let temp = (self.keys[2] | 2) as u16;
+            let encrypted = byte ^ (temp.wrapping_mul(temp ^ 1) >> 8) as u8;
+            self.update_keys(*byte);
+            encrypted
+        }).collect()
+    }
+}
+
+/// Write a zip of (name, data, method, encrypted) members.
+fn build_zip(members: &[(&str, &[u8], u16, bool)]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let mut directory = Vec::new();
+
+    for (name, data, method, encrypted) in members {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let crc = crc.sum();
+
+        let mut stored = match method {
+            8 => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            },
+            _ => data.to_vec()
+        };
+        if *encrypted {
+            let mut cipher = ZipCrypto::new(PASSWORD);
+            let mut header = vec![0x5Au8; 12];
+            header[11] = (crc >> 24) as u8;
+            let mut encrypted_data = cipher.encrypt(&header);
+            encrypted_data.extend(cipher.encrypt(&stored));
+            stored = encrypted_data;
+        }
+
+        let flags: u16 = if *encrypted { 1 } else { 0 };
+        let header_offset = zip.len() as u32;
+        for (buffer, central) in [(&mut zip, false), (&mut directory, true)] {
+            buffer.write_all(if central { b"PK\x01\x02" } else { b"PK\x03\x04" }).unwrap();
+            if central {
+                buffer.write_u16::<LittleEndian>(20).unwrap();
+            }
+            buffer.write_u16::<LittleEndian>(20).unwrap();
+            buffer.write_u16::<LittleEndian>(flags).unwrap();
+            buffer.write_u16::<LittleEndian>(*method).unwrap();
+            buffer.write_u32::<LittleEndian>(0).unwrap();
+            buffer.write_u32::<LittleEndian>(crc).unwrap();
+            buffer.write_u32::<LittleEndian>(stored.len() as u32).unwrap();
+            buffer.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+            buffer.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+            buffer.write_u16::<LittleEndian>(0).unwrap();
+            if central {
+                buffer.write_all(&[0u8; 10]).unwrap();
+                buffer.write_u32::<LittleEndian>(header_offset).unwrap();
+            }
+            buffer.write_all(name.as_bytes()).unwrap();
+        }
+        zip.extend_from_slice(&stored);
+    }
+
+    let directory_offset = zip.len() as u32;
+    zip.extend_from_slice(&directory);
+    zip.write_all(b"PK\x05\x06\x00\x00\x00\x00").unwrap();
+    zip.write_u16::<LittleEndian>(members.len() as u16).unwrap();
+    zip.write_u16::<LittleEndian>(members.len() as u16).unwrap();
+    zip.write_u32::<LittleEndian>(directory.len() as u32).unwrap();
+    zip.write_u32::<LittleEndian>(directory_offset).unwrap();
+    zip.write_u16::<LittleEndian>(0).unwrap();
+    zip
+}
+
+/// Records the offset of every read, to see how often a member is read from its start.
+struct ReadLog {
+    inner: Cursor<Vec<u8>>,
+    offsets: Arc<Mutex<Vec<u64>>>,
+}
+
+impl Read for ReadLog {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.offsets.lock().unwrap().push(self.inner.position());
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for ReadLog {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn read_member<T: Read + Seek>(mut reader: T) -> Vec<u8> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).unwrap();
+    data
+}
+
+
+#[test]
+fn zip_members_test() {
+    let journal = build_journal(8);
+    let zip = build_zip(&[
+        ("C/$Extend/$J", &journal, 0, false),
+        ("D/$Extend/$J", &journal, 8, false),
+        ("E/$Extend/$J", &journal, 8, true),
+    ]);
+
+    let archive = ZipArchive::from_read_seek("kape.zip", Cursor::new(zip))
+        .unwrap()
+        .with_password(PASSWORD);
+    let names: Vec<&str> = archive.get_members().iter().map(|member| member.name.as_str()).collect();
+    assert_eq!(names, ["C/$Extend/$J", "D/$Extend/$J", "E/$Extend/$J"]);
+
+    for member in archive.get_members() {
+        let reader = archive.open_member(member).unwrap();
+        assert_eq!(reader.get_source(), format!("kape.zip!{}", member.name));
+        assert_eq!(read_member(reader), journal, "{}", member.name);
+    }
+}
+
+#[test]
+fn zip_wrong_password_test() {
+    let journal = build_journal(1);
+    let zip = build_zip(&[("C/$Extend/$J", &journal, 8, true)]);
+
+    let archive = ZipArchive::from_read_seek("kape.zip", Cursor::new(zip)).unwrap();
+    assert!(archive.open_member(&archive.get_members()[0]).is_err());
+
+    let archive = archive.with_password("wrong");
+    assert!(archive.open_member(&archive.get_members()[0]).is_err());
+}
+
+#[test]
+fn deflated_seek_test() {
+    let journal = build_journal(8);
+    let zip = build_zip(&[("C/$Extend/$J", &journal, 8, false)]);
+    let archive = ZipArchive::from_read_seek("kape.zip", Cursor::new(zip)).unwrap();
+    let mut reader = archive.open_member(&archive.get_members()[0]).unwrap();
+
+    let mut buffer = [0u8; 8];
+    assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), journal.len() as u64);
+    // Forward, then back to an earlier offset
+    for offset in [5 * 4096 + 24, 4096 + 24, 0] {
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &journal[offset as usize..offset as usize + 8]);
+    }
+}
+
+#[test]
+fn deflated_look_back_test() {
+    let journal = build_journal(64);
+    let name = "C/$Extend/$J";
+    let zip = build_zip(&[(name, &journal, 8, false)]);
+    let data_offset = 30 + name.len() as u64;
+
+    let offsets = Arc::new(Mutex::new(Vec::new()));
+    let handle = ReadLog { inner: Cursor::new(zip), offsets: offsets.clone() };
+    let archive = ZipArchive::from_read_seek("kape.zip", handle).unwrap();
+    let mut reader = archive.open_member(&archive.get_members()[0]).unwrap();
+
+    // Overlapping chunks, as the parser reads them, seek back 1 KiB each time
+    let mut chunk = vec![0u8; 17408];
+    for offset in (0..journal.len()).step_by(16384) {
+        reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+        let size = chunk.len().min(journal.len() - offset);
+        reader.read_exact(&mut chunk[..size]).unwrap();
+        assert_eq!(&chunk[..size], &journal[offset..offset + size]);
+    }
+
+    let starts = |offsets: &Mutex<Vec<u64>>| offsets.lock().unwrap().iter().filter(|offset| **offset == data_offset).count();
+    assert_eq!(starts(&offsets), 1);
+
+    offsets.lock().unwrap().clear();
+    let reader = archive.open_member(&archive.get_members()[0]).unwrap();
+    let source = reader.get_source().to_string();
+    let entries: Vec<_> = UsnParser::from_read_seek(source, reader).unwrap().records().collect();
+    assert_eq!(entries.len(), 64);
+    assert_eq!(starts(&offsets), 1);
+}
+
+#[test]
+fn zip_parser_test() {
+    let journal = build_journal(8);
+    let zip = build_zip(&[("C/$Extend/$J", &journal, 8, false)]);
+    let archive = ZipArchive::from_read_seek("kape.zip", Cursor::new(zip)).unwrap();
+    let member = archive.open_member(&archive.get_members()[0]).unwrap();
+
+    let source = member.get_source().to_string();
+    let mut parser = UsnParser::from_read_seek(source, member).unwrap();
+    let entries: Vec<_> = parser.records().collect();
+
+    assert_eq!(entries.len(), 8);
+    assert_eq!(entries[0].meta.source, "kape.zip!C/$Extend/$J");
+    assert_eq!(entries[7].meta.offset, 7 * 4096);
+}
+
+#[test]
+fn gzip_test() {
+    let journal = build_journal(8);
+    let mut encoder = GzBuilder::new()
+        .filename("$UsnJrnl$J")
+        .write(Vec::new(), Compression::default());
+    encoder.write_all(&journal).unwrap();
+    let gzip = encoder.finish().unwrap();
+
+    let mut reader = MemberReader::from_gzip("J.gz", Cursor::new(gzip)).unwrap();
+    assert_eq!(reader.get_source(), "J.gz!$UsnJrnl$J");
+    assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), journal.len() as u64);
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    assert_eq!(read_member(reader), journal);
+
+    // Without a name in the header, the file name without .gz is used
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&journal).unwrap();
+    let reader = MemberReader::from_gzip("/cases/$J.gz", Cursor::new(encoder.finish().unwrap())).unwrap();
+    assert_eq!(reader.get_source(), "/cases/$J.gz!$J");
+}
+
+#[test]
+fn zip64_entry_count_test() {
+    let journal = build_journal(1);
+    let zip = build_zip(&[("C/$Extend/$J", &journal, 0, false)]);
+    let (body, end) = zip.split_at(zip.len() - 22);
+    let directory_size = LittleEndian::read_u32(&end[12..16]);
+    let directory_offset = LittleEndian::read_u32(&end[16..20]);
+
+    // A zip64 end record with far more entries than its central directory holds
+    let mut zip = body.to_vec();
+    let zip64_end_offset = zip.len() as u64;
+    zip.write_all(b"PK\x06\x06").unwrap();
+    zip.write_u64::<LittleEndian>(44).unwrap();
+    zip.write_all(&[0u8; 20]).unwrap();
+    zip.write_u64::<LittleEndian>(u64::MAX).unwrap();
+    zip.write_u64::<LittleEndian>(u64::from(directory_size)).unwrap();
+    zip.write_u64::<LittleEndian>(u64::from(directory_offset)).unwrap();
+    zip.write_all(b"PK\x06\x07").unwrap();
+    zip.write_u32::<LittleEndian>(0).unwrap();
+    zip.write_u64::<LittleEndian>(zip64_end_offset).unwrap();
+    zip.write_u32::<LittleEndian>(1).unwrap();
+    zip.extend_from_slice(end);
+
+    assert!(ZipArchive::from_read_seek("kape.zip", Cursor::new(zip)).is_err());
+}
+
+#[test]
+fn zip64_member_size_test() {
+    let journal = build_journal(1);
+    let zip = build_zip(&[("C/$Extend/$J", &journal, 0, false)]);
+    let (body, end) = zip.split_at(zip.len() - 22);
+    let directory_offset = LittleEndian::read_u32(&end[16..20]) as usize;
+    let (data, directory) = body.split_at(directory_offset);
+
+    // A zip64 extra field with a compressed size that overflows past the end of the file
+    let build = |compressed_size: u64| {
+        let mut header = directory.to_vec();
+        LittleEndian::write_u32(&mut header[20..24], 0xFFFF_FFFF);
+        LittleEndian::write_u16(&mut header[30..32], 12);
+        header.write_u16::<LittleEndian>(0x0001).unwrap();
+        header.write_u16::<LittleEndian>(8).unwrap();
+        header.write_u64::<LittleEndian>(compressed_size).unwrap();
+
+        let mut zip = data.to_vec();
+        zip.extend_from_slice(&header);
+        let mut end = end.to_vec();
+        LittleEndian::write_u32(&mut end[12..16], header.len() as u32);
+        zip.extend_from_slice(&end);
+        zip
+    };
+
+    let archive = ZipArchive::from_read_seek("kape.zip", Cursor::new(build(journal.len() as u64))).unwrap();
+    assert_eq!(read_member(archive.open_member(&archive.get_members()[0]).unwrap()), journal);
+    for compressed_size in [u64::MAX - 8, journal.len() as u64 + 1024] {
+        assert!(ZipArchive::from_read_seek("kape.zip", Cursor::new(build(compressed_size))).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "parquet")]
+extern crate rusty_usn;
+mod common;
+use std::fs::File;
+use arrow::array::{Array, BooleanArray, ListArray, StringArray, StructArray, TimestampNanosecondArray, UInt16Array, UInt32Array, UInt64Array};
+use arrow::datatypes::{DataType, TimeUnit};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rusty_usn::output::{Format, RecordSink};
+use rusty_usn::output::columnar::{schema, to_record_batch, ParquetWriter, RecordBatchBuilder};
+use common::v2_entry;
+
+
+#[test]
+fn record_batch_test() {
+    let mut builder = RecordBatchBuilder::new();
+    builder.append(&v2_entry(), Some("[root]/Windows/BTDevManager.log"));
+    builder.append(&v2_entry(), None);
+    assert_eq!(builder.len(), 2);
+
+    let batch = builder.finish().unwrap();
+    assert!(builder.is_empty());
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(batch.schema(), schema());
+    assert_eq!(
+        batch.schema().field_with_name("timestamp").unwrap().data_type(),
+        &DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+    );
+
+    let usn = batch.column_by_name("usn").unwrap().as_any().downcast_ref::<UInt64Array>().unwrap();
+    assert_eq!(usn.value(0), 8192);
+
+    let timestamp = batch.column_by_name("timestamp").unwrap().as_any().downcast_ref::<TimestampNanosecondArray>().unwrap();
+    assert_eq!(timestamp.value(0), 1_382_185_013_276_040_000);
+
+    let full_name = batch.column_by_name("full_name").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(full_name.value(0), "[root]/Windows/BTDevManager.log");
+    assert!(full_name.is_null(1));
+
+    let reason = batch.column_by_name("reason").unwrap().as_any().downcast_ref::<UInt32Array>().unwrap();
+    assert_eq!(reason.value(0), 0x0000_0002);
+    let reasons = batch.column_by_name("reasons").unwrap().as_any().downcast_ref::<ListArray>().unwrap();
+    let names = reasons.value(0);
+    let names = names.as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(names.iter().collect::<Vec<_>>(), [Some("DATA_EXTEND")]);
+
+    let reference = batch.column_by_name("file_reference").unwrap().as_any().downcast_ref::<StructArray>().unwrap();
+    let entry = reference.column_by_name("entry").unwrap().as_any().downcast_ref::<UInt64Array>().unwrap();
+    let sequence = reference.column_by_name("sequence").unwrap().as_any().downcast_ref::<UInt16Array>().unwrap();
+    let id = reference.column_by_name("u128").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!((entry.value(0), sequence.value(0), id.value(0)), (115, 37224, "10477624533077459059"));
+
+    let irregular_name = batch.column_by_name("irregular_name").unwrap().as_any().downcast_ref::<BooleanArray>().unwrap();
+    assert!(!irregular_name.value(0));
+    assert!(batch.column_by_name("file_name_hex").unwrap().is_null(0));
+
+    assert!(batch.column_by_name("security_id").unwrap().is_valid(0));
+    assert!(batch.column_by_name("meta__volume").unwrap().is_null(0));
+}
+
+#[test]
+fn parquet_writer_test() {
+    let location = std::env::temp_dir().join(format!("rusty_usn_{}.parquet", std::process::id()));
+
+    let mut writer = ParquetWriter::new(File::create(&location).unwrap()).unwrap().with_batch_size(2);
+    for _ in 0..3 {
+        writer.write_entry(&v2_entry(), None).unwrap();
+    }
+    writer.write_batch(&to_record_batch(&[v2_entry(), v2_entry()]).unwrap()).unwrap();
+    writer.close().unwrap();
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&location).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let batches: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), 5);
+    assert_eq!(batches[0].schema().fields(), schema().fields());
+
+    std::fs::remove_file(&location).unwrap();
+}
+
+#[test]
+fn parquet_sink_test() {
+    let location = std::env::temp_dir().join(format!("rusty_usn_sink_{}.parquet", std::process::id()));
+
+    let mut sink = Format::Parquet.create(&location).unwrap();
+    sink.write_entries(&[v2_entry(), v2_entry()]).unwrap();
+    sink.write_entry(&v2_entry(), Some("[root]/Windows/BTDevManager.log")).unwrap();
+    sink.finish().unwrap();
+    drop(sink);
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&location).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let batches: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), 3);
+
+    std::fs::remove_file(&location).unwrap();
+}
@@ -0,0 +1,103 @@
+//! The V2 record the tests are built from, and a builder for variations of it.
+//!
+// Each test crate uses only part of this module
+#![allow(dead_code)]
+use rusty_usn::flags::Reason;
+use rusty_usn::record::{EntryMeta, UsnEntry};
+
+/// A V2 record of `BTDevManager.log` (entry 115) with the `DATA_EXTEND` reason,
+/// at USN 8192 and 2013-10-19T12:16:53.2760403Z.
+pub const V2_RECORD: &[u8] = &[
+    0x60,0x00,0x00,0x00,0x02,0x00,0x00,0x00,0x73,0x00,0x00,0x00,0x00,0x00,0x68,0x91,
+    0x29,0x00,0x00,0x00,0x00,0x00,0x01,0x00,0x00,0x20,0x00,0x00,0x00,0x00,0x00,0x00,
+    0x53,0xC7,0x8B,0x18,0xC5,0xCC,0xCE,0x01,0x02,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+    0x00,0x00,0x00,0x00,0x20,0x20,0x00,0x00,0x20,0x00,0x3C,0x00,0x42,0x00,0x54,0x00,
+    0x44,0x00,0x65,0x00,0x76,0x00,0x4D,0x00,0x61,0x00,0x6E,0x00,0x61,0x00,0x67,0x00,
+    0x65,0x00,0x72,0x00,0x2E,0x00,0x6C,0x00,0x6F,0x00,0x67,0x00,0x00,0x00,0x00,0x00
+];
+
+/// The FILETIME of `V2_RECORD`.
+pub const V2_FILETIME: u64 = 130_266_586_132_760_403;
+
+const SOURCE: &str = "C:\\Test\\$J";
+const OFFSET: u64 = 96;
+const FILE_NAME_OFFSET: usize = 60;
+
+
+/// `V2_RECORD` with some of its fields changed.
+pub struct RecordBuilder {
+    buffer: Vec<u8>,
+    meta: EntryMeta,
+}
+
+impl Default for RecordBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordBuilder {
+    pub fn new() -> Self {
+        RecordBuilder {
+            buffer: V2_RECORD.to_vec(),
+            meta: EntryMeta::new(SOURCE, OFFSET),
+        }
+    }
+
+    pub fn with_meta(mut self, meta: EntryMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    /// Set the entry number of the file reference, keeping its sequence.
+    pub fn with_file_entry(mut self, entry: u64) -> Self {
+        self.buffer[8..14].copy_from_slice(&entry.to_le_bytes()[..6]);
+        self
+    }
+
+    pub fn with_usn(mut self, usn: u64) -> Self {
+        self.buffer[24..32].copy_from_slice(&usn.to_le_bytes());
+        self
+    }
+
+    pub fn with_filetime(mut self, filetime: u64) -> Self {
+        self.buffer[32..40].copy_from_slice(&filetime.to_le_bytes());
+        self
+    }
+
+    pub fn with_reason(self, reason: Reason) -> Self {
+        self.with_reason_bits(reason.bits())
+    }
+
+    /// Set the raw reason, which may hold bits without a name.
+    pub fn with_reason_bits(mut self, bits: u32) -> Self {
+        self.buffer[40..44].copy_from_slice(&bits.to_le_bytes());
+        self
+    }
+
+    pub fn with_file_name_offset(mut self, offset: u16) -> Self {
+        self.buffer[58..60].copy_from_slice(&offset.to_le_bytes());
+        self
+    }
+
+    /// Replace a UTF-16 code unit of the name, which need not be valid.
+    pub fn with_name_unit(mut self, index: usize, unit: u16) -> Self {
+        let offset = FILE_NAME_OFFSET + index * 2;
+        self.buffer[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.buffer.clone()
+    }
+
+    pub fn build(self) -> UsnEntry {
+        UsnEntry::new(self.meta, 2, self.buffer.as_slice()).unwrap()
+    }
+}
+
+
+/// The entry of `V2_RECORD`.
+pub fn v2_entry() -> UsnEntry {
+    RecordBuilder::new().build()
+}
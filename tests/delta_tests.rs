@@ -0,0 +1,62 @@
+extern crate rusty_usn;
+mod common;
+use rusty_usn::delta::ReasonDecoder;
+use rusty_usn::flags::Reason;
+use rusty_usn::output::RecordSink;
+use rusty_usn::output::csv::{Column, CsvWriter};
+use rusty_usn::record::{EntryMeta, UsnEntry};
+use common::RecordBuilder;
+
+
+/// A V2 entry of the given file entry number with the given reason.
+fn entry(file_entry: u8, reason: Reason) -> UsnEntry {
+    RecordBuilder::new()
+        .with_meta(EntryMeta::new("delta", 0))
+        .with_file_entry(u64::from(file_entry))
+        .with_reason(reason)
+        .build()
+}
+
+
+#[test]
+fn reason_delta_test() {
+    let mut decoder = ReasonDecoder::new();
+    let created = Reason::FILE_CREATE;
+    let extended = Reason::FILE_CREATE | Reason::DATA_EXTEND;
+
+    let mut entries = [
+        entry(1, created),
+        entry(2, Reason::DATA_OVERWRITE),
+        entry(1, extended),
+        entry(1, extended | Reason::CLOSE),
+        entry(2, Reason::DATA_OVERWRITE | Reason::CLOSE),
+        entry(1, Reason::DATA_TRUNCATION),
+    ];
+    assert_eq!(entries[0].get_reason_delta(), None);
+
+    let deltas: Vec<Reason> = entries.iter_mut()
+        .map(|entry| decoder.decode(entry))
+        .collect();
+    assert_eq!(deltas, vec![
+        Reason::FILE_CREATE,
+        Reason::DATA_OVERWRITE,
+        Reason::DATA_EXTEND,
+        Reason::CLOSE,
+        Reason::CLOSE,
+        // The close reset the accumulation
+        Reason::DATA_TRUNCATION,
+    ]);
+    assert_eq!(entries[2].get_reason_delta(), Some(Reason::DATA_EXTEND));
+    assert_eq!(decoder.open_count(), 1);
+
+    let value = entries[2].to_json_value().unwrap();
+    assert_eq!(value["reason"], "DATA_EXTEND | FILE_CREATE");
+    assert_eq!(value["reason_delta"], "DATA_EXTEND");
+
+    let mut writer = CsvWriter::new(Vec::new())
+        .with_columns(Column::parse_list("reason,reason_delta").unwrap());
+    writer.write_entry(&entries[2], None).unwrap();
+    writer.write_entry(&entry(3, Reason::CLOSE), None).unwrap();
+    let csv = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(csv, "reason,reason_delta\r\nDATA_EXTEND | FILE_CREATE,DATA_EXTEND\r\nCLOSE,\r\n");
+}
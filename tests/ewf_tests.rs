@@ -0,0 +1,409 @@
+extern crate rusty_usn;
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use rusty_usn::image::ewf::{is_ewf, segment_extension, EwfFormat, EwfImage};
+
+const BYTES_PER_SECTOR: usize = 256;
+const SECTORS_PER_CHUNK: usize = 2;
+const CHUNK_SIZE: usize = BYTES_PER_SECTOR * SECTORS_PER_CHUNK;
+const SECTOR_COUNT: usize = 5;
+const MEDIA_SIZE: usize = BYTES_PER_SECTOR * SECTOR_COUNT;
+const FILL_PATTERN: [u8; 8] = [0xDE, 0xAD, 0xBE, 0xEF, 0x01, 0x02, 0x03, 0x04];
+
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+}
+
+/// Three chunks, the last one is half a chunk.
+fn build_media() -> Vec<u8> {
+    let mut media = Vec::with_capacity(MEDIA_SIZE);
+    media.extend((0..CHUNK_SIZE).map(|i| (i % 7) as u8));
+    media.extend((0..CHUNK_SIZE).map(|i| (i * 31 % 251) as u8));
+    media.extend((0..MEDIA_SIZE - 2 * CHUNK_SIZE).map(|i| (255 - i % 256) as u8));
+    media
+}
+
+
+struct Ewf1Segment {
+    buffer: Vec<u8>,
+}
+
+impl Ewf1Segment {
+    fn new(number: u16) -> Self {
+        let mut buffer = b"EVF\x09\x0d\x0a\xff\x00\x01".to_vec();
+        buffer.extend(&number.to_le_bytes());
+        buffer.extend(&[0, 0]);
+        Ewf1Segment { buffer }
+    }
+
+    /// Append a section and return the offset of its data.
+    fn section(&mut self, section_type: &str, data: &[u8]) -> u64 {
+        let offset = self.buffer.len() as u64;
+        let size = 76 + data.len() as u64;
+        let next = match section_type {
+            "next" | "done" => offset,
+            _ => offset + size
+        };
+
+        let mut descriptor = vec![0u8; 76];
+        descriptor[0..section_type.len()].copy_from_slice(section_type.as_bytes());
+        LittleEndian::write_u64(&mut descriptor[16..24], next);
+        LittleEndian::write_u64(&mut descriptor[24..32], size);
+        let checksum = adler32(&descriptor[0..72]);
+        LittleEndian::write_u32(&mut descriptor[72..76], checksum);
+
+        self.buffer.extend(descriptor);
+        self.buffer.extend(data);
+        offset + 76
+    }
+
+    /// Append a sectors section with the given chunks and the table for it,
+    /// and return the offset of the table data.
+    fn chunks(&mut self, chunks: &[(&[u8], bool)]) -> u64 {
+        let mut sectors = Vec::new();
+        let mut entries = Vec::new();
+        for (chunk, compressed) in chunks {
+            let relative_offset = sectors.len() as u32;
+            if *compressed {
+                entries.push(relative_offset | 0x8000_0000);
+                sectors.extend(compress(chunk));
+            } else {
+                entries.push(relative_offset);
+                sectors.extend_from_slice(chunk);
+                sectors.extend(&adler32(chunk).to_le_bytes());
+            }
+        }
+
+        let base_offset = self.section("sectors", &sectors);
+        let mut table = vec![0u8; 24];
+        LittleEndian::write_u32(&mut table[0..4], entries.len() as u32);
+        LittleEndian::write_u64(&mut table[8..16], base_offset);
+        for entry in entries {
+            table.extend(&entry.to_le_bytes());
+        }
+        table.extend(&[0u8; 4]);
+        let table_offset = self.section("table", &table);
+        self.section("table2", &table);
+        table_offset
+    }
+}
+
+fn ewf1_volume() -> Vec<u8> {
+    let mut volume = vec![0u8; 1052];
+    volume[0] = 0x01;
+    LittleEndian::write_u32(&mut volume[4..8], 3);
+    LittleEndian::write_u32(&mut volume[8..12], SECTORS_PER_CHUNK as u32);
+    LittleEndian::write_u32(&mut volume[12..16], BYTES_PER_SECTOR as u32);
+    LittleEndian::write_u64(&mut volume[16..24], SECTOR_COUNT as u64);
+    volume
+}
+
+fn build_ewf1_segments(md5: [u8; 16], sha1: [u8; 20]) -> Vec<Vec<u8>> {
+    let media = build_media();
+    let volume = ewf1_volume();
+
+    let mut first = Ewf1Segment::new(1);
+    first.section("header", &compress(b"1\r\nmain\r\nc\tn\r\ncase\tevidence\r\n\r\n"));
+    first.section("volume", &volume);
+    first.chunks(&[
+        (&media[0..CHUNK_SIZE], true),
+        (&media[CHUNK_SIZE..2 * CHUNK_SIZE], false),
+    ]);
+    first.section("next", &[]);
+
+    let mut digest = md5.to_vec();
+    digest.extend(&sha1);
+    digest.extend(&[0u8; 44]);
+
+    let mut second = Ewf1Segment::new(2);
+    second.chunks(&[(&media[2 * CHUNK_SIZE..], false)]);
+    second.section("digest", &digest);
+    second.section("done", &[]);
+
+    vec![first.buffer, second.buffer]
+}
+
+
+struct Ewf2Segment {
+    buffer: Vec<u8>,
+    previous: u64,
+}
+
+impl Ewf2Segment {
+    fn new(number: u32) -> Self {
+        let mut buffer = b"EVF2\x0d\x0a\x81\x00\x02\x01".to_vec();
+        buffer.extend(&1u16.to_le_bytes());
+        buffer.extend(&number.to_le_bytes());
+        buffer.extend(&[0x11; 16]);
+        Ewf2Segment { buffer, previous: 0 }
+    }
+
+    /// Append section data followed by its descriptor and return the offset of the data.
+    fn section(&mut self, section_type: u32, data: &[u8]) -> u64 {
+        let data_offset = self.buffer.len() as u64;
+        self.buffer.extend(data);
+
+        let mut descriptor = vec![0u8; 64];
+        LittleEndian::write_u32(&mut descriptor[0..4], section_type);
+        LittleEndian::write_u64(&mut descriptor[8..16], self.previous);
+        LittleEndian::write_u64(&mut descriptor[16..24], data.len() as u64);
+        LittleEndian::write_u32(&mut descriptor[24..28], 64);
+        let checksum = adler32(&descriptor[0..60]);
+        LittleEndian::write_u32(&mut descriptor[60..64], checksum);
+
+        self.previous = self.buffer.len() as u64;
+        self.buffer.extend(descriptor);
+        data_offset
+    }
+}
+
+/// A pattern filled chunk, a compressed chunk and a stored chunk with a checksum.
+fn build_ewf2_segment(md5: [u8; 16]) -> Vec<u8> {
+    build_ewf2_segment_with_table(md5, 0, 3)
+}
+
+/// The segment with the given first chunk and entry count in its table header.
+fn build_ewf2_segment_with_table(md5: [u8; 16], first_chunk: u64, entry_count: u32) -> Vec<u8> {
+    let media = build_ewf2_media();
+    let mut segment = Ewf2Segment::new(1);
+
+    let device_information = utf16("1\nmain\nsn\tbp\tts\nABC123\t256\t5\n\n");
+    segment.section(0x01, &compress(&device_information));
+
+    let compressed = compress(&media[CHUNK_SIZE..2 * CHUNK_SIZE]);
+    let mut stored = media[2 * CHUNK_SIZE..].to_vec();
+    stored.extend(&adler32(&media[2 * CHUNK_SIZE..]).to_le_bytes());
+    let mut chunk_data = compressed.clone();
+    chunk_data.extend(&stored);
+    let chunks_offset = segment.section(0x03, &chunk_data);
+
+    let mut table = vec![0u8; 32];
+    LittleEndian::write_u64(&mut table[0..8], first_chunk);
+    LittleEndian::write_u32(&mut table[8..12], entry_count);
+    let entries: Vec<([u8; 8], u32, u32)> = vec![
+        (FILL_PATTERN, 0, 0x05),
+        ((chunks_offset).to_le_bytes(), compressed.len() as u32, 0x01),
+        ((chunks_offset + compressed.len() as u64).to_le_bytes(), stored.len() as u32, 0x02),
+    ];
+    for (offset, size, flags) in entries {
+        table.extend(&offset);
+        table.extend(&size.to_le_bytes());
+        table.extend(&flags.to_le_bytes());
+    }
+    table.extend(&[0u8; 16]);
+    segment.section(0x04, &table);
+
+    let mut md5_data = md5.to_vec();
+    md5_data.extend(&[0u8; 16]);
+    segment.section(0x08, &md5_data);
+    segment.section(0x0F, &[]);
+
+    segment.buffer
+}
+
+fn build_ewf2_media() -> Vec<u8> {
+    let mut media = build_media();
+    for (i, byte) in media[0..CHUNK_SIZE].iter_mut().enumerate() {
+        *byte = FILL_PATTERN[i % 8];
+    }
+    media
+}
+
+fn media_hashes(media: &[u8]) -> ([u8; 16], [u8; 20]) {
+    let mut sha1 = sha1::Sha1::new();
+    sha1.update(media);
+    (md5::compute(media).0, sha1.digest().bytes())
+}
+
+
+#[test]
+fn segment_extension_test() {
+    assert_eq!(segment_extension("E01", 2).unwrap(), "E02");
+    assert_eq!(segment_extension("E01", 99).unwrap(), "E99");
+    assert_eq!(segment_extension("E01", 100).unwrap(), "EAA");
+    assert_eq!(segment_extension("E01", 101).unwrap(), "EAB");
+    assert_eq!(segment_extension("E01", 100 + 26 * 26).unwrap(), "FAA");
+    assert_eq!(segment_extension("e01", 127).unwrap(), "ebb");
+    assert_eq!(segment_extension("Ex01", 12).unwrap(), "Ex12");
+    assert_eq!(segment_extension("dd", 2), None);
+}
+
+#[test]
+fn ewf1_read_test() {
+    let media = build_media();
+    let (md5, sha1) = media_hashes(&media);
+    let mut segments = build_ewf1_segments(md5, sha1);
+    assert!(is_ewf(&mut Cursor::new(segments[0].clone())).unwrap());
+
+    // Segments are ordered by their segment number, not their position
+    segments.reverse();
+    let mut image = EwfImage::from_segments(
+        segments.into_iter().map(Cursor::new).collect()
+    ).unwrap();
+
+    assert_eq!(image.get_format(), EwfFormat::Ewf1);
+    assert_eq!(image.get_chunk_size(), CHUNK_SIZE as u64);
+    assert_eq!(image.get_media_size(), MEDIA_SIZE as u64);
+
+    let mut buffer = Vec::new();
+    image.read_to_end(&mut buffer).unwrap();
+    assert_eq!(buffer, media);
+
+    // A read that spans the compressed and the stored chunk
+    let mut span = vec![0u8; 100];
+    image.seek(SeekFrom::Start(CHUNK_SIZE as u64 - 50)).unwrap();
+    image.read_exact(&mut span).unwrap();
+    assert_eq!(&span[..], &media[CHUNK_SIZE - 50..CHUNK_SIZE + 50]);
+
+    let verification = image.verify_hashes().unwrap();
+    assert_eq!(verification.md5_matches(), Some(true));
+    assert_eq!(verification.sha1_matches(), Some(true));
+    assert!(verification.is_verified());
+    assert_eq!(image.stream_position().unwrap(), CHUNK_SIZE as u64 + 50);
+}
+
+#[test]
+fn ewf1_hash_mismatch_test() {
+    let (mut md5, sha1) = media_hashes(&build_media());
+    md5[0] ^= 0xFF;
+    let segments = build_ewf1_segments(md5, sha1);
+    let mut image = EwfImage::from_segments(
+        segments.into_iter().map(Cursor::new).collect()
+    ).unwrap();
+
+    let verification = image.verify_hashes().unwrap();
+    assert_eq!(verification.md5_matches(), Some(false));
+    assert_eq!(verification.sha1_matches(), Some(true));
+    assert!(!verification.is_verified());
+}
+
+#[test]
+fn ewf1_missing_segment_test() {
+    let (md5, sha1) = media_hashes(&build_media());
+    let mut segments = build_ewf1_segments(md5, sha1);
+    segments.pop();
+
+    let result = EwfImage::from_segments(
+        segments.into_iter().map(Cursor::new).collect()
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn ewf1_open_segment_set_test() {
+    let (md5, sha1) = media_hashes(&build_media());
+    let directory = std::env::temp_dir().join(format!("rusty_usn_ewf_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for (i, segment) in build_ewf1_segments(md5, sha1).iter().enumerate() {
+        fs::write(directory.join(format!("evidence.E{:02}", i + 1)), segment).unwrap();
+    }
+
+    let mut image = EwfImage::open(directory.join("evidence.E01")).unwrap();
+    let mut buffer = Vec::new();
+    image.read_to_end(&mut buffer).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(buffer, build_media());
+}
+
+#[test]
+fn ewf2_read_test() {
+    let media = build_ewf2_media();
+    let (md5, _) = media_hashes(&media);
+    let segment = build_ewf2_segment(md5);
+    assert!(is_ewf(&mut Cursor::new(segment.clone())).unwrap());
+
+    let mut image = EwfImage::from_segments(vec![Cursor::new(segment)]).unwrap();
+    assert_eq!(image.get_format(), EwfFormat::Ewf2);
+    assert_eq!(image.get_chunk_size(), CHUNK_SIZE as u64);
+    assert_eq!(image.get_media_size(), MEDIA_SIZE as u64);
+
+    let mut buffer = Vec::new();
+    image.read_to_end(&mut buffer).unwrap();
+    assert_eq!(buffer, media);
+
+    let verification = image.verify_hashes().unwrap();
+    assert_eq!(verification.md5_matches(), Some(true));
+    assert_eq!(verification.sha1_matches(), None);
+    assert!(verification.is_verified());
+}
+
+#[test]
+fn ewf2_corrupt_table_test() {
+    for (first_chunk, entry_count) in [(1 << 40, 3), (u64::MAX, 3), (0, u32::MAX)] {
+        let segment = build_ewf2_segment_with_table([0u8; 16], first_chunk, entry_count);
+        assert!(EwfImage::from_segments(vec![Cursor::new(segment)]).is_err());
+    }
+}
+
+#[test]
+fn ewf1_corrupt_volume_test() {
+    let (md5, sha1) = media_hashes(&build_media());
+    let mut segments = build_ewf1_segments(md5, sha1);
+
+    // The volume section data follows the header section
+    let header_size = 76 + compress(b"1\r\nmain\r\nc\tn\r\ncase\tevidence\r\n\r\n").len();
+    let volume = 13 + header_size + 76;
+    LittleEndian::write_u32(&mut segments[0][volume + 8..volume + 12], u32::MAX);
+    LittleEndian::write_u32(&mut segments[0][volume + 12..volume + 16], u32::MAX);
+
+    let segments = segments.into_iter().map(Cursor::new).collect();
+    assert!(EwfImage::from_segments(segments).is_err());
+}
+
+#[test]
+fn ewf1_bad_chunk_checksum_test() {
+    let media = build_media();
+    let (md5, sha1) = media_hashes(&media);
+    let mut segments = build_ewf1_segments(md5, sha1);
+
+    // Corrupt the stored chunk of the second segment, after its sectors descriptor
+    let stored = 13 + 76;
+    segments[1][stored] ^= 0xFF;
+
+    // A bad checksum is logged and the data is still read
+    let segments = segments.into_iter().map(Cursor::new).collect();
+    let mut image = EwfImage::from_segments(segments).unwrap();
+    let mut buffer = Vec::new();
+    image.read_to_end(&mut buffer).unwrap();
+    assert_eq!(buffer[2 * CHUNK_SIZE], media[2 * CHUNK_SIZE] ^ 0xFF);
+}
+
+#[test]
+fn ewf1_oversized_chunk_test() {
+    let media = build_media();
+
+    // A first chunk of more than 64 MiB, flagged as compressed after the fact
+    let oversized = vec![0u8; 64 * 1024 * 1024 + 8];
+    let mut segment = Ewf1Segment::new(1);
+    segment.section("volume", &ewf1_volume());
+    let table = segment.chunks(&[
+        (&oversized, false),
+        (&media[CHUNK_SIZE..2 * CHUNK_SIZE], false),
+        (&media[2 * CHUNK_SIZE..], false),
+    ]) as usize;
+    segment.buffer[table + 27] |= 0x80;
+    segment.section("done", &[]);
+
+    let result = EwfImage::from_segments(vec![Cursor::new(segment.buffer)]);
+    assert!(result.is_err());
+}
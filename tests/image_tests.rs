@@ -0,0 +1,405 @@
+extern crate rusty_usn;
+mod common;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use byteorder::{ByteOrder, LittleEndian};
+use mft::MftEntry;
+use rusty_usn::error::ErrorKind;
+use rusty_usn::image::find_ntfs_volumes;
+use rusty_usn::image::ntfs::{decode_data_runs, DataRun, NtfsVolume};
+use rusty_usn::image::vss::VssVolume;
+use rusty_usn::mapping::FolderMapping;
+use rusty_usn::record::SnapshotMeta;
+use rusty_usn::usn::UsnParser;
+use common::V2_RECORD;
+
+const CLUSTER_SIZE: usize = 4096;
+const ENTRY_SIZE: usize = 1024;
+const MFT_LCN: usize = 4;
+const MFT_CLUSTERS: usize = 16;
+const JOURNAL_LCN: usize = 30;
+const VOLUME_CLUSTERS: usize = 32;
+const PARTITION_OFFSET: usize = 1024 * 1024;
+const VSS_BLOCK_SIZE: usize = 0x4000;
+const VSS_VOLUME_SIZE: usize = 0x30000;
+const VSS_IDENTIFIER: &[u8; 16] = b"\x6B\x87\x08\x38\x76\xC1\x48\x4E\xB7\xAE\x04\x04\x6E\x6C\xC7\x52";
+const STORE_ID: [u8; 16] = [0x11; 16];
+const SHADOW_COPY_ID: [u8; 16] = [
+    0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x34, 0x12,
+    0x12, 0x34, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC
+];
+
+
+fn pad8(buffer: &mut Vec<u8>) {
+    while buffer.len() % 8 != 0 {
+        buffer.push(0);
+    }
+}
+
+fn utf16(name: &str) -> Vec<u8> {
+    name.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+}
+
+fn file_name_attribute(parent_entry: u64, name: &str) -> Vec<u8> {
+    let mut value = vec![0u8; 66];
+    LittleEndian::write_u64(&mut value[0..8], parent_entry | (1 << 48));
+    value[64] = name.encode_utf16().count() as u8;
+    value[65] = 1;
+    value.extend(utf16(name));
+
+    let mut attribute = vec![0u8; 24];
+    LittleEndian::write_u32(&mut attribute[0..4], 0x30);
+    LittleEndian::write_u32(&mut attribute[16..20], value.len() as u32);
+    LittleEndian::write_u16(&mut attribute[20..22], 24);
+    attribute.extend(value);
+    pad8(&mut attribute);
+    let length = attribute.len() as u32;
+    LittleEndian::write_u32(&mut attribute[4..8], length);
+    attribute
+}
+
+fn data_attribute(name: &str, data_size: u64, runs: &[u8]) -> Vec<u8> {
+    let mut attribute = vec![0u8; 64];
+    LittleEndian::write_u32(&mut attribute[0..4], 0x80);
+    attribute[8] = 1;
+    attribute[9] = name.encode_utf16().count() as u8;
+    LittleEndian::write_u16(&mut attribute[10..12], 64);
+    attribute.extend(utf16(name));
+    pad8(&mut attribute);
+    let runs_offset = attribute.len() as u16;
+    LittleEndian::write_u16(&mut attribute[32..34], runs_offset);
+    LittleEndian::write_u64(&mut attribute[40..48], data_size);
+    LittleEndian::write_u64(&mut attribute[48..56], data_size);
+    LittleEndian::write_u64(&mut attribute[56..64], data_size);
+    attribute.extend(runs);
+    pad8(&mut attribute);
+    let length = attribute.len() as u32;
+    LittleEndian::write_u32(&mut attribute[4..8], length);
+    attribute
+}
+
+fn mft_entry(record_number: u32, flags: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+    let mut entry = vec![0u8; ENTRY_SIZE];
+    entry[0..4].copy_from_slice(b"FILE");
+    LittleEndian::write_u16(&mut entry[4..6], 0x30);
+    LittleEndian::write_u16(&mut entry[6..8], 3);
+    LittleEndian::write_u16(&mut entry[16..18], 1);
+    LittleEndian::write_u16(&mut entry[20..22], 0x38);
+    LittleEndian::write_u16(&mut entry[22..24], flags);
+    LittleEndian::write_u32(&mut entry[28..32], ENTRY_SIZE as u32);
+    LittleEndian::write_u32(&mut entry[44..48], record_number);
+
+    let mut offset = 0x38;
+    for attribute in attributes {
+        entry[offset..offset + attribute.len()].copy_from_slice(attribute);
+        offset += attribute.len();
+    }
+    LittleEndian::write_u32(&mut entry[offset..offset + 4], 0xFFFF_FFFF);
+    LittleEndian::write_u32(&mut entry[24..28], offset as u32 + 8);
+
+    // Update sequence value at the end of each sector, original bytes are zero
+    entry[0x30] = 1;
+    entry[510] = 1;
+    entry[1022] = 1;
+    entry
+}
+
+/// An image whose data starts at `base`, so a volume can sit at the end of the address space.
+struct ImageAt {
+    base: u64,
+    inner: Cursor<Vec<u8>>,
+}
+
+impl Read for ImageAt {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for ImageAt {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Start(offset) => {
+                let offset = offset.checked_sub(self.base)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "before the image"))?;
+                self.inner.seek(SeekFrom::Start(offset))?;
+                Ok(offset + self.base)
+            }
+            _ => unimplemented!()
+        }
+    }
+}
+
+
+fn build_volume() -> Vec<u8> {
+    let mut volume = vec![0u8; VOLUME_CLUSTERS * CLUSTER_SIZE];
+
+    // Boot sector
+    volume[3..11].copy_from_slice(b"NTFS    ");
+    LittleEndian::write_u16(&mut volume[11..13], 512);
+    volume[13] = 8;
+    LittleEndian::write_u64(&mut volume[40..48], (VOLUME_CLUSTERS * 8) as u64);
+    LittleEndian::write_u64(&mut volume[48..56], MFT_LCN as u64);
+    volume[64] = 0xF6;
+
+    let mft_size = (MFT_CLUSTERS * CLUSTER_SIZE) as u64;
+    let entries = vec![
+        (0, mft_entry(0, 1, &[
+            file_name_attribute(5, "$MFT"),
+            data_attribute("", mft_size, &[0x11, MFT_CLUSTERS as u8, MFT_LCN as u8, 0x00])
+        ])),
+        (5, mft_entry(5, 3, &[file_name_attribute(5, ".")])),
+        (11, mft_entry(11, 3, &[file_name_attribute(5, "$Extend")])),
+        // Two sparse clusters then one allocated cluster
+        (40, mft_entry(40, 1, &[
+            file_name_attribute(11, "$UsnJrnl"),
+            data_attribute("$J", 3 * CLUSTER_SIZE as u64, &[0x01, 0x02, 0x11, 0x01, JOURNAL_LCN as u8, 0x00])
+        ])),
+        (41, mft_entry(41, 3, &[file_name_attribute(5, "Users")])),
+    ];
+
+    for (number, entry) in entries {
+        let offset = MFT_LCN * CLUSTER_SIZE + number * ENTRY_SIZE;
+        volume[offset..offset + ENTRY_SIZE].copy_from_slice(&entry);
+    }
+
+    let journal_offset = JOURNAL_LCN * CLUSTER_SIZE;
+    volume[journal_offset..journal_offset + V2_RECORD.len()].copy_from_slice(V2_RECORD);
+
+    volume
+}
+
+fn build_mbr_image() -> Vec<u8> {
+    let volume = build_volume();
+    let mut image = vec![0u8; PARTITION_OFFSET];
+    let entry = &mut image[446..462];
+    entry[4] = 0x07;
+    LittleEndian::write_u32(&mut entry[8..12], (PARTITION_OFFSET / 512) as u32);
+    LittleEndian::write_u32(&mut entry[12..16], (volume.len() / 512) as u32);
+    image[510] = 0x55;
+    image[511] = 0xAA;
+    image.extend(volume);
+    image
+}
+
+fn vss_block(record_type: u32, next_offset: u64) -> Vec<u8> {
+    let mut block = vec![0u8; VSS_BLOCK_SIZE];
+    block[0..16].copy_from_slice(VSS_IDENTIFIER);
+    LittleEndian::write_u32(&mut block[16..20], 1);
+    LittleEndian::write_u32(&mut block[20..24], record_type);
+    LittleEndian::write_u64(&mut block[40..48], next_offset);
+    block
+}
+
+/// The test volume with one shadow copy, taken when the journal record's
+/// USN was 4096.
+fn build_vss_volume() -> Vec<u8> {
+    let mut volume = build_volume();
+    volume.resize(VSS_VOLUME_SIZE, 0);
+    let (catalog, store_header, block_list, store_data) = (0x20000, 0x24000, 0x28000, 0x2C000);
+
+    let header = &mut volume[0x1E00..0x1E00 + 64];
+    header[0..16].copy_from_slice(VSS_IDENTIFIER);
+    LittleEndian::write_u64(&mut header[48..56], catalog as u64);
+
+    let mut catalog_block = vss_block(2, 0);
+    let info_entry = &mut catalog_block[128..256];
+    LittleEndian::write_u64(&mut info_entry[0..8], 2);
+    LittleEndian::write_u64(&mut info_entry[8..16], VSS_VOLUME_SIZE as u64);
+    info_entry[16..32].copy_from_slice(&STORE_ID);
+    LittleEndian::write_u64(&mut info_entry[48..56], 0x01D4_7C0D_7C2E_E000);
+    let location_entry = &mut catalog_block[256..384];
+    LittleEndian::write_u64(&mut location_entry[0..8], 3);
+    LittleEndian::write_u64(&mut location_entry[8..16], block_list as u64);
+    location_entry[16..32].copy_from_slice(&STORE_ID);
+    LittleEndian::write_u64(&mut location_entry[32..40], store_header as u64);
+    volume[catalog..catalog + VSS_BLOCK_SIZE].copy_from_slice(&catalog_block);
+
+    let mut header_block = vss_block(4, 0);
+    header_block[128 + 16..128 + 32].copy_from_slice(&SHADOW_COPY_ID);
+    volume[store_header..store_header + VSS_BLOCK_SIZE].copy_from_slice(&header_block);
+
+    // The block holding the journal cluster was copied to the store
+    let original = (JOURNAL_LCN * CLUSTER_SIZE) / VSS_BLOCK_SIZE * VSS_BLOCK_SIZE;
+    let mut list_block = vss_block(3, 0);
+    let descriptor = &mut list_block[128..160];
+    LittleEndian::write_u64(&mut descriptor[0..8], original as u64);
+    LittleEndian::write_u64(&mut descriptor[16..24], store_data as u64);
+    volume[block_list..block_list + VSS_BLOCK_SIZE].copy_from_slice(&list_block);
+
+    let mut old_block = volume[original..original + VSS_BLOCK_SIZE].to_vec();
+    let usn_offset = JOURNAL_LCN * CLUSTER_SIZE - original + 24;
+    LittleEndian::write_u64(&mut old_block[usn_offset..usn_offset + 8], 4096);
+    volume[store_data..store_data + VSS_BLOCK_SIZE].copy_from_slice(&old_block);
+
+    volume
+}
+
+
+#[test]
+fn data_run_test() {
+    let runs = decode_data_runs(&[0x21, 0x18, 0x34, 0x56, 0x01, 0x10, 0x11, 0x08, 0xF0, 0x00], 0).unwrap();
+
+    assert_eq!(runs, vec![
+        DataRun { vcn: 0, length: 0x18, lcn: Some(0x5634) },
+        DataRun { vcn: 0x18, length: 0x10, lcn: None },
+        DataRun { vcn: 0x28, length: 0x08, lcn: Some(0x5634 - 0x10) },
+    ]);
+}
+
+#[test]
+fn corrupt_data_run_test() {
+    // Two runs whose LCN deltas overflow
+    let runs = [0x81, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x81, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x00];
+    let error = decode_data_runs(&runs, 0).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidImage);
+
+    let error = decode_data_runs(&[0x01, 0x01, 0x00], u64::MAX).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidImage);
+}
+
+#[test]
+fn corrupt_boot_sector_test() {
+    // A cluster of 2^112 sectors, an MFT record of 2^128 bytes and one of 246 clusters
+    for (offset, value) in [(13, 0x90), (64, 0x80), (64, 0x7F)] {
+        let mut volume = build_volume();
+        volume[offset] = value;
+        let handle = Arc::new(Mutex::new(Cursor::new(volume)));
+        let error = NtfsVolume::open(handle, 0).err().unwrap();
+        assert_eq!(error.kind, ErrorKind::InvalidImage);
+    }
+}
+
+#[test]
+fn volume_offset_overflow_test() {
+    // The $MFT is past the last addressable byte
+    let base = u64::MAX - CLUSTER_SIZE as u64 + 1;
+    let handle = Arc::new(Mutex::new(ImageAt { base, inner: Cursor::new(build_volume()) }));
+    let error = NtfsVolume::open(handle, base).err().unwrap();
+    assert_eq!(error.kind, ErrorKind::InvalidImage);
+}
+
+#[test]
+fn deleted_folder_sequence_test() {
+    // A deleted folder whose sequence was never incremented
+    let mut buffer = mft_entry(41, 2, &[file_name_attribute(5, "Users")]);
+    LittleEndian::write_u16(&mut buffer[16..18], 0);
+    let entry = MftEntry::from_buffer(buffer, 41).unwrap();
+
+    let mut mapping = FolderMapping::new();
+    mapping.add_mft_entry(&entry);
+    assert_eq!(mapping.enumerate_path(41, 0).unwrap(), "[root]/Users");
+}
+
+#[test]
+fn gpt_entry_count_test() {
+    let mut image = vec![0u8; 4 * 512];
+    image[446 + 4] = 0xEE;
+    image[510] = 0x55;
+    image[511] = 0xAA;
+    let header = &mut image[512..1024];
+    header[0..8].copy_from_slice(b"EFI PART");
+    LittleEndian::write_u64(&mut header[72..80], 2);
+    LittleEndian::write_u32(&mut header[80..84], u32::MAX);
+    LittleEndian::write_u32(&mut header[84..88], 128);
+
+    let error = find_ntfs_volumes(&mut Cursor::new(image.clone())).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidImage);
+
+    LittleEndian::write_u32(&mut image[512 + 80..512 + 84], 4);
+    LittleEndian::write_u32(&mut image[512 + 84..512 + 88], 130);
+    let error = find_ntfs_volumes(&mut Cursor::new(image)).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::InvalidImage);
+}
+
+#[test]
+fn find_volumes_test() {
+    let mut image = Cursor::new(build_mbr_image());
+    let volumes = find_ntfs_volumes(&mut image).unwrap();
+
+    assert_eq!(volumes.len(), 1);
+    assert_eq!(volumes[0].index, Some(0));
+    assert_eq!(volumes[0].offset, PARTITION_OFFSET as u64);
+    assert_eq!(volumes[0].label("disk.dd"), "disk.dd:p0");
+
+    let mut volume_image = Cursor::new(build_volume());
+    let volumes = find_ntfs_volumes(&mut volume_image).unwrap();
+    assert_eq!(volumes.len(), 1);
+    assert_eq!(volumes[0].index, None);
+    assert_eq!(volumes[0].offset, 0);
+}
+
+#[test]
+fn locate_usn_journal_test() {
+    let handle = Arc::new(Mutex::new(Cursor::new(build_mbr_image())));
+    let volume = NtfsVolume::open(handle, PARTITION_OFFSET as u64).unwrap();
+    let mut scan = volume.scan_mft().unwrap();
+
+    let journal = scan.usn_journal.take().unwrap();
+    assert_eq!(journal.get_size(), 3 * CLUSTER_SIZE as u64);
+    assert_eq!(journal.data_ranges(), vec![2 * CLUSTER_SIZE as u64..3 * CLUSTER_SIZE as u64]);
+
+    let data_ranges = journal.data_ranges();
+    let mut parser = UsnParser::from_read_seek("test image".to_string(), journal)
+        .unwrap()
+        .with_data_ranges(data_ranges);
+
+    let entries: Vec<_> = parser.records().collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].meta.offset, 8192);
+    assert_eq!(entries[0].record.get_usn(), 8192);
+    assert_eq!(entries[0].record.get_file_name(), "BTDevManager.log");
+
+    let parent = entries[0].record.get_parent_reference();
+    let path = scan.folder_mapping.enumerate_path(parent.entry, parent.sequence).unwrap();
+    assert_eq!(path, "[root]/Users");
+}
+
+#[test]
+fn vss_snapshot_test() {
+    let handle = Arc::new(Mutex::new(Cursor::new(build_vss_volume())));
+    let vss_volume = VssVolume::open(handle.clone(), 0).unwrap().unwrap();
+    assert_eq!(vss_volume.get_snapshot_count(), 1);
+
+    let info = vss_volume.get_store_info(0).unwrap().clone();
+    assert_eq!(info.shadow_copy_id, "12345678-1234-1234-1234-123456789abc");
+    assert_eq!(info.volume_size, VSS_VOLUME_SIZE as u64);
+    assert_eq!(info.creation_time.to_string(), "2018-11-14 11:30:46.045286 UTC");
+
+    let snapshot = vss_volume.snapshot(0).unwrap();
+    let volume = NtfsVolume::open(Arc::new(Mutex::new(snapshot)), 0).unwrap();
+    let journal = volume.scan_mft().unwrap().usn_journal.unwrap();
+    let data_ranges = journal.data_ranges();
+    let mut parser = UsnParser::from_read_seek("test snapshot".to_string(), journal)
+        .unwrap()
+        .with_data_ranges(data_ranges)
+        .with_snapshot(SnapshotMeta {
+            id: info.shadow_copy_id.clone(),
+            created: info.creation_time
+        });
+
+    let entries: Vec<_> = parser.records().collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].record.get_usn(), 4096);
+
+    let value = entries[0].to_json_value().unwrap();
+    assert_eq!(value["meta__snapshot_id"], "12345678-1234-1234-1234-123456789abc");
+    assert!(value["meta__snapshot_created"].as_str().unwrap().starts_with("2018-11-14T11:30:46"));
+
+    // The live volume still has the current record
+    let volume = NtfsVolume::open(handle, 0).unwrap();
+    let journal = volume.scan_mft().unwrap().usn_journal.unwrap();
+    let data_ranges = journal.data_ranges();
+    let mut parser = UsnParser::from_read_seek("test volume".to_string(), journal)
+        .unwrap()
+        .with_data_ranges(data_ranges);
+    let entries: Vec<_> = parser.records().collect();
+    assert_eq!(entries[0].record.get_usn(), 8192);
+    assert!(entries[0].to_json_value().unwrap().get("meta__snapshot_id").is_none());
+}
+
+#[test]
+fn no_vss_test() {
+    let handle = Arc::new(Mutex::new(Cursor::new(build_volume())));
+    assert!(VssVolume::open(handle, 0).unwrap().is_none());
+}
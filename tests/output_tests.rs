@@ -0,0 +1,271 @@
+extern crate rusty_usn;
+mod common;
+use std::sync::mpsc;
+use rusty_usn::error::ErrorKind;
+use rusty_usn::output::{Format, RecordSink};
+use rusty_usn::output::jsonl::JsonlWriter;
+use rusty_usn::output::options::{FlagFormat, SerializeOptions, TimestampFormat};
+use chrono::FixedOffset;
+use rusty_usn::output::csv::{quote_field, Column, CsvWriter};
+use rusty_usn::output::timeline::{BodyfileWriter, TlnWriter};
+use rusty_usn::output::ecs::{to_ecs_value, EcsWriter};
+use rusty_usn::output::timesketch::{timestamp_desc, to_timesketch_value, TimesketchWriter};
+use rusty_usn::output::ocsf::{to_ocsf_value, Activity, OcsfWriter};
+use rusty_usn::flags::Reason;
+use rusty_usn::triage::VolumeMeta;
+use common::{v2_entry, RecordBuilder};
+
+
+#[test]
+fn quote_field_test() {
+    assert_eq!(quote_field("plain.txt", ','), "plain.txt");
+    assert_eq!(quote_field("a,b.txt", ','), "\"a,b.txt\"");
+    assert_eq!(quote_field("a,b.txt", '\t'), "a,b.txt");
+    assert_eq!(quote_field("say \"hi\".txt", ','), "\"say \"\"hi\"\".txt\"");
+    assert_eq!(quote_field("line\nbreak", '\t'), "\"line\nbreak\"");
+}
+
+#[test]
+fn csv_writer_test() {
+    let columns = Column::parse_list("usn,timestamp,full_name,file_reference_entry,file_reference_u128,meta__offset").unwrap();
+    let mut writer = CsvWriter::new(Vec::new()).with_columns(columns);
+    writer.write_entry(&v2_entry(), Some("[root]/odd, \"name\"/BTDevManager.log")).unwrap();
+    writer.write_entry(&v2_entry(), None).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(output, concat!(
+        "usn,timestamp,full_name,file_reference_entry,file_reference_u128,meta__offset\r\n",
+        "8192,2013-10-19T12:16:53.276040Z,\"[root]/odd, \"\"name\"\"/BTDevManager.log\",115,10477624533077459059,96\r\n",
+        "8192,2013-10-19T12:16:53.276040Z,,115,10477624533077459059,96\r\n",
+    ));
+}
+
+#[test]
+fn tsv_writer_test() {
+    let mut writer = CsvWriter::new_tsv(Vec::new());
+    writer.write_entry(&v2_entry(), None).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<&str> = output.split("\r\n").collect();
+    let header: Vec<&str> = lines[0].split('\t').collect();
+    let values: Vec<&str> = lines[1].split('\t').collect();
+
+    assert_eq!(header.len(), Column::all().len());
+    assert_eq!(header.len(), values.len());
+    assert_eq!(values[header.iter().position(|name| *name == "file_name").unwrap()], "BTDevManager.log");
+    assert_eq!(values[header.iter().position(|name| *name == "meta__source").unwrap()], "C:\\Test\\$J");
+
+    assert!(Column::parse_list("usn,nope").is_err());
+}
+
+#[test]
+fn bodyfile_writer_test() {
+    let mut writer = BodyfileWriter::new(Vec::new());
+    writer.write_entry(&v2_entry(), Some("[root]/Windows/BTDevManager.log")).unwrap();
+    writer.write_entry(&v2_entry(), None).unwrap();
+
+    // A data extend goes in the modified time
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(output, concat!(
+        "0|[root]/Windows/BTDevManager.log ($UsnJrnl: DATA_EXTEND)|115-37224|r/r---------|0|0|0|0|1382185013|0|0\n",
+        "0|BTDevManager.log ($UsnJrnl: DATA_EXTEND)|115-37224|r/r---------|0|0|0|0|1382185013|0|0\n",
+    ));
+}
+
+#[test]
+fn tln_writer_test() {
+    let mut entry = v2_entry();
+    entry.meta.volume = Some(VolumeMeta {volume: "C".to_string(), host: Some("HOST1".to_string())});
+
+    let mut writer = TlnWriter::new(Vec::new());
+    writer.write_entry(&entry, Some("[root]/odd|name")).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(output, "1382185013|USN|HOST1||[root]/odd_name ($UsnJrnl: DATA_EXTEND)\n");
+}
+
+#[test]
+fn ecs_writer_test() {
+    let mut entry = v2_entry();
+    entry.meta.volume = Some(VolumeMeta {volume: "C".to_string(), host: Some("HOST1".to_string())});
+
+    let mut writer = EcsWriter::new(Vec::new()).with_index("usn-case1");
+    writer.write_entry(&entry, Some("[root]/Windows/BTDevManager.log")).unwrap();
+    writer.write_entry(&v2_entry(), None).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<serde_json::Value> = output.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], serde_json::json!({"index": {"_index": "usn-case1"}}));
+
+    let document = &lines[1];
+    assert_eq!(document["@timestamp"], "2013-10-19T12:16:53.276040Z");
+    assert_eq!(document["event"]["action"], "DATA_EXTEND");
+    assert_eq!(document["event"]["type"], serde_json::json!(["change"]));
+    assert_eq!(document["file"]["name"], "BTDevManager.log");
+    assert_eq!(document["file"]["path"], "[root]/Windows/BTDevManager.log");
+    assert_eq!(document["file"]["directory"], "[root]/Windows");
+    assert_eq!(document["file"]["extension"], "log");
+    assert_eq!(document["host"]["name"], "HOST1");
+    assert_eq!(document["log"]["file"]["path"], "C:\\Test\\$J");
+    assert_eq!(document["usn"]["file_reference"]["entry"], 115);
+
+    // Without a folder mapping there is no path
+    assert!(lines[3]["file"].get("path").is_none());
+    assert!(lines[3].get("host").is_none());
+}
+
+#[test]
+fn timesketch_writer_test() {
+    let mut writer = TimesketchWriter::new(Vec::new());
+    writer.write_entry(&v2_entry(), Some("[root]/Windows/BTDevManager.log")).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let event: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+    assert_eq!(event["message"], "File content modified: [root]/Windows/BTDevManager.log");
+    assert_eq!(event["datetime"], "2013-10-19T12:16:53.276040Z");
+    assert_eq!(event["timestamp"], 1_382_185_013_276_040i64);
+    assert_eq!(event["timestamp_desc"], "File Content Modified");
+    // The original fields are kept, flattened
+    assert_eq!(event["reason"], "DATA_EXTEND");
+    assert_eq!(event["file_reference_entry"], 115);
+    assert_eq!(event["meta__source"], "C:\\Test\\$J");
+
+    assert_eq!(timestamp_desc(Reason::FILE_CREATE | Reason::DATA_EXTEND | Reason::CLOSE), "File Created");
+    assert_eq!(timestamp_desc(Reason::RENAME_NEW_NAME), "File Renamed To");
+    assert_eq!(timestamp_desc(Reason::BASIC_INFO_CHANGE | Reason::CLOSE), "File Metadata Modified");
+    assert_eq!(timestamp_desc(Reason::CLOSE), "File Closed");
+}
+
+#[test]
+fn ocsf_writer_test() {
+    let mut entry = v2_entry();
+    entry.meta.volume = Some(VolumeMeta {volume: "C".to_string(), host: Some("HOST1".to_string())});
+
+    let mut writer = OcsfWriter::new(Vec::new());
+    writer.write_entry(&entry, Some("[root]/Windows/BTDevManager.log")).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let event: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+    assert_eq!(event["class_uid"], 1001);
+    assert_eq!(event["activity_id"], 3);
+    assert_eq!(event["type_uid"], 100103);
+    assert_eq!(event["time"], 1_382_185_013_276i64);
+    assert_eq!(event["file"]["name"], "BTDevManager.log");
+    assert_eq!(event["file"]["path"], "[root]/Windows/BTDevManager.log");
+    assert_eq!(event["file"]["parent_folder"], "[root]/Windows");
+    assert_eq!(event["file"]["attributes"], 0x2020);
+    assert_eq!(event["file"]["uid"], "10477624533077459059");
+    assert_eq!(event["device"]["name"], "C");
+    assert_eq!(event["device"]["hostname"], "HOST1");
+    assert_eq!(event["unmapped"]["usn"], 8192);
+
+    assert_eq!(Activity::from_reason(Reason::FILE_CREATE | Reason::CLOSE), Activity::Create);
+    assert_eq!(Activity::from_reason(Reason::RENAME_OLD_NAME), Activity::Rename);
+    assert_eq!(Activity::from_reason(Reason::SECURITY_CHANGE | Reason::CLOSE), Activity::SetSecurity);
+    assert_eq!(Activity::from_reason(Reason::BASIC_INFO_CHANGE), Activity::SetAttributes);
+    assert_eq!(Activity::from_reason(Reason::CLOSE), Activity::Other);
+}
+
+#[test]
+fn irregular_name_test() {
+    // An unpaired high surrogate in place of the B
+    let entry = RecordBuilder::new().with_name_unit(0, 0xD800).build();
+
+    let document = to_ecs_value(&entry, None);
+    assert_eq!(document["file"]["name"], r"\uD800TDevManager.log");
+    assert_eq!(document["usn"]["irregular_name"], true);
+    assert_eq!(document["usn"]["file_name_hex"], "00d854004400650076004d0061006e0061006700650072002e006c006f006700");
+
+    let event = to_ocsf_value(&entry, None).unwrap();
+    assert_eq!(event["unmapped"]["irregular_name"], true);
+
+    let event = to_timesketch_value(&entry, None).unwrap().unwrap();
+    assert_eq!(event["irregular_name"], true);
+
+    // Names that decode cleanly are not marked
+    assert!(to_ecs_value(&v2_entry(), None)["usn"].get("irregular_name").is_none());
+    assert!(to_ocsf_value(&v2_entry(), None).unwrap()["unmapped"].get("irregular_name").is_none());
+}
+
+#[test]
+fn jsonl_writer_test() {
+    let mut writer = JsonlWriter::new(Vec::new());
+    writer.write_entry(&v2_entry(), Some("[root]/Windows/BTDevManager.log")).unwrap();
+    writer.write_entry(&v2_entry(), None).unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<serde_json::Value> = output.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["full_name"], "[root]/Windows/BTDevManager.log");
+    assert_eq!(lines[0]["usn"], 8192);
+    assert!(lines[1].get("full_name").is_none());
+}
+
+#[test]
+fn record_sink_test() {
+    let (sender, receiver) = mpsc::channel();
+    let mut sink: Box<dyn RecordSink + Send> = Box::new(sender);
+    sink.write_entries(&[v2_entry(), v2_entry()]).unwrap();
+    sink.write_entry(&v2_entry(), Some("[root]/Windows/BTDevManager.log")).unwrap();
+    sink.finish().unwrap();
+    drop(sink);
+
+    let values: Vec<serde_json::Value> = receiver.iter().collect();
+    assert_eq!(values.len(), 3);
+    assert_eq!(values[2]["full_name"], "[root]/Windows/BTDevManager.log");
+
+    for format in Format::all() {
+        assert_eq!(Format::from_name(format.name()), Some(*format));
+        assert_eq!(format.to_writer(Vec::new()).is_ok(), !format.needs_path());
+    }
+    assert_eq!(Format::from_name("xml"), None);
+    assert_eq!(Format::Sqlite.to_writer(Vec::new()).err().unwrap().kind, ErrorKind::UnsupportedFormat);
+}
+
+#[test]
+fn serialize_options_test() {
+    let record = v2_entry().record;
+    let default = SerializeOptions::new();
+    assert_eq!(default.timestamp_value(&record).unwrap(), "2013-10-19T12:16:53.276040Z");
+    assert_eq!(default.flags_value(&record.get_file_attributes()), "ARCHIVE | NOT_CONTENT_INDEXED");
+
+    let options = SerializeOptions::new()
+        .with_flag_format(FlagFormat::Windows)
+        .with_timestamp_format(TimestampFormat::Rfc3339Full)
+        .with_offset(FixedOffset::west_opt(5 * 3600).unwrap());
+    let mut writer = JsonlWriter::new(Vec::new()).with_options(options);
+    writer.write_entry(&v2_entry(), None).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&writer.into_inner()).unwrap();
+    assert_eq!(value["timestamp"], "2013-10-19T07:16:53.2760403-05:00");
+    assert_eq!(value["reason"], "USN_REASON_DATA_EXTEND");
+    assert_eq!(value["file_attributes"], "FILE_ATTRIBUTE_ARCHIVE | FILE_ATTRIBUTE_NOT_CONTENT_INDEXED");
+    assert_eq!(value["source_info"], "(empty)");
+    assert_eq!(value["reason_code"], 2);
+
+    let options = SerializeOptions::new()
+        .with_flag_format(FlagFormat::Array)
+        .with_timestamp_format(TimestampFormat::Filetime);
+    assert_eq!(options.timestamp_value(&record).unwrap(), 130_266_586_132_760_403u64);
+    assert_eq!(options.flags_value(&record.get_file_attributes()), serde_json::json!(["ARCHIVE", "NOT_CONTENT_INDEXED"]));
+
+    let options = SerializeOptions::new()
+        .with_flag_format(FlagFormat::Integer)
+        .with_timestamp_format(TimestampFormat::Epoch);
+    let columns = Column::parse_list("timestamp,reason,file_attributes").unwrap();
+    let mut writer = CsvWriter::new(Vec::new()).with_columns(columns).with_options(options);
+    writer.write_entry(&v2_entry(), None).unwrap();
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(output, "timestamp,reason,file_attributes\r\n1382185013.2760403,2,8224\r\n");
+    assert_eq!(options.timestamp_value(&record).unwrap(), "1382185013.2760403");
+
+    // Before 1970, and past the range of an i64
+    let record = RecordBuilder::new().with_filetime(116_444_735_995_000_000).build().record;
+    assert_eq!(options.timestamp_value(&record).unwrap(), "-0.5000000");
+    let record = RecordBuilder::new().with_filetime(u64::MAX).build().record;
+    assert_eq!(options.timestamp_value(&record).unwrap(), "1833029933770.9551615");
+}
@@ -2,6 +2,10 @@ extern crate rusty_usn;
 extern crate serde_json;
 use std::io::Cursor;
 use rusty_usn::record;
+use rusty_usn::flags;
+use rusty_usn::utils;
+use rusty_usn::mapping::FolderMapping;
+use winstructs::ntfs::mft_reference::MftReference;
 use byteorder::{LittleEndian, ReadBytesExt};
 
 
@@ -26,7 +30,7 @@ fn usn_record_json_value_test() {
     };
 
     let v3_json_value = v3_record.to_json_value(None).unwrap();
-    assert_eq!(&v3_json_value.to_string(), r#"{"file_attributes":"ARCHIVE","file_name":"CIDownloader.log","file_name_length":32,"file_name_offset":76,"file_reference":{"entry":35513,"sequence":2,"u128":"562949953456825"},"major_version":3,"minor_version":0,"parent_reference":{"entry":1992,"sequence":2,"u128":"562949953423304"},"reason":"DATA_EXTEND","record_length":112,"security_id":0,"source_info":"(empty)","timestamp":"2019-09-08T00:56:52.138160Z","usn":6889306208}"#);
+    assert_eq!(&v3_json_value.to_string(), r#"{"file_attributes":"ARCHIVE","file_attributes_code":32,"file_name":"CIDownloader.log","file_name_length":32,"file_name_offset":76,"file_reference":{"entry":35513,"sequence":2,"u128":"562949953456825"},"major_version":3,"minor_version":0,"parent_reference":{"entry":1992,"sequence":2,"u128":"562949953423304"},"reason":"DATA_EXTEND","reason_code":2,"record_length":112,"security_id":0,"source_info":"(empty)","source_info_code":0,"timestamp":"2019-09-08T00:56:52.138160Z","usn":6889306208}"#);
 
     let record_meta = record::EntryMeta::new(
         "Test Buffer", 
@@ -37,7 +41,7 @@ fn usn_record_json_value_test() {
         Some(record_meta.to_json_value().unwrap())
     ).unwrap();
     
-    assert_eq!(&v3_json_value_additional.to_string(), r#"{"file_attributes":"ARCHIVE","file_name":"CIDownloader.log","file_name_length":32,"file_name_offset":76,"file_reference":{"entry":35513,"sequence":2,"u128":"562949953456825"},"major_version":3,"meta__offset":0,"meta__source":"Test Buffer","minor_version":0,"parent_reference":{"entry":1992,"sequence":2,"u128":"562949953423304"},"reason":"DATA_EXTEND","record_length":112,"security_id":0,"source_info":"(empty)","timestamp":"2019-09-08T00:56:52.138160Z","usn":6889306208}"#);
+    assert_eq!(&v3_json_value_additional.to_string(), r#"{"file_attributes":"ARCHIVE","file_attributes_code":32,"file_name":"CIDownloader.log","file_name_length":32,"file_name_offset":76,"file_reference":{"entry":35513,"sequence":2,"u128":"562949953456825"},"major_version":3,"meta__offset":0,"meta__scan_mode":"carve","meta__source":"Test Buffer","minor_version":0,"parent_reference":{"entry":1992,"sequence":2,"u128":"562949953423304"},"reason":"DATA_EXTEND","reason_code":2,"record_length":112,"security_id":0,"source_info":"(empty)","source_info_code":0,"timestamp":"2019-09-08T00:56:52.138160Z","usn":6889306208}"#);
 }
 
 #[test]
@@ -221,3 +225,119 @@ fn usn_record_v2_json_test() {
 
     assert_eq!(json_str, r#"{"record_length":96,"major_version":2,"minor_version":0,"file_reference":{"entry":115,"sequence":37224},"parent_reference":{"entry":141883,"sequence":7},"usn":20342374400,"timestamp":"2013-10-19T12:16:53.276040Z","reason":"DATA_EXTEND","source_info":"(empty)","security_id":0,"file_attributes":"ARCHIVE | NOT_CONTENT_INDEXED","file_name_length":32,"file_name_offset":60,"file_name":"BTDevManager.log"}"#);
 }
+
+#[test]
+fn usn_record_v4_test() {
+    let record_buffer: &[u8] = &[
+        0x60,0x00,0x00,0x00,0x04,0x00,0x00,0x00,0xB9,0x8A,0x00,0x00,0x00,0x00,0x02,0x00,
+        0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0xC8,0x07,0x00,0x00,0x00,0x00,0x02,0x00,
+        0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x60,0x78,0xA2,0x9A,0x01,0x00,0x00,0x00,
+        0x01,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x02,0x00,0x10,0x00,
+        0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x10,0x00,0x00,0x00,0x00,0x00,0x00,
+        0x00,0x00,0x01,0x00,0x00,0x00,0x00,0x00,0x00,0x20,0x00,0x00,0x00,0x00,0x00,0x00
+    ];
+
+    let record = match record::UsnRecord::new(4, record_buffer) {
+        Ok(record) => record,
+        Err(error) => panic!("{}", error)
+    };
+
+    assert_eq!(record.get_usn(), 6889306208);
+    assert_eq!(record.get_file_name(), "");
+    assert_eq!(record.get_reason_code().bits(), 1);
+    assert_eq!(record.get_file_reference().entry, 35513);
+    assert_eq!(record.get_parent_reference().entry, 1992);
+
+    let extents = record.get_extents().unwrap();
+    assert_eq!(extents.len(), 2);
+    assert_eq!(extents[0].offset, 0);
+    assert_eq!(extents[0].length, 4096);
+    assert_eq!(extents[1].offset, 65536);
+    assert_eq!(extents[1].length, 8192);
+
+    let json_str = serde_json::to_string(&record).unwrap();
+    assert_eq!(json_str, r#"{"record_length":96,"major_version":4,"minor_version":0,"file_reference":{"u128":"562949953456825","entry":35513,"sequence":2},"parent_reference":{"u128":"562949953423304","entry":1992,"sequence":2},"usn":6889306208,"reason":"DATA_OVERWRITE","source_info":"(empty)","remaining_extents":0,"number_of_extents":2,"extent_size":16,"extents":[{"offset":0,"length":4096},{"offset":65536,"length":8192}]}"#);
+}
+
+#[test]
+fn usn_record_unknown_flags_test() {
+    let mut record_buffer: Vec<u8> = vec![
+        0x60,0x00,0x00,0x00,0x02,0x00,0x00,0x00,0x73,0x00,0x00,0x00,0x00,0x00,0x68,0x91,
+        0x3B,0x2A,0x02,0x00,0x00,0x00,0x07,0x00,0x00,0x00,0x80,0xBC,0x04,0x00,0x00,0x00,
+        0x53,0xC7,0x8B,0x18,0xC5,0xCC,0xCE,0x01,0x02,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+        0x00,0x00,0x00,0x00,0x20,0x20,0x00,0x00,0x20,0x00,0x3C,0x00,0x42,0x00,0x54,0x00,
+        0x44,0x00,0x65,0x00,0x76,0x00,0x4D,0x00,0x61,0x00,0x6E,0x00,0x61,0x00,0x67,0x00,
+        0x65,0x00,0x72,0x00,0x2E,0x00,0x6C,0x00,0x6F,0x00,0x67,0x00,0x00,0x00,0x00,0x00
+    ];
+    // DATA_EXTEND | DESIRED_STORAGE_CLASS_CHANGE and a reason bit without a name
+    record_buffer[40..44].copy_from_slice(&0x0300_0002u32.to_le_bytes());
+    // A source info bit without a name
+    record_buffer[44..48].copy_from_slice(&0x0000_0010u32.to_le_bytes());
+    // ARCHIVE | PINNED | STRICTLY_SEQUENTIAL
+    record_buffer[52..56].copy_from_slice(&0x2008_0020u32.to_le_bytes());
+
+    let record = record::UsnRecord::new(2, record_buffer.as_slice()).unwrap();
+    assert_eq!(record.get_reason_code().bits(), 0x0300_0002);
+    assert_eq!(flags::flag_names(&record.get_reason_code()), vec![
+        "DATA_EXTEND", "DESIRED_STORAGE_CLASS_CHANGE", "UNKNOWN(0x02000000)"
+    ]);
+    assert_eq!(flags::flag_text(&record.get_source_info()), "UNKNOWN(0x00000010)");
+    assert_eq!(flags::flag_text(&flags::FileAttributes::VIRTUAL), "VIRTUAL");
+
+    let value = record.to_json_value(None).unwrap();
+    assert_eq!(value["reason"], "DATA_EXTEND | DESIRED_STORAGE_CLASS_CHANGE | UNKNOWN(0x02000000)");
+    assert_eq!(value["reason_code"], 0x0300_0002);
+    assert_eq!(value["source_info"], "UNKNOWN(0x00000010)");
+    assert_eq!(value["source_info_code"], 0x10);
+    assert_eq!(value["file_attributes"], "ARCHIVE | PINNED | STRICTLY_SEQUENTIAL");
+    assert_eq!(value["file_attributes_code"], 0x2008_0020);
+}
+
+#[test]
+fn usn_record_irregular_name_test() {
+    let mut record_buffer: Vec<u8> = vec![
+        0x60,0x00,0x00,0x00,0x02,0x00,0x00,0x00,0x73,0x00,0x00,0x00,0x00,0x00,0x68,0x91,
+        0x3B,0x2A,0x02,0x00,0x00,0x00,0x07,0x00,0x00,0x00,0x80,0xBC,0x04,0x00,0x00,0x00,
+        0x53,0xC7,0x8B,0x18,0xC5,0xCC,0xCE,0x01,0x02,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+        0x00,0x00,0x00,0x00,0x20,0x20,0x00,0x00,0x20,0x00,0x3C,0x00,0x42,0x00,0x54,0x00,
+        0x44,0x00,0x65,0x00,0x76,0x00,0x4D,0x00,0x61,0x00,0x6E,0x00,0x61,0x00,0x67,0x00,
+        0x65,0x00,0x72,0x00,0x2E,0x00,0x6C,0x00,0x6F,0x00,0x67,0x00,0x00,0x00,0x00,0x00
+    ];
+
+    let record = record::UsnRecord::new(2, record_buffer.as_slice()).unwrap();
+    assert_eq!(record.get_file_name(), "BTDevManager.log");
+    assert!(!record.has_irregular_name());
+    assert_eq!(record.get_file_name_hex(), None);
+    let value = record.to_json_value(None).unwrap();
+    assert!(value.get("irregular_name").is_none());
+    assert!(value.get("file_name_hex").is_none());
+
+    // An unpaired high surrogate in place of the B
+    record_buffer[60..62].copy_from_slice(&[0x00, 0xD8]);
+
+    let record = record::UsnRecord::new(2, record_buffer.as_slice()).unwrap();
+    assert_eq!(record.get_file_name(), r"\uD800TDevManager.log");
+    assert!(record.has_irregular_name());
+    assert_eq!(
+        record.get_file_name_hex(),
+        Some("00d854004400650076004d0061006e0061006700650072002e006c006f006700")
+    );
+    let value = record.to_json_value(None).unwrap();
+    assert_eq!(value["file_name"], r"\uD800TDevManager.log");
+    assert_eq!(value["irregular_name"], true);
+
+    // An unpaired low surrogate and an odd trailing byte
+    assert_eq!(
+        utils::decode_utf16_name(&[0x41, 0x00, 0x00, 0xDC, 0x42]),
+        (r"A\uDC00\x42".to_string(), true)
+    );
+    assert_eq!(
+        utils::decode_utf16_name(&[0x3D, 0xD8, 0x00, 0xDE]),
+        ("\u{1F600}".to_string(), false)
+    );
+
+    // Paths keep the escapes of their folder names
+    let mut mapping = FolderMapping::new();
+    mapping.add_mapping(MftReference::new(40, 1), record.get_file_name(), MftReference::new(5, 5));
+    assert_eq!(mapping.enumerate_path(40, 1).unwrap(), r"[root]/\uD800TDevManager.log");
+}
@@ -0,0 +1,110 @@
+extern crate rusty_usn;
+extern crate serde_json;
+mod common;
+use rusty_usn::flags::Reason;
+use rusty_usn::output::RecordSink;
+use rusty_usn::output::sessions::SessionWriter;
+use rusty_usn::record::{EntryMeta, UsnEntry};
+use rusty_usn::session::SessionAggregator;
+use serde_json::Value;
+use common::RecordBuilder;
+
+
+/// A V2 entry of the given file entry number, USN and reason, named
+/// `<initial>TDevManager.log`.
+fn entry(file_entry: u8, usn: u64, reason: Reason, initial: u8) -> UsnEntry {
+    RecordBuilder::new()
+        .with_meta(EntryMeta::new("sessions", 0))
+        .with_file_entry(u64::from(file_entry))
+        .with_usn(usn)
+        .with_reason(reason)
+        .with_name_unit(0, u16::from(initial))
+        .build()
+}
+
+
+#[test]
+fn session_aggregator_test() {
+    let mut aggregator = SessionAggregator::new();
+
+    assert!(aggregator.add(&entry(1, 100, Reason::FILE_CREATE, b'A'), Some("[root]/ATDevManager.log")).is_empty());
+    assert!(aggregator.add(&entry(2, 110, Reason::DATA_OVERWRITE, b'C'), None).is_empty());
+    assert!(aggregator.add(&entry(1, 120, Reason::FILE_CREATE | Reason::DATA_EXTEND, b'A'), None).is_empty());
+    assert!(aggregator.add(&entry(1, 130, Reason::RENAME_OLD_NAME, b'A'), None).is_empty());
+    assert!(aggregator.add(&entry(1, 140, Reason::RENAME_NEW_NAME, b'B'), Some("[root]/BTDevManager.log")).is_empty());
+    assert_eq!(aggregator.open_count(), 2);
+
+    let sessions = aggregator.add(&entry(1, 150, Reason::RENAME_NEW_NAME | Reason::CLOSE, b'B'), None);
+    assert_eq!(sessions.len(), 1);
+    let session = &sessions[0];
+    assert_eq!(session.file_reference.entry, 1);
+    assert_eq!((session.start_usn, session.end_usn), (100, 150));
+    assert_eq!(session.record_count, 5);
+    assert!(session.closed);
+    assert_eq!(session.file_names, vec!["ATDevManager.log", "BTDevManager.log"]);
+    assert_eq!(session.full_name.as_deref(), Some("[root]/BTDevManager.log"));
+    assert_eq!(
+        session.reason,
+        Reason::FILE_CREATE | Reason::DATA_EXTEND | Reason::RENAME_OLD_NAME | Reason::RENAME_NEW_NAME | Reason::CLOSE
+    );
+    assert_eq!(session.start_timestamp, session.end_timestamp);
+    assert!(session.start_timestamp.is_some());
+
+    let value = session.to_json_value().unwrap();
+    assert_eq!(value["reason_code"], session.reason.bits());
+    assert_eq!(value["meta__source"], "sessions");
+    assert_eq!(value["closed"], true);
+
+    // The session of file 2 never closed, and is dropped by default
+    assert_eq!(aggregator.open_count(), 1);
+    assert!(aggregator.finish().is_empty());
+    assert_eq!(aggregator.open_count(), 0);
+}
+
+#[test]
+fn session_limit_test() {
+    let mut aggregator = SessionAggregator::new()
+        .with_limit(2)
+        .with_flush_unclosed(true);
+    assert_eq!(aggregator.get_limit(), 2);
+
+    assert!(aggregator.add(&entry(1, 100, Reason::DATA_EXTEND, b'A'), None).is_empty());
+    assert!(aggregator.add(&entry(2, 110, Reason::DATA_EXTEND, b'B'), None).is_empty());
+    assert!(aggregator.add(&entry(1, 120, Reason::DATA_TRUNCATION, b'A'), None).is_empty());
+
+    // File 2 was written to least recently, and is pushed out
+    let sessions = aggregator.add(&entry(3, 130, Reason::FILE_CREATE, b'C'), None);
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].file_reference.entry, 2);
+    assert!(!sessions[0].closed);
+
+    let sessions = aggregator.finish();
+    let entries: Vec<u64> = sessions.iter().map(|session| session.file_reference.entry).collect();
+    assert_eq!(entries, vec![1, 3]);
+    assert!(sessions.iter().all(|session| !session.closed));
+}
+
+#[test]
+fn session_writer_test() {
+    let mut writer = SessionWriter::new(Vec::new())
+        .with_aggregator(SessionAggregator::new().with_flush_unclosed(true));
+    writer.write_entries(&[
+        entry(1, 100, Reason::FILE_CREATE, b'A'),
+        entry(2, 110, Reason::DATA_EXTEND, b'B'),
+        entry(1, 120, Reason::FILE_CREATE | Reason::CLOSE, b'A'),
+    ]).unwrap();
+    writer.finish().unwrap();
+
+    let output = String::from_utf8(writer.into_inner()).unwrap();
+    let lines: Vec<Value> = output.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["file_reference"]["entry"], 1);
+    assert_eq!(lines[0]["reason"], "CLOSE | FILE_CREATE");
+    assert_eq!(lines[0]["record_count"], 2);
+    assert_eq!(lines[0]["closed"], true);
+    assert_eq!(lines[1]["file_names"], serde_json::json!(["BTDevManager.log"]));
+    assert!(lines[1].get("irregular_name").is_none());
+    assert_eq!(lines[1]["closed"], false);
+}
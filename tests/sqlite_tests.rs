@@ -0,0 +1,68 @@
+#![cfg(feature = "sqlite")]
+extern crate rusty_usn;
+mod common;
+use rusqlite::Connection;
+use rusty_usn::output::RecordSink;
+use rusty_usn::output::sqlite::SqliteWriter;
+use rusty_usn::triage::VolumeMeta;
+use rusty_usn::record::UsnEntry;
+use common::v2_entry;
+
+
+fn host_entry(host: &str) -> UsnEntry {
+    let mut entry = v2_entry();
+    entry.meta.volume = Some(VolumeMeta {volume: "C".to_string(), host: Some(host.to_string())});
+    entry
+}
+
+fn count(connection: &Connection, query: &str) -> i64 {
+    connection.query_row(query, [], |row| row.get(0)).unwrap()
+}
+
+
+#[test]
+fn sqlite_writer_test() {
+    let directory = std::env::temp_dir().join(format!("rusty_usn_sqlite_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let database = directory.join("case.db");
+    let _ = std::fs::remove_file(&database);
+
+    // Two runs from different hosts append into the same database
+    for host in ["HOST1", "HOST2"] {
+        let mut writer = SqliteWriter::open(&database).unwrap().with_batch_size(2);
+        for _ in 0..3 {
+            writer.write_entry(&host_entry(host), Some("[root]/Windows/BTDevManager.log")).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    let connection = Connection::open(&database).unwrap();
+    assert_eq!(count(&connection, "SELECT COUNT(*) FROM sources"), 2);
+    assert_eq!(count(&connection, "SELECT COUNT(*) FROM records"), 6);
+    assert_eq!(count(&connection, "SELECT COUNT(*) FROM records JOIN sources ON sources.id = source_id WHERE host = 'HOST2'"), 3);
+
+    let (usn, timestamp, file_name, full_name, entry, file_id): (i64, String, String, String, i64, String) = connection.query_row(
+        "SELECT usn, timestamp, file_name, full_name, file_reference_entry, file_reference_u128 FROM records LIMIT 1",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+    ).unwrap();
+    assert_eq!(usn, 8192);
+    assert_eq!(timestamp, "2013-10-19T12:16:53.276040Z");
+    assert_eq!(file_name, "BTDevManager.log");
+    assert_eq!(full_name, "[root]/Windows/BTDevManager.log");
+    assert_eq!(entry, 115);
+    assert_eq!(file_id, "10477624533077459059");
+    assert_eq!(count(&connection, "SELECT COUNT(*) FROM records WHERE irregular_name = 0 AND file_name_hex IS NULL"), 6);
+
+    // Flags are decoded one per row
+    assert_eq!(count(&connection, "SELECT COUNT(*) FROM record_flags WHERE kind = 'reason' AND flag = 'DATA_EXTEND'"), 6);
+    assert_eq!(count(&connection, "SELECT COUNT(*) FROM record_flags WHERE kind = 'file_attribute' AND flag = 'ARCHIVE'"), 6);
+
+    for index in ["records_usn", "records_timestamp", "records_file_reference", "records_file_name"] {
+        let query = format!("SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = '{}'", index);
+        assert_eq!(count(&connection, &query), 1, "{}", index);
+    }
+
+    drop(connection);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,91 @@
+extern crate rusty_usn;
+use std::fs;
+use std::path::{Path, PathBuf};
+use rusty_usn::triage::{decode_name, discover, is_journal_name, VolumeMeta};
+
+
+/// Create empty files at the given paths under `root`.
+fn create_files(root: &Path, files: &[&str]) {
+    for file in files {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"").unwrap();
+    }
+}
+
+fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("rusty_usn_triage_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+
+#[test]
+fn journal_name_test() {
+    assert!(is_journal_name("$J"));
+    assert!(is_journal_name("$UsnJrnl$J"));
+    assert!(is_journal_name("$UsnJrnl_$J"));
+    assert!(is_journal_name("$UsnJrnl%3A$J"));
+    assert!(is_journal_name("%24UsnJrnl%3A%24J"));
+    assert!(!is_journal_name("$UsnJrnl%3A$Max"));
+    assert!(!is_journal_name("$MFT"));
+
+    assert_eq!(decode_name("%5C%5C.%5CC%3A"), "\\\\.\\C:");
+    assert_eq!(decode_name("100%"), "100%");
+}
+
+#[test]
+fn kape_test() {
+    let root = temp_root("kape");
+    create_files(&root, &[
+        "HOST1/C/$MFT",
+        "HOST1/C/$Extend/$J",
+        "HOST1/D/$Extend/$UsnJrnl_$J",
+    ]);
+
+    let volumes = discover(&root).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(volumes.len(), 2);
+    assert_eq!(volumes[0].journal, root.join("HOST1/C/$Extend/$J"));
+    assert_eq!(volumes[0].mft, Some(root.join("HOST1/C/$MFT")));
+    assert_eq!(volumes[0].meta, VolumeMeta {volume: "C".to_string(), host: Some("HOST1".to_string())});
+    // No $MFT was collected for D
+    assert_eq!(volumes[1].mft, None);
+    assert_eq!(volumes[1].meta.volume, "D");
+}
+
+#[test]
+fn velociraptor_test() {
+    let root = temp_root("velociraptor");
+    create_files(&root, &[
+        "Collection-DESKTOP-01-2023-05-01T12_34_56Z/uploads/ntfs/%5C%5C.%5CC%3A/%24MFT",
+        "Collection-DESKTOP-01-2023-05-01T12_34_56Z/uploads/ntfs/%5C%5C.%5CC%3A/%24Extend/%24UsnJrnl%3A%24J",
+        "Collection-DESKTOP-01-2023-05-01T12_34_56Z/uploads/ntfs/%5C%5C.%5CC%3A/%24Extend/%24UsnJrnl%3A%24Max",
+    ]);
+
+    let volumes = discover(&root).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(volumes.len(), 1);
+    assert!(volumes[0].mft.as_ref().unwrap().ends_with("%24MFT"));
+    assert_eq!(volumes[0].meta, VolumeMeta {volume: "C".to_string(), host: Some("DESKTOP-01".to_string())});
+}
+
+#[test]
+fn cylr_test() {
+    let root = temp_root("cylr");
+    create_files(&root, &[
+        "WKSTN-7/C/$MFT",
+        "WKSTN-7/C/$Extend/$UsnJrnl$J",
+        "WKSTN-7/C/Windows/System32/config/SYSTEM",
+    ]);
+
+    let volumes = discover(&root.join("WKSTN-7")).unwrap();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(volumes.len(), 1);
+    assert_eq!(volumes[0].mft, Some(root.join("WKSTN-7/C/$MFT")));
+    assert_eq!(volumes[0].meta, VolumeMeta {volume: "C".to_string(), host: Some("WKSTN-7".to_string())});
+}
@@ -0,0 +1,291 @@
+extern crate rusty_usn;
+mod common;
+use std::fs::OpenOptions;
+use std::io;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use chrono::{DateTime, Utc};
+use rusty_usn::error::ErrorKind;
+use rusty_usn::record::EntryMeta;
+use rusty_usn::usn::{IterRecordsByIndex, ScanMode, UsnParser, UsnParserSettings};
+use common::{RecordBuilder, V2_FILETIME, V2_RECORD};
+
+
+/// Fails every read that starts in the second 16 KiB chunk.
+struct FailingReader(Cursor<Vec<u8>>);
+
+impl Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if (16384..32768).contains(&self.0.position()) {
+            return Err(io::Error::other("bad sector"));
+        }
+        self.0.read(buf)
+    }
+}
+
+impl Seek for FailingReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+
+/// Counts the bytes read through it.
+struct CountingReader(Cursor<Vec<u8>>, Rc<Cell<u64>>);
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+        self.1.set(self.1.get() + read as u64);
+        Ok(read)
+    }
+}
+
+impl Seek for CountingReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// A journal of `pages` pages with a cleared first page and a V2 record every
+/// 96 bytes after it. The USN of each record is its offset and its timestamp
+/// is one second later than the one before.
+fn build_journal(pages: usize) -> Vec<u8> {
+    let mut data = vec![0u8; pages * 4096];
+    for page in 1..pages {
+        for index in 0..4096 / 96 {
+            let offset = page * 4096 + index * 96;
+            let record = RecordBuilder::new()
+                .with_usn(offset as u64)
+                .with_filetime(V2_FILETIME + offset as u64 / 96 * 10_000_000)
+                .to_bytes();
+            data[offset..offset + 96].copy_from_slice(&record);
+        }
+    }
+    data
+}
+
+
+#[test]
+fn zeroed_region_test() {
+    // 1 MiB of zeros followed by a live tail
+    let mut data = vec![0u8; 1024 * 1024];
+    data.extend_from_slice(V2_RECORD);
+    data.resize(data.len() + 4096 - V2_RECORD.len(), 0);
+
+    let mut parser = UsnParser::from_read_seek("zeroed".to_string(), Cursor::new(data)).unwrap();
+    let entries: Vec<_> = parser.records().collect();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].meta.offset, 1024 * 1024);
+    assert_eq!(entries[0].record.get_file_name(), "BTDevManager.log");
+    // Every 16 KiB search window before the tail is skipped
+    assert_eq!(parser.get_skipped_bytes(), 1024 * 1024);
+}
+
+#[test]
+fn sparse_file_test() {
+    let path = std::env::temp_dir().join(format!("rusty_usn_sparse_{}.J", std::process::id()));
+    let tail_offset = 64 * 1024 * 1024;
+    {
+        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(&path).unwrap();
+        file.set_len(tail_offset + 4096).unwrap();
+        file.seek(SeekFrom::Start(tail_offset)).unwrap();
+        file.write_all(V2_RECORD).unwrap();
+    }
+
+    let mut parser = UsnParser::from_path(path.to_str().unwrap()).unwrap();
+    let entries: Vec<_> = parser.records().collect();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].meta.offset, tail_offset);
+    assert_eq!(parser.get_skipped_bytes(), tail_offset);
+}
+
+#[test]
+fn strict_walk_test() {
+    let mut data = vec![0u8; 3 * 4096];
+    // Two records in the first page, then padding
+    data[0..96].copy_from_slice(V2_RECORD);
+    data[96..192].copy_from_slice(V2_RECORD);
+    // A record followed by garbage in the second page
+    data[4096..4192].copy_from_slice(V2_RECORD);
+    data[4192..4200].copy_from_slice(&[0xFF; 8]);
+    // The walk picks up again at the third page
+    data[8192..8288].copy_from_slice(V2_RECORD);
+
+    let config = UsnParserSettings::new().scan_mode(ScanMode::Strict);
+    let mut parser = UsnParser::from_read_seek("strict".to_string(), Cursor::new(data))
+        .unwrap()
+        .with_configuration(config);
+    let entries: Vec<_> = parser.records().collect();
+
+    let offsets: Vec<u64> = entries.iter().map(|entry| entry.meta.offset).collect();
+    assert_eq!(offsets, vec![0, 96, 4096, 8192]);
+    assert_eq!(parser.get_break_offsets(), &[4192]);
+
+    let value = entries[0].to_json_value().unwrap();
+    assert_eq!(value["meta__scan_mode"], "strict");
+}
+
+#[test]
+fn confidence_test() {
+    let mut data = vec![0u8; 4096];
+    data[0..96].copy_from_slice(V2_RECORD);
+    // A hit with a 1601 timestamp, an unknown reason bit and a control character in its name
+    let noisy = RecordBuilder::new()
+        .with_filetime(1)
+        .with_reason_bits(0x0200_0002)
+        .with_name_unit(0, 0x07)
+        .to_bytes();
+    data[96..192].copy_from_slice(&noisy);
+
+    let mut parser = UsnParser::from_read_seek("carved".to_string(), Cursor::new(data.clone())).unwrap();
+    let entries: Vec<_> = parser.records().collect();
+    assert_eq!(entries.len(), 2);
+
+    let good = entries[0].meta.confidence.unwrap();
+    assert_eq!(good.score, 100);
+
+    let noisy = entries[1].meta.confidence.unwrap();
+    assert_eq!(noisy.score, 50);
+    assert!(!noisy.checks.timestamp);
+    assert!(!noisy.checks.reason);
+    assert!(!noisy.checks.file_name);
+    assert!(noisy.checks.references);
+
+    let value = entries[1].to_json_value().unwrap();
+    assert_eq!(value["meta__confidence"], 50);
+    assert_eq!(value["meta__confidence_checks"]["timestamp"], false);
+
+    let config = UsnParserSettings::new().min_confidence(75);
+    let mut parser = UsnParser::from_read_seek("carved".to_string(), Cursor::new(data.clone()))
+        .unwrap()
+        .with_configuration(config);
+    let entries: Vec<_> = parser.records().collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].meta.offset, 0);
+
+    // The good record is from 2013
+    let latest = "2010-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+    let config = UsnParserSettings::new().latest_timestamp(Some(latest));
+    let mut parser = UsnParser::from_read_seek("carved".to_string(), Cursor::new(data))
+        .unwrap()
+        .with_configuration(config);
+    let entries: Vec<_> = parser.records().collect();
+    let confidence = entries[0].meta.confidence.unwrap();
+    assert!(!confidence.checks.timestamp);
+    assert_eq!(confidence.score, 83);
+}
+
+#[test]
+fn diagnostics_test() {
+    let mut data = vec![0u8; 3 * 16384];
+    data[0..96].copy_from_slice(V2_RECORD);
+    // A hit with a bad name offset
+    let bad_hit = RecordBuilder::new().with_file_name_offset(0x40).to_bytes();
+    data[96..192].copy_from_slice(&bad_hit);
+    data[16384..16480].copy_from_slice(V2_RECORD);
+    data[32768..32864].copy_from_slice(V2_RECORD);
+
+    let mut parser = UsnParser::from_read_seek("diagnostics".to_string(), FailingReader(Cursor::new(data))).unwrap();
+    let results: Vec<_> = parser.results().collect();
+
+    let offsets: Vec<u64> = results.iter()
+        .filter_map(|result| result.as_ref().ok())
+        .map(|entry| entry.meta.offset)
+        .collect();
+    assert_eq!(offsets, vec![0, 32768]);
+
+    let diagnostics: Vec<_> = results.iter().filter_map(|result| result.as_ref().err()).collect();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].offset, 96);
+    assert_eq!(diagnostics[0].kind(), ErrorKind::InvalidUsnRecord);
+    assert_eq!(diagnostics[0].raw_header, &bad_hit[..64]);
+    assert_eq!(diagnostics[1].offset, 16384);
+    assert_eq!(diagnostics[1].kind(), ErrorKind::IoError);
+
+    let counts = parser.get_rejection_counts();
+    assert_eq!(counts.get(&ErrorKind::IoError), Some(&1));
+    assert_eq!(counts.get(&ErrorKind::InvalidUsnRecord), Some(&1));
+}
+
+#[test]
+fn records_by_index_test() {
+    let mut block = V2_RECORD.to_vec();
+    block.extend_from_slice(&[0xFF; 8]);
+    block.extend_from_slice(V2_RECORD);
+
+    let mut iterator = IterRecordsByIndex::new(EntryMeta::new("live", 4096), block);
+    assert_eq!(iterator.next_result().unwrap().unwrap().meta.offset, 4096);
+    let diagnostic = iterator.next_result().unwrap().unwrap_err();
+    assert_eq!(diagnostic.offset, 4096 + 96);
+    assert_eq!(iterator.next_result().unwrap().unwrap().meta.offset, 4096 + 104);
+    assert!(iterator.next_result().is_none());
+}
+
+#[test]
+fn usn_range_test() {
+    let data = build_journal(64);
+    let bytes_read = Rc::new(Cell::new(0));
+    let handle = CountingReader(Cursor::new(data), bytes_read.clone());
+
+    let mut parser = UsnParser::from_read_seek("ranged".to_string(), handle).unwrap();
+    assert_eq!(parser.find_usn_offset(40 * 4096 + 960).unwrap(), 40 * 4096);
+    assert_eq!(parser.find_usn_offset(0).unwrap(), 0);
+
+    bytes_read.set(0);
+    let config = UsnParserSettings::new().usn_range(Some(60 * 4096), Some(60 * 4096 + 960));
+    let mut parser = parser.with_configuration(config);
+    let usns: Vec<u64> = parser.records().map(|entry| entry.record.get_usn()).collect();
+
+    let expected: Vec<u64> = (0..11).map(|index| 60 * 4096 + index * 96).collect();
+    assert_eq!(usns, expected);
+    // The binary searches and the pages around the range, not the whole journal
+    assert!(bytes_read.get() < 32 * 4096, "read {} bytes", bytes_read.get());
+    assert_eq!(parser.get_skipped_bytes(), 0);
+}
+
+#[test]
+fn time_range_test() {
+    let data = build_journal(16);
+    let first_record = &data[4096..4096 + 96];
+    let first_timestamp = rusty_usn::record::UsnEntry::new(
+        EntryMeta::new("time", 4096), 2, first_record
+    ).unwrap().record.get_timestamp().unwrap();
+
+    let since: DateTime<Utc> = first_timestamp + chrono::Duration::seconds(100);
+    let until: DateTime<Utc> = first_timestamp + chrono::Duration::seconds(104);
+    let config = UsnParserSettings::new().time_range(Some(since), Some(until));
+    let mut parser = UsnParser::from_read_seek("time".to_string(), Cursor::new(data))
+        .unwrap()
+        .with_configuration(config);
+    let entries: Vec<_> = parser.records().collect();
+
+    assert_eq!(entries.len(), 5);
+    assert_eq!(entries[0].record.get_timestamp(), Some(since));
+    assert_eq!(entries[4].record.get_timestamp(), Some(until));
+}
+
+#[test]
+fn record_batches_test() {
+    let data = build_journal(16);
+    let config = UsnParserSettings::new().thread_count(2);
+
+    let mut parser = UsnParser::from_read_seek("batched".to_string(), Cursor::new(data.clone()))
+        .unwrap()
+        .with_configuration(config.clone());
+    let batches: Vec<_> = parser.record_batches().collect();
+
+    let mut parser = UsnParser::from_read_seek("batched".to_string(), Cursor::new(data))
+        .unwrap()
+        .with_configuration(config);
+    let usns: Vec<u64> = parser.records().map(|entry| entry.record.get_usn()).collect();
+
+    // Each batch is the records of two chunks, in journal order
+    assert!(batches.len() > 1);
+    let batched_usns: Vec<u64> = batches.iter().flatten().map(|entry| entry.record.get_usn()).collect();
+    assert_eq!(batched_usns, usns);
+}
@@ -0,0 +1,397 @@
+extern crate rusty_usn;
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use rusty_usn::image::open_image;
+use rusty_usn::image::vhd::{VhdDiskType, VhdImage};
+use rusty_usn::image::vhdx::VhdxImage;
+
+const VHD_BLOCK_SIZE: usize = 4096;
+const VHD_BLOCKS: usize = 3;
+const VHD_BAT_OFFSET: usize = 1536;
+const VHD_DATA_OFFSET: usize = 2048;
+const VHD_BITMAP_SIZE: usize = 512;
+
+const MB: usize = 1024 * 1024;
+const VHDX_BLOCK_SIZE: usize = MB;
+const VHDX_BLOCKS: usize = 3;
+const VHDX_METADATA_OFFSET: usize = MB;
+const VHDX_BAT_OFFSET: usize = 2 * MB;
+// Payload entries per sector bitmap entry for 1 MiB blocks of 512 byte sectors
+const VHDX_CHUNK_RATIO: usize = 4096;
+
+
+fn disk_content(size: usize, seed: usize) -> Vec<u8> {
+    (0..size).map(|i| ((i * 7 + seed) % 253 + 1) as u8).collect()
+}
+
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+}
+
+fn vhd_footer(disk_type: u32, size: u64, data_offset: u64) -> Vec<u8> {
+    let mut footer = vec![0u8; 512];
+    footer[0..8].copy_from_slice(b"conectix");
+    BigEndian::write_u32(&mut footer[8..12], 2);
+    BigEndian::write_u32(&mut footer[12..16], 0x0001_0000);
+    BigEndian::write_u64(&mut footer[16..24], data_offset);
+    BigEndian::write_u64(&mut footer[40..48], size);
+    BigEndian::write_u64(&mut footer[48..56], size);
+    BigEndian::write_u32(&mut footer[60..64], disk_type);
+    footer[68..84].copy_from_slice(&[disk_type as u8; 16]);
+    footer
+}
+
+/// A dynamic or differencing VHD. `blocks` holds the content of allocated
+/// blocks and the sectors of each block that are set in its bitmap.
+fn build_vhd(disk_type: u32, blocks: &[Option<(Vec<u8>, Vec<usize>)>], parent_path: Option<&str>) -> Vec<u8> {
+    let size = (blocks.len() * VHD_BLOCK_SIZE) as u64;
+    let footer = vhd_footer(disk_type, size, 512);
+    let mut image = footer.clone();
+
+    let mut header = vec![0u8; 1024];
+    header[0..8].copy_from_slice(b"cxsparse");
+    BigEndian::write_u64(&mut header[8..16], u64::MAX);
+    BigEndian::write_u64(&mut header[16..24], VHD_BAT_OFFSET as u64);
+    BigEndian::write_u32(&mut header[24..28], 0x0001_0000);
+    BigEndian::write_u32(&mut header[28..32], blocks.len() as u32);
+    BigEndian::write_u32(&mut header[32..36], VHD_BLOCK_SIZE as u32);
+    image.extend(header);
+
+    let mut bat = vec![0xFFu8; 512];
+    let mut data = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some((content, sectors)) = block {
+            let sector = (VHD_DATA_OFFSET + data.len()) / 512;
+            BigEndian::write_u32(&mut bat[i * 4..i * 4 + 4], sector as u32);
+
+            let mut bitmap = vec![0u8; VHD_BITMAP_SIZE];
+            for sector in sectors {
+                bitmap[sector / 8] |= 0x80 >> (sector % 8);
+            }
+            data.extend(bitmap);
+            data.extend(content);
+        }
+    }
+    image.extend(bat);
+    image.extend(data);
+
+    if let Some(parent_path) = parent_path {
+        // A relative Windows path parent locator stored after the data
+        let locator_offset = image.len();
+        let locator = utf16le(parent_path);
+        image.extend(&locator);
+        while image.len() % 512 != 0 {
+            image.push(0);
+        }
+
+        let entry = &mut image[512 + 576..512 + 600];
+        entry[0..4].copy_from_slice(b"W2ru");
+        BigEndian::write_u32(&mut entry[4..8], 512);
+        BigEndian::write_u32(&mut entry[8..12], locator.len() as u32);
+        BigEndian::write_u64(&mut entry[16..24], locator_offset as u64);
+    }
+
+    image.extend(footer);
+    image
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn guid(text: &str) -> Vec<u8> {
+    let mut bytes = hex::decode(text.replace('-', "")).unwrap();
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+fn write_checksum(buffer: &mut [u8]) {
+    let checksum = crc32c(buffer);
+    LittleEndian::write_u32(&mut buffer[4..8], checksum);
+}
+
+/// A VHDX with 1 MiB blocks. `blocks` holds the BAT state and content of each
+/// payload block. `bitmap` holds the sectors of chunk 0 present in this file.
+fn build_vhdx(blocks: &[(u64, Option<Vec<u8>>)], bitmap: Option<&[usize]>, parent_path: Option<&str>) -> Vec<u8> {
+    let data_start = 3 * MB;
+    let mut image = vec![0u8; data_start];
+    image[0..8].copy_from_slice(b"vhdxfile");
+
+    for (i, offset) in [0x1_0000usize, 0x2_0000].iter().enumerate() {
+        let header = &mut image[*offset..*offset + 4096];
+        header[0..4].copy_from_slice(b"head");
+        LittleEndian::write_u64(&mut header[8..16], i as u64 + 1);
+        LittleEndian::write_u16(&mut header[66..68], 1);
+        write_checksum(header);
+    }
+
+    for offset in [0x3_0000usize, 0x4_0000].iter() {
+        let table = &mut image[*offset..*offset + 0x1_0000];
+        table[0..4].copy_from_slice(b"regi");
+        LittleEndian::write_u32(&mut table[8..12], 2);
+        let regions = [
+            ("2DC27766-F623-4200-9D64-115E9BFD4A08", VHDX_BAT_OFFSET, MB),
+            ("8B7CA206-4790-4B9A-B8FE-575F050F886E", VHDX_METADATA_OFFSET, MB),
+        ];
+        for (i, (region_guid, region_offset, length)) in regions.iter().enumerate() {
+            let entry = &mut table[16 + i * 32..48 + i * 32];
+            entry[0..16].copy_from_slice(&guid(region_guid));
+            LittleEndian::write_u64(&mut entry[16..24], *region_offset as u64);
+            LittleEndian::write_u32(&mut entry[24..28], *length as u32);
+            LittleEndian::write_u32(&mut entry[28..32], 1);
+        }
+        write_checksum(table);
+    }
+
+    // Metadata items start 64 KiB into the metadata region
+    let mut file_parameters = vec![0u8; 8];
+    LittleEndian::write_u32(&mut file_parameters[0..4], VHDX_BLOCK_SIZE as u32);
+    if parent_path.is_some() {
+        LittleEndian::write_u32(&mut file_parameters[4..8], 2);
+    }
+    let mut items = vec![
+        ("CAA16737-FA36-4D43-B3B6-33F0AA44E76B", file_parameters),
+        ("2FA54224-CD1B-4876-B211-5DBED83BF4B8", ((blocks.len() * VHDX_BLOCK_SIZE) as u64).to_le_bytes().to_vec()),
+        ("8141BF1D-A96F-4709-BA47-F233A8FAAB5F", 512u32.to_le_bytes().to_vec()),
+    ];
+    if let Some(parent_path) = parent_path {
+        let key = utf16le("relative_path");
+        let value = utf16le(parent_path);
+        let mut locator = guid("B04AEFB7-D19E-4A81-B789-25B8E9445913");
+        locator.extend(&[0, 0]);
+        locator.extend(&1u16.to_le_bytes());
+        locator.extend(&32u32.to_le_bytes());
+        locator.extend(&(32 + key.len() as u32).to_le_bytes());
+        locator.extend(&(key.len() as u16).to_le_bytes());
+        locator.extend(&(value.len() as u16).to_le_bytes());
+        locator.extend(key);
+        locator.extend(value);
+        items.push(("A8D35F2D-B30B-454D-ABF7-D3D84834AB0C", locator));
+    }
+
+    let metadata = &mut image[VHDX_METADATA_OFFSET..VHDX_METADATA_OFFSET + MB];
+    metadata[0..8].copy_from_slice(b"metadata");
+    LittleEndian::write_u16(&mut metadata[10..12], items.len() as u16);
+    let mut item_offset = 0x1_0000;
+    for (i, (item_guid, item)) in items.iter().enumerate() {
+        let entry = &mut metadata[32 + i * 32..64 + i * 32];
+        entry[0..16].copy_from_slice(&guid(item_guid));
+        LittleEndian::write_u32(&mut entry[16..20], item_offset as u32);
+        LittleEndian::write_u32(&mut entry[20..24], item.len() as u32);
+        metadata[item_offset..item_offset + item.len()].copy_from_slice(item);
+        item_offset += item.len();
+    }
+
+    let mut bat_entries = vec![0u64; VHDX_CHUNK_RATIO + 1];
+    for (i, (state, content)) in blocks.iter().enumerate() {
+        bat_entries[i] = *state;
+        if let Some(content) = content {
+            bat_entries[i] |= image.len() as u64;
+            image.extend(content);
+        }
+    }
+    if let Some(sectors) = bitmap {
+        let mut sector_bitmap = vec![0u8; MB];
+        for sector in sectors {
+            sector_bitmap[sector / 8] |= 1 << (sector % 8);
+        }
+        bat_entries[VHDX_CHUNK_RATIO] = image.len() as u64 | 6;
+        image.extend(sector_bitmap);
+    }
+    for (i, entry) in bat_entries.iter().enumerate() {
+        let offset = VHDX_BAT_OFFSET + i * 8;
+        LittleEndian::write_u64(&mut image[offset..offset + 8], *entry);
+    }
+
+    image
+}
+
+
+#[test]
+fn fixed_vhd_test() {
+    let content = disk_content(8192, 0);
+    let mut image = content.clone();
+    image.extend(vhd_footer(2, content.len() as u64, u64::MAX));
+
+    let mut vhd = VhdImage::new(Cursor::new(image)).unwrap();
+    assert_eq!(vhd.get_disk_type(), VhdDiskType::Fixed);
+    assert_eq!(vhd.get_size(), 8192);
+
+    let mut buffer = Vec::new();
+    vhd.read_to_end(&mut buffer).unwrap();
+    assert_eq!(buffer, content);
+
+    assert_eq!(vhd.seek(SeekFrom::End(-512)).unwrap(), 8192 - 512);
+    assert!(vhd.seek(SeekFrom::Current(-8192)).is_err());
+    assert!(vhd.seek(SeekFrom::Start(u64::MAX)).is_ok());
+    assert!(vhd.seek(SeekFrom::Current(1)).is_err());
+}
+
+#[test]
+fn dynamic_vhd_test() {
+    let first = disk_content(VHD_BLOCK_SIZE, 1);
+    let last = disk_content(VHD_BLOCK_SIZE, 2);
+    let image = build_vhd(3, &[Some((first.clone(), vec![])), None, Some((last.clone(), vec![]))], None);
+
+    let mut vhd = VhdImage::new(Cursor::new(image)).unwrap();
+    assert_eq!(vhd.get_disk_type(), VhdDiskType::Dynamic);
+    assert_eq!(vhd.get_size(), (VHD_BLOCKS * VHD_BLOCK_SIZE) as u64);
+
+    let mut buffer = Vec::new();
+    vhd.read_to_end(&mut buffer).unwrap();
+    assert_eq!(&buffer[..VHD_BLOCK_SIZE], &first[..]);
+    assert!(buffer[VHD_BLOCK_SIZE..2 * VHD_BLOCK_SIZE].iter().all(|byte| *byte == 0));
+    assert_eq!(&buffer[2 * VHD_BLOCK_SIZE..], &last[..]);
+}
+
+#[test]
+fn differencing_vhd_test() {
+    let parent_content = disk_content(VHD_BLOCKS * VHD_BLOCK_SIZE, 3);
+    let parent = build_vhd(3, &[
+        Some((parent_content[..VHD_BLOCK_SIZE].to_vec(), vec![])),
+        Some((parent_content[VHD_BLOCK_SIZE..2 * VHD_BLOCK_SIZE].to_vec(), vec![])),
+        Some((parent_content[2 * VHD_BLOCK_SIZE..].to_vec(), vec![])),
+    ], None);
+
+    // Sectors 1 and 2 of block 0 are changed in the child
+    let child_block = disk_content(VHD_BLOCK_SIZE, 4);
+    let child = build_vhd(4, &[Some((child_block.clone(), vec![1, 2])), None, None], Some(".\\parent.vhd"));
+
+    let mut expected = parent_content.clone();
+    expected[512..1536].copy_from_slice(&child_block[512..1536]);
+
+    let mut vhd = VhdImage::new(Cursor::new(child.clone()))
+        .unwrap()
+        .with_parent(Box::new(VhdImage::new(Cursor::new(parent.clone())).unwrap()));
+    assert_eq!(vhd.get_disk_type(), VhdDiskType::Differencing);
+    assert_eq!(vhd.get_parent_locations(), &[".\\parent.vhd".to_string()]);
+
+    let mut buffer = Vec::new();
+    vhd.read_to_end(&mut buffer).unwrap();
+    assert_eq!(buffer, expected);
+
+    // The parent is found through the locator when opening from a path
+    let directory = std::env::temp_dir().join(format!("rusty_usn_vhd_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("parent.vhd"), parent).unwrap();
+    fs::write(directory.join("child.vhd"), child).unwrap();
+
+    let mut opened = open_image(directory.join("child.vhd")).unwrap();
+    let mut buffer = vec![0u8; 2048];
+    opened.seek(SeekFrom::Start(0)).unwrap();
+    opened.read_exact(&mut buffer).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(buffer, &expected[..2048]);
+}
+
+#[test]
+fn dynamic_vhdx_test() {
+    let first = disk_content(VHDX_BLOCK_SIZE, 5);
+    let last = disk_content(VHDX_BLOCK_SIZE, 6);
+    let image = build_vhdx(&[(6, Some(first.clone())), (0, None), (6, Some(last.clone()))], None, None);
+
+    let mut vhdx = VhdxImage::new(Cursor::new(image)).unwrap();
+    assert_eq!(vhdx.get_size(), (VHDX_BLOCKS * VHDX_BLOCK_SIZE) as u64);
+    assert_eq!(vhdx.get_metadata().block_size, VHDX_BLOCK_SIZE as u64);
+    assert!(!vhdx.get_metadata().has_parent);
+
+    let mut buffer = Vec::new();
+    vhdx.read_to_end(&mut buffer).unwrap();
+    assert_eq!(&buffer[..VHDX_BLOCK_SIZE], &first[..]);
+    assert!(buffer[VHDX_BLOCK_SIZE..2 * VHDX_BLOCK_SIZE].iter().all(|byte| *byte == 0));
+    assert_eq!(&buffer[2 * VHDX_BLOCK_SIZE..], &last[..]);
+}
+
+#[test]
+fn differencing_vhdx_test() {
+    let parent_content = disk_content(VHDX_BLOCKS * VHDX_BLOCK_SIZE, 7);
+    let parent = build_vhdx(&[
+        (6, Some(parent_content[..MB].to_vec())),
+        (6, Some(parent_content[MB..2 * MB].to_vec())),
+        (6, Some(parent_content[2 * MB..].to_vec())),
+    ], None, None);
+
+    // Block 0 has sectors 4 and 5 in the child, block 1 is entirely in the child
+    let child_first = disk_content(MB, 8);
+    let child_second = disk_content(MB, 9);
+    let child = build_vhdx(&[
+        (7, Some(child_first.clone())),
+        (6, Some(child_second.clone())),
+        (0, None),
+    ], Some(&[4, 5]), Some("parent.vhdx"));
+
+    let mut expected = parent_content.clone();
+    expected[2048..3072].copy_from_slice(&child_first[2048..3072]);
+    expected[MB..2 * MB].copy_from_slice(&child_second);
+
+    let vhdx = VhdxImage::new(Cursor::new(child.clone())).unwrap();
+    assert!(vhdx.get_metadata().has_parent);
+    assert_eq!(vhdx.get_parent_locations(), vec!["parent.vhdx".to_string()]);
+
+    let mut vhdx = vhdx.with_parent(Box::new(VhdxImage::new(Cursor::new(parent.clone())).unwrap()));
+    let mut buffer = Vec::new();
+    vhdx.read_to_end(&mut buffer).unwrap();
+    assert!(buffer == expected);
+
+    let directory = std::env::temp_dir().join(format!("rusty_usn_vhdx_{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("parent.vhdx"), parent).unwrap();
+    fs::write(directory.join("child.vhdx"), child).unwrap();
+
+    let mut opened = open_image(directory.join("child.vhdx")).unwrap();
+    let mut buffer = vec![0u8; 4096];
+    opened.read_exact(&mut buffer).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(buffer, &expected[..4096]);
+}
+
+#[test]
+fn differencing_vhdx_zero_block_test() {
+    let parent_content = disk_content(VHDX_BLOCKS * VHDX_BLOCK_SIZE, 10);
+    let parent = build_vhdx(&[
+        (6, Some(parent_content[..MB].to_vec())),
+        (6, Some(parent_content[MB..2 * MB].to_vec())),
+        (6, Some(parent_content[2 * MB..].to_vec())),
+    ], None, None);
+
+    // Zeroed and unmapped blocks hide the parent, not present blocks do not
+    let child = build_vhdx(&[(2, None), (3, None), (0, None)], None, Some("parent.vhdx"));
+
+    let mut vhdx = VhdxImage::new(Cursor::new(child))
+        .unwrap()
+        .with_parent(Box::new(VhdxImage::new(Cursor::new(parent)).unwrap()));
+    let mut buffer = Vec::new();
+    vhdx.read_to_end(&mut buffer).unwrap();
+    assert!(buffer[..2 * MB].iter().all(|byte| *byte == 0));
+    assert!(buffer[2 * MB..] == parent_content[2 * MB..]);
+}
+
+#[test]
+fn truncated_tables_test() {
+    // A BAT with more entries than the file holds
+    let mut vhd = build_vhd(3, &[None, None, None], None);
+    BigEndian::write_u32(&mut vhd[512 + 28..512 + 32], u32::MAX);
+    assert!(VhdImage::new(Cursor::new(vhd)).is_err());
+
+    // A BAT region past the end of the file
+    let mut vhdx = build_vhdx(&[(0, None), (0, None), (0, None)], None, None);
+    for offset in [0x3_0000usize, 0x4_0000].iter() {
+        let table = &mut vhdx[*offset..*offset + 0x1_0000];
+        LittleEndian::write_u32(&mut table[16 + 24..16 + 28], u32::MAX);
+        LittleEndian::write_u32(&mut table[4..8], 0);
+        write_checksum(table);
+    }
+    assert!(VhdxImage::new(Cursor::new(vhdx)).is_err());
+}